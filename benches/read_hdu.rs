@@ -0,0 +1,45 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Compares reading MWAX gpubox HDUs through cfitsio against reading them directly.
+//!
+//! Run with `cargo bench --bench read_hdu`.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mwalib::{CorrelatorContext, CorrelatorReadConfig};
+
+const MWAX_METAFITS: &str = "test_files/1244973688_1_timestep/1244973688.metafits";
+const MWAX_GPUBOX: &str =
+    "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+fn read_hdu(c: &mut Criterion) {
+    let mut context = CorrelatorContext::new(MWAX_METAFITS, &[MWAX_GPUBOX])
+        .expect("Failed to create CorrelatorContext");
+    let coarse_chan_index = 10;
+    let mut buffer: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+
+    let mut group = c.benchmark_group("mwax_read_by_baseline");
+
+    context.read_config = CorrelatorReadConfig::new();
+    group.bench_function("cfitsio", |b| {
+        b.iter(|| {
+            context
+                .read_by_baseline_into_buffer(0, coarse_chan_index, black_box(&mut buffer))
+                .unwrap()
+        })
+    });
+
+    context.read_config = CorrelatorReadConfig::new().with_direct_read(true);
+    group.bench_function("direct", |b| {
+        b.iter(|| {
+            context
+                .read_by_baseline_into_buffer(0, coarse_chan_index, black_box(&mut buffer))
+                .unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, read_hdu);
+criterion_main!(benches);
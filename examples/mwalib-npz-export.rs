@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/// Given gpubox files, export a selection of the visibilities and their metadata to a NumPy .npz file.
+use anyhow::*;
+use clap::Parser;
+use mwalib::*;
+
+#[derive(Parser, Debug)]
+#[clap(name = "mwalib-npz-export", author)]
+struct Opt {
+    /// Selection expression, e.g. "time=0~4; chan=131~132; pol=XX,YY". Everything is exported if not given.
+    #[clap(short, long, default_value = "")]
+    select: String,
+
+    /// Path to the metafits file.
+    #[clap(short, long, parse(from_os_str))]
+    metafits: std::path::PathBuf,
+
+    /// Paths to the gpubox files.
+    #[clap(name = "GPUBOX FILE", parse(from_os_str))]
+    files: Vec<std::path::PathBuf>,
+
+    /// Output .npz filename
+    #[clap(short, long, parse(from_os_str))]
+    output_filename: std::path::PathBuf,
+}
+
+fn export_npz<T: AsRef<std::path::Path>>(
+    metafits: T,
+    files: &[T],
+    expression: &str,
+    output_filename: T,
+) -> Result<(), anyhow::Error> {
+    let context = CorrelatorContext::new(metafits, files)?;
+    let selection = context.select(expression)?;
+
+    println!(
+        "Exporting {} timesteps, {} coarse channels x {} fine channels, {} baselines and {} pols to {}...",
+        selection.timestep_indices.len(),
+        selection.coarse_chan_indices.len(),
+        selection.fine_chan_indices.len(),
+        selection.baseline_indices.len(),
+        selection.pol_indices.len(),
+        output_filename.as_ref().display()
+    );
+
+    context.write_npz(output_filename, &selection)?;
+
+    println!("Done. Load it with numpy.load()");
+
+    Ok(())
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    env_logger::try_init().unwrap_or(());
+    let opts = Opt::parse();
+
+    export_npz(
+        opts.metafits,
+        &opts.files,
+        &opts.select,
+        opts.output_filename,
+    )?;
+    Ok(())
+}
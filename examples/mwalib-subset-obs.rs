@@ -0,0 +1,71 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/// Given gpubox or voltage files, write some of their timesteps, coarse channels and tiles to a new metafits and
+/// data files, which can be opened like any other observation.
+use anyhow::*;
+use clap::Parser;
+use mwalib::*;
+
+#[derive(Parser, Debug)]
+#[clap(name = "mwalib-subset-obs", author)]
+struct Opt {
+    /// Timestep indices to keep, e.g. "0,1,2". All provided timesteps are kept if not given.
+    #[clap(short, long, value_delimiter = ',')]
+    timesteps: Option<Vec<usize>>,
+
+    /// Coarse channel indices to keep, e.g. "10,11". All provided coarse channels are kept if not given.
+    #[clap(short, long, value_delimiter = ',')]
+    coarse_chans: Option<Vec<usize>>,
+
+    /// Antenna (tile) indices to keep, e.g. "0,1,2,3". All antennas are kept if not given.
+    #[clap(short, long, value_delimiter = ',')]
+    antennas: Option<Vec<usize>>,
+
+    /// Path to the metafits file.
+    #[clap(short, long, parse(from_os_str))]
+    metafits: std::path::PathBuf,
+
+    /// Paths to the gpubox files (.fits) or voltage files (.dat or .sub).
+    #[clap(name = "DATA FILE", parse(from_os_str))]
+    files: Vec<std::path::PathBuf>,
+
+    /// Directory to write the subset to
+    #[clap(short, long, parse(from_os_str))]
+    output_dir: std::path::PathBuf,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    env_logger::try_init().unwrap_or(());
+    let opts = Opt::parse();
+
+    let mut config = SubsetConfig::new();
+    if let Some(timesteps) = &opts.timesteps {
+        config = config.with_timestep_indices(timesteps);
+    }
+    if let Some(coarse_chans) = &opts.coarse_chans {
+        config = config.with_coarse_chan_indices(coarse_chans);
+    }
+    if let Some(antennas) = &opts.antennas {
+        config = config.with_antenna_indices(antennas);
+    }
+
+    let is_gpubox = opts
+        .files
+        .iter()
+        .all(|f| f.extension().map_or(false, |e| e == "fits"));
+    let files = match is_gpubox {
+        true => CorrelatorContext::new(&opts.metafits, &opts.files)?
+            .write_subset(&opts.output_dir, &config)?,
+        false => VoltageContext::new(&opts.metafits, &opts.files)?
+            .write_subset(&opts.output_dir, &config)?,
+    };
+
+    println!("Wrote {}", files.metafits_filename.display());
+    for filename in files.data_filenames.iter() {
+        println!("Wrote {}", filename.display());
+    }
+
+    Ok(())
+}
@@ -337,6 +337,152 @@ impl CoarseChannel {
         coarse_chan_indices
     }
 
+    /// Groups coarse channels into bands of contiguous receiver channels (e.g. for picket fence observations).
+    ///
+    /// # Arguments
+    ///
+    /// * `coarse_chans` - Reference to a slice of coarse channels in ascending receiver channel order (as they are
+    ///   in every context).
+    ///
+    /// # Returns
+    ///
+    /// * A vector of `CoarseChannelBand`s in ascending sky frequency order. Coarse channel indices in each band are
+    ///   indices into `coarse_chans`.
+    ///
+    pub fn get_contiguous_bands(coarse_chans: &[Self]) -> Vec<CoarseChannelBand> {
+        let mut bands: Vec<CoarseChannelBand> = Vec::new();
+
+        for (index, coarse_chan) in coarse_chans.iter().enumerate() {
+            match bands.last_mut() {
+                Some(band) if band.last_rec_chan_number + 1 == coarse_chan.rec_chan_number => {
+                    band.coarse_chan_indices.push(index);
+                    band.num_coarse_chans += 1;
+                    band.last_rec_chan_number = coarse_chan.rec_chan_number;
+                    band.end_hz = coarse_chan.chan_end_hz;
+                }
+                _ => bands.push(CoarseChannelBand {
+                    coarse_chan_indices: vec![index],
+                    num_coarse_chans: 1,
+                    first_rec_chan_number: coarse_chan.rec_chan_number,
+                    last_rec_chan_number: coarse_chan.rec_chan_number,
+                    start_hz: coarse_chan.chan_start_hz,
+                    end_hz: coarse_chan.chan_end_hz,
+                    centre_hz: 0,
+                    bandwidth_hz: 0,
+                }),
+            }
+        }
+
+        for band in bands.iter_mut() {
+            band.bandwidth_hz = band.end_hz - band.start_hz;
+            band.centre_hz = band.start_hz + band.bandwidth_hz / 2;
+        }
+
+        bands
+    }
+
+    /// Finds the coarse channels which overlap a range of sky frequencies.
+    ///
+    /// # Arguments
+    ///
+    /// * `coarse_chans` - Reference to a slice of coarse channels.
+    ///
+    /// * `start_hz` - start of the frequency range in Hz (inclusive).
+    ///
+    /// * `end_hz` - end of the frequency range in Hz (inclusive).
+    ///
+    /// # Returns
+    ///
+    /// * A vector of the indices (into `coarse_chans`) of every coarse channel whose range [`chan_start_hz`, `chan_end_hz`)
+    ///   intersects [`start_hz`, `end_hz`]. Empty if `end_hz` < `start_hz`.
+    ///
+    pub fn get_coarse_chan_indices_in_freq_range(
+        coarse_chans: &[Self],
+        start_hz: f64,
+        end_hz: f64,
+    ) -> Vec<usize> {
+        coarse_chans
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.chan_start_hz as f64 <= end_hz && (c.chan_end_hz as f64) > start_hz)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Finds the fine channels whose centre frequencies are within a range of sky frequencies.
+    ///
+    /// # Arguments
+    ///
+    /// * `fine_chan_freqs_hz` - Centre frequencies of all the fine channels of a set of coarse channels, as returned by
+    ///   `get_fine_chan_centres_array_hz`.
+    ///
+    /// * `num_fine_chans_per_coarse` - Number of fine channels per coarse channel.
+    ///
+    /// * `start_hz` - start of the frequency range in Hz (inclusive).
+    ///
+    /// * `end_hz` - end of the frequency range in Hz (inclusive).
+    ///
+    /// # Returns
+    ///
+    /// * A vector of (coarse channel index, fine channel index within the coarse channel) pairs, in the order of
+    ///   `fine_chan_freqs_hz`. Empty if `end_hz` < `start_hz`.
+    ///
+    pub(crate) fn get_fine_chan_indices_in_freq_range(
+        fine_chan_freqs_hz: &[f64],
+        num_fine_chans_per_coarse: usize,
+        start_hz: f64,
+        end_hz: f64,
+    ) -> Vec<(usize, usize)> {
+        if num_fine_chans_per_coarse == 0 {
+            return vec![];
+        }
+
+        fine_chan_freqs_hz
+            .iter()
+            .enumerate()
+            .filter(|(_, &f)| f >= start_hz && f <= end_hz)
+            .map(|(i, _)| (i / num_fine_chans_per_coarse, i % num_fine_chans_per_coarse))
+            .collect()
+    }
+
+    /// Finds the fine channel whose centre frequency is nearest to a sky frequency.
+    ///
+    /// # Arguments
+    ///
+    /// * `fine_chan_freqs_hz` - Centre frequencies of all the fine channels of a set of coarse channels, as returned by
+    ///   `get_fine_chan_centres_array_hz`.
+    ///
+    /// * `num_fine_chans_per_coarse` - Number of fine channels per coarse channel.
+    ///
+    /// * `freq_hz` - the sky frequency in Hz.
+    ///
+    /// # Returns
+    ///
+    /// * The (coarse channel index, fine channel index within the coarse channel) of the nearest fine channel, or None
+    ///   if there are no fine channels. Ties go to the lower frequency.
+    ///
+    pub(crate) fn get_nearest_fine_chan_index(
+        fine_chan_freqs_hz: &[f64],
+        num_fine_chans_per_coarse: usize,
+        freq_hz: f64,
+    ) -> Option<(usize, usize)> {
+        if num_fine_chans_per_coarse == 0 {
+            return None;
+        }
+
+        fine_chan_freqs_hz
+            .iter()
+            .enumerate()
+            .fold(None, |nearest: Option<(usize, f64)>, (i, &f)| {
+                let distance = (f - freq_hz).abs();
+                match nearest {
+                    Some((_, nearest_distance)) if nearest_distance <= distance => nearest,
+                    _ => Some((i, distance)),
+                }
+            })
+            .map(|(i, _)| (i / num_fine_chans_per_coarse, i % num_fine_chans_per_coarse))
+    }
+
     /// Calculate the centre frequency of each fine channel of the provided coarse channels.
     ///
     ///
@@ -438,3 +584,58 @@ impl fmt::Debug for CoarseChannel {
         )
     }
 }
+
+/// A band of coarse channels with contiguous receiver channel numbers
+#[derive(Clone, PartialEq, Eq)]
+pub struct CoarseChannelBand {
+    /// Indices of the coarse channels in this band, within the coarse channel vector the band was made from
+    pub coarse_chan_indices: Vec<usize>,
+
+    /// Number of coarse channels in this band
+    pub num_coarse_chans: usize,
+
+    /// Receiver channel number of the lowest coarse channel in this band
+    pub first_rec_chan_number: usize,
+
+    /// Receiver channel number of the highest coarse channel in this band
+    pub last_rec_chan_number: usize,
+
+    /// Starting frequency of this band in Hz
+    pub start_hz: u32,
+
+    /// Ending frequency of this band in Hz
+    pub end_hz: u32,
+
+    /// Centre frequency of this band in Hz
+    pub centre_hz: u32,
+
+    /// Total bandwidth of this band in Hz
+    pub bandwidth_hz: u32,
+}
+
+/// Implements fmt::Debug for
+///CoarseChannelBand struct
+///
+/// # Arguments
+///
+/// * `f` - A fmt::Formatter
+///
+///
+/// # Returns
+///
+/// * `fmt::Result` - Result of this method
+///
+///
+impl fmt::Debug for CoarseChannelBand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rec={}-{} ({} chans) {:.3}-{:.3} MHz",
+            self.first_rec_chan_number,
+            self.last_rec_chan_number,
+            self.num_coarse_chans,
+            self.start_hz as f32 / 1_000_000.,
+            self.end_hz as f32 / 1_000_000.
+        )
+    }
+}
@@ -918,3 +918,123 @@ fn test_get_fine_chan_centres_array_hz_mwaxv2_2khz() {
         calc_fine_chan_centre_array_hz[0]
     );
 }
+
+#[test]
+fn test_get_contiguous_bands_picket_fence() {
+    let coarse_chans: Vec<CoarseChannel> = [62, 63, 64, 100, 101, 140]
+        .iter()
+        .enumerate()
+        .map(|(i, rec)| CoarseChannel::new(i, *rec, *rec, 1_280_000))
+        .collect();
+
+    let bands = CoarseChannel::get_contiguous_bands(&coarse_chans);
+
+    assert_eq!(bands.len(), 3);
+
+    assert_eq!(bands[0].coarse_chan_indices, vec![0, 1, 2]);
+    assert_eq!(bands[0].num_coarse_chans, 3);
+    assert_eq!(bands[0].first_rec_chan_number, 62);
+    assert_eq!(bands[0].last_rec_chan_number, 64);
+    assert_eq!(bands[0].start_hz, 78_720_000);
+    assert_eq!(bands[0].end_hz, 82_560_000);
+    assert_eq!(bands[0].centre_hz, 80_640_000);
+    assert_eq!(bands[0].bandwidth_hz, 3_840_000);
+
+    assert_eq!(bands[1].coarse_chan_indices, vec![3, 4]);
+    assert_eq!(bands[1].centre_hz, 128_640_000);
+
+    assert_eq!(bands[2].coarse_chan_indices, vec![5]);
+    assert_eq!(bands[2].centre_hz, coarse_chans[5].chan_centre_hz);
+    assert_eq!(bands[2].bandwidth_hz, 1_280_000);
+}
+
+#[test]
+fn test_get_contiguous_bands_contiguous_and_empty() {
+    let coarse_chans: Vec<CoarseChannel> = (109..133)
+        .enumerate()
+        .map(|(i, rec)| CoarseChannel::new(i, rec, i + 1, 1_280_000))
+        .collect();
+
+    let bands = CoarseChannel::get_contiguous_bands(&coarse_chans);
+    assert_eq!(bands.len(), 1);
+    assert_eq!(
+        bands[0].coarse_chan_indices,
+        (0..24).collect::<Vec<usize>>()
+    );
+    assert_eq!(bands[0].centre_hz, 154_240_000);
+
+    assert!(CoarseChannel::get_contiguous_bands(&[]).is_empty());
+}
+
+#[test]
+fn test_get_coarse_chan_indices_in_freq_range() {
+    // Centres 128.0, 129.28, 130.56, 134.4 MHz
+    let coarse_chans: Vec<CoarseChannel> = [100, 101, 102, 105]
+        .iter()
+        .enumerate()
+        .map(|(i, rec)| CoarseChannel::new(i, *rec, *rec, 1_280_000))
+        .collect();
+
+    // A single frequency
+    assert_eq!(
+        CoarseChannel::get_coarse_chan_indices_in_freq_range(&coarse_chans, 129e6, 129e6),
+        vec![1]
+    );
+    // Exactly on the boundary between two coarse channels belongs to the upper one
+    assert_eq!(
+        CoarseChannel::get_coarse_chan_indices_in_freq_range(&coarse_chans, 128.64e6, 128.64e6),
+        vec![1]
+    );
+    // Spanning a gap
+    assert_eq!(
+        CoarseChannel::get_coarse_chan_indices_in_freq_range(&coarse_chans, 130e6, 134e6),
+        vec![2, 3]
+    );
+    // Entirely in a gap
+    assert!(
+        CoarseChannel::get_coarse_chan_indices_in_freq_range(&coarse_chans, 132e6, 133e6)
+            .is_empty()
+    );
+    // Reversed range
+    assert!(
+        CoarseChannel::get_coarse_chan_indices_in_freq_range(&coarse_chans, 134e6, 128e6)
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_get_fine_chan_indices_in_freq_range_and_nearest() {
+    // 2 coarse channels of 4 fine channels each
+    let fine_chan_freqs_hz: Vec<f64> = vec![10., 20., 30., 40., 70., 80., 90., 100.];
+
+    assert_eq!(
+        CoarseChannel::get_fine_chan_indices_in_freq_range(&fine_chan_freqs_hz, 4, 30., 80.),
+        vec![(0, 2), (0, 3), (1, 0), (1, 1)]
+    );
+    assert!(
+        CoarseChannel::get_fine_chan_indices_in_freq_range(&fine_chan_freqs_hz, 4, 50., 60.)
+            .is_empty()
+    );
+
+    assert_eq!(
+        CoarseChannel::get_nearest_fine_chan_index(&fine_chan_freqs_hz, 4, 61.),
+        Some((1, 0))
+    );
+    assert_eq!(
+        CoarseChannel::get_nearest_fine_chan_index(&fine_chan_freqs_hz, 4, 0.),
+        Some((0, 0))
+    );
+    assert_eq!(
+        CoarseChannel::get_nearest_fine_chan_index(&fine_chan_freqs_hz, 4, 1e9),
+        Some((1, 3))
+    );
+    // Ties go to the lower frequency
+    assert_eq!(
+        CoarseChannel::get_nearest_fine_chan_index(&fine_chan_freqs_hz, 4, 55.),
+        Some((0, 3))
+    );
+    assert_eq!(
+        CoarseChannel::get_nearest_fine_chan_index(&[], 4, 55.),
+        None
+    );
+}
@@ -7,10 +7,13 @@
 //!
 //! Major contributor: Brian Crosse (Curtin Institute for Radio Astronomy)
 
+use crate::baseline::Baseline;
 use crate::misc::*;
 use crate::rfinput::*;
+use crate::stokes::{convert_linear_to_pol_products, PolProducts};
 use log::trace;
 use std::fmt;
+use std::sync::{Arc, Mutex};
 
 #[cfg(test)]
 mod test;
@@ -64,7 +67,7 @@ impl LegacyConversionBaseline {
     /// # Returns
     ///
     /// * Returns a Result containing a populated
-    ///LegacyConversionBaseline if Ok.
+    ///   LegacyConversionBaseline if Ok.
     ///
     fn new(baseline: usize, ant1: usize, ant2: usize, xx: i32, xy: i32, yx: i32, yy: i32) -> Self {
         Self {
@@ -151,8 +154,8 @@ impl fmt::Debug for LegacyConversionBaseline {
 /// # Returns
 ///
 /// * A Vector with one element per rf_input vs rf_input (256x256). Positive numbers represent the index of the
-/// input HDU to get data from, negative numbers mean to take the complex conjugate of the data at the index of
-/// the input HDU.
+///   input HDU to get data from, negative numbers mean to take the complex conjugate of the data at the index of
+///   the input HDU.
 ///
 fn generate_full_matrix(mwax_order: Vec<usize>) -> Vec<i32> {
     let mut row1st: usize;
@@ -221,9 +224,8 @@ fn generate_full_matrix(mwax_order: Vec<usize>) -> Vec<i32> {
 ///
 /// # Returns
 ///
-/// * A Vector of `
-///LegacyConversionBaseline`s which tell us, for a specific output baseline, where in the input HDU
-/// to get data from (and whether it needs to be conjugated).
+/// * A Vector of `LegacyConversionBaseline`s which tell us, for a specific output baseline, where in the input HDU
+///   to get data from (and whether it needs to be conjugated).
 ///
 pub(crate) fn generate_conversion_array(rf_inputs: &[Rfinput]) -> Vec<LegacyConversionBaseline> {
     // Ensure we have a 256 element array of rf_inputs
@@ -244,6 +246,27 @@ pub(crate) fn generate_conversion_array(rf_inputs: &[Rfinput]) -> Vec<LegacyConv
 
     // Generate the full matrix
     let full_matrix: Vec<i32> = generate_full_matrix(mwax_order);
+
+    generate_conversion_array_from_full_matrix(&full_matrix)
+}
+
+/// Generates the conversion array for use when we convert legacy HDUs from a full matrix (see `generate_full_matrix`).
+///
+/// # Arguments
+///
+/// * `full_matrix` - the 256x256 matrix mapping rf_input pairs to complex visibility indices of the legacy HDU.
+///
+///
+/// # Returns
+///
+/// * A Vector of `LegacyConversionBaseline`s which tell us, for a specific output baseline, where in the input HDU
+///   to get data from (and whether it needs to be conjugated).
+///
+fn generate_conversion_array_from_full_matrix(
+    full_matrix: &[i32],
+) -> Vec<LegacyConversionBaseline> {
+    assert_eq!(full_matrix.len(), 65536);
+
     // Now step through the 256 x 256 square, but in the order of the wanted triangular output!
     // Each step, we need to pick up the source position index that we stored in the 256 x 256 square.
     let (mut xx, mut xy, mut yx, mut yy): (i32, i32, i32, i32); // Indexes to the polarisations for this pair of tiles
@@ -554,3 +577,528 @@ pub(crate) fn convert_mwax_hdu_to_frequency_order(
         }
     }
 }
+
+/// The order of the baselines in visibilities returned by the `CorrelatorContext` read functions.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaselineOrder {
+    /// Row-major upper triangle, ordered by antenna 1 then antenna 2: 0-0, 0-1 .. 0-N, 1-1, 1-2 .. (the MWAX order)
+    Ant1Major = 0,
+    /// Column-major upper triangle, ordered by antenna 2 then antenna 1: 0-0, 0-1, 1-1, 0-2, 1-2, 2-2 ..
+    Ant2Major = 1,
+}
+
+impl Default for BaselineOrder {
+    fn default() -> Self {
+        BaselineOrder::Ant1Major
+    }
+}
+
+/// The conjugation convention of visibilities returned by the `CorrelatorContext` read functions.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConjugationConvention {
+    /// The convention of the MWAX correlator (legacy visibilities are conjugated to match)
+    Mwax = 0,
+    /// The complex conjugate of `Mwax`
+    Opposite = 1,
+}
+
+impl Default for ConjugationConvention {
+    fn default() -> Self {
+        ConjugationConvention::Mwax
+    }
+}
+
+///
+/// The layout of visibilities returned by the `CorrelatorContext` read functions, for one timestep and coarse
+/// channel.
+///
+/// The default is the MWAX layout: every baseline in `BaselineOrder::Ant1Major` order with the MWAX conjugation
+/// convention, and [baseline][frequency][pol][r][i] (or [frequency][baseline][pol][r][i]) order.
+///
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VisibilityLayout {
+    /// The order of the baselines.
+    pub baseline_order: BaselineOrder,
+    /// The conjugation convention.
+    pub conjugation: ConjugationConvention,
+    /// If true, the autocorrelations are left out.
+    pub exclude_autos: bool,
+    /// If true, pol is the slowest varying axis, i.e. [pol][baseline][frequency][r][i] (or
+    /// [pol][frequency][baseline][r][i]), rather than the fastest.
+    pub pol_major: bool,
+}
+
+impl VisibilityLayout {
+    /// Creates a new `VisibilityLayout` with the default (MWAX) layout.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A new `VisibilityLayout`
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the order of the baselines.
+    ///
+    /// # Arguments
+    ///
+    /// * `baseline_order` - the order of the baselines.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `VisibilityLayout`
+    ///
+    pub fn with_baseline_order(mut self, baseline_order: BaselineOrder) -> Self {
+        self.baseline_order = baseline_order;
+        self
+    }
+
+    /// Sets the conjugation convention.
+    ///
+    /// # Arguments
+    ///
+    /// * `conjugation` - the conjugation convention.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `VisibilityLayout`
+    ///
+    pub fn with_conjugation(mut self, conjugation: ConjugationConvention) -> Self {
+        self.conjugation = conjugation;
+        self
+    }
+
+    /// Includes or excludes the autocorrelations.
+    ///
+    /// # Arguments
+    ///
+    /// * `exclude_autos` - true to leave the autocorrelations out.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `VisibilityLayout`
+    ///
+    pub fn with_exclude_autos(mut self, exclude_autos: bool) -> Self {
+        self.exclude_autos = exclude_autos;
+        self
+    }
+
+    /// Makes pol the slowest (true) or fastest (false) varying axis.
+    ///
+    /// # Arguments
+    ///
+    /// * `pol_major` - true for pol-major layout.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `VisibilityLayout`
+    ///
+    pub fn with_pol_major(mut self, pol_major: bool) -> Self {
+        self.pol_major = pol_major;
+        self
+    }
+
+    /// Works out which baselines are output, and in what order, for this layout.
+    ///
+    /// # Arguments
+    ///
+    /// * `baselines` - the baselines of the observation, in MWAX order.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A vector of indices into `baselines`, one per output baseline, in output order.
+    ///
+    pub(crate) fn get_output_baseline_indices(&self, baselines: &[Baseline]) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..baselines.len())
+            .filter(|&b| {
+                !(self.exclude_autos && baselines[b].ant1_index == baselines[b].ant2_index)
+            })
+            .collect();
+
+        if self.baseline_order == BaselineOrder::Ant2Major {
+            indices.sort_by_key(|&b| (baselines[b].ant2_index, baselines[b].ant1_index));
+        }
+
+        indices
+    }
+}
+
+/// Structure for storing where in the input visibilities to get each pol of an output baseline from, for any
+/// `VisibilityLayout`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OutputConversionBaseline {
+    /// Index of the real part of xx, xy, yx and yy within one fine channel of the input (see
+    /// `convert_hdu_to_layout`)
+    pub indices: [usize; 4],
+    /// If true, we need to conjugate this pol
+    pub conjugate: [bool; 4],
+}
+
+/// Generates the conversion table for a `VisibilityLayout`, from either MWAX HDUs or, via the legacy conversion
+/// table, legacy HDUs. The conjugation needed to get legacy visibilities into the MWAX convention is folded in.
+///
+/// # Arguments
+///
+/// * `layout` - the output layout.
+///
+/// * `baselines` - the baselines of the observation, in MWAX order.
+///
+/// * `legacy_conversion_table` - Some legacy conversion table (from `generate_conversion_array`) if the input is
+///   legacy HDUs, or None if the input is in MWAX [baseline][frequency][pol][r][i] order.
+///
+/// * `num_fine_chans` - Number of fine channels per coarse channel.
+///
+///
+/// # Returns
+///
+/// * A Vector of `OutputConversionBaseline`s, one per output baseline, in output order.
+///
+pub(crate) fn generate_output_conversion_table(
+    layout: &VisibilityLayout,
+    baselines: &[Baseline],
+    legacy_conversion_table: Option<&[LegacyConversionBaseline]>,
+    num_fine_chans: usize,
+) -> Vec<OutputConversionBaseline> {
+    let conjugate_all = layout.conjugation == ConjugationConvention::Opposite;
+
+    layout
+        .get_output_baseline_indices(baselines)
+        .into_iter()
+        .map(|b| match legacy_conversion_table {
+            // Legacy visibilities are always conjugated at the end of the conversion into MWAX order
+            Some(table) => {
+                let l = &table[b];
+                OutputConversionBaseline {
+                    indices: [l.xx_index, l.xy_index, l.yx_index, l.yy_index],
+                    conjugate: [
+                        l.xx_conjugate == conjugate_all,
+                        l.xy_conjugate == conjugate_all,
+                        l.yx_conjugate == conjugate_all,
+                        l.yy_conjugate == conjugate_all,
+                    ],
+                }
+            }
+            None => {
+                let start = b * num_fine_chans * 8;
+                OutputConversionBaseline {
+                    indices: [start, start + 2, start + 4, start + 6],
+                    conjugate: [conjugate_all; 4],
+                }
+            }
+        })
+        .collect()
+}
+
+/// An output conversion table, with the layout it is for and whether it is for legacy HDUs.
+type CachedOutputConversionTable = (VisibilityLayout, bool, Arc<Vec<OutputConversionBaseline>>);
+
+/// Holds the output conversion table of the `VisibilityLayout` last read with, so that the table is only generated
+/// again when the layout changes (`read_config` is a public field, so it can change between any two reads).
+#[derive(Debug, Default)]
+pub(crate) struct OutputConversionTableCache {
+    table: Mutex<Option<CachedOutputConversionTable>>,
+}
+
+impl OutputConversionTableCache {
+    /// Get the output conversion table for a layout, generating it (see `generate_output_conversion_table`) if it
+    /// is not the one cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `layout` - the output layout.
+    ///
+    /// * `baselines` - the baselines of the observation, in MWAX order.
+    ///
+    /// * `legacy_conversion_table` - Some legacy conversion table if the input is legacy HDUs, or None if the input
+    ///   is in MWAX order.
+    ///
+    /// * `num_fine_chans` - Number of fine channels per coarse channel.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The conversion table, shared with any other reads using the same layout.
+    ///
+    pub(crate) fn get(
+        &self,
+        layout: &VisibilityLayout,
+        baselines: &[Baseline],
+        legacy_conversion_table: Option<&[LegacyConversionBaseline]>,
+        num_fine_chans: usize,
+    ) -> Arc<Vec<OutputConversionBaseline>> {
+        let is_legacy = legacy_conversion_table.is_some();
+        let mut cached = self
+            .table
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        match cached.as_ref() {
+            Some((cached_layout, cached_is_legacy, table))
+                if cached_layout == layout && *cached_is_legacy == is_legacy =>
+            {
+                table.clone()
+            }
+            _ => {
+                let table = Arc::new(generate_output_conversion_table(
+                    layout,
+                    baselines,
+                    legacy_conversion_table,
+                    num_fine_chans,
+                ));
+                *cached = Some((*layout, is_legacy, table.clone()));
+                table
+            }
+        }
+    }
+}
+
+/// Using a precalculated output conversion table, reorder visibilities into any `VisibilityLayout` in one pass.
+///
+/// The input is read as `input_buffer[fine_chan * input_fine_chan_stride + index]`, where `index` comes from the
+/// conversion table. So for legacy HDUs ([fine_chan][baseline][pol][r][i]) the stride is the number of floats per fine
+/// channel, and for MWAX HDUs ([baseline][fine_chan][pol][r][i]) it is the number of floats per visibility.
+///
+/// Conversion of the linear pols into other polarisation products is done in the same pass, one visibility at a
+/// time.
+///
+/// # Arguments
+///
+/// * `conversion_table` - the table from `generate_output_conversion_table`.
+///
+/// * `input_buffer` - Float vector read from an HDU.
+///
+/// * `output_buffer` - Float vector to write converted data into.
+///
+/// * `num_fine_chans` - Number of fine channels per coarse channel.
+///
+/// * `input_fine_chan_stride` - Number of floats between consecutive fine channels of the input.
+///
+/// * `by_frequency` - true to output frequency before baseline, false for baseline before frequency.
+///
+/// * `pol_major` - true to output pol as the slowest varying axis, false for the fastest. Only supported for
+///   linear pols.
+///
+/// * `pol_products` - the polarisation products to output.
+///
+///
+/// # Returns
+///
+/// * Nothing
+///
+///
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn convert_hdu_to_layout(
+    conversion_table: &[OutputConversionBaseline],
+    input_buffer: &[f32],
+    output_buffer: &mut [f32],
+    num_fine_chans: usize,
+    input_fine_chan_stride: usize,
+    by_frequency: bool,
+    pol_major: bool,
+    pol_products: PolProducts,
+) {
+    let num_baselines = conversion_table.len();
+    let num_floats_per_vis = pol_products.num_pols() * 2;
+
+    assert!(!pol_major || pol_products == PolProducts::Linear);
+    assert!(output_buffer.len() >= num_baselines * num_fine_chans * num_floats_per_vis);
+
+    // The linear pols of one visibility, if they need converting
+    let mut linear_vis = [0_f32; 8];
+
+    for fine_chan_index in 0..num_fine_chans {
+        let source_index = fine_chan_index * input_fine_chan_stride;
+
+        for (baseline_index, baseline) in conversion_table.iter().enumerate() {
+            // Index of the visibility within the [baseline][fine_chan] or [fine_chan][baseline] plane
+            let vis_index = if by_frequency {
+                fine_chan_index * num_baselines + baseline_index
+            } else {
+                baseline_index * num_fine_chans + fine_chan_index
+            };
+
+            for pol_index in 0..4 {
+                let source = source_index + baseline.indices[pol_index];
+                let real = input_buffer[source];
+                let imag = if baseline.conjugate[pol_index] {
+                    -input_buffer[source + 1]
+                } else {
+                    input_buffer[source + 1]
+                };
+
+                if pol_products != PolProducts::Linear {
+                    linear_vis[pol_index * 2] = real;
+                    linear_vis[pol_index * 2 + 1] = imag;
+                    continue;
+                }
+
+                let destination = if pol_major {
+                    (pol_index * num_baselines * num_fine_chans + vis_index) * 2
+                } else {
+                    (vis_index * 4 + pol_index) * 2
+                };
+                output_buffer[destination] = real;
+                output_buffer[destination + 1] = imag;
+            }
+
+            if pol_products != PolProducts::Linear {
+                let destination = vis_index * num_floats_per_vis;
+                convert_linear_to_pol_products(
+                    &linear_vis,
+                    &mut output_buffer[destination..destination + num_floats_per_vis],
+                    pol_products,
+                );
+            }
+        }
+    }
+}
+
+/// Returns the index of the real part of each of the 128 redundant visibilities within one fine channel of a
+/// legacy HDU. These are the bottom left of each 2x2 correlation square on the diagonal of the legacy correlator's
+/// output (see `generate_full_matrix`), which is the conjugate of the top right (the next visibility), so they are
+/// never referenced by the conversion table.
+///
+/// # Arguments
+///
+/// * None
+///
+///
+/// # Returns
+///
+/// * An iterator over the float index of each redundant visibility.
+///
+fn legacy_redundant_indices() -> impl Iterator<Item = usize> {
+    // Column k of 2x2 squares has k + 1 squares, the last of which is on the diagonal. Each square is 4 visibilities:
+    // top left, bottom left, top right, bottom right.
+    (0..128).map(|k| ((k * (k + 1) / 2 + k) * 4 + 1) * 2)
+}
+
+/// Using the precalculated conversion table, reorder visibilities in MWAX [baseline][freq][pol][r][i] order back into
+/// the legacy correlator's HDU layout, i.e. the inverse of `convert_legacy_hdu_to_mwax_baseline_order`. This includes
+/// the fine PFB ordering and conjugations of the legacy correlator, and fills in its redundant visibilities.
+///
+/// Where the legacy correlator produced only one of the XY and YX visibilities of an autocorrelation (they share a
+/// 2x2 square), only one of the two in `input_buffer` ends up in the output.
+///
+/// # Arguments
+///
+/// * `conversion_table` - A vector containing all of the `
+///LegacyConversionBaseline`s we have pre-calculated.
+///
+/// * `input_buffer` - Float vector in MWAX [baseline][freq][pol][r][i] order.
+///
+/// * `output_buffer` - Float vector to write legacy [freq][legacy visibility][r][i] data into.
+///
+/// * `num_fine_chans` - Number of file channels in this observation.
+///
+///
+/// # Returns
+///
+/// * Nothing
+///
+///
+pub(crate) fn convert_mwax_hdu_to_legacy_order(
+    conversion_table: &[LegacyConversionBaseline],
+    input_buffer: &[f32],
+    output_buffer: &mut [f32],
+    num_fine_chans: usize,
+) {
+    // Note: hardcoded values are safe here because they are only for the case where we are using the
+    // legacy correlator which ALWAYS has 128 tiles
+    let num_baselines = get_baseline_count(128);
+
+    // Striding for output array
+    let floats_per_baseline_fine_chan = 8; // xx_r,xx_i,xy_r,xy_i,yx_r,yx_i,yy_r,yy_i
+    let floats_per_fine_chan = num_baselines * floats_per_baseline_fine_chan;
+
+    // Striding for input array
+    let floats_per_baseline = floats_per_baseline_fine_chan * num_fine_chans;
+
+    assert!(input_buffer.len() >= num_fine_chans * floats_per_fine_chan);
+    assert!(output_buffer.len() >= num_fine_chans * floats_per_fine_chan);
+
+    for fine_chan_index in 0..num_fine_chans {
+        let destination_index = fine_chan_index * floats_per_fine_chan;
+
+        for (baseline_index, baseline) in conversion_table.iter().enumerate() {
+            let source_index = (baseline_index * floats_per_baseline)
+                + (fine_chan_index * floats_per_baseline_fine_chan);
+
+            for (pol_index, (legacy_index, conjugate)) in [
+                (baseline.xx_index, baseline.xx_conjugate),
+                (baseline.xy_index, baseline.xy_conjugate),
+                (baseline.yx_index, baseline.yx_conjugate),
+                (baseline.yy_index, baseline.yy_conjugate),
+            ]
+            .iter()
+            .enumerate()
+            {
+                // Undo the conjugation of ALL imaginaries and then any conjugation in the table
+                let source = source_index + pol_index * 2;
+                output_buffer[destination_index + legacy_index] = input_buffer[source];
+                output_buffer[destination_index + legacy_index + 1] = if *conjugate {
+                    input_buffer[source + 1]
+                } else {
+                    -input_buffer[source + 1]
+                };
+            }
+        }
+
+        // The redundant visibilities are the conjugate of the one after
+        for redundant_index in legacy_redundant_indices() {
+            let destination = destination_index + redundant_index;
+            output_buffer[destination] = output_buffer[destination + 2];
+            output_buffer[destination + 1] = -output_buffer[destination + 3];
+        }
+    }
+}
+
+/// Convert one timestep and coarse channel of visibilities in MWAX [baseline][freq][pol][r][i] order (e.g. from
+/// `CorrelatorContext::read_by_baseline` with the default read config) into the layout of a legacy correlator HDU,
+/// for tools which still expect legacy data. This is the inverse of the conversion mwalib does when reading legacy
+/// data, so it includes the legacy correlator's fine PFB ordering and conjugations.
+///
+/// # Arguments
+///
+/// * `rf_inputs` - the 256 rf_inputs of the observation (`MetafitsContext::rf_inputs`). The legacy correlator always
+///   has 128 tiles.
+///
+/// * `input` - visibilities of 8256 baselines in [baseline][freq][pol][r][i] order.
+///
+/// * `output` - buffer to fill with visibilities in legacy [freq][legacy visibility][r][i] order. Must be the same
+///   length as `input`.
+///
+/// * `num_fine_chans` - Number of fine channels in `input`.
+///
+///
+/// # Returns
+///
+/// * Nothing
+///
+pub fn convert_mwax_to_legacy_hdu(
+    rf_inputs: &[Rfinput],
+    input: &[f32],
+    output: &mut [f32],
+    num_fine_chans: usize,
+) {
+    assert_eq!(input.len(), output.len());
+    assert_eq!(input.len(), get_baseline_count(128) * num_fine_chans * 8);
+
+    let conversion_table = generate_conversion_array(rf_inputs);
+
+    convert_mwax_hdu_to_legacy_order(&conversion_table, input, output, num_fine_chans);
+}
@@ -648,3 +648,416 @@ fn test_mwax_conversion_to_frequency_order() {
         }
     }
 }
+
+#[test]
+fn test_visibility_layout_output_baseline_indices() {
+    // 3 antennas: 0-0, 0-1, 0-2, 1-1, 1-2, 2-2
+    let baselines = Baseline::populate_baselines(3);
+
+    assert_eq!(
+        VisibilityLayout::new().get_output_baseline_indices(&baselines),
+        vec![0, 1, 2, 3, 4, 5]
+    );
+    assert_eq!(
+        VisibilityLayout::new()
+            .with_exclude_autos(true)
+            .get_output_baseline_indices(&baselines),
+        vec![1, 2, 4]
+    );
+    // 0-0, 0-1, 1-1, 0-2, 1-2, 2-2
+    assert_eq!(
+        VisibilityLayout::new()
+            .with_baseline_order(BaselineOrder::Ant2Major)
+            .get_output_baseline_indices(&baselines),
+        vec![0, 1, 3, 2, 4, 5]
+    );
+    assert_eq!(
+        VisibilityLayout::new()
+            .with_baseline_order(BaselineOrder::Ant2Major)
+            .with_exclude_autos(true)
+            .get_output_baseline_indices(&baselines),
+        vec![1, 2, 4]
+    );
+}
+
+#[test]
+fn test_convert_mwax_hdu_to_layout() {
+    let num_ants = 3;
+    let num_fine_chans = 2;
+    let baselines = Baseline::populate_baselines(num_ants);
+    let num_baselines = baselines.len();
+
+    // MWAX order: [baseline][fine_chan][pol][r][i], each float is its own index
+    let input: Vec<f32> = (0..num_baselines * num_fine_chans * 8)
+        .map(|i| i as f32)
+        .collect();
+
+    // The default layout is a straight copy by baseline, and the MWAX frequency reordering by frequency
+    let table = generate_output_conversion_table(
+        &VisibilityLayout::new(),
+        &baselines,
+        None,
+        num_fine_chans,
+    );
+    let mut output = vec![0.; input.len()];
+    convert_hdu_to_layout(
+        &table,
+        &input,
+        &mut output,
+        num_fine_chans,
+        8,
+        false,
+        false,
+        PolProducts::Linear,
+    );
+    assert_eq!(output, input);
+
+    let mut expected = vec![0.; input.len()];
+    convert_mwax_hdu_to_frequency_order(&input, &mut expected, num_baselines, num_fine_chans, 4);
+    convert_hdu_to_layout(
+        &table,
+        &input,
+        &mut output,
+        num_fine_chans,
+        8,
+        true,
+        false,
+        PolProducts::Linear,
+    );
+    assert_eq!(output, expected);
+
+    // Every option at once: [pol][frequency][baseline][r][i] for 0-1, 0-2, 1-2, conjugated
+    let layout = VisibilityLayout::new()
+        .with_baseline_order(BaselineOrder::Ant2Major)
+        .with_conjugation(ConjugationConvention::Opposite)
+        .with_exclude_autos(true)
+        .with_pol_major(true);
+    let output_baselines = layout.get_output_baseline_indices(&baselines);
+    let num_output_baselines = output_baselines.len();
+    let table = generate_output_conversion_table(&layout, &baselines, None, num_fine_chans);
+    let mut output = vec![0.; num_output_baselines * num_fine_chans * 8];
+    convert_hdu_to_layout(
+        &table,
+        &input,
+        &mut output,
+        num_fine_chans,
+        8,
+        true,
+        true,
+        PolProducts::Linear,
+    );
+
+    for pol in 0..4 {
+        for fine_chan in 0..num_fine_chans {
+            for (output_baseline, &baseline) in output_baselines.iter().enumerate() {
+                let source = ((baseline * num_fine_chans + fine_chan) * 4 + pol) * 2;
+                let destination = ((pol * num_fine_chans + fine_chan) * num_output_baselines
+                    + output_baseline)
+                    * 2;
+
+                assert_eq!(output[destination], input[source]);
+                assert_eq!(output[destination + 1], -input[source + 1]);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_convert_hdu_to_layout_pol_products() {
+    let num_ants = 3;
+    let num_fine_chans = 2;
+    let baselines = Baseline::populate_baselines(num_ants);
+    let input: Vec<f32> = (0..baselines.len() * num_fine_chans * 8)
+        .map(|i| i as f32)
+        .collect();
+
+    let layout = VisibilityLayout::new()
+        .with_baseline_order(BaselineOrder::Ant2Major)
+        .with_conjugation(ConjugationConvention::Opposite);
+    let table = generate_output_conversion_table(&layout, &baselines, None, num_fine_chans);
+    let mut linear = vec![0.; input.len()];
+    convert_hdu_to_layout(
+        &table,
+        &input,
+        &mut linear,
+        num_fine_chans,
+        8,
+        true,
+        false,
+        PolProducts::Linear,
+    );
+
+    // Converting the pols in the same pass is the same as converting them afterwards
+    for pol_products in [PolProducts::Stokes, PolProducts::PseudoStokesI] {
+        let mut expected = vec![0.; input.len() / 4 * pol_products.num_pols()];
+        convert_linear_to_pol_products(&linear, &mut expected, pol_products);
+
+        let mut output = vec![0.; expected.len()];
+        convert_hdu_to_layout(
+            &table,
+            &input,
+            &mut output,
+            num_fine_chans,
+            8,
+            true,
+            false,
+            pol_products,
+        );
+        assert_eq!(output, expected);
+    }
+}
+
+#[test]
+fn test_output_conversion_table_cache() {
+    let num_fine_chans = 2;
+    let baselines = Baseline::populate_baselines(3);
+    let cache = OutputConversionTableCache::default();
+    let layout = VisibilityLayout::new().with_exclude_autos(true);
+
+    let first = cache.get(&layout, &baselines, None, num_fine_chans);
+    assert_eq!(
+        *first,
+        generate_output_conversion_table(&layout, &baselines, None, num_fine_chans)
+    );
+
+    // The same layout reuses the table
+    let second = cache.get(&layout, &baselines, None, num_fine_chans);
+    assert!(Arc::ptr_eq(&first, &second));
+
+    // A different layout does not
+    let other_layout = VisibilityLayout::new();
+    let third = cache.get(&other_layout, &baselines, None, num_fine_chans);
+    assert_eq!(
+        *third,
+        generate_output_conversion_table(&other_layout, &baselines, None, num_fine_chans)
+    );
+    assert!(!Arc::ptr_eq(&first, &third));
+}
+
+#[test]
+fn test_convert_legacy_hdu_to_layout() {
+    let metafits = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let context = MetafitsContext::new(metafits, None).expect("Failed to create MetafitsContext");
+    let legacy_table = generate_conversion_array(&context.rf_inputs);
+    let num_fine_chans = 2;
+    let floats_per_fine_chan = context.num_baselines * 8;
+
+    // Legacy order: [fine_chan][baseline][pol][r][i]
+    let input: Vec<f32> = (0..num_fine_chans * floats_per_fine_chan)
+        .map(|i| i as f32)
+        .collect();
+
+    // The default layout matches the existing legacy conversions
+    let table = generate_output_conversion_table(
+        &VisibilityLayout::new(),
+        &context.baselines,
+        Some(&legacy_table),
+        num_fine_chans,
+    );
+    let mut expected = vec![0.; input.len()];
+    let mut output = vec![0.; input.len()];
+
+    convert_legacy_hdu_to_mwax_baseline_order(&legacy_table, &input, &mut expected, num_fine_chans);
+    convert_hdu_to_layout(
+        &table,
+        &input,
+        &mut output,
+        num_fine_chans,
+        floats_per_fine_chan,
+        false,
+        false,
+        PolProducts::Linear,
+    );
+    assert_eq!(output, expected);
+
+    convert_legacy_hdu_to_mwax_frequency_order(
+        &legacy_table,
+        &input,
+        &mut expected,
+        num_fine_chans,
+    );
+    convert_hdu_to_layout(
+        &table,
+        &input,
+        &mut output,
+        num_fine_chans,
+        floats_per_fine_chan,
+        true,
+        false,
+        PolProducts::Linear,
+    );
+    assert_eq!(output, expected);
+
+    // The opposite conjugation is the complex conjugate of the MWAX convention
+    let table = generate_output_conversion_table(
+        &VisibilityLayout::new().with_conjugation(ConjugationConvention::Opposite),
+        &context.baselines,
+        Some(&legacy_table),
+        num_fine_chans,
+    );
+    convert_hdu_to_layout(
+        &table,
+        &input,
+        &mut output,
+        num_fine_chans,
+        floats_per_fine_chan,
+        true,
+        false,
+        PolProducts::Linear,
+    );
+    for (o, e) in output.chunks_exact(2).zip(expected.chunks_exact(2)) {
+        assert_eq!(o[0], e[0]);
+        assert_eq!(o[1], -e[1]);
+    }
+}
+
+/// Reads the hand crafted full matrix in test_files/1101503312_1_timestep/1101503312_full_matrix.csv
+fn read_csv_full_matrix() -> Vec<i32> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .from_path("test_files/1101503312_1_timestep/1101503312_full_matrix.csv")
+        .unwrap();
+
+    reader
+        .deserialize()
+        .flat_map(|result| {
+            let record: Vec<i32> = result.expect("Failed to deserialize CSV");
+            assert_eq!(record.len(), 256);
+            record
+        })
+        .collect()
+}
+
+#[test]
+fn test_legacy_redundant_indices() {
+    let full_matrix = read_csv_full_matrix();
+    assert_eq!(full_matrix.len(), 256 * 256);
+    let conversion_table = generate_conversion_array_from_full_matrix(&full_matrix);
+
+    // Every legacy visibility is referenced by the conversion table, except the redundant ones
+    let num_legacy_vis = get_baseline_count(128) * 4;
+    let mut referenced = vec![false; num_legacy_vis];
+    for baseline in conversion_table.iter() {
+        for index in [
+            baseline.xx_index,
+            baseline.xy_index,
+            baseline.yx_index,
+            baseline.yy_index,
+        ] {
+            referenced[index / 2] = true;
+        }
+    }
+
+    let redundant: Vec<usize> = legacy_redundant_indices().map(|i| i / 2).collect();
+    assert_eq!(redundant.len(), 128);
+    assert_eq!(redundant[0], 1);
+    assert_eq!(redundant[127], num_legacy_vis - 3);
+
+    for (vis, is_referenced) in referenced.iter().enumerate() {
+        assert_eq!(
+            *is_referenced,
+            !redundant.contains(&vis),
+            "legacy visibility {}",
+            vis
+        );
+    }
+}
+
+#[test]
+fn test_convert_mwax_hdu_to_legacy_order_round_trip() {
+    let full_matrix = read_csv_full_matrix();
+    let conversion_table = generate_conversion_array_from_full_matrix(&full_matrix);
+    let num_fine_chans = 2;
+    let floats_per_fine_chan = get_baseline_count(128) * 8;
+
+    // Legacy order: [fine_chan][legacy vis][r][i], each float is its own index, with the redundant visibilities
+    // being the conjugate of the one after as the legacy correlator produces
+    let mut legacy: Vec<f32> = (0..num_fine_chans * floats_per_fine_chan)
+        .map(|i| i as f32)
+        .collect();
+    for fine_chan_index in 0..num_fine_chans {
+        for redundant_index in legacy_redundant_indices() {
+            let index = fine_chan_index * floats_per_fine_chan + redundant_index;
+            legacy[index] = legacy[index + 2];
+            legacy[index + 1] = -legacy[index + 3];
+        }
+    }
+
+    let mut mwax = vec![0.; legacy.len()];
+    convert_legacy_hdu_to_mwax_baseline_order(
+        &conversion_table,
+        &legacy,
+        &mut mwax,
+        num_fine_chans,
+    );
+
+    // Each matrix cell of the first fine channel goes to its own place in the MWAX order
+    for (baseline_index, baseline) in Baseline::populate_baselines(128).iter().enumerate() {
+        for (pol_index, (pol1, pol2)) in [(0, 0), (0, 1), (1, 0), (1, 1)].iter().enumerate() {
+            let cell = full_matrix
+                [(baseline.ant1_index * 2 + pol1) * 256 + baseline.ant2_index * 2 + pol2];
+            let mwax_index = baseline_index * num_fine_chans * 8 + pol_index * 2;
+            let legacy_index = cell.unsigned_abs() as usize * 2;
+
+            assert_eq!(mwax[mwax_index], legacy[legacy_index]);
+            if cell < 0 {
+                assert_eq!(mwax[mwax_index + 1], legacy[legacy_index + 1]);
+            } else {
+                assert_eq!(mwax[mwax_index + 1], -legacy[legacy_index + 1]);
+            }
+        }
+    }
+
+    let mut round_trip = vec![0.; legacy.len()];
+    convert_mwax_hdu_to_legacy_order(&conversion_table, &mwax, &mut round_trip, num_fine_chans);
+    assert_eq!(round_trip, legacy);
+}
+
+#[test]
+fn test_convert_mwax_to_legacy_hdu() {
+    // Open a context and load in a test metafits and gpubox file
+    let metafits = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let gpuboxfiles =
+        vec!["test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits"];
+    let context =
+        CorrelatorContext::new(metafits, &gpuboxfiles).expect("Failed to create CorrelatorContext");
+
+    // The conversion table from the metafits is the same as from the hand crafted full matrix
+    assert_eq!(
+        format!(
+            "{:?}",
+            generate_conversion_array(&context.metafits_context.rf_inputs)
+        ),
+        format!(
+            "{:?}",
+            generate_conversion_array_from_full_matrix(&read_csv_full_matrix())
+        )
+    );
+
+    // Converting back gives the HDU as it is in the gpubox file, except the redundant visibilities
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    let mwax_hdu: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+    let (_, hdu_index) = context.gpubox_time_map[&context.timesteps[0].unix_time_ms]
+        [&context.coarse_chans[0].gpubox_number];
+    let mut raw_hdu: Vec<f32> = vec![0.; mwax_hdu.len()];
+    context
+        .read_raw_hdu_into_buffer(gpuboxfiles[0], hdu_index, &mut raw_hdu)
+        .expect("Error!");
+
+    let mut legacy_hdu: Vec<f32> = vec![0.; mwax_hdu.len()];
+    convert_mwax_to_legacy_hdu(
+        &context.metafits_context.rf_inputs,
+        &mwax_hdu,
+        &mut legacy_hdu,
+        num_fine_chans,
+    );
+
+    let redundant: Vec<usize> = legacy_redundant_indices().collect();
+    let floats_per_fine_chan = get_baseline_count(128) * 8;
+    for (index, (legacy, raw)) in legacy_hdu.iter().zip(raw_hdu.iter()).enumerate() {
+        let vis_index = index % floats_per_fine_chan / 2 * 2;
+        if !redundant.contains(&vis_index) {
+            assert_eq!(legacy, raw, "float {}", index);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Reads of visibilities averaged in time and/or frequency, weighted by the MWAX visibility weights.
+
+use super::*;
+
+///
+/// Visibilities which have been averaged in time and/or frequency, as returned by
+/// `CorrelatorContext::read_averaged`.
+///
+/// Averaged timesteps are made from consecutive groups of `time_avg_factor` of the requested
+/// timesteps, and averaged fine channels from consecutive groups of `freq_avg_factor` fine channels
+/// within each coarse channel. Timesteps and coarse channels with no data simply do not contribute
+/// to the average; if nothing contributed to an averaged visibility, its data and weight are 0.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct AveragedVisibilities {
+    /// Number of timesteps averaged together
+    pub time_avg_factor: usize,
+    /// Number of fine channels averaged together
+    pub freq_avg_factor: usize,
+    /// Number of averaged timesteps
+    pub num_timesteps: usize,
+    /// Number of coarse channels
+    pub num_coarse_chans: usize,
+    /// Number of baselines
+    pub num_baselines: usize,
+    /// Number of averaged fine channels per coarse channel
+    pub num_fine_chans_per_coarse: usize,
+    /// Number of visibility pols
+    pub num_pols: usize,
+    /// For each averaged timestep, the indices within the CorrelatorContext timestep array which went into it
+    pub timestep_indices: Vec<Vec<usize>>,
+    /// For each averaged timestep, the weighted centroid of the centres of the timesteps which contributed
+    /// to it (UNIX time in milliseconds). If nothing contributed, the unweighted centroid.
+    pub centroid_unix_times_ms: Vec<f64>,
+    /// The centre sky frequencies of each averaged fine channel, in [coarse_chan][frequency] order
+    pub fine_chan_freqs_hz: Vec<f64>,
+    /// Averaged visibilities in [time][coarse_chan][baseline][frequency][pol][r][i] order
+    pub data: Vec<f32>,
+    /// Sum of the weights of the visibilities which went into each averaged visibility, in
+    /// [time][coarse_chan][baseline][frequency][pol] order
+    pub weights: Vec<f32>,
+}
+
+/// Adds one HDU of visibilities to running weighted sums of averaged visibilities.
+///
+/// # Arguments
+///
+/// * `hdu_buffer` - visibilities of one timestep and coarse channel in [baseline][frequency][pol][r][i] order.
+///
+/// * `hdu_weights` - weights of the visibilities in [baseline][pol] order.
+///
+/// * `num_fine_chans` - number of (unaveraged) fine channels in `hdu_buffer`.
+///
+/// * `num_pols` - number of visibility pols.
+///
+/// * `freq_avg_factor` - number of fine channels to average together. Must divide `num_fine_chans`.
+///
+/// * `vis_sums` - weighted sums of visibilities in [baseline][averaged frequency][pol][r][i] order.
+///
+/// * `weight_sums` - sums of weights in [baseline][averaged frequency][pol] order.
+///
+///
+/// # Returns
+///
+/// * The sum of `hdu_weights` over every visibility in the HDU.
+///
+pub(crate) fn accumulate_weighted_visibilities(
+    hdu_buffer: &[f32],
+    hdu_weights: &[f32],
+    num_fine_chans: usize,
+    num_pols: usize,
+    freq_avg_factor: usize,
+    vis_sums: &mut [f64],
+    weight_sums: &mut [f64],
+) -> f64 {
+    let num_avg_fine_chans = num_fine_chans / freq_avg_factor;
+    let mut total_weight: f64 = 0.;
+
+    for (baseline_index, baseline_weights) in hdu_weights.chunks_exact(num_pols).enumerate() {
+        let baseline_vis = &hdu_buffer[baseline_index * num_fine_chans * num_pols * 2..];
+
+        for fine_chan_index in 0..num_fine_chans {
+            let avg_fine_chan_index =
+                baseline_index * num_avg_fine_chans + fine_chan_index / freq_avg_factor;
+
+            for (pol_index, &weight) in baseline_weights.iter().enumerate() {
+                let weight = weight as f64;
+                let in_index = (fine_chan_index * num_pols + pol_index) * 2;
+                let out_index = avg_fine_chan_index * num_pols + pol_index;
+
+                vis_sums[out_index * 2] += baseline_vis[in_index] as f64 * weight;
+                vis_sums[out_index * 2 + 1] += baseline_vis[in_index + 1] as f64 * weight;
+                weight_sums[out_index] += weight;
+                total_weight += weight;
+            }
+        }
+    }
+
+    total_weight
+}
+
+/// Turns weighted sums of visibilities into weighted averages.
+///
+/// # Arguments
+///
+/// * `vis_sums` - weighted sums of visibilities in [...][pol][r][i] order.
+///
+/// * `weight_sums` - sums of weights in [...][pol] order.
+///
+/// * `data` - buffer to fill with the averaged visibilities. Visibilities with a total weight of 0 are set to 0.
+///
+/// * `weights` - buffer to fill with the total weights.
+///
+///
+/// # Returns
+///
+/// * Nothing
+///
+pub(crate) fn finalise_weighted_average(
+    vis_sums: &[f64],
+    weight_sums: &[f64],
+    data: &mut [f32],
+    weights: &mut [f32],
+) {
+    for (index, &weight) in weight_sums.iter().enumerate() {
+        let (r, i) = if weight > 0. {
+            (
+                vis_sums[index * 2] / weight,
+                vis_sums[index * 2 + 1] / weight,
+            )
+        } else {
+            (0., 0.)
+        };
+
+        data[index * 2] = r as f32;
+        data[index * 2 + 1] = i as f32;
+        weights[index] = weight as f32;
+    }
+}
+
+impl CorrelatorContext {
+    /// Read the visibility weights of a single timestep for a single coarse channel.
+    /// The output weights are in order:
+    /// baseline,pol
+    ///
+    /// MWAX v2 gpubox files store weights in the HDU following each visibility HDU. Legacy gpubox files have
+    /// no weights, so every weight is 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array for the desired coarse channel.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing vector of 32 bit floats containing the weights in [baseline][pol] order, if Ok.
+    ///
+    pub fn read_weights_by_baseline(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+    ) -> Result<Vec<f32>, GpuboxError> {
+        let mut buffer: Vec<f32> = vec![
+            0.;
+            self.metafits_context.num_baselines
+                * self.metafits_context.num_visibility_pols
+        ];
+
+        let (fits_filename, _, hdu_index) =
+            self.get_fits_filename_and_batch_and_hdu(corr_timestep_index, corr_coarse_chan_index)?;
+
+        self.read_hdu_weights_into_buffer(fits_filename, hdu_index, &mut buffer)?;
+
+        Ok(buffer)
+    }
+
+    /// Read the weights which go with the visibility HDU `hdu_index` of a gpubox file, in [baseline][pol] order.
+    pub(super) fn read_hdu_weights_into_buffer(
+        &self,
+        fits_filename: &str,
+        hdu_index: usize,
+        buffer: &mut [f32],
+    ) -> Result<(), GpuboxError> {
+        match self.mwa_version {
+            MWAVersion::CorrMWAXv2 => {
+                self.read_raw_hdu_into_buffer(fits_filename, hdu_index + 1, buffer)
+            }
+            _ => {
+                buffer.fill(1.);
+                Ok(())
+            }
+        }
+    }
+
+    /// Read many timesteps and coarse channels, averaging `time_avg_factor` timesteps and `freq_avg_factor`
+    /// fine channels together. Visibilities are weighted by the MWAX visibility weights (see
+    /// `read_weights_by_baseline`), after `read_config` has been applied. As the weights are per linear pol,
+    /// the averaged visibilities are always linear pols, whatever `read_config.pol_products` is.
+    ///
+    /// Timesteps and coarse channels with no data (i.e. `NoDataForTimeStepCoarseChannel`) are skipped, so an
+    /// averaged timestep may be made from fewer than `time_avg_factor` timesteps, or none at all.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_indices` - indices within the CorrelatorContext timestep array for the desired timesteps.
+    ///   Consecutive groups of `time_avg_factor` of these are averaged together (the last
+    ///   group may be smaller).
+    ///
+    /// * `corr_coarse_chan_indices` - indices within the CorrelatorContext coarse_chan array for the desired coarse channels.
+    ///
+    /// * `time_avg_factor` - number of timesteps to average together. Must be at least 1.
+    ///
+    /// * `freq_avg_factor` - number of fine channels to average together. Must be at least 1 and divide the
+    ///   number of fine channels per coarse channel.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the `AveragedVisibilities`, or a GpuboxError on failure.
+    ///
+    pub fn read_averaged(
+        &self,
+        corr_timestep_indices: &[usize],
+        corr_coarse_chan_indices: &[usize],
+        time_avg_factor: usize,
+        freq_avg_factor: usize,
+    ) -> Result<AveragedVisibilities, GpuboxError> {
+        let num_fine_chans = self.metafits_context.num_corr_fine_chans_per_coarse;
+        let num_baselines = self.metafits_context.num_baselines;
+        let num_pols = self.metafits_context.num_visibility_pols;
+
+        self.validate_timestep_and_coarse_chan_indices(
+            corr_timestep_indices,
+            corr_coarse_chan_indices,
+        )?;
+        if time_avg_factor == 0 {
+            return Err(GpuboxError::InvalidTimeAveragingFactor(time_avg_factor));
+        }
+        if freq_avg_factor == 0 || num_fine_chans % freq_avg_factor != 0 {
+            return Err(GpuboxError::InvalidFreqAveragingFactor {
+                factor: freq_avg_factor,
+                num_fine_chans_per_coarse: num_fine_chans,
+            });
+        }
+
+        let num_avg_fine_chans = num_fine_chans / freq_avg_factor;
+        let timestep_indices: Vec<Vec<usize>> = corr_timestep_indices
+            .chunks(time_avg_factor)
+            .map(|c| c.to_vec())
+            .collect();
+        let num_avg_timesteps = timestep_indices.len();
+        let num_coarse_chans = corr_coarse_chan_indices.len();
+        let chunk_weights_len = num_baselines * num_avg_fine_chans * num_pols;
+
+        let mut data: Vec<f32> =
+            vec![0.; num_avg_timesteps * num_coarse_chans * chunk_weights_len * 2];
+        let mut weights: Vec<f32> =
+            vec![0.; num_avg_timesteps * num_coarse_chans * chunk_weights_len];
+        let mut centroid_unix_times_ms: Vec<f64> = Vec::with_capacity(num_avg_timesteps);
+
+        let mut hdu_buffer: Vec<f32> = vec![0.; self.num_timestep_coarse_chan_floats];
+        let mut hdu_weights: Vec<f32> = vec![0.; num_baselines * num_pols];
+        let mut vis_sums: Vec<f64> = vec![0.; chunk_weights_len * 2];
+        let mut weight_sums: Vec<f64> = vec![0.; chunk_weights_len];
+
+        let mut chunks = data
+            .chunks_exact_mut(chunk_weights_len * 2)
+            .zip(weights.chunks_exact_mut(chunk_weights_len));
+
+        let half_int_time_ms = self.metafits_context.corr_int_time_ms as f64 / 2.;
+
+        for window in timestep_indices.iter() {
+            // Total weight of each timestep in the window, across all coarse channels
+            let mut timestep_weights: Vec<f64> = vec![0.; window.len()];
+
+            for &corr_coarse_chan_index in corr_coarse_chan_indices {
+                vis_sums.fill(0.);
+                weight_sums.fill(0.);
+
+                for (position, &corr_timestep_index) in window.iter().enumerate() {
+                    let (fits_filename, _, hdu_index) = match self
+                        .get_fits_filename_and_batch_and_hdu(
+                            corr_timestep_index,
+                            corr_coarse_chan_index,
+                        ) {
+                        Ok(f) => f,
+                        Err(GpuboxError::NoDataForTimeStepCoarseChannel { .. }) => continue,
+                        Err(e) => return Err(e),
+                    };
+
+                    self.read_hdu_by_baseline_into_buffer(
+                        fits_filename,
+                        hdu_index,
+                        &mut hdu_buffer,
+                    )?;
+                    self.read_hdu_weights_into_buffer(fits_filename, hdu_index, &mut hdu_weights)?;
+
+                    timestep_weights[position] += accumulate_weighted_visibilities(
+                        &hdu_buffer,
+                        &hdu_weights,
+                        num_fine_chans,
+                        num_pols,
+                        freq_avg_factor,
+                        &mut vis_sums,
+                        &mut weight_sums,
+                    );
+                }
+
+                // The sizes of data and weights were worked out above, so there is always a chunk
+                let (data_chunk, weights_chunk) = chunks.next().unwrap();
+                finalise_weighted_average(&vis_sums, &weight_sums, data_chunk, weights_chunk);
+            }
+
+            let timestep_centres_ms: Vec<f64> = window
+                .iter()
+                .map(|&t| self.timesteps[t].unix_time_ms as f64 + half_int_time_ms)
+                .collect();
+            let total_weight: f64 = timestep_weights.iter().sum();
+
+            centroid_unix_times_ms.push(if total_weight > 0. {
+                timestep_centres_ms
+                    .iter()
+                    .zip(timestep_weights.iter())
+                    .map(|(t, w)| t * w)
+                    .sum::<f64>()
+                    / total_weight
+            } else {
+                timestep_centres_ms.iter().sum::<f64>() / window.len() as f64
+            });
+        }
+
+        let fine_chan_freqs_hz: Vec<f64> = self
+            .get_fine_chan_freqs_hz_array(corr_coarse_chan_indices)
+            .chunks(freq_avg_factor)
+            .map(|f| f.iter().sum::<f64>() / freq_avg_factor as f64)
+            .collect();
+
+        Ok(AveragedVisibilities {
+            time_avg_factor,
+            freq_avg_factor,
+            num_timesteps: num_avg_timesteps,
+            num_coarse_chans,
+            num_baselines,
+            num_fine_chans_per_coarse: num_avg_fine_chans,
+            num_pols,
+            timestep_indices,
+            centroid_unix_times_ms,
+            fine_chan_freqs_hz,
+            data,
+            weights,
+        })
+    }
+}
@@ -0,0 +1,370 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Reads of visibilities averaged by different amounts on each baseline, so that short baselines
+//! (which decorrelate slowly) are averaged more than long ones.
+//!
+//! The averaging factors are chosen so that the worst case loss of amplitude due to averaging stays
+//! within a tolerance. Averaging a signal whose phase winds through `phi` radians reduces its amplitude
+//! by a factor of `sinc(phi / 2)`. The worst cases considered are:
+//!
+//! * time: the maximum fringe rate of the baseline, `omega_earth * length / wavelength`, at the highest
+//!   frequency being read.
+//!
+//! * frequency: a source on the horizon in the direction of the baseline, i.e. a delay of `length / c`.
+
+use super::averaged_read::{accumulate_weighted_visibilities, finalise_weighted_average};
+use super::*;
+
+/// Rotation rate of the Earth, in radians per (sidereal) second
+const EARTH_ROTATION_RATE_RAD_PER_S: f64 = 7.292_115_0e-5;
+
+///
+/// The visibilities of one baseline after baseline-dependent averaging, as returned by
+/// `CorrelatorContext::read_baseline_dependent_averaged`. Each baseline has its own time and frequency axes.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaselineAveragedVisibilities {
+    /// Index within the metafits baseline array
+    pub baseline_index: usize,
+    /// Length of the baseline in metres
+    pub baseline_length_m: f64,
+    /// Number of timesteps averaged together on this baseline
+    pub time_avg_factor: usize,
+    /// Number of fine channels averaged together on this baseline
+    pub freq_avg_factor: usize,
+    /// Number of averaged timesteps
+    pub num_timesteps: usize,
+    /// Number of averaged fine channels, across all coarse channels
+    pub num_fine_chans: usize,
+    /// Number of visibility pols
+    pub num_pols: usize,
+    /// For each averaged timestep, the centroid of the centres of the timesteps which contributed to it,
+    /// weighted by this baseline's weights (UNIX time in milliseconds). If nothing contributed, the unweighted centroid.
+    pub centroid_unix_times_ms: Vec<f64>,
+    /// The centre sky frequencies of each averaged fine channel, in [coarse_chan][frequency] order
+    pub fine_chan_freqs_hz: Vec<f64>,
+    /// Averaged visibilities in [time][coarse_chan][frequency][pol][r][i] order
+    pub data: Vec<f32>,
+    /// Sum of the weights of the visibilities which went into each averaged visibility, in
+    /// [time][coarse_chan][frequency][pol] order
+    pub weights: Vec<f32>,
+}
+
+/// Running sums of the averaged visibilities of one baseline.
+struct BaselineSums {
+    time_avg_factor: usize,
+    freq_avg_factor: usize,
+    /// Weighted sums of visibilities in [time][coarse_chan][frequency][pol][r][i] order
+    vis_sums: Vec<f64>,
+    /// Sums of weights in [time][coarse_chan][frequency][pol] order
+    weight_sums: Vec<f64>,
+    /// For each averaged timestep, the weighted sum of the centres of its timesteps
+    centre_sums: Vec<f64>,
+    /// For each averaged timestep, the sum of the weights of its timesteps
+    centre_weights: Vec<f64>,
+}
+
+/// Returns the fraction of amplitude lost by averaging a signal whose phase winds through `phase_rad` radians.
+fn decorrelation(phase_rad: f64) -> f64 {
+    let x = phase_rad / 2.;
+
+    if x == 0. {
+        0.
+    } else {
+        1. - (x.sin() / x).abs()
+    }
+}
+
+/// Works out how many timesteps can be averaged together on a baseline without the worst case
+/// decorrelation exceeding a tolerance.
+///
+/// # Arguments
+///
+/// * `baseline_length_m` - length of the baseline in metres.
+///
+/// * `max_freq_hz` - the highest sky frequency being averaged.
+///
+/// * `int_time_s` - the integration time of one timestep, in seconds.
+///
+/// * `decorrelation_tolerance` - the maximum acceptable fractional loss of amplitude.
+///
+/// * `max_time_avg_factor` - the largest factor to return.
+///
+///
+/// # Returns
+///
+/// * The time averaging factor, between 1 and `max_time_avg_factor`.
+///
+pub(crate) fn get_baseline_time_avg_factor(
+    baseline_length_m: f64,
+    max_freq_hz: f64,
+    int_time_s: f64,
+    decorrelation_tolerance: f64,
+    max_time_avg_factor: usize,
+) -> usize {
+    let fringe_rate_rad_per_s =
+        2. * std::f64::consts::PI * EARTH_ROTATION_RATE_RAD_PER_S * baseline_length_m * max_freq_hz
+            / MWALIB_SPEED_OF_LIGHT_IN_VACUUM_M_PER_S;
+
+    (2..=max_time_avg_factor)
+        .take_while(|&n| {
+            decorrelation(fringe_rate_rad_per_s * int_time_s * n as f64) <= decorrelation_tolerance
+        })
+        .last()
+        .unwrap_or(1)
+}
+
+/// Works out how many fine channels can be averaged together on a baseline without the worst case
+/// decorrelation exceeding a tolerance.
+///
+/// # Arguments
+///
+/// * `baseline_length_m` - length of the baseline in metres.
+///
+/// * `fine_chan_width_hz` - the width of one fine channel.
+///
+/// * `num_fine_chans_per_coarse` - the number of fine channels per coarse channel. The factor returned always divides this.
+///
+/// * `decorrelation_tolerance` - the maximum acceptable fractional loss of amplitude.
+///
+/// * `max_freq_avg_factor` - the largest factor to return.
+///
+///
+/// # Returns
+///
+/// * The frequency averaging factor, between 1 and `max_freq_avg_factor`.
+///
+pub(crate) fn get_baseline_freq_avg_factor(
+    baseline_length_m: f64,
+    fine_chan_width_hz: f64,
+    num_fine_chans_per_coarse: usize,
+    decorrelation_tolerance: f64,
+    max_freq_avg_factor: usize,
+) -> usize {
+    let delay_s = baseline_length_m / MWALIB_SPEED_OF_LIGHT_IN_VACUUM_M_PER_S;
+
+    (2..=max_freq_avg_factor.min(num_fine_chans_per_coarse))
+        .filter(|m| num_fine_chans_per_coarse % m == 0)
+        .take_while(|&m| {
+            decorrelation(2. * std::f64::consts::PI * delay_s * fine_chan_width_hz * m as f64)
+                <= decorrelation_tolerance
+        })
+        .last()
+        .unwrap_or(1)
+}
+
+impl CorrelatorContext {
+    /// Returns the length in metres of a baseline, from the antenna positions in the metafits.
+    fn get_baseline_length_m(&self, baseline_index: usize) -> f64 {
+        let baseline = &self.metafits_context.baselines[baseline_index];
+        let ant1 = &self.metafits_context.antennas[baseline.ant1_index];
+        let ant2 = &self.metafits_context.antennas[baseline.ant2_index];
+
+        ((ant1.east_m - ant2.east_m).powi(2)
+            + (ant1.north_m - ant2.north_m).powi(2)
+            + (ant1.height_m - ant2.height_m).powi(2))
+        .sqrt()
+    }
+
+    /// Read many timesteps and coarse channels, averaging each baseline as much as possible without its
+    /// decorrelation exceeding `decorrelation_tolerance`. Visibilities are weighted as in `read_averaged`, and
+    /// timesteps and coarse channels with no data are likewise skipped. The averaged visibilities are always linear pols.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_indices` - indices within the CorrelatorContext timestep array for the desired timesteps.
+    ///   On each baseline, consecutive groups of its time averaging factor of these are
+    ///   averaged together (the last group may be smaller).
+    ///
+    /// * `corr_coarse_chan_indices` - indices within the CorrelatorContext coarse_chan array for the desired coarse channels.
+    ///
+    /// * `decorrelation_tolerance` - the maximum acceptable fractional loss of amplitude due to averaging, separately
+    ///   in time and frequency, e.g. 0.01 for 1%. Must be between 0 and 1.
+    ///
+    /// * `max_time_avg_factor` - the most timesteps to average together on any baseline. Must be at least 1.
+    ///
+    /// * `max_freq_avg_factor` - the most fine channels to average together on any baseline. Must be at least 1.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing a `BaselineAveragedVisibilities` for each baseline, or a GpuboxError on failure.
+    ///
+    pub fn read_baseline_dependent_averaged(
+        &self,
+        corr_timestep_indices: &[usize],
+        corr_coarse_chan_indices: &[usize],
+        decorrelation_tolerance: f64,
+        max_time_avg_factor: usize,
+        max_freq_avg_factor: usize,
+    ) -> Result<Vec<BaselineAveragedVisibilities>, GpuboxError> {
+        let num_fine_chans = self.metafits_context.num_corr_fine_chans_per_coarse;
+        let num_baselines = self.metafits_context.num_baselines;
+        let num_pols = self.metafits_context.num_visibility_pols;
+        let num_coarse_chans = corr_coarse_chan_indices.len();
+
+        self.validate_timestep_and_coarse_chan_indices(
+            corr_timestep_indices,
+            corr_coarse_chan_indices,
+        )?;
+        if !(decorrelation_tolerance > 0. && decorrelation_tolerance < 1.) {
+            return Err(GpuboxError::InvalidDecorrelationTolerance(
+                decorrelation_tolerance,
+            ));
+        }
+        if max_time_avg_factor == 0 {
+            return Err(GpuboxError::InvalidTimeAveragingFactor(max_time_avg_factor));
+        }
+        if max_freq_avg_factor == 0 {
+            return Err(GpuboxError::InvalidFreqAveragingFactor {
+                factor: max_freq_avg_factor,
+                num_fine_chans_per_coarse: num_fine_chans,
+            });
+        }
+
+        let fine_chan_width_hz = self.metafits_context.corr_fine_chan_width_hz as f64;
+        let fine_chan_freqs_hz = self.get_fine_chan_freqs_hz_array(corr_coarse_chan_indices);
+        let max_freq_hz = fine_chan_freqs_hz
+            .iter()
+            .fold(0., |max: f64, &f| max.max(f + fine_chan_width_hz / 2.));
+        let int_time_s = self.metafits_context.corr_int_time_ms as f64 / 1000.;
+        let half_int_time_ms = self.metafits_context.corr_int_time_ms as f64 / 2.;
+        let timestep_centres_ms: Vec<f64> = corr_timestep_indices
+            .iter()
+            .map(|&t| self.timesteps[t].unix_time_ms as f64 + half_int_time_ms)
+            .collect();
+
+        let mut sums: Vec<BaselineSums> = (0..num_baselines)
+            .map(|baseline_index| {
+                let baseline_length_m = self.get_baseline_length_m(baseline_index);
+                let time_avg_factor = get_baseline_time_avg_factor(
+                    baseline_length_m,
+                    max_freq_hz,
+                    int_time_s,
+                    decorrelation_tolerance,
+                    max_time_avg_factor,
+                );
+                let freq_avg_factor = get_baseline_freq_avg_factor(
+                    baseline_length_m,
+                    fine_chan_width_hz,
+                    num_fine_chans,
+                    decorrelation_tolerance,
+                    max_freq_avg_factor,
+                );
+                let num_avg_timesteps =
+                    (corr_timestep_indices.len() + time_avg_factor - 1) / time_avg_factor;
+                let num_weights = num_avg_timesteps * num_coarse_chans * num_fine_chans
+                    / freq_avg_factor
+                    * num_pols;
+
+                BaselineSums {
+                    time_avg_factor,
+                    freq_avg_factor,
+                    vis_sums: vec![0.; num_weights * 2],
+                    weight_sums: vec![0.; num_weights],
+                    centre_sums: vec![0.; num_avg_timesteps],
+                    centre_weights: vec![0.; num_avg_timesteps],
+                }
+            })
+            .collect();
+
+        let mut hdu_buffer: Vec<f32> = vec![0.; self.num_timestep_coarse_chan_floats];
+        let mut hdu_weights: Vec<f32> = vec![0.; num_baselines * num_pols];
+        let baseline_floats = num_fine_chans * num_pols * 2;
+
+        for (coarse_chan_position, &corr_coarse_chan_index) in
+            corr_coarse_chan_indices.iter().enumerate()
+        {
+            for (timestep_position, &corr_timestep_index) in
+                corr_timestep_indices.iter().enumerate()
+            {
+                let (fits_filename, _, hdu_index) = match self.get_fits_filename_and_batch_and_hdu(
+                    corr_timestep_index,
+                    corr_coarse_chan_index,
+                ) {
+                    Ok(f) => f,
+                    Err(GpuboxError::NoDataForTimeStepCoarseChannel { .. }) => continue,
+                    Err(e) => return Err(e),
+                };
+
+                self.read_hdu_by_baseline_into_buffer(fits_filename, hdu_index, &mut hdu_buffer)?;
+                self.read_hdu_weights_into_buffer(fits_filename, hdu_index, &mut hdu_weights)?;
+
+                for (baseline_index, baseline_sums) in sums.iter_mut().enumerate() {
+                    let avg_timestep_index = timestep_position / baseline_sums.time_avg_factor;
+                    let chunk_weights_len =
+                        num_fine_chans / baseline_sums.freq_avg_factor * num_pols;
+                    let chunk_index = avg_timestep_index * num_coarse_chans + coarse_chan_position;
+
+                    let weight = accumulate_weighted_visibilities(
+                        &hdu_buffer[baseline_index * baseline_floats..][..baseline_floats],
+                        &hdu_weights[baseline_index * num_pols..][..num_pols],
+                        num_fine_chans,
+                        num_pols,
+                        baseline_sums.freq_avg_factor,
+                        &mut baseline_sums.vis_sums[chunk_index * chunk_weights_len * 2..]
+                            [..chunk_weights_len * 2],
+                        &mut baseline_sums.weight_sums[chunk_index * chunk_weights_len..]
+                            [..chunk_weights_len],
+                    );
+
+                    baseline_sums.centre_sums[avg_timestep_index] +=
+                        timestep_centres_ms[timestep_position] * weight;
+                    baseline_sums.centre_weights[avg_timestep_index] += weight;
+                }
+            }
+        }
+
+        Ok(sums
+            .into_iter()
+            .enumerate()
+            .map(|(baseline_index, baseline_sums)| {
+                let BaselineSums {
+                    time_avg_factor,
+                    freq_avg_factor,
+                    vis_sums,
+                    weight_sums,
+                    centre_sums,
+                    centre_weights,
+                } = baseline_sums;
+
+                let mut data: Vec<f32> = vec![0.; vis_sums.len()];
+                let mut weights: Vec<f32> = vec![0.; weight_sums.len()];
+                finalise_weighted_average(&vis_sums, &weight_sums, &mut data, &mut weights);
+
+                let centroid_unix_times_ms: Vec<f64> = timestep_centres_ms
+                    .chunks(time_avg_factor)
+                    .zip(centre_sums.iter().zip(centre_weights.iter()))
+                    .map(|(centres, (&sum, &weight))| {
+                        if weight > 0. {
+                            sum / weight
+                        } else {
+                            centres.iter().sum::<f64>() / centres.len() as f64
+                        }
+                    })
+                    .collect();
+
+                let avg_fine_chan_freqs_hz: Vec<f64> = fine_chan_freqs_hz
+                    .chunks(freq_avg_factor)
+                    .map(|f| f.iter().sum::<f64>() / freq_avg_factor as f64)
+                    .collect();
+
+                BaselineAveragedVisibilities {
+                    baseline_index,
+                    baseline_length_m: self.get_baseline_length_m(baseline_index),
+                    time_avg_factor,
+                    freq_avg_factor,
+                    num_timesteps: centroid_unix_times_ms.len(),
+                    num_fine_chans: avg_fine_chan_freqs_hz.len(),
+                    num_pols,
+                    centroid_unix_times_ms,
+                    fine_chan_freqs_hz: avg_fine_chan_freqs_hz,
+                    data,
+                    weights,
+                }
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,192 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Reads of many timesteps and coarse channels at once into a single buffer.
+
+use std::sync::{Arc, Mutex};
+
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuildError};
+
+use super::*;
+
+impl CorrelatorContext {
+    /// Read many timesteps and coarse channels into a supplied buffer.
+    /// The output visibilities are in order:
+    /// time,coarse_chan,baseline,frequency,pol,r,i
+    /// where time and coarse_chan are in the order given by `corr_timestep_indices` and `corr_coarse_chan_indices`.
+    ///
+    /// The HDUs of each gpubox file are read in turn by a single thread, and different gpubox files are read in parallel. The number of
+    /// threads used is controlled by `read_config.num_threads`.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_indices` - indices within the CorrelatorContext timestep array for the desired timesteps.
+    ///
+    /// * `corr_coarse_chan_indices` - indices within the CorrelatorContext coarse_chan array for the desired coarse channels.
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled in [time][coarse_chan][baseline][frequency][pol][r][i] order.
+    ///   Must be exactly timesteps * coarse chans * `num_output_timestep_coarse_chan_floats()` floats long.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure. If any timestep and coarse channel has no data,
+    ///   what happens depends on `read_config.missing_data_policy`: either `GpuboxError::NoDataForTimeStepCoarseChannel`
+    ///   is returned, or that part of the buffer is filled.
+    ///
+    pub fn read_cube(
+        &self,
+        corr_timestep_indices: &[usize],
+        corr_coarse_chan_indices: &[usize],
+        buffer: &mut [f32],
+    ) -> Result<(), GpuboxError> {
+        let mut missing: Vec<bool> =
+            vec![false; corr_timestep_indices.len() * corr_coarse_chan_indices.len()];
+
+        self.read_cube_with_flags(
+            corr_timestep_indices,
+            corr_coarse_chan_indices,
+            buffer,
+            &mut missing,
+        )
+    }
+
+    /// Read many timesteps and coarse channels into a supplied buffer, as per `read_cube`, also reporting
+    /// which timesteps and coarse channels had no data (and so were filled as per `read_config.missing_data_policy`).
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_indices` - indices within the CorrelatorContext timestep array for the desired timesteps.
+    ///
+    /// * `corr_coarse_chan_indices` - indices within the CorrelatorContext coarse_chan array for the desired coarse channels.
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled in [time][coarse_chan][baseline][frequency][pol][r][i] order.
+    ///   Must be exactly timesteps * coarse chans * `num_output_timestep_coarse_chan_floats()` floats long.
+    ///
+    /// * `missing` - buffer of flags which will be filled in [time][coarse_chan] order, true where there was no data.
+    ///   Must be exactly timesteps * coarse chans long.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    pub fn read_cube_with_flags(
+        &self,
+        corr_timestep_indices: &[usize],
+        corr_coarse_chan_indices: &[usize],
+        buffer: &mut [f32],
+        missing: &mut [bool],
+    ) -> Result<(), GpuboxError> {
+        let hdu_floats = self.num_output_timestep_coarse_chan_floats();
+        let expected_len =
+            corr_timestep_indices.len() * corr_coarse_chan_indices.len() * hdu_floats;
+        if buffer.len() != expected_len {
+            return Err(GpuboxError::InvalidBufferSize {
+                expected: expected_len,
+                got: buffer.len(),
+            });
+        }
+        let expected_missing_len = corr_timestep_indices.len() * corr_coarse_chan_indices.len();
+        if missing.len() != expected_missing_len {
+            return Err(GpuboxError::InvalidBufferSize {
+                expected: expected_missing_len,
+                got: missing.len(),
+            });
+        }
+
+        // Work out which HDU of which file goes into each part of the buffer, grouped by file
+        let mut hdus_by_file: BTreeMap<&str, Vec<(usize, &mut [f32])>> = BTreeMap::new();
+        let mut hdu_buffers = buffer.chunks_exact_mut(hdu_floats).zip(missing.iter_mut());
+
+        for &corr_timestep_index in corr_timestep_indices {
+            for &corr_coarse_chan_index in corr_coarse_chan_indices {
+                // The buffer lengths were checked above, so there is always a chunk
+                let (hdu_buffer, hdu_missing) = hdu_buffers.next().unwrap();
+
+                let (fits_filename, hdu_index) = match self.get_hdu_or_fill(
+                    corr_timestep_index,
+                    corr_coarse_chan_index,
+                    hdu_buffer,
+                )? {
+                    Some(h) => h,
+                    None => {
+                        *hdu_missing = true;
+                        continue;
+                    }
+                };
+                *hdu_missing = false;
+
+                hdus_by_file
+                    .entry(fits_filename)
+                    .or_default()
+                    .push((hdu_index, hdu_buffer));
+            }
+        }
+
+        let read_files = || {
+            hdus_by_file
+                .into_par_iter()
+                .try_for_each(|(fits_filename, hdus)| {
+                    for (hdu_index, hdu_buffer) in hdus {
+                        self.read_hdu_by_baseline_into_output_buffer(
+                            fits_filename,
+                            hdu_index,
+                            hdu_buffer,
+                        )?;
+                    }
+
+                    Ok(())
+                })
+        };
+
+        match self.read_config.num_threads {
+            0 => read_files(),
+            num_threads => self.thread_pool.get(num_threads)?.install(read_files),
+        }
+    }
+}
+
+/// The rayon thread pool used by reads when `read_config.num_threads` is not 0. It is built by the first such read and
+/// reused by later reads, until `read_config.num_threads` changes.
+#[derive(Debug, Default)]
+pub(crate) struct ThreadPoolCache {
+    pool: Mutex<Option<(usize, Arc<ThreadPool>)>>,
+}
+
+impl ThreadPoolCache {
+    /// Get the thread pool with `num_threads` threads, building it if it is not the one cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `num_threads` - the number of threads in the pool. Must not be 0.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the thread pool, shared with any other reads using the same number of threads, or a
+    ///   `ThreadPoolBuildError` if the pool could not be built.
+    ///
+    pub(crate) fn get(&self, num_threads: usize) -> Result<Arc<ThreadPool>, ThreadPoolBuildError> {
+        let mut cached = self
+            .pool
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        match cached.as_ref() {
+            Some((cached_num_threads, pool)) if *cached_num_threads == num_threads => {
+                Ok(pool.clone())
+            }
+            _ => {
+                let pool = Arc::new(
+                    rayon::ThreadPoolBuilder::new()
+                        .num_threads(num_threads)
+                        .build()?,
+                );
+                *cached = Some((num_threads, pool.clone()));
+                Ok(pool)
+            }
+        }
+    }
+}
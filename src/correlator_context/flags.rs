@@ -0,0 +1,326 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Generation of flag masks from what the metafits and gpubox files tell us about an observation.
+
+use super::*;
+
+///
+/// Options which control which flags `CorrelatorContext::get_flags` generates.
+///
+/// The default is to generate no flags at all.
+///
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CorrelatorFlagConfig {
+    /// Width (in Hz) of the band at each edge of every coarse channel to flag. Any fine channel which overlaps
+    /// this band is flagged.
+    pub edge_width_hz: u32,
+    /// If true, flag the centre (DC) fine channel of every coarse channel.
+    pub flag_dc: bool,
+    /// If true, flag timesteps which start before the end of the quack time (`MetafitsContext::good_time_unix_ms`).
+    pub flag_quack: bool,
+    /// If true, flag every baseline which includes a tile with either of its rf inputs flagged in the metafits.
+    pub flag_flagged_tiles: bool,
+    /// If true, flag timesteps and coarse channels for which no gpubox HDU was provided.
+    pub flag_missing_hdus: bool,
+}
+
+impl CorrelatorFlagConfig {
+    /// Creates a new `CorrelatorFlagConfig` with all flagging disabled.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A new `CorrelatorFlagConfig`
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the width of the band to flag at each edge of every coarse channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `edge_width_hz` - width in Hz to flag at each edge, or 0 to not flag edges.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorFlagConfig`
+    ///
+    pub fn with_edge_width_hz(mut self, edge_width_hz: u32) -> Self {
+        self.edge_width_hz = edge_width_hz;
+        self
+    }
+
+    /// Enables or disables flagging of the centre (DC) fine channel of every coarse channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag_dc` - true to flag the DC fine channels.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorFlagConfig`
+    ///
+    pub fn with_dc_flags(mut self, flag_dc: bool) -> Self {
+        self.flag_dc = flag_dc;
+        self
+    }
+
+    /// Enables or disables flagging of timesteps in the quack time.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag_quack` - true to flag timesteps which start before the end of the quack time.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorFlagConfig`
+    ///
+    pub fn with_quack_flags(mut self, flag_quack: bool) -> Self {
+        self.flag_quack = flag_quack;
+        self
+    }
+
+    /// Enables or disables flagging of baselines which include flagged tiles.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag_flagged_tiles` - true to flag baselines including a tile flagged in the metafits.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorFlagConfig`
+    ///
+    pub fn with_flagged_tile_flags(mut self, flag_flagged_tiles: bool) -> Self {
+        self.flag_flagged_tiles = flag_flagged_tiles;
+        self
+    }
+
+    /// Enables or disables flagging of timesteps and coarse channels with no data.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag_missing_hdus` - true to flag timesteps and coarse channels for which no gpubox HDU was provided.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorFlagConfig`
+    ///
+    pub fn with_missing_hdu_flags(mut self, flag_missing_hdus: bool) -> Self {
+        self.flag_missing_hdus = flag_missing_hdus;
+        self
+    }
+}
+
+/// Works out which fine channels of a coarse channel are flagged.
+///
+/// # Arguments
+///
+/// * `num_fine_chans` - number of fine channels per coarse channel.
+///
+/// * `fine_chan_width_hz` - width of a fine channel in Hz.
+///
+/// * `flag_config` - which flags to generate. Only `edge_width_hz` and `flag_dc` are used.
+///
+///
+/// # Returns
+///
+/// * A vector of `num_fine_chans` flags.
+///
+pub(crate) fn get_fine_chan_flags(
+    num_fine_chans: usize,
+    fine_chan_width_hz: u32,
+    flag_config: &CorrelatorFlagConfig,
+) -> Vec<bool> {
+    let mut flags: Vec<bool> = vec![false; num_fine_chans];
+
+    if flag_config.edge_width_hz > 0 && fine_chan_width_hz > 0 {
+        let num_edge_chans =
+            ((flag_config.edge_width_hz + fine_chan_width_hz - 1) / fine_chan_width_hz) as usize;
+        let num_edge_chans = num_edge_chans.min(num_fine_chans);
+
+        flags[..num_edge_chans].fill(true);
+        flags[num_fine_chans - num_edge_chans..].fill(true);
+    }
+
+    if flag_config.flag_dc && num_fine_chans > 0 {
+        flags[num_fine_chans / 2] = true;
+    }
+
+    flags
+}
+
+impl CorrelatorContext {
+    /// Generate flags for many timesteps and coarse channels.
+    /// The output flags are in order:
+    /// time,coarse_chan,baseline,frequency
+    /// where time and coarse_chan are in the order given by `corr_timestep_indices` and `corr_coarse_chan_indices`.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_indices` - indices within the CorrelatorContext timestep array for the desired timesteps.
+    ///
+    /// * `corr_coarse_chan_indices` - indices within the CorrelatorContext coarse_chan array for the desired coarse channels.
+    ///
+    /// * `flag_config` - which flags to generate.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing a vector of flags (true is flagged) in [time][coarse_chan][baseline][frequency] order, if Ok.
+    ///
+    pub fn get_flags(
+        &self,
+        corr_timestep_indices: &[usize],
+        corr_coarse_chan_indices: &[usize],
+        flag_config: &CorrelatorFlagConfig,
+    ) -> Result<Vec<bool>, GpuboxError> {
+        let mut buffer: Vec<bool> = vec![
+            false;
+            corr_timestep_indices.len()
+                * corr_coarse_chan_indices.len()
+                * self.metafits_context.num_baselines
+                * self.metafits_context.num_corr_fine_chans_per_coarse
+        ];
+
+        self.get_flags_into_buffer(
+            corr_timestep_indices,
+            corr_coarse_chan_indices,
+            flag_config,
+            &mut buffer,
+        )?;
+
+        Ok(buffer)
+    }
+
+    /// Generate flags for many timesteps and coarse channels into a supplied buffer.
+    /// The output flags are in order:
+    /// time,coarse_chan,baseline,frequency
+    /// where time and coarse_chan are in the order given by `corr_timestep_indices` and `corr_coarse_chan_indices`.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_indices` - indices within the CorrelatorContext timestep array for the desired timesteps.
+    ///
+    /// * `corr_coarse_chan_indices` - indices within the CorrelatorContext coarse_chan array for the desired coarse channels.
+    ///
+    /// * `flag_config` - which flags to generate.
+    ///
+    /// * `buffer` - buffer of flags which will be filled in [time][coarse_chan][baseline][frequency] order (true is flagged).
+    ///   Must be exactly timesteps * coarse chans * baselines * fine chans per coarse long.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    pub fn get_flags_into_buffer(
+        &self,
+        corr_timestep_indices: &[usize],
+        corr_coarse_chan_indices: &[usize],
+        flag_config: &CorrelatorFlagConfig,
+        buffer: &mut [bool],
+    ) -> Result<(), GpuboxError> {
+        let num_baselines = self.metafits_context.num_baselines;
+        let num_fine_chans = self.metafits_context.num_corr_fine_chans_per_coarse;
+        let hdu_flags_len = num_baselines * num_fine_chans;
+
+        let expected_len =
+            corr_timestep_indices.len() * corr_coarse_chan_indices.len() * hdu_flags_len;
+        if buffer.len() != expected_len {
+            return Err(GpuboxError::InvalidBufferSize {
+                expected: expected_len,
+                got: buffer.len(),
+            });
+        }
+        if corr_timestep_indices
+            .iter()
+            .any(|&t| t >= self.num_timesteps)
+        {
+            return Err(GpuboxError::InvalidTimeStepIndex(self.num_timesteps - 1));
+        }
+        if corr_coarse_chan_indices
+            .iter()
+            .any(|&c| c >= self.num_coarse_chans)
+        {
+            return Err(GpuboxError::InvalidCoarseChanIndex(
+                self.num_coarse_chans - 1,
+            ));
+        }
+
+        // Flags which are the same for every timestep and coarse channel
+        let fine_chan_flags = get_fine_chan_flags(
+            num_fine_chans,
+            self.metafits_context.corr_fine_chan_width_hz,
+            flag_config,
+        );
+        let baseline_flags: Vec<bool> = self
+            .metafits_context
+            .baselines
+            .iter()
+            .map(|b| {
+                let ant1 = &self.metafits_context.antennas[b.ant1_index];
+                let ant2 = &self.metafits_context.antennas[b.ant2_index];
+
+                flag_config.flag_flagged_tiles
+                    && (ant1.rfinput_x.flagged
+                        || ant1.rfinput_y.flagged
+                        || ant2.rfinput_x.flagged
+                        || ant2.rfinput_y.flagged)
+            })
+            .collect();
+
+        let mut hdu_flags = buffer.chunks_exact_mut(hdu_flags_len);
+
+        for &corr_timestep_index in corr_timestep_indices {
+            let quacked = flag_config.flag_quack
+                && self.timesteps[corr_timestep_index].unix_time_ms
+                    < self.metafits_context.good_time_unix_ms;
+
+            for &corr_coarse_chan_index in corr_coarse_chan_indices {
+                let missing = flag_config.flag_missing_hdus
+                    && match self.get_fits_filename_and_batch_and_hdu(
+                        corr_timestep_index,
+                        corr_coarse_chan_index,
+                    ) {
+                        Ok(_) => false,
+                        Err(GpuboxError::NoDataForTimeStepCoarseChannel { .. }) => true,
+                        Err(e) => return Err(e),
+                    };
+
+                // The buffer length was checked above, so there is always a chunk
+                let flags = hdu_flags.next().unwrap();
+
+                if quacked || missing {
+                    flags.fill(true);
+                    continue;
+                }
+
+                for (baseline_flags_out, &baseline_flagged) in flags
+                    .chunks_exact_mut(num_fine_chans)
+                    .zip(baseline_flags.iter())
+                {
+                    if baseline_flagged {
+                        baseline_flags_out.fill(true);
+                    } else {
+                        baseline_flags_out.copy_from_slice(&fine_chan_flags);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,439 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A single fine channel frequency axis spanning many coarse channels ("full band"), and reads which
+//! fill it directly.
+//!
+//! `CorrelatorContext::coarse_chans` is already in ascending sky frequency (receiver channel) order, with
+//! the reversal of legacy gpubox numbering for receiver channels over 128 taken care of when the coarse
+//! channels were populated. So stitching is just a matter of laying the coarse channels out by receiver
+//! channel number and leaving a gap wherever a receiver channel is not part of the selection (e.g. in
+//! picket fence observations).
+
+use super::*;
+
+/// A fine channel frequency axis spanning a selection of coarse channels, in ascending sky frequency order.
+///
+/// The axis is divided into "slots", one per receiver channel between the lowest and highest selected coarse
+/// channels. Each slot is either a selected coarse channel or a gap, and contains `num_fine_chans_per_coarse`
+/// fine channels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FullBand {
+    /// Receiver channel number of the first slot.
+    pub first_rec_chan_number: usize,
+    /// Number of fine channels in each slot.
+    pub num_fine_chans_per_coarse: usize,
+    /// For each slot, the index within the CorrelatorContext coarse_chan array of the coarse channel it
+    /// contains, or None if the slot is a gap.
+    pub slot_coarse_chan_indices: Vec<Option<usize>>,
+    /// Total number of fine channels along the axis, including gaps.
+    pub num_fine_chans: usize,
+    /// Centre sky frequency of every fine channel along the axis, including gaps.
+    pub fine_chan_freqs_hz: Vec<f64>,
+}
+
+impl FullBand {
+    /// Returns the number of slots (coarse channels and gaps) along the axis.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The number of slots.
+    ///
+    pub fn num_slots(&self) -> usize {
+        self.slot_coarse_chan_indices.len()
+    }
+
+    /// Returns the indices within the CorrelatorContext coarse_chan array of the coarse channels along the axis,
+    /// in ascending sky frequency order.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A vector of coarse channel indices.
+    ///
+    pub fn coarse_chan_indices(&self) -> Vec<usize> {
+        self.slot_coarse_chan_indices
+            .iter()
+            .filter_map(|c| *c)
+            .collect()
+    }
+
+    /// Returns true if a fine channel along the axis lies in a gap.
+    ///
+    /// # Arguments
+    ///
+    /// * `fine_chan_index` - index of the fine channel along the full band axis.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * true if the fine channel is in a gap (or beyond the end of the axis).
+    ///
+    pub fn is_gap(&self, fine_chan_index: usize) -> bool {
+        match self.num_fine_chans_per_coarse {
+            0 => true,
+            n => !matches!(
+                self.slot_coarse_chan_indices.get(fine_chan_index / n),
+                Some(Some(_))
+            ),
+        }
+    }
+}
+
+impl CorrelatorContext {
+    /// Get the full band axis spanning all of the coarse channels in the observation.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A `FullBand` covering every coarse channel, with gaps between non-contiguous coarse channels.
+    ///
+    pub fn get_full_band(&self) -> FullBand {
+        let all_coarse_chan_indices: Vec<usize> = (0..self.num_coarse_chans).collect();
+
+        // All of the indices are valid so this can't fail
+        self.get_full_band_for_coarse_chans(&all_coarse_chan_indices)
+            .unwrap()
+    }
+
+    /// Get the full band axis spanning a selection of coarse channels.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_coarse_chan_indices` - indices within the CorrelatorContext coarse_chan array for the desired coarse
+    ///   channels, in any order. Duplicates are ignored.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing a `FullBand` covering the selected coarse channels, with gaps between non-contiguous
+    ///   coarse channels, if Ok.
+    ///
+    pub fn get_full_band_for_coarse_chans(
+        &self,
+        corr_coarse_chan_indices: &[usize],
+    ) -> Result<FullBand, GpuboxError> {
+        if corr_coarse_chan_indices
+            .iter()
+            .any(|&c| c >= self.num_coarse_chans)
+        {
+            return Err(GpuboxError::InvalidCoarseChanIndex(
+                self.num_coarse_chans - 1,
+            ));
+        }
+
+        let num_fine_chans_per_coarse = self.metafits_context.num_corr_fine_chans_per_coarse;
+
+        // Ascending sky frequency order
+        let mut sorted_indices: Vec<usize> = corr_coarse_chan_indices.to_vec();
+        sorted_indices.sort_unstable_by_key(|&c| self.coarse_chans[c].rec_chan_number);
+        sorted_indices.dedup();
+
+        let (first_rec_chan_number, last_rec_chan_number) =
+            match (sorted_indices.first(), sorted_indices.last()) {
+                (Some(&first), Some(&last)) => (
+                    self.coarse_chans[first].rec_chan_number,
+                    self.coarse_chans[last].rec_chan_number,
+                ),
+                _ => {
+                    return Ok(FullBand {
+                        first_rec_chan_number: 0,
+                        num_fine_chans_per_coarse,
+                        slot_coarse_chan_indices: vec![],
+                        num_fine_chans: 0,
+                        fine_chan_freqs_hz: vec![],
+                    })
+                }
+            };
+
+        let mut slot_coarse_chan_indices: Vec<Option<usize>> =
+            vec![None; last_rec_chan_number - first_rec_chan_number + 1];
+        for &c in &sorted_indices {
+            slot_coarse_chan_indices
+                [self.coarse_chans[c].rec_chan_number - first_rec_chan_number] = Some(c);
+        }
+
+        // Gaps get the frequencies of the receiver channel they stand in for
+        let slot_coarse_chans: Vec<CoarseChannel> = slot_coarse_chan_indices
+            .iter()
+            .enumerate()
+            .map(|(slot, c)| match c {
+                Some(c) => self.coarse_chans[*c].clone(),
+                None => CoarseChannel::new(
+                    0,
+                    first_rec_chan_number + slot,
+                    0,
+                    self.metafits_context.coarse_chan_width_hz,
+                ),
+            })
+            .collect();
+
+        let fine_chan_freqs_hz = CoarseChannel::get_fine_chan_centres_array_hz_inner(
+            self.mwa_version,
+            slot_coarse_chans.iter(),
+            self.metafits_context.corr_fine_chan_width_hz,
+            num_fine_chans_per_coarse,
+        );
+
+        Ok(FullBand {
+            first_rec_chan_number,
+            num_fine_chans_per_coarse,
+            num_fine_chans: slot_coarse_chan_indices.len() * num_fine_chans_per_coarse,
+            slot_coarse_chan_indices,
+            fine_chan_freqs_hz,
+        })
+    }
+
+    /// Read a single timestep across the full band.
+    /// The output visibilities are in order:
+    /// baseline,frequency,pol,r,i
+    /// where frequency is along the full band axis. Gaps are filled with the `read_config.missing_data_policy` fill
+    /// value, or zeros if the policy is to error.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `full_band` - the full band axis to fill, from `get_full_band` or `get_full_band_for_coarse_chans`.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing vector of 32 bit floats containing the data in [baseline][frequency][pol][r][i] order, if Ok.
+    ///
+    pub fn read_full_band_by_baseline(
+        &self,
+        corr_timestep_index: usize,
+        full_band: &FullBand,
+    ) -> Result<Vec<f32>, GpuboxError> {
+        let mut buffer: Vec<f32> = vec![0.; self.num_full_band_floats(full_band)];
+
+        self.read_full_band_by_baseline_into_buffer(corr_timestep_index, full_band, &mut buffer)?;
+
+        Ok(buffer)
+    }
+
+    /// Read a single timestep across the full band.
+    /// The output visibilities are in order:
+    /// frequency,baseline,pol,r,i
+    /// where frequency is along the full band axis. Gaps are filled with the `read_config.missing_data_policy` fill
+    /// value, or zeros if the policy is to error.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `full_band` - the full band axis to fill, from `get_full_band` or `get_full_band_for_coarse_chans`.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing vector of 32 bit floats containing the data in [frequency][baseline][pol][r][i] order, if Ok.
+    ///
+    pub fn read_full_band_by_frequency(
+        &self,
+        corr_timestep_index: usize,
+        full_band: &FullBand,
+    ) -> Result<Vec<f32>, GpuboxError> {
+        let mut buffer: Vec<f32> = vec![0.; self.num_full_band_floats(full_band)];
+
+        self.read_full_band_by_frequency_into_buffer(corr_timestep_index, full_band, &mut buffer)?;
+
+        Ok(buffer)
+    }
+
+    /// Read a single timestep across the full band into a supplied buffer.
+    /// The output visibilities are in order:
+    /// baseline,frequency,pol,r,i
+    /// where frequency is along the full band axis. Gaps are filled with the `read_config.missing_data_policy` fill
+    /// value, or zeros if the policy is to error.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `full_band` - the full band axis to fill, from `get_full_band` or `get_full_band_for_coarse_chans`.
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled in [baseline][frequency][pol][r][i] order. Must be
+    ///   baselines * `full_band.num_fine_chans` * pols * 2 long.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    pub fn read_full_band_by_baseline_into_buffer(
+        &self,
+        corr_timestep_index: usize,
+        full_band: &FullBand,
+        buffer: &mut [f32],
+    ) -> Result<(), GpuboxError> {
+        self.validate_full_band_buffer(full_band, buffer)?;
+
+        let slot_floats_per_baseline =
+            full_band.num_fine_chans_per_coarse * self.num_output_pols() * 2;
+        let band_floats_per_baseline = full_band.num_slots() * slot_floats_per_baseline;
+        let mut coarse_chan_buffer: Vec<f32> =
+            vec![0.; self.num_output_timestep_coarse_chan_floats()];
+
+        for (slot, coarse_chan_index) in full_band.slot_coarse_chan_indices.iter().enumerate() {
+            let slot_offset = slot * slot_floats_per_baseline;
+
+            match coarse_chan_index {
+                Some(c) => {
+                    self.read_by_baseline_into_buffer(
+                        corr_timestep_index,
+                        *c,
+                        &mut coarse_chan_buffer,
+                    )?;
+
+                    for (baseline_out, baseline_in) in buffer
+                        .chunks_exact_mut(band_floats_per_baseline)
+                        .zip(coarse_chan_buffer.chunks_exact(slot_floats_per_baseline))
+                    {
+                        baseline_out[slot_offset..slot_offset + slot_floats_per_baseline]
+                            .copy_from_slice(baseline_in);
+                    }
+                }
+                None => {
+                    let fill_value = self.get_full_band_gap_fill_value();
+
+                    for baseline_out in buffer.chunks_exact_mut(band_floats_per_baseline) {
+                        baseline_out[slot_offset..slot_offset + slot_floats_per_baseline]
+                            .fill(fill_value);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a single timestep across the full band into a supplied buffer.
+    /// The output visibilities are in order:
+    /// frequency,baseline,pol,r,i
+    /// where frequency is along the full band axis. Gaps are filled with the `read_config.missing_data_policy` fill
+    /// value, or zeros if the policy is to error.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `full_band` - the full band axis to fill, from `get_full_band` or `get_full_band_for_coarse_chans`.
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled in [frequency][baseline][pol][r][i] order. Must be
+    ///   `full_band.num_fine_chans` * baselines * pols * 2 long.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    pub fn read_full_band_by_frequency_into_buffer(
+        &self,
+        corr_timestep_index: usize,
+        full_band: &FullBand,
+        buffer: &mut [f32],
+    ) -> Result<(), GpuboxError> {
+        self.validate_full_band_buffer(full_band, buffer)?;
+
+        // In frequency order each slot is a contiguous block of the output
+        for (slot_buffer, coarse_chan_index) in buffer
+            .chunks_exact_mut(self.num_output_timestep_coarse_chan_floats())
+            .zip(full_band.slot_coarse_chan_indices.iter())
+        {
+            match coarse_chan_index {
+                Some(c) => {
+                    self.read_by_frequency_into_buffer(corr_timestep_index, *c, slot_buffer)?
+                }
+                None => slot_buffer.fill(self.get_full_band_gap_fill_value()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of floats needed to hold a single timestep across the full band.
+    ///
+    /// # Arguments
+    ///
+    /// * `full_band` - the full band axis.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The number of output floats per timestep.
+    ///
+    pub fn num_full_band_floats(&self, full_band: &FullBand) -> usize {
+        full_band.num_fine_chans * self.metafits_context.num_baselines * self.num_output_pols() * 2
+    }
+
+    /// Returns the value gaps in the full band are filled with.
+    fn get_full_band_gap_fill_value(&self) -> f32 {
+        self.read_config
+            .missing_data_policy
+            .fill_value()
+            .unwrap_or(0.)
+    }
+
+    /// Checks that a full band axis is consistent, was made for this context's fine channels and that a buffer is the
+    /// right size for it.
+    fn validate_full_band_buffer(
+        &self,
+        full_band: &FullBand,
+        buffer: &[f32],
+    ) -> Result<(), GpuboxError> {
+        self.validate_default_layout()?;
+
+        if full_band
+            .slot_coarse_chan_indices
+            .iter()
+            .any(|c| matches!(c, Some(c) if *c >= self.num_coarse_chans))
+        {
+            return Err(GpuboxError::InvalidCoarseChanIndex(
+                self.num_coarse_chans - 1,
+            ));
+        }
+
+        // The fields are public, so make sure they still agree with each other
+        let expected_num_fine_chans = full_band.num_slots() * full_band.num_fine_chans_per_coarse;
+        if full_band.num_fine_chans != expected_num_fine_chans
+            || full_band.fine_chan_freqs_hz.len() != expected_num_fine_chans
+        {
+            return Err(GpuboxError::InvalidFullBand {
+                num_slots: full_band.num_slots(),
+                num_fine_chans_per_coarse: full_band.num_fine_chans_per_coarse,
+                expected: expected_num_fine_chans,
+                num_fine_chans: full_band.num_fine_chans,
+                num_fine_chan_freqs: full_band.fine_chan_freqs_hz.len(),
+            });
+        }
+
+        let expected_len = self.num_full_band_floats(full_band);
+        if full_band.num_fine_chans_per_coarse
+            != self.metafits_context.num_corr_fine_chans_per_coarse
+            || buffer.len() != expected_len
+        {
+            return Err(GpuboxError::InvalidBufferSize {
+                expected: expected_len,
+                got: buffer.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -7,15 +7,46 @@
 use std::collections::BTreeMap;
 use std::fmt;
 use std::path::Path;
+use std::sync::Mutex;
+
+use log::warn;
 
 use crate::coarse_channel::*;
 use crate::convert::*;
+use crate::data_iterator::*;
 use crate::error::*;
 use crate::gpubox_files::*;
 use crate::metafits_context::*;
+use crate::selection::{error::SelectionError, Selection};
 use crate::timestep::*;
 use crate::*;
 
+mod read_config;
+pub use read_config::{CorrelatorReadConfig, MissingDataPolicy};
+
+mod flags;
+pub use flags::CorrelatorFlagConfig;
+
+mod subset_read;
+
+mod cube_read;
+use cube_read::ThreadPoolCache;
+
+mod averaged_read;
+pub use averaged_read::AveragedVisibilities;
+
+mod baseline_averaged_read;
+pub use baseline_averaged_read::BaselineAveragedVisibilities;
+
+mod full_band;
+pub use full_band::FullBand;
+
+mod visibility_view;
+pub use visibility_view::{VisibilityOrder, VisibilityView};
+
+#[cfg(feature = "ndarray")]
+mod ndarray_read;
+
 #[cfg(test)]
 mod test;
 
@@ -111,8 +142,22 @@ pub struct CorrelatorContext {
     /// number, batch number and HDU index are everything needed to find the
     /// correct HDU out of all gpubox files.
     pub gpubox_time_map: BTreeMap<u64, BTreeMap<usize, (usize, usize)>>,
+    /// The byte offset and number of floats of the image data of each MWAX HDU, if it can be read
+    /// directly from the file, keyed by gpubox filename and HDU index. Filled in as HDUs are first
+    /// read with `CorrelatorReadConfig::direct_read` set.
+    pub(crate) gpubox_data_offsets: Mutex<GpuboxDataOffsetMap>,
+    /// Options applied to visibilities by all of the read functions. See `CorrelatorReadConfig`.
+    pub read_config: CorrelatorReadConfig,
     /// A conversion table to optimise reading of legacy MWA HDUs
     pub(crate) legacy_conversion_table: Vec<LegacyConversionBaseline>,
+    /// The conversion table for `read_config.layout`, generated on the first read with that layout
+    pub(crate) output_conversion_table: OutputConversionTableCache,
+    /// gpubox files which are kept open between reads
+    pub(crate) fits_handle_pool: FitsHandlePool,
+    /// gpubox files which are kept open (mapped) between direct reads
+    pub(crate) direct_read_file_pool: DirectReadFilePool,
+    /// The thread pool for reads with `read_config.num_threads` > 0, built on the first such read
+    pub(crate) thread_pool: ThreadPoolCache,
 }
 
 impl CorrelatorContext {
@@ -371,13 +416,226 @@ impl CorrelatorContext {
             num_provided_coarse_chans: num_provided_coarse_chan_indices,
             gpubox_batches: gpubox_info.batches,
             gpubox_time_map: gpubox_info.time_map,
+            gpubox_data_offsets: Mutex::default(),
             num_timestep_coarse_chan_bytes: gpubox_info.hdu_size * 4,
             num_timestep_coarse_chan_floats: gpubox_info.hdu_size,
             num_gpubox_files: gpubox_filenames.len(),
+            read_config: CorrelatorReadConfig::default(),
             legacy_conversion_table,
+            output_conversion_table: OutputConversionTableCache::default(),
+            fits_handle_pool: FitsHandlePool::default(),
+            direct_read_file_pool: DirectReadFilePool::default(),
+            thread_pool: ThreadPoolCache::default(),
         })
     }
 
+    /// Close any gpubox files which have been kept open by previous reads. They will be reopened by
+    /// subsequent reads as needed.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * Nothing
+    ///
+    pub fn close_gpubox_files(&self) {
+        self.fits_handle_pool.clear();
+        self.direct_read_file_pool.clear();
+    }
+
+    /// Get a handle to a (possibly already open) gpubox file from the handle pool.
+    fn get_gpubox_fits_handle(&self, fits_filename: &str) -> Result<FitsHandle, GpuboxError> {
+        Ok(self
+            .fits_handle_pool
+            .get(fits_filename, self.read_config.max_open_files)?)
+    }
+
+    /// Get the byte offset and number of floats of the image of a gpubox HDU, if it can be read
+    /// directly (see `determine_raw_float_image_data_offset`). This is worked out (via cfitsio) the
+    /// first time it is needed for each HDU, then remembered.
+    fn get_gpubox_data_offset(
+        &self,
+        fits_filename: &str,
+        hdu_index: usize,
+    ) -> Result<Option<(u64, usize)>, GpuboxError> {
+        let key = (fits_filename.to_string(), hdu_index);
+        if let Some(&offset) = self
+            .gpubox_data_offsets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&key)
+        {
+            return Ok(offset);
+        }
+
+        let fits_handle = self.get_gpubox_fits_handle(fits_filename)?;
+        let mut fptr = lock_fits_handle(&fits_handle);
+        let hdu = fits_open_hdu!(&mut fptr, hdu_index)?;
+        let offset = determine_raw_float_image_data_offset(&mut fptr, &hdu)?;
+        drop(fptr);
+
+        self.gpubox_data_offsets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(key, offset);
+
+        Ok(offset)
+    }
+
+    /// Returns an iterator over the data of this observation, one timestep and coarse channel at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_set` - which timestep and coarse channel indices to visit.
+    ///
+    /// * `order` - whether to visit all coarse channels of a timestep first (time-major) or all timesteps of a coarse channel first (frequency-major).
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A `CorrelatorDataIterator` yielding (timestep index, coarse chan index, Result containing the data and
+    ///   whether it was missing).
+    ///
+    pub fn data_iter(
+        &self,
+        index_set: IndexSet,
+        order: IterationOrder,
+    ) -> CorrelatorDataIterator<'_> {
+        let (timestep_indices, coarse_chan_indices): (Vec<usize>, Vec<usize>) = match index_set {
+            IndexSet::All => (
+                (0..self.num_timesteps).collect(),
+                (0..self.num_coarse_chans).collect(),
+            ),
+            IndexSet::Provided => (
+                self.provided_timestep_indices.clone(),
+                self.provided_coarse_chan_indices.clone(),
+            ),
+            IndexSet::Common => (
+                self.common_timestep_indices.clone(),
+                self.common_coarse_chan_indices.clone(),
+            ),
+            IndexSet::CommonGood => (
+                self.common_good_timestep_indices.clone(),
+                self.common_good_coarse_chan_indices.clone(),
+            ),
+        };
+
+        CorrelatorDataIterator::new(self, &timestep_indices, &coarse_chan_indices, order)
+    }
+
+    /// Returns the number of pols in each visibility returned by the read functions, which depends on
+    /// `read_config.pol_products`.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The number of output pols.
+    ///
+    pub fn num_output_pols(&self) -> usize {
+        match self.read_config.pol_products {
+            PolProducts::Linear => self.metafits_context.num_visibility_pols,
+            pol_products => pol_products.num_pols(),
+        }
+    }
+
+    /// Returns the number of baselines in the visibilities returned by the read functions, which depends on
+    /// `read_config.layout`.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The number of output baselines.
+    ///
+    pub fn num_output_baselines(&self) -> usize {
+        if self.read_config.layout.exclude_autos {
+            self.metafits_context.num_baselines - self.metafits_context.num_ants
+        } else {
+            self.metafits_context.num_baselines
+        }
+    }
+
+    /// Returns the baselines of the visibilities returned by the read functions, in the order given by
+    /// `read_config.layout`.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A vector of indices into `metafits_context.baselines`, one per output baseline.
+    ///
+    pub fn get_output_baseline_indices(&self) -> Vec<usize> {
+        self.read_config
+            .layout
+            .get_output_baseline_indices(&self.metafits_context.baselines)
+    }
+
+    /// Returns the number of floats returned by the read functions for a single timestep and coarse channel,
+    /// i.e. `num_timestep_coarse_chan_floats` adjusted for `read_config.pol_products` and `read_config.layout`.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The number of output floats per timestep and coarse channel.
+    ///
+    pub fn num_output_timestep_coarse_chan_floats(&self) -> usize {
+        self.num_timestep_coarse_chan_floats / self.metafits_context.num_baselines
+            * self.num_output_baselines()
+            / self.metafits_context.num_visibility_pols
+            * self.num_output_pols()
+    }
+
+    /// Parse a data selection expression, e.g. `time=10~20; chan=131~140; baseline=!auto; pol=XX,YY`, and
+    /// resolve it against this observation. See `Selection` for the syntax.
+    ///
+    /// # Arguments
+    ///
+    /// * `expression` - the selection expression.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the timestep, coarse channel, fine channel, antenna, baseline and pol indices selected
+    ///   if Ok, or a `SelectionError` giving the position of the offending token.
+    ///
+    pub fn select(&self, expression: &str) -> Result<Selection, SelectionError> {
+        Selection::new(self, expression)
+    }
+
+    /// Group the correlator coarse channels into bands of contiguous receiver channels
+    /// (e.g. for picket fence observations).
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * a vector of `CoarseChannelBand`s in ascending sky frequency order. Coarse channel indices in each band are
+    ///   indices into `coarse_chans`.
+    ///
+    pub fn get_contiguous_bands(&self) -> Vec<CoarseChannelBand> {
+        CoarseChannel::get_contiguous_bands(&self.coarse_chans)
+    }
+
     /// For a given slice of correlator coarse channel indices, return a vector of the center
     /// frequencies for all the fine channels in the given coarse channels
     ///
@@ -404,6 +662,71 @@ impl CorrelatorContext {
         )
     }
 
+    /// Return the indices of the correlator coarse channels which overlap a range of sky frequencies.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_hz` - start of the frequency range in Hz (inclusive).
+    ///
+    /// * `end_hz` - end of the frequency range in Hz (inclusive).
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * a vector of indices into `coarse_chans` of every coarse channel whose [`chan_start_hz`, `chan_end_hz`) range
+    ///   intersects [`start_hz`, `end_hz`].
+    ///
+    pub fn get_coarse_chan_indices_for_freq_range(&self, start_hz: f64, end_hz: f64) -> Vec<usize> {
+        CoarseChannel::get_coarse_chan_indices_in_freq_range(&self.coarse_chans, start_hz, end_hz)
+    }
+
+    /// Return the fine channels whose centre frequencies are within a range of sky frequencies.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_hz` - start of the frequency range in Hz (inclusive).
+    ///
+    /// * `end_hz` - end of the frequency range in Hz (inclusive).
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * a vector of (index into `coarse_chans`, fine channel index within the coarse channel) pairs in ascending
+    ///   frequency order, consistent with `get_fine_chan_freqs_hz_array`.
+    ///
+    pub fn get_fine_chan_indices_for_freq_range(
+        &self,
+        start_hz: f64,
+        end_hz: f64,
+    ) -> Vec<(usize, usize)> {
+        CoarseChannel::get_fine_chan_indices_in_freq_range(
+            &self.get_fine_chan_freqs_hz_array(&(0..self.num_coarse_chans).collect::<Vec<usize>>()),
+            self.metafits_context.num_corr_fine_chans_per_coarse,
+            start_hz,
+            end_hz,
+        )
+    }
+
+    /// Return the fine channel whose centre frequency is nearest to a sky frequency.
+    ///
+    /// # Arguments
+    ///
+    /// * `freq_hz` - the sky frequency in Hz.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * the (index into `coarse_chans`, fine channel index within the coarse channel) of the nearest fine channel,
+    ///   consistent with `get_fine_chan_freqs_hz_array`, or None if there are no fine channels.
+    ///
+    pub fn get_nearest_fine_chan_index(&self, freq_hz: f64) -> Option<(usize, usize)> {
+        CoarseChannel::get_nearest_fine_chan_index(
+            &self.get_fine_chan_freqs_hz_array(&(0..self.num_coarse_chans).collect::<Vec<usize>>()),
+            self.metafits_context.num_corr_fine_chans_per_coarse,
+            freq_hz,
+        )
+    }
+
     /// Read a single timestep for a single coarse channel
     /// The output visibilities are in order:
     /// baseline,frequency,pol,r,i
@@ -427,7 +750,7 @@ impl CorrelatorContext {
         corr_timestep_index: usize,
         corr_coarse_chan_index: usize,
     ) -> Result<Vec<f32>, GpuboxError> {
-        let mut return_buffer: Vec<f32> = vec![0.; self.num_timestep_coarse_chan_floats];
+        let mut return_buffer: Vec<f32> = vec![0.; self.num_output_timestep_coarse_chan_floats()];
 
         self.read_by_baseline_into_buffer(
             corr_timestep_index,
@@ -461,7 +784,7 @@ impl CorrelatorContext {
         corr_timestep_index: usize,
         corr_coarse_chan_index: usize,
     ) -> Result<Vec<f32>, GpuboxError> {
-        let mut return_buffer: Vec<f32> = vec![0.; self.num_timestep_coarse_chan_floats];
+        let mut return_buffer: Vec<f32> = vec![0.; self.num_output_timestep_coarse_chan_floats()];
 
         self.read_by_frequency_into_buffer(
             corr_timestep_index,
@@ -570,14 +893,175 @@ impl CorrelatorContext {
         corr_coarse_chan_index: usize,
         buffer: &mut [f32],
     ) -> Result<(), GpuboxError> {
-        // Validate input timestep_index and coarse_chan_index and return the fits_filename, batch index and hdu of the corresponding data
-        let (fits_filename, _, hdu_index) =
-            self.get_fits_filename_and_batch_and_hdu(corr_timestep_index, corr_coarse_chan_index)?;
+        // Check the buffer before it might be filled in for missing data
+        self.validate_output_buffer_len(buffer)?;
 
-        // Open the fits file
-        let mut fptr = fits_open!(&fits_filename)?;
-        let hdu = fits_open_hdu!(&mut fptr, hdu_index)?;
+        // Validate input timestep_index and coarse_chan_index and return the fits_filename and hdu of the corresponding data
+        let (fits_filename, hdu_index) =
+            match self.get_hdu_or_fill(corr_timestep_index, corr_coarse_chan_index, buffer)? {
+                Some(h) => h,
+                None => return Ok(()),
+            };
+
+        self.read_hdu_by_baseline_into_output_buffer(fits_filename, hdu_index, buffer)
+    }
+
+    /// Validate input timestep_index and coarse_chan_index and return the fits_filename and hdu of the
+    /// corresponding data. If there is no data and `read_config.missing_data_policy` says to fill it in,
+    /// `buffer` is filled instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array for the desired coarse channel.
+    ///
+    /// * `buffer` - Float buffer as a slice to fill if there is no data. Callers must have already checked its length.
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the fits_filename and hdu_index, or None if `buffer` was filled, or a GpuboxError on failure.
+    ///
+    fn get_hdu_or_fill(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+        buffer: &mut [f32],
+    ) -> Result<Option<(&str, usize)>, GpuboxError> {
+        match self.get_fits_filename_and_batch_and_hdu(corr_timestep_index, corr_coarse_chan_index)
+        {
+            Ok((fits_filename, _, hdu_index)) => Ok(Some((fits_filename, hdu_index))),
+            Err(e @ GpuboxError::NoDataForTimeStepCoarseChannel { .. }) => {
+                match self.read_config.missing_data_policy.fill_value() {
+                    Some(fill_value) => {
+                        buffer.fill(fill_value);
+                        Ok(None)
+                    }
+                    None => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
 
+    /// Read a single HDU of a gpubox file into a supplied buffer, converting it to `read_config.pol_products`.
+    /// The output visibilities are in order:
+    /// baseline,frequency,pol,r,i
+    ///
+    /// # Arguments
+    ///
+    /// * `fits_filename` - filename of the gpubox file.
+    ///
+    /// * `hdu_index` - index of the HDU to read.
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled with data from the HDU read in [baseline][frequency][pol][r][i] order.
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    fn read_hdu_by_baseline_into_output_buffer(
+        &self,
+        fits_filename: &str,
+        hdu_index: usize,
+        buffer: &mut [f32],
+    ) -> Result<(), GpuboxError> {
+        if self.read_config.layout != VisibilityLayout::default() {
+            return self.read_hdu_into_layout_buffer(fits_filename, hdu_index, buffer, false);
+        }
+
+        match self.read_config.pol_products {
+            PolProducts::Linear => {
+                self.read_hdu_by_baseline_into_buffer(fits_filename, hdu_index, buffer)
+            }
+            pol_products => {
+                self.validate_output_buffer_len(buffer)?;
+
+                let mut linear_buffer: Vec<f32> = vec![0.; self.num_timestep_coarse_chan_floats];
+                self.read_hdu_by_baseline_into_buffer(
+                    fits_filename,
+                    hdu_index,
+                    &mut linear_buffer,
+                )?;
+                convert_linear_to_pol_products(&linear_buffer, buffer, pol_products);
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Check every timestep and coarse channel index of a multi-HDU read is valid, before any of them are used.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_indices` - indices within the CorrelatorContext timestep array.
+    ///
+    /// * `corr_coarse_chan_indices` - indices within the CorrelatorContext coarse_chan array.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if all of the indices are valid, or a GpuboxError for the first kind which is not.
+    ///
+    pub(crate) fn validate_timestep_and_coarse_chan_indices(
+        &self,
+        corr_timestep_indices: &[usize],
+        corr_coarse_chan_indices: &[usize],
+    ) -> Result<(), GpuboxError> {
+        if corr_timestep_indices
+            .iter()
+            .any(|&t| t >= self.num_timesteps)
+        {
+            return Err(GpuboxError::InvalidTimeStepIndex(self.num_timesteps - 1));
+        }
+        if corr_coarse_chan_indices
+            .iter()
+            .any(|&c| c >= self.num_coarse_chans)
+        {
+            return Err(GpuboxError::InvalidCoarseChanIndex(
+                self.num_coarse_chans - 1,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check a buffer being filled with visibilities converted to `read_config.pol_products` is the right size.
+    fn validate_output_buffer_len(&self, buffer: &[f32]) -> Result<(), GpuboxError> {
+        let expected_len = self.num_output_timestep_coarse_chan_floats();
+
+        if buffer.len() != expected_len {
+            return Err(GpuboxError::InvalidBufferSize {
+                expected: expected_len,
+                got: buffer.len(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Read a single HDU of a gpubox file into a supplied buffer, always as linear pols
+    /// The output visibilities are in order:
+    /// baseline,frequency,pol,r,i
+    ///
+    /// # Arguments
+    ///
+    /// * `fits_filename` - filename of the gpubox file.
+    ///
+    /// * `hdu_index` - index of the HDU to read.
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled with data from the HDU read in [baseline][frequency][pol][r][i] order.
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    fn read_hdu_by_baseline_into_buffer(
+        &self,
+        fits_filename: &str,
+        hdu_index: usize,
+        buffer: &mut [f32],
+    ) -> Result<(), GpuboxError> {
         // If legacy correlator, then convert the HDU into the correct output format
         if self.mwa_version == MWAVersion::CorrOldLegacy
             || self.mwa_version == MWAVersion::CorrLegacy
@@ -592,7 +1076,7 @@ impl CorrelatorContext {
             ];
 
             // Read into temp buffer
-            get_fits_float_image_into_buffer!(&mut fptr, &hdu, &mut temp_buffer)?;
+            self.read_raw_hdu_into_buffer(fits_filename, hdu_index, &mut temp_buffer)?;
 
             convert::convert_legacy_hdu_to_mwax_baseline_order(
                 &self.legacy_conversion_table,
@@ -600,14 +1084,14 @@ impl CorrelatorContext {
                 buffer,
                 self.metafits_context.num_corr_fine_chans_per_coarse,
             );
-
-            Ok(())
         } else {
             // Read into caller's buffer
-            get_fits_float_image_into_buffer!(&mut fptr, &hdu, buffer)?;
-
-            Ok(())
+            self.read_raw_hdu_into_buffer(fits_filename, hdu_index, buffer)?;
         }
+
+        self.apply_read_config(buffer, false)?;
+
+        Ok(())
     }
 
     /// Read a single timestep for a single coarse channel into a supplied buffer
@@ -632,13 +1116,19 @@ impl CorrelatorContext {
         corr_coarse_chan_index: usize,
         buffer: &mut [f32],
     ) -> Result<(), GpuboxError> {
-        // Validate input timestep_index and coarse_chan_index and return the fits_filename, batch index and hdu of the corresponding data
-        let (fits_filename, _, hdu_index) =
-            self.get_fits_filename_and_batch_and_hdu(corr_timestep_index, corr_coarse_chan_index)?;
-
-        // Open the fits file
-        let mut fptr = fits_open!(&fits_filename)?;
-        let hdu = fits_open_hdu!(&mut fptr, hdu_index)?;
+        // Check the buffer before it might be filled in for missing data
+        self.validate_output_buffer_len(buffer)?;
+
+        // Validate input timestep_index and coarse_chan_index and return the fits_filename and hdu of the corresponding data
+        let (fits_filename, hdu_index) =
+            match self.get_hdu_or_fill(corr_timestep_index, corr_coarse_chan_index, buffer)? {
+                Some(h) => h,
+                None => return Ok(()),
+            };
+
+        if self.read_config.layout != VisibilityLayout::default() {
+            return self.read_hdu_into_layout_buffer(fits_filename, hdu_index, buffer, true);
+        }
 
         // Prepare temporary buffer
         let mut temp_buffer = vec![
@@ -650,7 +1140,19 @@ impl CorrelatorContext {
         ];
 
         // Read the hdu into our temp buffer
-        get_fits_float_image_into_buffer!(&mut fptr, &hdu, &mut temp_buffer)?;
+        self.read_raw_hdu_into_buffer(fits_filename, hdu_index, &mut temp_buffer)?;
+
+        // Reorder straight into the caller's buffer, unless we need to convert the pols afterwards
+        let pol_products = self.read_config.pol_products;
+        let mut linear_buffer: Vec<f32> = Vec::new();
+        let frequency_buffer: &mut [f32] = match pol_products {
+            PolProducts::Linear => &mut *buffer,
+            _ => {
+                self.validate_output_buffer_len(buffer)?;
+                linear_buffer.resize(self.num_timestep_coarse_chan_floats, 0.);
+                &mut linear_buffer
+            }
+        };
 
         // If legacy correlator, then convert the HDU into the correct output format
         if self.mwa_version == MWAVersion::CorrOldLegacy
@@ -659,23 +1161,278 @@ impl CorrelatorContext {
             convert::convert_legacy_hdu_to_mwax_frequency_order(
                 &self.legacy_conversion_table,
                 &temp_buffer,
-                buffer,
+                frequency_buffer,
                 self.metafits_context.num_corr_fine_chans_per_coarse,
             );
-
-            Ok(())
         } else {
             // Do conversion for mwax (it is in baseline order, we want it in freq order)
             convert::convert_mwax_hdu_to_frequency_order(
                 &temp_buffer,
-                buffer,
+                frequency_buffer,
                 self.metafits_context.num_baselines,
                 self.metafits_context.num_corr_fine_chans_per_coarse,
                 self.metafits_context.num_visibility_pols,
             );
+        }
+
+        self.apply_read_config(frequency_buffer, true)?;
 
-            Ok(())
+        if pol_products != PolProducts::Linear {
+            convert_linear_to_pol_products(&linear_buffer, buffer, pol_products);
         }
+
+        Ok(())
+    }
+
+    /// Read a single HDU of a gpubox file into a supplied buffer in the layout given by `read_config.layout`,
+    /// converting it to `read_config.pol_products`. The reordering, conjugation, removal of autos and pol conversion
+    /// is done in the same pass as the legacy conversion or the MWAX frequency reordering, so there is no extra copy,
+    /// except for legacy data with the Van Vleck correction, which needs the visibilities in MWAX order to be
+    /// corrected.
+    ///
+    /// # Arguments
+    ///
+    /// * `fits_filename` - filename of the gpubox file.
+    ///
+    /// * `hdu_index` - index of the HDU to read.
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled with the visibilities in the output layout.
+    ///
+    /// * `by_frequency` - true to output frequency before baseline, false for baseline before frequency.
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    fn read_hdu_into_layout_buffer(
+        &self,
+        fits_filename: &str,
+        hdu_index: usize,
+        buffer: &mut [f32],
+        by_frequency: bool,
+    ) -> Result<(), GpuboxError> {
+        let layout = self.read_config.layout;
+        let pol_products = self.read_config.pol_products;
+
+        if layout.pol_major && pol_products != PolProducts::Linear {
+            return Err(GpuboxError::UnsupportedVisibilityLayout(format!(
+                "pol-major output is only supported for linear pols, not {}",
+                pol_products
+            )));
+        }
+
+        self.validate_output_buffer_len(buffer)?;
+
+        let num_fine_chans = self.metafits_context.num_corr_fine_chans_per_coarse;
+        let num_floats_per_fine_chan = self.metafits_context.num_visibility_pols * 2;
+
+        let mut temp_buffer =
+            vec![
+                0.;
+                num_fine_chans * num_floats_per_fine_chan * self.metafits_context.num_baselines
+            ];
+        self.read_raw_hdu_into_buffer(fits_filename, hdu_index, &mut temp_buffer)?;
+
+        let is_legacy = self.mwa_version == MWAVersion::CorrOldLegacy
+            || self.mwa_version == MWAVersion::CorrLegacy;
+
+        // The Van Vleck correction works on MWAX ordered visibilities, so legacy data must be converted first
+        let (mut source_buffer, legacy_conversion_table, input_fine_chan_stride) =
+            if is_legacy && !self.read_config.apply_van_vleck_correction {
+                (
+                    temp_buffer,
+                    Some(self.legacy_conversion_table.as_slice()),
+                    num_floats_per_fine_chan * self.metafits_context.num_baselines,
+                )
+            } else {
+                let source_buffer = if is_legacy {
+                    let mut mwax_buffer = vec![0.; temp_buffer.len()];
+                    convert::convert_legacy_hdu_to_mwax_baseline_order(
+                        &self.legacy_conversion_table,
+                        &temp_buffer,
+                        &mut mwax_buffer,
+                        num_fine_chans,
+                    );
+                    mwax_buffer
+                } else {
+                    temp_buffer
+                };
+                (source_buffer, None, num_floats_per_fine_chan)
+            };
+        self.apply_read_config(&mut source_buffer, false)?;
+
+        let conversion_table = self.output_conversion_table.get(
+            &layout,
+            &self.metafits_context.baselines,
+            legacy_conversion_table,
+            num_fine_chans,
+        );
+
+        convert::convert_hdu_to_layout(
+            &conversion_table,
+            &source_buffer,
+            buffer,
+            num_fine_chans,
+            input_fine_chan_stride,
+            by_frequency,
+            layout.pol_major,
+            pol_products,
+        );
+
+        Ok(())
+    }
+
+    /// Returns whether any gpubox file provided data for a timestep and coarse channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * true if there is data for the timestep and coarse channel.
+    ///
+    pub(crate) fn has_data(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+    ) -> bool {
+        self.gpubox_time_map
+            .get(&self.timesteps[corr_timestep_index].unix_time_ms)
+            .map_or(false, |c| {
+                c.contains_key(&self.coarse_chans[corr_coarse_chan_index].gpubox_number)
+            })
+    }
+
+    /// Check `read_config.layout` is the default MWAX layout, for read functions which only support that.
+    pub(crate) fn validate_default_layout(&self) -> Result<(), GpuboxError> {
+        if self.read_config.layout != VisibilityLayout::default() {
+            return Err(GpuboxError::UnsupportedVisibilityLayout(format!(
+                "this read function only supports the default MWAX layout, not {:?}",
+                self.read_config.layout
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Read the image of a single HDU of a gpubox file, exactly as it is stored (i.e. with no
+    /// conversion of legacy data) into a supplied buffer.
+    ///
+    /// If `read_config.direct_read` is set and the HDU is an MWAX plain float image (see
+    /// `get_gpubox_data_offset`) of exactly `buffer.len()` floats, the data is read straight from
+    /// the file. Otherwise, or if that fails, it is read via cfitsio.
+    ///
+    /// # Arguments
+    ///
+    /// * `fits_filename` - filename of the gpubox file.
+    ///
+    /// * `hdu_index` - index of the HDU to read.
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled with the HDU's image.
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    pub(crate) fn read_raw_hdu_into_buffer(
+        &self,
+        fits_filename: &str,
+        hdu_index: usize,
+        buffer: &mut [f32],
+    ) -> Result<(), GpuboxError> {
+        if self.read_config.direct_read && self.mwa_version == MWAVersion::CorrMWAXv2 {
+            if let Some((offset, num_floats)) =
+                self.get_gpubox_data_offset(fits_filename, hdu_index)?
+            {
+                // Only the image itself may be read directly, never past it into the next HDU
+                let result = if buffer.len() == num_floats {
+                    self.direct_read_file_pool
+                        .get(fits_filename, self.read_config.max_open_files)
+                        .and_then(|file| file.read_be_f32_at(offset, buffer))
+                } else {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "buffer is {} floats but the image is {} floats",
+                            buffer.len(),
+                            num_floats
+                        ),
+                    ))
+                };
+
+                match result {
+                    Ok(_) => return Ok(()),
+                    Err(e) => warn!(
+                        "Direct read of {} HDU {} failed ({}); falling back to cfitsio",
+                        fits_filename,
+                        hdu_index + 1,
+                        e
+                    ),
+                }
+            }
+        }
+
+        // Get the (possibly already open) fits file
+        let fits_handle = self.get_gpubox_fits_handle(fits_filename)?;
+        let mut fptr = lock_fits_handle(&fits_handle);
+        let hdu = fits_open_hdu!(&mut fptr, hdu_index)?;
+
+        get_fits_float_image_into_buffer!(&mut fptr, &hdu, buffer)?;
+
+        Ok(())
+    }
+
+    /// Applies the processing requested in `self.read_config` to a buffer of visibilities
+    /// which has just been read (and converted into MWAX order, if legacy).
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Float buffer as a slice containing one timestep and coarse channel of visibilities.
+    ///
+    /// * `by_frequency` - true if `buffer` is in [frequency][baseline][pol][r][i] order, false if it is in
+    ///   [baseline][frequency][pol][r][i] order.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success, or a GpuboxError if the read config can't be applied to this
+    ///   observation's data.
+    ///
+    fn apply_read_config(&self, buffer: &mut [f32], by_frequency: bool) -> Result<(), GpuboxError> {
+        // Van Vleck correction must happen on the raw correlator output
+        if self.read_config.apply_van_vleck_correction {
+            // Only the legacy correlator input quantiser is modelled
+            if self.mwa_version == MWAVersion::CorrMWAXv2 {
+                return Err(GpuboxError::VanVleckCorrectionNotSupported {
+                    mwa_version: self.mwa_version,
+                });
+            }
+
+            let num_samples = self.metafits_context.corr_fine_chan_width_hz as f64
+                * self.metafits_context.corr_int_time_ms as f64
+                / 1000.0;
+
+            van_vleck::apply_van_vleck_correction(
+                buffer,
+                &self.metafits_context.baselines,
+                self.metafits_context.num_ants,
+                self.metafits_context.num_corr_fine_chans_per_coarse,
+                by_frequency,
+                num_samples,
+                van_vleck::MWA_LEGACY_VAN_VLECK_MAX_LEVEL,
+            );
+        }
+
+        // RAWSCALE only applies to MWAX visibilities
+        if self.read_config.apply_raw_scale_factor && self.mwa_version == MWAVersion::CorrMWAXv2 {
+            read_config::apply_scale_factor(buffer, self.metafits_context.corr_raw_scale_factor);
+        }
+
+        Ok(())
     }
 
     /// Validates the first HDU of a gpubox file against metafits metadata
@@ -0,0 +1,196 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Typed visibility reads into `ndarray` arrays of `num_complex::Complex<f32>`.
+//! Only available with the `ndarray` feature.
+
+use ndarray::{Array3, ArrayViewMut3};
+use num_complex::Complex;
+
+use super::*;
+
+impl CorrelatorContext {
+    /// Returns the shape of a 3D array of visibilities for one timestep and coarse channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `by_frequency` - true for [frequency][baseline][pol], false for [baseline][frequency][pol].
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The shape of the array.
+    ///
+    fn get_visibility_array_shape(&self, by_frequency: bool) -> [usize; 3] {
+        let num_baselines = self.metafits_context.num_baselines;
+        let num_fine_chans = self.metafits_context.num_corr_fine_chans_per_coarse;
+        let num_pols = self.num_output_pols();
+
+        if by_frequency {
+            [num_fine_chans, num_baselines, num_pols]
+        } else {
+            [num_baselines, num_fine_chans, num_pols]
+        }
+    }
+
+    /// Read a single timestep for a single coarse channel into a new array of complex visibilities
+    /// with shape [baseline][frequency][pol].
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array for the desired coarse channel.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing an `Array3<Complex<f32>>` in [baseline][frequency][pol] order, if Ok.
+    ///
+    pub fn read_by_baseline_array(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+    ) -> Result<Array3<Complex<f32>>, GpuboxError> {
+        let mut array = Array3::zeros(self.get_visibility_array_shape(false));
+
+        self.read_by_baseline_into_array(
+            corr_timestep_index,
+            corr_coarse_chan_index,
+            array.view_mut(),
+        )?;
+
+        Ok(array)
+    }
+
+    /// Read a single timestep for a single coarse channel into a new array of complex visibilities
+    /// with shape [frequency][baseline][pol].
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array for the desired coarse channel.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing an `Array3<Complex<f32>>` in [frequency][baseline][pol] order, if Ok.
+    ///
+    pub fn read_by_frequency_array(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+    ) -> Result<Array3<Complex<f32>>, GpuboxError> {
+        let mut array = Array3::zeros(self.get_visibility_array_shape(true));
+
+        self.read_by_frequency_into_array(
+            corr_timestep_index,
+            corr_coarse_chan_index,
+            array.view_mut(),
+        )?;
+
+        Ok(array)
+    }
+
+    /// Read a single timestep for a single coarse channel into an existing array of complex
+    /// visibilities with shape [baseline][frequency][pol].
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array for the desired coarse channel.
+    ///
+    /// * `array` - mutable view of an array with shape [baseline][frequency][pol] to fill. If it is not
+    ///   in standard (C) layout, the data is read via a temporary buffer.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    pub fn read_by_baseline_into_array(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+        array: ArrayViewMut3<Complex<f32>>,
+    ) -> Result<(), GpuboxError> {
+        self.read_into_array(array, false, |buffer| {
+            self.read_by_baseline_into_buffer(corr_timestep_index, corr_coarse_chan_index, buffer)
+        })
+    }
+
+    /// Read a single timestep for a single coarse channel into an existing array of complex
+    /// visibilities with shape [frequency][baseline][pol].
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array for the desired coarse channel.
+    ///
+    /// * `array` - mutable view of an array with shape [frequency][baseline][pol] to fill. If it is not
+    ///   in standard (C) layout, the data is read via a temporary buffer.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    pub fn read_by_frequency_into_array(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+        array: ArrayViewMut3<Complex<f32>>,
+    ) -> Result<(), GpuboxError> {
+        self.read_into_array(array, true, |buffer| {
+            self.read_by_frequency_into_buffer(corr_timestep_index, corr_coarse_chan_index, buffer)
+        })
+    }
+
+    /// Validate the shape of `array` and fill it using `read_fn`, which reads interleaved
+    /// real/imaginary floats into a slice.
+    fn read_into_array<F>(
+        &self,
+        mut array: ArrayViewMut3<Complex<f32>>,
+        by_frequency: bool,
+        read_fn: F,
+    ) -> Result<(), GpuboxError>
+    where
+        F: FnOnce(&mut [f32]) -> Result<(), GpuboxError>,
+    {
+        self.validate_default_layout()?;
+
+        let expected_shape = self.get_visibility_array_shape(by_frequency);
+        if array.shape() != expected_shape {
+            return Err(GpuboxError::InvalidBufferShape {
+                expected: expected_shape.to_vec(),
+                got: array.shape().to_vec(),
+            });
+        }
+
+        match array.as_slice_mut() {
+            Some(slice) => {
+                // `Complex<T>` is `repr(C)` (re, im), so a contiguous slice of them can be
+                // read into directly as interleaved floats.
+                let floats = unsafe {
+                    std::slice::from_raw_parts_mut(slice.as_mut_ptr() as *mut f32, slice.len() * 2)
+                };
+                read_fn(floats)
+            }
+            None => {
+                let mut buffer: Vec<f32> = vec![0.; self.num_output_timestep_coarse_chan_floats()];
+                read_fn(&mut buffer)?;
+
+                array
+                    .iter_mut()
+                    .zip(buffer.chunks_exact(2))
+                    .for_each(|(vis, ri)| *vis = Complex::new(ri[0], ri[1]));
+
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Options which control how visibilities are processed when read via a `CorrelatorContext`.
+
+use crate::convert::VisibilityLayout;
+use crate::stokes::PolProducts;
+
+/// What the `CorrelatorContext` read functions do when there is no data for a timestep and coarse channel.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingDataPolicy {
+    /// Return `GpuboxError::NoDataForTimeStepCoarseChannel`
+    Error = 0,
+    /// Fill the output with zeros
+    FillZeros = 1,
+    /// Fill the output with NaNs
+    FillNaN = 2,
+}
+
+impl MissingDataPolicy {
+    /// Returns the value to fill missing data with, or None if missing data is an error.
+    pub(crate) fn fill_value(&self) -> Option<f32> {
+        match self {
+            MissingDataPolicy::Error => None,
+            MissingDataPolicy::FillZeros => Some(0.),
+            MissingDataPolicy::FillNaN => Some(f32::NAN),
+        }
+    }
+}
+
+impl Default for MissingDataPolicy {
+    fn default() -> Self {
+        MissingDataPolicy::Error
+    }
+}
+
+///
+/// Options which control how `CorrelatorContext` read functions read visibilities. Any
+/// processing is applied after the HDU has been read (and, for legacy data, converted into
+/// MWAX order).
+///
+/// The default is to apply no processing at all, i.e. visibilities are returned exactly as
+/// they were in the gpubox files (plus the legacy reordering).
+///
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CorrelatorReadConfig {
+    /// If true, MWAX visibilities are multiplied by the metafits `RAWSCALE` value
+    /// (`MetafitsContext::corr_raw_scale_factor`). Legacy visibilities are never scaled, as
+    /// `RAWSCALE` only describes the MWAX correlator output (legacy metafits files generally
+    /// do not have the key, in which case mwalib defaults it to 1.0).
+    pub apply_raw_scale_factor: bool,
+    /// If true, apply a Van Vleck correction for the quantisation of the correlator input to both
+    /// the auto and cross-correlations. See the `van_vleck` module for the details. Only legacy
+    /// correlator data can be corrected; reading MWAX data with this set is an error
+    /// (`GpuboxError::VanVleckCorrectionNotSupported`).
+    pub apply_van_vleck_correction: bool,
+    /// Number of threads used to read gpubox files in parallel in `CorrelatorContext::read_cube`.
+    /// 0 (the default) means use rayon's global thread pool.
+    pub num_threads: usize,
+    /// Maximum number of gpubox files the `CorrelatorContext` keeps open between reads. 0 (the default)
+    /// means no limit.
+    pub max_open_files: usize,
+    /// If true, MWAX HDUs which are plain (uncompressed, unscaled) float images are read straight from
+    /// the gpubox file, bypassing cfitsio: on unix the file is memory mapped and the floats are byte
+    /// swapped (with SIMD where available) straight into the caller's buffer. Anything else, or any
+    /// failure of the direct read, falls back to reading via cfitsio.
+    pub direct_read: bool,
+    /// The polarisation products visibilities are returned as. The default is the linear pols produced by the
+    /// correlator; see `CorrelatorContext::num_output_pols` for the number of pols this gives. This is applied
+    /// last, after all other processing.
+    pub pol_products: PolProducts,
+    /// What to do when there is no data for a timestep and coarse channel. The default is to return an error.
+    /// If the missing data is filled in instead, `CorrelatorContext::read_cube_with_flags` reports which
+    /// timesteps and coarse channels were filled.
+    pub missing_data_policy: MissingDataPolicy,
+    /// The order, conjugation and selection of baselines, and the position of the pol axis, of the visibilities
+    /// returned by `read_by_baseline`, `read_by_frequency` and `read_cube` (and their variants). The default is the
+    /// MWAX layout. The subset and averaging read functions always return the MWAX layout, and the full band,
+    /// ndarray and `VisibilityView` functions return an error for any other layout.
+    pub layout: VisibilityLayout,
+}
+
+impl CorrelatorReadConfig {
+    /// Creates a new `CorrelatorReadConfig` with all processing disabled.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A new `CorrelatorReadConfig`
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enables or disables applying the `RAWSCALE` scale factor to MWAX visibilities.
+    ///
+    /// # Arguments
+    ///
+    /// * `apply_raw_scale_factor` - true to multiply MWAX visibilities by `corr_raw_scale_factor`.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorReadConfig`
+    ///
+    pub fn with_raw_scale_factor(mut self, apply_raw_scale_factor: bool) -> Self {
+        self.apply_raw_scale_factor = apply_raw_scale_factor;
+        self
+    }
+
+    /// Enables or disables the Van Vleck correction of auto and cross-correlations.
+    ///
+    /// # Arguments
+    ///
+    /// * `apply_van_vleck_correction` - true to correct visibilities for the quantisation of the correlator input.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorReadConfig`
+    ///
+    pub fn with_van_vleck_correction(mut self, apply_van_vleck_correction: bool) -> Self {
+        self.apply_van_vleck_correction = apply_van_vleck_correction;
+        self
+    }
+
+    /// Sets the number of threads used by `CorrelatorContext::read_cube`.
+    ///
+    /// # Arguments
+    ///
+    /// * `num_threads` - number of threads to read with, or 0 to use rayon's global thread pool.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorReadConfig`
+    ///
+    pub fn with_num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// Sets the maximum number of gpubox files kept open between reads.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_open_files` - maximum number of open gpubox files, or 0 for no limit.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorReadConfig`
+    ///
+    pub fn with_max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = max_open_files;
+        self
+    }
+
+    /// Enables or disables reading MWAX HDUs directly from the gpubox files, bypassing cfitsio.
+    ///
+    /// # Arguments
+    ///
+    /// * `direct_read` - true to read plain float image HDUs directly from the file.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorReadConfig`
+    ///
+    pub fn with_direct_read(mut self, direct_read: bool) -> Self {
+        self.direct_read = direct_read;
+        self
+    }
+
+    /// Sets the polarisation products visibilities are returned as.
+    ///
+    /// # Arguments
+    ///
+    /// * `pol_products` - linear pols, Stokes I, Q, U, V or pseudo-Stokes I only.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorReadConfig`
+    ///
+    pub fn with_pol_products(mut self, pol_products: PolProducts) -> Self {
+        self.pol_products = pol_products;
+        self
+    }
+
+    /// Sets what to do when there is no data for a timestep and coarse channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `missing_data_policy` - return an error, or fill the output with zeros or NaNs.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorReadConfig`
+    ///
+    pub fn with_missing_data_policy(mut self, missing_data_policy: MissingDataPolicy) -> Self {
+        self.missing_data_policy = missing_data_policy;
+        self
+    }
+
+    /// Sets the layout of the visibilities returned by the read functions.
+    ///
+    /// # Arguments
+    ///
+    /// * `layout` - the baseline order, conjugation convention, whether to exclude autos and whether pol is the
+    ///   slowest varying axis.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `CorrelatorReadConfig`
+    ///
+    pub fn with_layout(mut self, layout: VisibilityLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+}
+
+/// Multiply every float (real and imaginary parts alike) in `buffer` by `scale_factor`.
+///
+/// # Arguments
+///
+/// * `buffer` - slice of visibilities (any ordering) to scale in place.
+///
+/// * `scale_factor` - value to multiply each float by.
+///
+///
+/// # Returns
+///
+/// * Nothing
+///
+pub(crate) fn apply_scale_factor(buffer: &mut [f32], scale_factor: f32) {
+    // Avoid touching the data if it would make no difference
+    if scale_factor == 1.0 {
+        return;
+    }
+
+    buffer.iter_mut().for_each(|v| *v *= scale_factor);
+}
@@ -0,0 +1,521 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Reads of a subset of the baselines and/or fine channels of a single timestep and coarse channel.
+//!
+//! Rather than reading the whole HDU, only the rectangular regions of the image which contain the
+//! requested data are read (via cfitsio's `fits_read_subset`).
+
+use super::*;
+
+/// Splits a list of indices into runs of consecutive, increasing values.
+///
+/// # Arguments
+///
+/// * `indices` - slice of indices, in any order.
+///
+///
+/// # Returns
+///
+/// * A vector of (position of the first index of the run within `indices`, first index of the run, length of the run).
+///
+pub(crate) fn get_contiguous_runs(indices: &[usize]) -> Vec<(usize, usize, usize)> {
+    let mut runs: Vec<(usize, usize, usize)> = Vec::new();
+
+    for (position, &index) in indices.iter().enumerate() {
+        match runs.last_mut() {
+            Some((_, start, length)) if *start + *length == index => *length += 1,
+            _ => runs.push((position, index, 1)),
+        }
+    }
+
+    runs
+}
+
+impl CorrelatorContext {
+    /// Read a subset of the baselines and fine channels of a single timestep for a single coarse channel.
+    /// The output visibilities are in order:
+    /// baseline,frequency,pol,r,i
+    /// where baseline and frequency are in the order given by `baseline_indices` and `fine_chan_indices`.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array for the desired coarse channel.
+    ///
+    /// * `baseline_indices` - indices within the metafits baseline array of the desired baselines.
+    ///
+    /// * `fine_chan_indices` - indices (within the coarse channel) of the desired fine channels.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing vector of 32 bit floats containing the data in [baseline][frequency][pol][r][i] order, if Ok.
+    ///
+    pub fn read_by_baseline_subset(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+        baseline_indices: &[usize],
+        fine_chan_indices: &[usize],
+    ) -> Result<Vec<f32>, GpuboxError> {
+        let mut buffer: Vec<f32> =
+            vec![0.; baseline_indices.len() * fine_chan_indices.len() * self.num_output_pols() * 2];
+
+        self.read_subset_into_buffer(
+            corr_timestep_index,
+            corr_coarse_chan_index,
+            baseline_indices,
+            fine_chan_indices,
+            &mut buffer,
+            false,
+        )?;
+
+        Ok(buffer)
+    }
+
+    /// Read a subset of the baselines and fine channels of a single timestep for a single coarse channel.
+    /// The output visibilities are in order:
+    /// frequency,baseline,pol,r,i
+    /// where baseline and frequency are in the order given by `baseline_indices` and `fine_chan_indices`.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array for the desired coarse channel.
+    ///
+    /// * `baseline_indices` - indices within the metafits baseline array of the desired baselines.
+    ///
+    /// * `fine_chan_indices` - indices (within the coarse channel) of the desired fine channels.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing vector of 32 bit floats containing the data in [frequency][baseline][pol][r][i] order, if Ok.
+    ///
+    pub fn read_by_frequency_subset(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+        baseline_indices: &[usize],
+        fine_chan_indices: &[usize],
+    ) -> Result<Vec<f32>, GpuboxError> {
+        let mut buffer: Vec<f32> =
+            vec![0.; baseline_indices.len() * fine_chan_indices.len() * self.num_output_pols() * 2];
+
+        self.read_subset_into_buffer(
+            corr_timestep_index,
+            corr_coarse_chan_index,
+            baseline_indices,
+            fine_chan_indices,
+            &mut buffer,
+            true,
+        )?;
+
+        Ok(buffer)
+    }
+
+    /// Read a subset of the baselines and fine channels of a single timestep for a single coarse channel
+    /// into a supplied buffer.
+    /// The output visibilities are in order:
+    /// baseline,frequency,pol,r,i
+    /// where baseline and frequency are in the order given by `baseline_indices` and `fine_chan_indices`.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array for the desired coarse channel.
+    ///
+    /// * `baseline_indices` - indices within the metafits baseline array of the desired baselines.
+    ///
+    /// * `fine_chan_indices` - indices (within the coarse channel) of the desired fine channels.
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled in [baseline][frequency][pol][r][i] order. Must be
+    ///   exactly baselines * fine chans * pols * 2 floats long.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    pub fn read_by_baseline_subset_into_buffer(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+        baseline_indices: &[usize],
+        fine_chan_indices: &[usize],
+        buffer: &mut [f32],
+    ) -> Result<(), GpuboxError> {
+        self.read_subset_into_buffer(
+            corr_timestep_index,
+            corr_coarse_chan_index,
+            baseline_indices,
+            fine_chan_indices,
+            buffer,
+            false,
+        )
+    }
+
+    /// Read a subset of the baselines and fine channels of a single timestep for a single coarse channel
+    /// into a supplied buffer.
+    /// The output visibilities are in order:
+    /// frequency,baseline,pol,r,i
+    /// where baseline and frequency are in the order given by `baseline_indices` and `fine_chan_indices`.
+    ///
+    /// # Arguments
+    ///
+    /// * `corr_timestep_index` - index within the CorrelatorContext timestep array for the desired timestep.
+    ///
+    /// * `corr_coarse_chan_index` - index within the CorrelatorContext coarse_chan array for the desired coarse channel.
+    ///
+    /// * `baseline_indices` - indices within the metafits baseline array of the desired baselines.
+    ///
+    /// * `fine_chan_indices` - indices (within the coarse channel) of the desired fine channels.
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled in [frequency][baseline][pol][r][i] order. Must be
+    ///   exactly baselines * fine chans * pols * 2 floats long.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a GpuboxError on failure.
+    ///
+    pub fn read_by_frequency_subset_into_buffer(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+        baseline_indices: &[usize],
+        fine_chan_indices: &[usize],
+        buffer: &mut [f32],
+    ) -> Result<(), GpuboxError> {
+        self.read_subset_into_buffer(
+            corr_timestep_index,
+            corr_coarse_chan_index,
+            baseline_indices,
+            fine_chan_indices,
+            buffer,
+            true,
+        )
+    }
+
+    /// Validates the inputs, reads the subset and applies `self.read_config`, including the conversion to `pol_products`.
+    fn read_subset_into_buffer(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+        baseline_indices: &[usize],
+        fine_chan_indices: &[usize],
+        buffer: &mut [f32],
+        by_frequency: bool,
+    ) -> Result<(), GpuboxError> {
+        let num_baselines = self.metafits_context.num_baselines;
+        let num_fine_chans = self.metafits_context.num_corr_fine_chans_per_coarse;
+
+        if baseline_indices.iter().any(|&b| b >= num_baselines) {
+            return Err(GpuboxError::InvalidBaselineIndex(num_baselines - 1));
+        }
+        if fine_chan_indices.iter().any(|&f| f >= num_fine_chans) {
+            return Err(GpuboxError::InvalidFineChanIndex(num_fine_chans - 1));
+        }
+
+        let num_vis = baseline_indices.len() * fine_chan_indices.len();
+        let expected_len = num_vis * self.num_output_pols() * 2;
+        if buffer.len() != expected_len {
+            return Err(GpuboxError::InvalidBufferSize {
+                expected: expected_len,
+                got: buffer.len(),
+            });
+        }
+
+        if self
+            .get_hdu_or_fill(corr_timestep_index, corr_coarse_chan_index, buffer)?
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        match self.read_config.pol_products {
+            PolProducts::Linear => self.read_linear_subset_into_buffer(
+                corr_timestep_index,
+                corr_coarse_chan_index,
+                baseline_indices,
+                fine_chan_indices,
+                buffer,
+                by_frequency,
+            ),
+            pol_products => {
+                let mut linear_buffer: Vec<f32> =
+                    vec![0.; num_vis * self.metafits_context.num_visibility_pols * 2];
+                self.read_linear_subset_into_buffer(
+                    corr_timestep_index,
+                    corr_coarse_chan_index,
+                    baseline_indices,
+                    fine_chan_indices,
+                    &mut linear_buffer,
+                    by_frequency,
+                )?;
+                convert_linear_to_pol_products(&linear_buffer, buffer, pol_products);
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads the subset as linear pols and applies the rest of `self.read_config`. The inputs must
+    /// already have been validated.
+    fn read_linear_subset_into_buffer(
+        &self,
+        corr_timestep_index: usize,
+        corr_coarse_chan_index: usize,
+        baseline_indices: &[usize],
+        fine_chan_indices: &[usize],
+        buffer: &mut [f32],
+        by_frequency: bool,
+    ) -> Result<(), GpuboxError> {
+        let num_fine_chans = self.metafits_context.num_corr_fine_chans_per_coarse;
+        let floats_per_vis = self.metafits_context.num_visibility_pols * 2;
+
+        // Validate input timestep_index and coarse_chan_index and return the fits_filename, batch index and hdu of the corresponding data
+        let (fits_filename, _, hdu_index) =
+            self.get_fits_filename_and_batch_and_hdu(corr_timestep_index, corr_coarse_chan_index)?;
+
+        // The Van Vleck correction needs the autocorrelations of every antenna, so in that case
+        // fall back to reading (and correcting) the whole HDU.
+        if self.read_config.apply_van_vleck_correction {
+            let mut full_buffer: Vec<f32> = vec![0.; self.num_timestep_coarse_chan_floats];
+            self.read_hdu_by_baseline_into_buffer(fits_filename, hdu_index, &mut full_buffer)?;
+
+            for (b, &baseline_index) in baseline_indices.iter().enumerate() {
+                for (f, &fine_chan_index) in fine_chan_indices.iter().enumerate() {
+                    let source_index =
+                        (baseline_index * num_fine_chans + fine_chan_index) * floats_per_vis;
+                    let destination_index = self.get_subset_index(
+                        b,
+                        f,
+                        baseline_indices.len(),
+                        fine_chan_indices.len(),
+                        by_frequency,
+                    );
+                    buffer[destination_index..destination_index + floats_per_vis]
+                        .copy_from_slice(&full_buffer[source_index..source_index + floats_per_vis]);
+                }
+            }
+
+            return Ok(());
+        }
+
+        // Get the (possibly already open) fits file
+        let fits_handle = self.get_gpubox_fits_handle(fits_filename)?;
+        let mut fptr = lock_fits_handle(&fits_handle);
+        let hdu = fits_open_hdu!(&mut fptr, hdu_index)?;
+
+        if self.mwa_version == MWAVersion::CorrOldLegacy
+            || self.mwa_version == MWAVersion::CorrLegacy
+        {
+            self.read_legacy_subset(
+                &mut fptr,
+                &hdu,
+                baseline_indices,
+                fine_chan_indices,
+                buffer,
+                by_frequency,
+            )?;
+        } else {
+            self.read_mwax_subset(
+                &mut fptr,
+                &hdu,
+                baseline_indices,
+                fine_chan_indices,
+                buffer,
+                by_frequency,
+            )?;
+        }
+
+        // RAWSCALE only applies to MWAX visibilities
+        if self.read_config.apply_raw_scale_factor && self.mwa_version == MWAVersion::CorrMWAXv2 {
+            read_config::apply_scale_factor(buffer, self.metafits_context.corr_raw_scale_factor);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the index of the first float of a visibility in a subset output buffer.
+    fn get_subset_index(
+        &self,
+        baseline_position: usize,
+        fine_chan_position: usize,
+        num_baselines: usize,
+        num_fine_chans: usize,
+        by_frequency: bool,
+    ) -> usize {
+        let visibility_index = if by_frequency {
+            fine_chan_position * num_baselines + baseline_position
+        } else {
+            baseline_position * num_fine_chans + fine_chan_position
+        };
+
+        visibility_index * self.metafits_context.num_visibility_pols * 2
+    }
+
+    /// Reads a subset of an MWAX HDU, which is in [baseline][fine_chan][pol][r][i] order, one
+    /// rectangle of consecutive baselines and fine channels at a time.
+    fn read_mwax_subset(
+        &self,
+        fptr: &mut fitsio::FitsFile,
+        hdu: &fitsio::hdu::FitsHdu,
+        baseline_indices: &[usize],
+        fine_chan_indices: &[usize],
+        buffer: &mut [f32],
+        by_frequency: bool,
+    ) -> Result<(), GpuboxError> {
+        let floats_per_vis = self.metafits_context.num_visibility_pols * 2;
+        let baseline_runs = get_contiguous_runs(baseline_indices);
+        let fine_chan_runs = get_contiguous_runs(fine_chan_indices);
+        let mut temp_buffer: Vec<f32> = Vec::new();
+
+        for &(baseline_position, baseline_start, num_run_baselines) in baseline_runs.iter() {
+            for &(fine_chan_position, fine_chan_start, num_run_fine_chans) in fine_chan_runs.iter()
+            {
+                temp_buffer.resize(num_run_baselines * num_run_fine_chans * floats_per_vis, 0.);
+
+                get_fits_float_image_subset_into_buffer!(
+                    fptr,
+                    hdu,
+                    &[
+                        baseline_start..baseline_start + num_run_baselines,
+                        fine_chan_start * floats_per_vis
+                            ..(fine_chan_start + num_run_fine_chans) * floats_per_vis,
+                    ],
+                    &mut temp_buffer
+                )?;
+
+                for (b, source) in temp_buffer
+                    .chunks_exact(num_run_fine_chans * floats_per_vis)
+                    .enumerate()
+                {
+                    for (f, vis) in source.chunks_exact(floats_per_vis).enumerate() {
+                        let destination_index = self.get_subset_index(
+                            baseline_position + b,
+                            fine_chan_position + f,
+                            baseline_indices.len(),
+                            fine_chan_indices.len(),
+                            by_frequency,
+                        );
+                        buffer[destination_index..destination_index + floats_per_vis]
+                            .copy_from_slice(vis);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a subset of a legacy HDU, which is in [fine_chan][legacy baseline][pol][r][i] order.
+    /// The requested baselines are mapped through the legacy conversion table so that only the
+    /// complex values which are needed are read, and then reordered (and conjugated) as per
+    /// `convert_legacy_hdu_to_mwax_baseline_order`.
+    fn read_legacy_subset(
+        &self,
+        fptr: &mut fitsio::FitsFile,
+        hdu: &fitsio::hdu::FitsHdu,
+        baseline_indices: &[usize],
+        fine_chan_indices: &[usize],
+        buffer: &mut [f32],
+        by_frequency: bool,
+    ) -> Result<(), GpuboxError> {
+        // The (complex) columns of the legacy HDU we need, sorted and without duplicates
+        let mut source_columns: Vec<usize> = baseline_indices
+            .iter()
+            .flat_map(|&b| {
+                let baseline = &self.legacy_conversion_table[b];
+                [
+                    baseline.xx_index / 2,
+                    baseline.xy_index / 2,
+                    baseline.yx_index / 2,
+                    baseline.yy_index / 2,
+                ]
+            })
+            .collect();
+        source_columns.sort_unstable();
+        source_columns.dedup();
+
+        // Where each needed column ends up in our compacted temp buffer
+        let column_positions: BTreeMap<usize, usize> = source_columns
+            .iter()
+            .enumerate()
+            .map(|(position, &column)| (column, position))
+            .collect();
+        let column_runs = get_contiguous_runs(&source_columns);
+        let floats_per_row = source_columns.len() * 2;
+
+        let mut region_buffer: Vec<f32> = Vec::new();
+        let mut compact_buffer: Vec<f32> = Vec::new();
+
+        for &(fine_chan_position, fine_chan_start, num_run_fine_chans) in
+            get_contiguous_runs(fine_chan_indices).iter()
+        {
+            compact_buffer.resize(num_run_fine_chans * floats_per_row, 0.);
+
+            // Read each run of columns for this run of fine channels into the compacted buffer
+            for &(column_position, column_start, num_columns) in column_runs.iter() {
+                region_buffer.resize(num_run_fine_chans * num_columns * 2, 0.);
+
+                get_fits_float_image_subset_into_buffer!(
+                    fptr,
+                    hdu,
+                    &[
+                        fine_chan_start..fine_chan_start + num_run_fine_chans,
+                        column_start * 2..(column_start + num_columns) * 2,
+                    ],
+                    &mut region_buffer
+                )?;
+
+                for (row, source) in region_buffer.chunks_exact(num_columns * 2).enumerate() {
+                    let destination_index = row * floats_per_row + column_position * 2;
+                    compact_buffer[destination_index..destination_index + num_columns * 2]
+                        .copy_from_slice(source);
+                }
+            }
+
+            // Now reorder into the output buffer
+            for (f, row) in compact_buffer.chunks_exact(floats_per_row).enumerate() {
+                for (b, &baseline_index) in baseline_indices.iter().enumerate() {
+                    let baseline = &self.legacy_conversion_table[baseline_index];
+                    let destination_index = self.get_subset_index(
+                        b,
+                        fine_chan_position + f,
+                        baseline_indices.len(),
+                        fine_chan_indices.len(),
+                        by_frequency,
+                    );
+
+                    for (pol, (source_index, conjugate)) in [
+                        (baseline.xx_index, baseline.xx_conjugate),
+                        (baseline.xy_index, baseline.xy_conjugate),
+                        (baseline.yx_index, baseline.yx_conjugate),
+                        (baseline.yy_index, baseline.yy_conjugate),
+                    ]
+                    .iter()
+                    .enumerate()
+                    {
+                        let source_index = column_positions[&(source_index / 2)] * 2;
+                        buffer[destination_index + pol * 2] = row[source_index];
+                        buffer[destination_index + pol * 2 + 1] = if *conjugate {
+                            // We have to conjugate the visibility
+                            -row[source_index + 1]
+                        } else {
+                            row[source_index + 1]
+                        };
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
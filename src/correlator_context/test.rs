@@ -447,706 +447,1187 @@ fn test_mwax_read() {
 }
 
 #[test]
-fn test_validate_first_hdu() {
-    // Open the test mwax file
-    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
-    let filename = "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+fn test_mwax_read_with_raw_scale_factor() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    //
-    // Read the observation using mwalib
-    //
     // Open a context and load in a test metafits and gpubox file
-    let gpuboxfiles = vec![filename];
-    let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+    let gpuboxfiles = vec![mwax_filename];
+    let mut context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
         .expect("Failed to create CorrelatorContext");
 
-    let coarse_chan = context.coarse_chans[0].gpubox_number;
-    let (batch_index, _) =
-        context.gpubox_time_map[&context.timesteps[0].unix_time_ms][&coarse_chan];
-
-    let mut fptr =
-        fits_open!(&context.gpubox_batches[batch_index].gpubox_files[0].filename).unwrap();
-
-    let result_valid = CorrelatorContext::validate_first_hdu(
-        context.mwa_version,
-        context.metafits_context.num_corr_fine_chans_per_coarse,
-        context.metafits_context.num_baselines,
-        context.metafits_context.num_visibility_pols,
-        &mut fptr,
-    );
-
-    let result_invalid1 = CorrelatorContext::validate_first_hdu(
-        context.mwa_version,
-        context.metafits_context.num_corr_fine_chans_per_coarse + 1,
-        context.metafits_context.num_baselines,
-        context.metafits_context.num_visibility_pols,
-        &mut fptr,
-    );
+    // Default is to not scale
+    assert_eq!(context.read_config, CorrelatorReadConfig::default());
+    let unscaled_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    let unscaled_by_freq: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
 
-    let result_invalid2 = CorrelatorContext::validate_first_hdu(
-        context.mwa_version,
-        context.metafits_context.num_corr_fine_chans_per_coarse,
-        context.metafits_context.num_baselines + 1,
-        context.metafits_context.num_visibility_pols,
-        &mut fptr,
-    );
+    // This metafits has no RAWSCALE key (so the factor is 1.0), so use one which will make a difference
+    assert_eq!(context.metafits_context.corr_raw_scale_factor, 1.0);
+    context.metafits_context.corr_raw_scale_factor = 0.5;
 
-    let result_invalid3 = CorrelatorContext::validate_first_hdu(
-        context.mwa_version,
-        context.metafits_context.num_corr_fine_chans_per_coarse,
-        context.metafits_context.num_baselines,
-        context.metafits_context.num_visibility_pols + 1,
-        &mut fptr,
+    // Still not scaled until asked for
+    assert_eq!(
+        context.read_by_baseline(0, 10).expect("Error!"),
+        unscaled_by_bl
     );
 
-    // This is valid
-    assert!(result_valid.is_ok());
+    // Now turn on the scaling and read again
+    context.read_config = CorrelatorReadConfig::new().with_raw_scale_factor(true);
+    let scaled_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    let scaled_by_freq: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
 
-    assert!(result_invalid1.is_err());
+    assert_eq!(scaled_by_bl.len(), unscaled_by_bl.len());
+    assert_eq!(scaled_by_freq.len(), unscaled_by_freq.len());
+    assert!(unscaled_by_bl.iter().any(|v| *v != 0.0));
 
-    assert!(result_invalid2.is_err());
+    for (u, s) in unscaled_by_bl.iter().zip(scaled_by_bl.iter()) {
+        assert!(approx_eq!(f32, *u * 0.5, *s, F32Margin::default()));
+    }
 
-    assert!(result_invalid3.is_err());
+    for (u, s) in unscaled_by_freq.iter().zip(scaled_by_freq.iter()) {
+        assert!(approx_eq!(f32, *u * 0.5, *s, F32Margin::default()));
+    }
 }
 
 #[test]
-fn test_validate_hdu_axes_good() {
-    let metafits_fine_chans_per_coarse = 128;
-    let metafits_baselines = 8256;
-    let visibility_pols = 4;
-    let result_good1 = CorrelatorContext::validate_hdu_axes(
-        MWAVersion::CorrOldLegacy,
-        metafits_fine_chans_per_coarse,
-        metafits_baselines,
-        visibility_pols,
-        8256 * 4 * 2,
-        128,
-    );
-
-    assert!(result_good1.is_ok());
+fn test_legacy_read_with_raw_scale_factor_is_unscaled() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let gpubox_filename =
+        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
 
-    let result_good2 = CorrelatorContext::validate_hdu_axes(
-        MWAVersion::CorrLegacy,
-        metafits_fine_chans_per_coarse,
-        metafits_baselines,
-        visibility_pols,
-        8256 * 4 * 2,
-        128,
-    );
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![gpubox_filename];
+    let mut context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
 
-    assert!(result_good2.is_ok());
+    let unscaled_by_bl: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+    let unscaled_by_freq: Vec<f32> = context.read_by_frequency(0, 0).expect("Error!");
 
-    let result_good3 = CorrelatorContext::validate_hdu_axes(
-        MWAVersion::CorrMWAXv2,
-        metafits_fine_chans_per_coarse,
-        metafits_baselines,
-        visibility_pols,
-        128 * 4 * 2,
-        8256,
-    );
+    // Legacy data must never be scaled by RAWSCALE, even if asked to (and even if the factor is not 1.0)
+    context.metafits_context.corr_raw_scale_factor = 0.5;
+    context.read_config = CorrelatorReadConfig::new().with_raw_scale_factor(true);
+    let scaled_by_bl: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+    let scaled_by_freq: Vec<f32> = context.read_by_frequency(0, 0).expect("Error!");
 
-    assert!(result_good3.is_ok());
+    assert_eq!(unscaled_by_bl, scaled_by_bl);
+    assert_eq!(unscaled_by_freq, scaled_by_freq);
 }
 
 #[test]
-fn test_validate_hdu_axes_naxis_mismatches_oldlegacy() {
-    let metafits_fine_chans_per_coarse = 128;
-    let metafits_baselines = 8256;
-    let visibility_pols = 4;
-    let values = 1;
+fn test_legacy_read_with_van_vleck_correction() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let gpubox_filename =
+        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
 
-    // Check for NAXIS1 mismatch
-    let result_bad1 = CorrelatorContext::validate_hdu_axes(
-        MWAVersion::CorrOldLegacy,
-        metafits_fine_chans_per_coarse,
-        metafits_baselines,
-        visibility_pols,
-        metafits_baselines * visibility_pols * values,
-        128,
-    );
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![gpubox_filename];
+    let mut context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
 
-    assert!(matches!(
-        result_bad1.unwrap_err(),
-        GpuboxError::LegacyNaxis1Mismatch {
-            metafits_baselines: _,
-            visibility_pols: _,
-            naxis1: _,
-            naxis2: _,
-            calculated_naxis1: _
+    let raw_by_bl: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+
+    context.read_config = CorrelatorReadConfig::new().with_van_vleck_correction(true);
+    let corrected_by_bl: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+    let corrected_by_freq: Vec<f32> = context.read_by_frequency(0, 0).expect("Error!");
+
+    // Check the autocorrelations (xx_r and yy_r) which are well within the range of the quantiser
+    // against Sheppard's correction, i.e. the true variance is the quantised variance - 1/12
+    let num_samples = context.metafits_context.corr_fine_chan_width_hz as f64
+        * context.metafits_context.corr_int_time_ms as f64
+        / 1000.0;
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    let mut num_checked = 0;
+    for (baseline_index, baseline) in context.metafits_context.baselines.iter().enumerate() {
+        if baseline.ant1_index != baseline.ant2_index {
+            continue;
         }
-    ));
-
-    // Check for NAXIS2 mismatch
-    let result_bad2 = CorrelatorContext::validate_hdu_axes(
-        MWAVersion::CorrOldLegacy,
-        metafits_fine_chans_per_coarse,
-        metafits_baselines,
-        visibility_pols,
-        8256 * 4 * 2,
-        129,
-    );
 
-    assert!(matches!(
-        result_bad2.unwrap_err(),
-        GpuboxError::LegacyNaxis2Mismatch {
-            metafits_fine_chans_per_coarse: _,
-            naxis2: _,
-            calculated_naxis2: _
+        for fine_chan_index in 0..num_fine_chans {
+            for pol_offset in [0, 6] {
+                let index = (baseline_index * num_fine_chans + fine_chan_index) * 8 + pol_offset;
+                let sighat2 = raw_by_bl[index] as f64 / (2.0 * num_samples);
+                if !(0.9..=1.7).contains(&sighat2.sqrt()) {
+                    continue;
+                }
+
+                let expected = 2.0 * num_samples * (sighat2 - 1.0 / 12.0);
+                assert!(
+                    approx_eq!(
+                        f64,
+                        corrected_by_bl[index] as f64,
+                        expected,
+                        F64Margin::default().epsilon(1e-4 * expected)
+                    ),
+                    "baseline {} fine chan {}: {} != {}",
+                    baseline_index,
+                    fine_chan_index,
+                    corrected_by_bl[index],
+                    expected
+                );
+                num_checked += 1;
+            }
         }
+    }
+    assert!(num_checked > 0);
+
+    // Both orderings must be corrected the same way
+    let sum_freq: f64 = corrected_by_freq.iter().fold(0., |sum, x| sum + *x as f64);
+    let sum_bl: f64 = corrected_by_bl.iter().fold(0., |sum, x| sum + *x as f64);
+    assert!(approx_eq!(
+        f64,
+        sum_bl,
+        sum_freq,
+        F64Margin::default().epsilon(1e-6 * sum_bl.abs())
     ));
 }
 
 #[test]
-fn test_validate_hdu_axes_naxis_mismatches_legacy() {
-    let metafits_fine_chans_per_coarse = 128;
-    let metafits_baselines = 8256;
-    let visibility_pols = 4;
-    let values = 1;
+fn test_mwax_read_with_van_vleck_correction() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    // Check for NAXIS1 mismatch
-    let result_bad1 = CorrelatorContext::validate_hdu_axes(
-        MWAVersion::CorrLegacy,
-        metafits_fine_chans_per_coarse,
-        metafits_baselines,
-        visibility_pols,
-        metafits_baselines * visibility_pols * values,
-        128,
-    );
+    let gpuboxfiles = vec![mwax_filename];
+    let mut context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
 
-    assert!(matches!(
-        result_bad1.unwrap_err(),
-        GpuboxError::LegacyNaxis1Mismatch {
-            metafits_baselines: _,
-            visibility_pols: _,
-            naxis1: _,
-            naxis2: _,
-            calculated_naxis1: _
-        }
-    ));
+    // The MWAX correlator input quantiser is not modelled
+    context.read_config = CorrelatorReadConfig::new().with_van_vleck_correction(true);
+    for result in [
+        context.read_by_baseline(0, 10),
+        context.read_by_frequency(0, 10),
+    ] {
+        assert!(matches!(
+            result,
+            Err(GpuboxError::VanVleckCorrectionNotSupported {
+                mwa_version: MWAVersion::CorrMWAXv2
+            })
+        ));
+    }
+}
 
-    // Check for NAXIS2 mismatch
-    let result_bad2 = CorrelatorContext::validate_hdu_axes(
-        MWAVersion::CorrLegacy,
-        metafits_fine_chans_per_coarse,
-        metafits_baselines,
-        visibility_pols,
-        8256 * 4 * 2,
-        129,
-    );
+#[test]
+fn test_apply_scale_factor() {
+    let mut buffer: Vec<f32> = vec![1.0, -2.0, 0.5, 0.0];
 
-    assert!(matches!(
-        result_bad2.unwrap_err(),
-        GpuboxError::LegacyNaxis2Mismatch {
-            metafits_fine_chans_per_coarse: _,
-            naxis2: _,
-            calculated_naxis2: _
-        }
-    ));
+    read_config::apply_scale_factor(&mut buffer, 1.0);
+    assert_eq!(buffer, vec![1.0, -2.0, 0.5, 0.0]);
+
+    read_config::apply_scale_factor(&mut buffer, 4.0);
+    assert_eq!(buffer, vec![4.0, -8.0, 2.0, 0.0]);
 }
 
 #[test]
-fn test_validate_hdu_axes_naxis_mismatches_v2() {
-    let metafits_fine_chans_per_coarse = 128;
-    let metafits_baselines = 8256;
-    let visibility_pols = 4;
-    let values = 2;
-
-    // Check for NAXIS1 mismatch
-    let result_bad1 = CorrelatorContext::validate_hdu_axes(
-        MWAVersion::CorrMWAXv2,
-        metafits_fine_chans_per_coarse,
-        metafits_baselines,
-        visibility_pols,
-        metafits_fine_chans_per_coarse * visibility_pols,
-        8256,
+fn test_get_contiguous_runs() {
+    assert!(subset_read::get_contiguous_runs(&[]).is_empty());
+    assert_eq!(subset_read::get_contiguous_runs(&[5]), vec![(0, 5, 1)]);
+    assert_eq!(
+        subset_read::get_contiguous_runs(&[0, 1, 2, 7, 8, 3, 3]),
+        vec![(0, 0, 3), (3, 7, 2), (5, 3, 1), (6, 3, 1)]
     );
+}
 
-    assert!(matches!(
-        result_bad1.unwrap_err(),
-        GpuboxError::MwaxNaxis1Mismatch {
-            metafits_fine_chans_per_coarse: _,
-            visibility_pols: _,
-            naxis1: _,
-            naxis2: _,
-            calculated_naxis1: _
-        }
-    ));
+/// Checks that a subset read matches the same visibilities extracted from a full read
+fn check_subset_read_matches_full_read(
+    context: &CorrelatorContext,
+    corr_coarse_chan_index: usize,
+    baseline_indices: &[usize],
+    fine_chan_indices: &[usize],
+) {
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    let num_baselines = context.metafits_context.num_baselines;
+
+    let full_by_bl: Vec<f32> = context
+        .read_by_baseline(0, corr_coarse_chan_index)
+        .expect("Error!");
+    let full_by_freq: Vec<f32> = context
+        .read_by_frequency(0, corr_coarse_chan_index)
+        .expect("Error!");
+    let subset_by_bl: Vec<f32> = context
+        .read_by_baseline_subset(
+            0,
+            corr_coarse_chan_index,
+            baseline_indices,
+            fine_chan_indices,
+        )
+        .expect("Error!");
+    let subset_by_freq: Vec<f32> = context
+        .read_by_frequency_subset(
+            0,
+            corr_coarse_chan_index,
+            baseline_indices,
+            fine_chan_indices,
+        )
+        .expect("Error!");
 
-    // Check for NAXIS2 mismatch
-    let result_bad2 = CorrelatorContext::validate_hdu_axes(
-        MWAVersion::CorrMWAXv2,
-        metafits_fine_chans_per_coarse,
-        metafits_baselines,
-        visibility_pols,
-        metafits_fine_chans_per_coarse * visibility_pols * values,
-        8257,
+    assert_eq!(
+        subset_by_bl.len(),
+        baseline_indices.len() * fine_chan_indices.len() * 8
     );
-
-    assert!(matches!(
-        result_bad2.unwrap_err(),
-        GpuboxError::MwaxNaxis2Mismatch {
-            metafits_baselines: _,
-            naxis2: _,
-            calculated_naxis2: _
+    assert_eq!(subset_by_freq.len(), subset_by_bl.len());
+
+    for (b, &baseline_index) in baseline_indices.iter().enumerate() {
+        for (f, &fine_chan_index) in fine_chan_indices.iter().enumerate() {
+            let full_bl_index = (baseline_index * num_fine_chans + fine_chan_index) * 8;
+            let full_freq_index = (fine_chan_index * num_baselines + baseline_index) * 8;
+            let subset_bl_index = (b * fine_chan_indices.len() + f) * 8;
+            let subset_freq_index = (f * baseline_indices.len() + b) * 8;
+
+            assert_eq!(
+                subset_by_bl[subset_bl_index..subset_bl_index + 8],
+                full_by_bl[full_bl_index..full_bl_index + 8]
+            );
+            assert_eq!(
+                subset_by_freq[subset_freq_index..subset_freq_index + 8],
+                full_by_freq[full_freq_index..full_freq_index + 8]
+            );
         }
-    ));
+    }
 }
 
 #[test]
-fn test_read_by_baseline_into_buffer_mwax() {
-    // Open the test mwa file
-    // a) using mwalib (by baseline) (data will be ordered [baseline][freq][pol][r][i])
-    // b) using mwalib (by frequency) (data will be ordered [freq][baseline][pol][r][i])
-    // Then check b) is the same as a) and
+fn test_read_subset_mwax() {
     let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
     let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    //
-    // Read the mwax file by frequency using mwalib
-    //
-    // Open a context and load in a test metafits and gpubox file
     let gpuboxfiles = vec![mwax_filename];
-    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+    let mut context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
         .expect("Failed to create CorrelatorContext");
 
-    // Read and convert first HDU by baseline
-    let mwalib_hdu_data_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    // A mixture of runs, single values and out of order values
+    let baseline_indices = [0, 1, 2, 5, 4, 7];
+    let fine_chan_indices = [0, 1, 3];
+    check_subset_read_matches_full_read(&context, 10, &baseline_indices, &fine_chan_indices);
 
-    // Read and convert first HDU by frequency
-    let mwalib_hdu_data_by_freq: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
+    // Everything
+    let all_baselines: Vec<usize> = (0..context.metafits_context.num_baselines).collect();
+    let all_fine_chans: Vec<usize> =
+        (0..context.metafits_context.num_corr_fine_chans_per_coarse).collect();
+    check_subset_read_matches_full_read(&context, 10, &all_baselines, &all_fine_chans);
 
-    // Read into buffer by baseline
-    let mut mwalib_hdu_data_by_bl2: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+    // Read config is honoured
+    context.read_config = CorrelatorReadConfig::new().with_raw_scale_factor(true);
+    check_subset_read_matches_full_read(&context, 10, &baseline_indices, &fine_chan_indices);
+}
 
-    let result_read_bl_buffer =
-        context.read_by_baseline_into_buffer(0, 10, &mut mwalib_hdu_data_by_bl2);
+#[test]
+fn test_read_subset_legacy() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let gpubox_filename =
+        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
 
-    assert!(result_read_bl_buffer.is_ok());
+    let gpuboxfiles = vec![gpubox_filename];
+    let mut context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
 
-    let sum_freq: f64 = mwalib_hdu_data_by_freq
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+    // Includes autos and baselines which need conjugating
+    let baseline_indices = [0, 1, 2, 128, 129, 8255, 4000];
+    let fine_chan_indices = [0, 2, 3, 1];
+    check_subset_read_matches_full_read(&context, 0, &baseline_indices, &fine_chan_indices);
 
-    let sum_bl: f64 = mwalib_hdu_data_by_bl
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+    // Van Vleck correction falls back to a full read
+    context.read_config = CorrelatorReadConfig::new().with_van_vleck_correction(true);
+    check_subset_read_matches_full_read(&context, 0, &baseline_indices, &fine_chan_indices);
+}
 
-    let sum_bl2: f64 = mwalib_hdu_data_by_bl2
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+#[test]
+fn test_read_subset_invalid_inputs() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    // Check sums are not 0
-    assert!(!approx_eq!(f64, sum_freq, 0., F64Margin::default()));
-    assert!(!approx_eq!(f64, sum_bl, 0., F64Margin::default()));
-    assert!(!approx_eq!(f64, sum_bl2, 0., F64Margin::default()));
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    let num_baselines = context.metafits_context.num_baselines;
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
 
-    // Check they all match each other
-    assert!(approx_eq!(f64, sum_bl, sum_freq, F64Margin::default()));
-    assert!(approx_eq!(f64, sum_bl, sum_bl2, F64Margin::default()));
+    let result = context.read_by_baseline_subset(0, 10, &[0, num_baselines], &[0]);
+    assert!(matches!(
+        result.unwrap_err(),
+        GpuboxError::InvalidBaselineIndex(n) if n == num_baselines - 1
+    ));
 
-    // Do another test, this time with no data for the timestep/cc combo
-    let result = context.read_by_baseline_into_buffer(10, 0, &mut mwalib_hdu_data_by_bl2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(
-            error,
-            GpuboxError::NoDataForTimeStepCoarseChannel {
-                timestep_index: _,
-                coarse_chan_index: _
-            }
-        ),
-        "Error was {:?}",
-        error
-    );
+    let result = context.read_by_frequency_subset(0, 10, &[0], &[num_fine_chans]);
+    assert!(matches!(
+        result.unwrap_err(),
+        GpuboxError::InvalidFineChanIndex(n) if n == num_fine_chans - 1
+    ));
 
-    // Do another test, this time with extremely out of bound indices (timestep)
-    let result = context.read_by_baseline_into_buffer(999, 10, &mut mwalib_hdu_data_by_bl2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(error, GpuboxError::InvalidTimeStepIndex(119)),
-        "Error was {:?}",
-        error
-    );
+    let mut buffer: Vec<f32> = vec![0.; 7];
+    let result = context.read_by_baseline_subset_into_buffer(0, 10, &[0], &[0], &mut buffer);
+    assert!(matches!(
+        result.unwrap_err(),
+        GpuboxError::InvalidBufferSize {
+            expected: 8,
+            got: 7
+        }
+    ));
 
-    // Do another test, this time with extremely out of bound indices (cc)
-    let result = context.read_by_baseline_into_buffer(0, 999, &mut mwalib_hdu_data_by_bl2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(error, GpuboxError::InvalidCoarseChanIndex(23)),
-        "Error was {:?}",
-        error
-    );
+    let result = context.read_by_baseline_subset(0, 23, &[0], &[0]);
+    assert!(matches!(
+        result.unwrap_err(),
+        GpuboxError::InvalidCoarseChanIndex(23)
+    ));
 }
 
 #[test]
-fn test_read_by_frequency_into_buffer_mwax() {
-    // Open the test mwa file
-    // a) using mwalib (by baseline) (data will be ordered [baseline][freq][pol][r][i])
-    // b) using mwalib (by frequency) (data will be ordered [freq][baseline][pol][r][i])
-    // Then check b) is the same as a) and
+fn test_read_cube_mwax() {
     let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
     let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    //
-    // Read the mwax file by frequency using mwalib
-    //
-    // Open a context and load in a test metafits and gpubox file
     let gpuboxfiles = vec![mwax_filename];
-    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+    let mut context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
         .expect("Failed to create CorrelatorContext");
+    let hdu_floats = context.num_timestep_coarse_chan_floats;
 
-    // Read and convert first HDU by baseline
-    let mwalib_hdu_data_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
-
-    // Read and convert first HDU by frequency
-    let mwalib_hdu_data_by_freq: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
+    let expected: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
 
-    // Read into buffer by baseline
-    let mut mwalib_hdu_data_by_freq2: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+    // Same HDU twice, to check each part of the cube is filled
+    for num_threads in [0, 2] {
+        context.read_config = CorrelatorReadConfig::new().with_num_threads(num_threads);
 
-    let result_read_bl_buffer =
-        context.read_by_frequency_into_buffer(0, 10, &mut mwalib_hdu_data_by_freq2);
+        let mut cube: Vec<f32> = vec![0.; 2 * hdu_floats];
+        context
+            .read_cube(&[0], &[10, 10], &mut cube)
+            .expect("Error!");
 
-    assert!(result_read_bl_buffer.is_ok());
+        assert_eq!(cube[0..hdu_floats], expected[..]);
+        assert_eq!(cube[hdu_floats..], expected[..]);
+    }
+}
 
-    let sum_bl: f64 = mwalib_hdu_data_by_bl
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+#[test]
+fn test_read_cube_invalid_inputs() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    let sum_freq: f64 = mwalib_hdu_data_by_freq
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    let hdu_floats = context.num_timestep_coarse_chan_floats;
 
-    let sum_freq2: f64 = mwalib_hdu_data_by_freq2
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+    // Buffer too small
+    let mut cube: Vec<f32> = vec![0.; hdu_floats];
+    let result = context.read_cube(&[0], &[10, 10], &mut cube);
+    assert!(matches!(
+        result.unwrap_err(),
+        GpuboxError::InvalidBufferSize { expected, got } if expected == 2 * hdu_floats && got == hdu_floats
+    ));
 
-    // Check sums are not 0
-    assert!(!approx_eq!(f64, sum_bl, 0., F64Margin::default()));
-    assert!(!approx_eq!(f64, sum_freq, 0., F64Margin::default()));
-    assert!(!approx_eq!(f64, sum_freq2, 0., F64Margin::default()));
+    // No data for coarse chan 0
+    let result = context.read_cube(&[0], &[0, 10], &mut vec![0.; 2 * hdu_floats]);
+    assert!(matches!(
+        result.unwrap_err(),
+        GpuboxError::NoDataForTimeStepCoarseChannel {
+            timestep_index: 0,
+            coarse_chan_index: 0
+        }
+    ));
+}
 
-    // Check they all match each other
-    assert!(approx_eq!(f64, sum_bl, sum_freq, F64Margin::default()));
-    assert!(approx_eq!(f64, sum_freq, sum_freq2, F64Margin::default()));
+#[test]
+fn test_thread_pool_cache() {
+    let cache = ThreadPoolCache::default();
+
+    // The same pool is reused until the number of threads changes
+    let pool = cache.get(2).expect("Error!");
+    assert_eq!(pool.current_num_threads(), 2);
+    assert!(std::sync::Arc::ptr_eq(
+        &pool,
+        &cache.get(2).expect("Error!")
+    ));
 
-    // Do another test, this time with no data for the timestep/cc combo
-    let result = context.read_by_frequency_into_buffer(10, 0, &mut mwalib_hdu_data_by_freq2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(
-            error,
-            GpuboxError::NoDataForTimeStepCoarseChannel {
-                timestep_index: _,
-                coarse_chan_index: _
-            }
-        ),
-        "Error was {:?}",
-        error
-    );
+    let pool3 = cache.get(3).expect("Error!");
+    assert_eq!(pool3.current_num_threads(), 3);
+    assert!(!std::sync::Arc::ptr_eq(&pool, &pool3));
+}
 
-    // Do another test, this time with extremely out of bound indices (timestep)
-    let result = context.read_by_frequency_into_buffer(999, 10, &mut mwalib_hdu_data_by_freq2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(error, GpuboxError::InvalidTimeStepIndex(119)),
-        "Error was {:?}",
-        error
+#[test]
+fn test_accumulate_weighted_visibilities() {
+    // 2 baselines, 4 fine chans, 1 pol
+    let hdu_buffer: Vec<f32> = vec![
+        1., -1., 3., -3., 5., 5., 7., 7., // baseline 0
+        2., 0., 2., 0., 4., 0., 4., 0., // baseline 1
+    ];
+    let mut vis_sums: Vec<f64> = vec![0.; 2 * 2 * 2];
+    let mut weight_sums: Vec<f64> = vec![0.; 2 * 2];
+
+    let total_weight = averaged_read::accumulate_weighted_visibilities(
+        &hdu_buffer,
+        &[1., 0.5],
+        4,
+        1,
+        2,
+        &mut vis_sums,
+        &mut weight_sums,
     );
-
-    // Do another test, this time with extremely out of bound indices (cc)
-    let result = context.read_by_frequency_into_buffer(0, 999, &mut mwalib_hdu_data_by_freq2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(error, GpuboxError::InvalidCoarseChanIndex(23)),
-        "Error was {:?}",
-        error
+    assert_eq!(total_weight, 6.);
+    assert_eq!(vis_sums, vec![4., -4., 12., 12., 2., 0., 4., 0.]);
+    assert_eq!(weight_sums, vec![2., 2., 1., 1.]);
+
+    // A second HDU with zero weight for baseline 1 only changes baseline 0
+    let total_weight = averaged_read::accumulate_weighted_visibilities(
+        &hdu_buffer,
+        &[1., 0.],
+        4,
+        1,
+        2,
+        &mut vis_sums,
+        &mut weight_sums,
     );
+    assert_eq!(total_weight, 4.);
+
+    let mut data: Vec<f32> = vec![0.; 8];
+    let mut weights: Vec<f32> = vec![0.; 4];
+    averaged_read::finalise_weighted_average(&vis_sums, &weight_sums, &mut data, &mut weights);
+    assert_eq!(data, vec![2., -2., 6., 6., 2., 0., 4., 0.]);
+    assert_eq!(weights, vec![4., 4., 1., 1.]);
+
+    // Nothing contributed
+    averaged_read::finalise_weighted_average(&[3., 3.], &[0.], &mut data[0..2], &mut weights[0..1]);
+    assert_eq!(data[0..2], [0., 0.]);
+    assert_eq!(weights[0], 0.);
 }
 
 #[test]
-fn test_read_by_baseline_into_buffer_legacy() {
-    // Open the test mwa file
-    // a) using mwalib (by baseline) (data will be ordered [baseline][freq][pol][r][i])
-    // b) using mwalib (by frequency) (data will be ordered [freq][baseline][pol][r][i])
-    // Then check b) is the same as a) and
-    let mwax_metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
-    let mwax_filename =
-        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+fn test_read_averaged_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    //
-    // Read the legacy file by frequency using mwalib
-    //
-    // Open a context and load in a test metafits and gpubox file
     let gpuboxfiles = vec![mwax_filename];
     let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
         .expect("Failed to create CorrelatorContext");
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    let num_baselines = context.metafits_context.num_baselines;
+    let num_pols = context.metafits_context.num_visibility_pols;
+
+    let expected: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    let expected_weights: Vec<f32> = context.read_weights_by_baseline(0, 10).expect("Error!");
+    assert_eq!(expected_weights.len(), num_baselines * num_pols);
+
+    // No averaging gives back the data, wherever it has a non-zero weight
+    let averaged = context.read_averaged(&[0], &[10], 1, 1).expect("Error!");
+    assert_eq!(averaged.num_timesteps, 1);
+    assert_eq!(averaged.num_fine_chans_per_coarse, num_fine_chans);
+    assert_eq!(
+        averaged.fine_chan_freqs_hz,
+        context.get_fine_chan_freqs_hz_array(&[10])
+    );
+    for (index, &weight) in averaged.weights.iter().enumerate() {
+        let baseline_index = index / (num_fine_chans * num_pols);
+        let pol_index = index % num_pols;
+        assert_eq!(
+            weight,
+            expected_weights[baseline_index * num_pols + pol_index]
+        );
+
+        if weight > 0. {
+            assert!(approx_eq!(
+                f32,
+                averaged.data[index * 2],
+                expected[index * 2],
+                F32Margin::default()
+            ));
+        }
+    }
+
+    // Timestep 1 has no data, so averaging it with timestep 0 gives the same result,
+    // centred on timestep 0
+    assert!(context.num_timesteps > 1);
+    let averaged_2 = context.read_averaged(&[0, 1], &[10], 2, 1).expect("Error!");
+    assert_eq!(averaged_2.timestep_indices, vec![vec![0, 1]]);
+    assert_eq!(averaged_2.data, averaged.data);
+    assert_eq!(averaged_2.weights, averaged.weights);
+    assert_eq!(
+        averaged_2.centroid_unix_times_ms,
+        vec![
+            context.timesteps[0].unix_time_ms as f64
+                + context.metafits_context.corr_int_time_ms as f64 / 2.
+        ]
+    );
 
-    // Read and convert first HDU by baseline
-    let mwalib_hdu_data_by_bl: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
-
-    // Read and convert first HDU by frequency
-    let mwalib_hdu_data_by_freq: Vec<f32> = context.read_by_frequency(0, 0).expect("Error!");
+    // Averaging all fine channels of a coarse channel
+    let averaged_all = context
+        .read_averaged(&[0], &[10], 1, num_fine_chans)
+        .expect("Error!");
+    assert_eq!(averaged_all.num_fine_chans_per_coarse, 1);
+    assert_eq!(averaged_all.data.len(), num_baselines * num_pols * 2);
+    assert_eq!(averaged_all.fine_chan_freqs_hz.len(), 1);
+    let fine_chan_freqs_hz = context.get_fine_chan_freqs_hz_array(&[10]);
+    assert!(approx_eq!(
+        f64,
+        averaged_all.fine_chan_freqs_hz[0],
+        fine_chan_freqs_hz.iter().sum::<f64>() / num_fine_chans as f64,
+        F64Margin::default()
+    ));
 
-    // Read into buffer by baseline
-    let mut mwalib_hdu_data_by_bl2: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+    // A window with no data at all
+    let averaged_none = context.read_averaged(&[1], &[10], 1, 1).expect("Error!");
+    assert!(averaged_none.weights.iter().all(|&w| w == 0.));
+    assert!(averaged_none.data.iter().all(|&d| d == 0.));
+}
 
-    let result_read_bl_buffer =
-        context.read_by_baseline_into_buffer(0, 0, &mut mwalib_hdu_data_by_bl2);
+#[test]
+fn test_read_averaged_invalid_inputs() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    assert!(result_read_bl_buffer.is_ok());
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
 
-    let sum_freq: f64 = mwalib_hdu_data_by_freq
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+    assert!(matches!(
+        context.read_averaged(&[0], &[10], 0, 1).unwrap_err(),
+        GpuboxError::InvalidTimeAveragingFactor(0)
+    ));
+    assert!(matches!(
+        context.read_averaged(&[0], &[10], 1, 0).unwrap_err(),
+        GpuboxError::InvalidFreqAveragingFactor { factor: 0, .. }
+    ));
+    assert!(matches!(
+        context.read_averaged(&[0], &[10], 1, 7).unwrap_err(),
+        GpuboxError::InvalidFreqAveragingFactor { factor: 7, .. }
+    ));
+    assert!(matches!(
+        context.read_averaged(&[0], &[24], 1, 1).unwrap_err(),
+        GpuboxError::InvalidCoarseChanIndex(23)
+    ));
 
-    let sum_bl: f64 = mwalib_hdu_data_by_bl
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+    // Bad timesteps are errors, even if there are no coarse channels to read them for
+    let num_timesteps = context.num_timesteps;
+    for coarse_chans in [&[10][..], &[]] {
+        assert!(matches!(
+            context
+                .read_averaged(&[0, num_timesteps], coarse_chans, 1, 1)
+                .unwrap_err(),
+            GpuboxError::InvalidTimeStepIndex(t) if t == num_timesteps - 1
+        ));
+    }
+}
 
-    let sum_bl2: f64 = mwalib_hdu_data_by_bl2
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+#[test]
+fn test_get_baseline_avg_factors() {
+    // Autos never decorrelate
+    assert_eq!(
+        baseline_averaged_read::get_baseline_time_avg_factor(0., 200e6, 2., 0.01, 8),
+        8
+    );
+    assert_eq!(
+        baseline_averaged_read::get_baseline_freq_avg_factor(0., 10e3, 128, 0.01, 1000),
+        128
+    );
 
-    // Check sums are not 0
-    assert!(!approx_eq!(f64, sum_freq, 0., F64Margin::default()));
-    assert!(!approx_eq!(f64, sum_bl, 0., F64Margin::default()));
-    assert!(!approx_eq!(f64, sum_bl2, 0., F64Margin::default()));
+    // 100m at 200MHz loses just under 1% over 8 x 2s
+    assert_eq!(
+        baseline_averaged_read::get_baseline_time_avg_factor(100., 200e6, 2., 0.01, 20),
+        8
+    );
+    assert_eq!(
+        baseline_averaged_read::get_baseline_time_avg_factor(100., 200e6, 2., 0.01, 5),
+        5
+    );
 
-    // Check they all match each other
-    assert!(approx_eq!(f64, sum_bl, sum_freq, F64Margin::default()));
-    assert!(approx_eq!(f64, sum_bl, sum_bl2, F64Margin::default()));
+    // 5km can't be averaged at all
+    assert_eq!(
+        baseline_averaged_read::get_baseline_time_avg_factor(5000., 200e6, 2., 0.01, 20),
+        1
+    );
 
-    // Do another test, this time with no data for the timestep/cc combo
-    let result = context.read_by_baseline_into_buffer(10, 10, &mut mwalib_hdu_data_by_bl2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(
-            error,
-            GpuboxError::NoDataForTimeStepCoarseChannel {
-                timestep_index: _,
-                coarse_chan_index: _
-            }
-        ),
-        "Error was {:?}",
-        error
+    // 1km loses 0.7% over 2 x 10kHz, 2.9% over 4 x 10kHz
+    assert_eq!(
+        baseline_averaged_read::get_baseline_freq_avg_factor(1000., 10e3, 128, 0.01, 128),
+        2
+    );
+    assert_eq!(
+        baseline_averaged_read::get_baseline_freq_avg_factor(1000., 10e3, 128, 0.05, 128),
+        4
     );
 
-    // Do another test, this time with extremely out of bound indices (timestep)
-    let result = context.read_by_baseline_into_buffer(999, 0, &mut mwalib_hdu_data_by_bl2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(error, GpuboxError::InvalidTimeStepIndex(55)),
-        "Error was {:?}",
-        error
+    // Factors must divide the number of fine channels
+    assert_eq!(
+        baseline_averaged_read::get_baseline_freq_avg_factor(0., 10e3, 24, 0.01, 16),
+        12
     );
+}
 
-    // Do another test, this time with extremely out of bound indices (cc)
-    let result = context.read_by_baseline_into_buffer(0, 999, &mut mwalib_hdu_data_by_bl2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(error, GpuboxError::InvalidCoarseChanIndex(23)),
-        "Error was {:?}",
-        error
-    );
+#[test]
+fn test_read_baseline_dependent_averaged_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    let num_pols = context.metafits_context.num_visibility_pols;
+
+    let unaveraged = context.read_averaged(&[0, 1], &[10], 1, 1).expect("Error!");
+    let averaged = context
+        .read_baseline_dependent_averaged(&[0, 1], &[10], 0.01, 2, num_fine_chans)
+        .expect("Error!");
+    assert_eq!(averaged.len(), context.metafits_context.num_baselines);
+
+    for baseline in averaged.iter() {
+        assert_eq!(baseline.num_timesteps, 2 / baseline.time_avg_factor);
+        assert_eq!(
+            baseline.num_fine_chans,
+            num_fine_chans / baseline.freq_avg_factor
+        );
+        assert_eq!(
+            baseline.data.len(),
+            baseline.num_timesteps * baseline.num_fine_chans * num_pols * 2
+        );
+        assert_eq!(
+            baseline.centroid_unix_times_ms.len(),
+            baseline.num_timesteps
+        );
+    }
+
+    // Autocorrelations are averaged as much as allowed
+    let auto = &averaged[0];
+    assert_eq!(auto.baseline_length_m, 0.);
+    assert_eq!(auto.time_avg_factor, 2);
+    assert_eq!(auto.freq_avg_factor, num_fine_chans);
+
+    // Unaveraged baselines match read_averaged (timestep 1 has no data)
+    let longest = averaged
+        .iter()
+        .max_by(|a, b| {
+            a.baseline_length_m
+                .partial_cmp(&b.baseline_length_m)
+                .unwrap()
+        })
+        .unwrap();
+    if longest.time_avg_factor == 1 && longest.freq_avg_factor == 1 {
+        let baseline_floats = num_fine_chans * num_pols * 2;
+        assert_eq!(
+            longest.data[0..baseline_floats],
+            unaveraged.data[longest.baseline_index * baseline_floats..][..baseline_floats]
+        );
+        assert!(longest.weights[baseline_floats / 2..]
+            .iter()
+            .all(|&w| w == 0.));
+    }
 }
 
 #[test]
-fn test_read_by_frequency_into_buffer_legacy() {
-    // Open the test mwa file
-    // a) using mwalib (by baseline) (data will be ordered [baseline][freq][pol][r][i])
-    // b) using mwalib (by frequency) (data will be ordered [freq][baseline][pol][r][i])
-    // Then check b) is the same as a) and
-    let mwax_metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
-    let mwax_filename =
-        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+fn test_read_baseline_dependent_averaged_invalid_inputs() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    //
-    // Read the legacy file by frequency using mwalib
-    //
-    // Open a context and load in a test metafits and gpubox file
     let gpuboxfiles = vec![mwax_filename];
     let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
         .expect("Failed to create CorrelatorContext");
 
-    // Read and convert first HDU by baseline
-    let mwalib_hdu_data_by_bl: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+    for tolerance in [0., 1., -0.5, f64::NAN] {
+        assert!(matches!(
+            context
+                .read_baseline_dependent_averaged(&[0], &[10], tolerance, 1, 1)
+                .unwrap_err(),
+            GpuboxError::InvalidDecorrelationTolerance(_)
+        ));
+    }
+    assert!(matches!(
+        context
+            .read_baseline_dependent_averaged(&[0], &[10], 0.01, 0, 1)
+            .unwrap_err(),
+        GpuboxError::InvalidTimeAveragingFactor(0)
+    ));
+    assert!(matches!(
+        context
+            .read_baseline_dependent_averaged(&[0], &[10], 0.01, 1, 0)
+            .unwrap_err(),
+        GpuboxError::InvalidFreqAveragingFactor { factor: 0, .. }
+    ));
 
-    // Read and convert first HDU by frequency
-    let mwalib_hdu_data_by_freq: Vec<f32> = context.read_by_frequency(0, 0).expect("Error!");
+    // Out of range indices, with and without anything else to read
+    let num_timesteps = context.num_timesteps;
+    let num_coarse_chans = context.num_coarse_chans;
+    for coarse_chans in [&[10][..], &[]] {
+        assert!(matches!(
+            context
+                .read_baseline_dependent_averaged(&[num_timesteps], coarse_chans, 0.01, 1, 1)
+                .unwrap_err(),
+            GpuboxError::InvalidTimeStepIndex(t) if t == num_timesteps - 1
+        ));
+    }
+    for timesteps in [&[0][..], &[]] {
+        assert!(matches!(
+            context
+                .read_baseline_dependent_averaged(timesteps, &[10, num_coarse_chans], 0.01, 1, 1)
+                .unwrap_err(),
+            GpuboxError::InvalidCoarseChanIndex(c) if c == num_coarse_chans - 1
+        ));
+    }
+}
 
-    // Read into buffer by baseline
-    let mut mwalib_hdu_data_by_freq2: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+#[test]
+fn test_read_pol_products_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    let result_read_bl_buffer =
-        context.read_by_frequency_into_buffer(0, 0, &mut mwalib_hdu_data_by_freq2);
+    let gpuboxfiles = vec![mwax_filename];
+    let mut context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    assert_eq!(context.num_output_pols(), 4);
+    assert_eq!(
+        context.num_output_timestep_coarse_chan_floats(),
+        context.num_timestep_coarse_chan_floats
+    );
 
-    assert!(result_read_bl_buffer.is_ok());
+    let linear_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    let linear_by_freq: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
+    let linear_subset: Vec<f32> = context
+        .read_by_frequency_subset(0, 10, &[5, 1], &[0, 3])
+        .expect("Error!");
+
+    for pol_products in [PolProducts::Stokes, PolProducts::PseudoStokesI] {
+        context.read_config = CorrelatorReadConfig::new().with_pol_products(pol_products);
+        assert_eq!(context.num_output_pols(), pol_products.num_pols());
+        let output_floats = context.num_output_timestep_coarse_chan_floats();
+        assert_eq!(
+            output_floats,
+            context.num_timestep_coarse_chan_floats / 4 * pol_products.num_pols()
+        );
+
+        let mut expected: Vec<f32> = vec![0.; output_floats];
+        convert_linear_to_pol_products(&linear_by_bl, &mut expected, pol_products);
+        assert_eq!(context.read_by_baseline(0, 10).expect("Error!"), expected);
+
+        let mut cube: Vec<f32> = vec![0.; output_floats];
+        context.read_cube(&[0], &[10], &mut cube).expect("Error!");
+        assert_eq!(cube, expected);
+
+        convert_linear_to_pol_products(&linear_by_freq, &mut expected, pol_products);
+        assert_eq!(context.read_by_frequency(0, 10).expect("Error!"), expected);
+
+        let mut expected_subset: Vec<f32> = vec![0.; 2 * 2 * 2 * pol_products.num_pols()];
+        convert_linear_to_pol_products(&linear_subset, &mut expected_subset, pol_products);
+        assert_eq!(
+            context
+                .read_by_frequency_subset(0, 10, &[5, 1], &[0, 3])
+                .expect("Error!"),
+            expected_subset
+        );
+
+        // A linear sized buffer is too big
+        let mut buffer: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+        assert!(matches!(
+            context
+                .read_by_baseline_into_buffer(0, 10, &mut buffer)
+                .unwrap_err(),
+            GpuboxError::InvalidBufferSize { expected, .. } if expected == output_floats
+        ));
+    }
+}
 
-    let sum_bl: f64 = mwalib_hdu_data_by_bl
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+#[test]
+fn test_get_fine_chan_flags() {
+    let no_flags = CorrelatorFlagConfig::new();
+    assert_eq!(
+        flags::get_fine_chan_flags(8, 10_000, &no_flags),
+        vec![false; 8]
+    );
 
-    let sum_freq: f64 = mwalib_hdu_data_by_freq
-        .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+    // 15kHz overlaps 2 x 10kHz channels at each edge
+    let edges = CorrelatorFlagConfig::new().with_edge_width_hz(15_000);
+    assert_eq!(
+        flags::get_fine_chan_flags(8, 10_000, &edges),
+        vec![true, true, false, false, false, false, true, true]
+    );
 
-    let sum_freq2: f64 = mwalib_hdu_data_by_freq2
+    let edges_and_dc = edges.with_dc_flags(true);
+    assert_eq!(
+        flags::get_fine_chan_flags(8, 10_000, &edges_and_dc),
+        vec![true, true, false, false, true, false, true, true]
+    );
+
+    // Edges wider than the coarse channel
+    let wide_edges = CorrelatorFlagConfig::new().with_edge_width_hz(1_000_000);
+    assert_eq!(
+        flags::get_fine_chan_flags(8, 10_000, &wide_edges),
+        vec![true; 8]
+    );
+}
+
+#[test]
+fn test_get_flags_legacy() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let gpubox_filename =
+        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    let gpuboxfiles = vec![gpubox_filename];
+    let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    let hdu_flags_len = context.metafits_context.num_baselines * num_fine_chans;
+
+    // No flags
+    let flags = context
+        .get_flags(&[0, 1], &[0], &CorrelatorFlagConfig::new())
+        .expect("Error!");
+    assert_eq!(flags.len(), 2 * hdu_flags_len);
+    assert!(flags.iter().all(|&f| !f));
+
+    // Missing data
+    let flags = context
+        .get_flags(
+            &[0, 1],
+            &[0],
+            &CorrelatorFlagConfig::new().with_missing_hdu_flags(true),
+        )
+        .expect("Error!");
+    assert!(flags[0..hdu_flags_len].iter().all(|&f| !f));
+    assert!(flags[hdu_flags_len..].iter().all(|&f| f));
+
+    // Flagged tiles
+    let flags = context
+        .get_flags(
+            &[0],
+            &[0],
+            &CorrelatorFlagConfig::new().with_flagged_tile_flags(true),
+        )
+        .expect("Error!");
+    for (baseline, baseline_flags) in context
+        .metafits_context
+        .baselines
         .iter()
-        .fold(0., |sum, x| sum + *x as f64);
+        .zip(flags.chunks_exact(num_fine_chans))
+    {
+        let ant1 = &context.metafits_context.antennas[baseline.ant1_index];
+        let ant2 = &context.metafits_context.antennas[baseline.ant2_index];
+        let flagged = ant1.rfinput_x.flagged
+            || ant1.rfinput_y.flagged
+            || ant2.rfinput_x.flagged
+            || ant2.rfinput_y.flagged;
+        assert!(baseline_flags.iter().all(|&f| f == flagged));
+    }
+
+    // Quack time
+    let flags = context
+        .get_flags(
+            &[0],
+            &[0],
+            &CorrelatorFlagConfig::new().with_quack_flags(true),
+        )
+        .expect("Error!");
+    let quacked = context.timesteps[0].unix_time_ms < context.metafits_context.good_time_unix_ms;
+    assert!(flags.iter().all(|&f| f == quacked));
+
+    // Edges and DC are the same on every baseline
+    let flag_config = CorrelatorFlagConfig::new()
+        .with_edge_width_hz(context.metafits_context.corr_fine_chan_width_hz)
+        .with_dc_flags(true);
+    let flags = context.get_flags(&[0], &[0], &flag_config).expect("Error!");
+    for baseline_flags in flags.chunks_exact(num_fine_chans) {
+        assert!(baseline_flags[0]);
+        assert!(baseline_flags[num_fine_chans / 2]);
+        assert!(baseline_flags[num_fine_chans - 1]);
+        assert_eq!(baseline_flags.iter().filter(|&&f| f).count(), 3);
+    }
+
+    // Invalid inputs
+    assert!(matches!(
+        context.get_flags(&[0], &[24], &flag_config).unwrap_err(),
+        GpuboxError::InvalidCoarseChanIndex(23)
+    ));
+    let mut buffer: Vec<bool> = vec![false; 3];
+    assert!(matches!(
+        context
+            .get_flags_into_buffer(&[0], &[0], &flag_config, &mut buffer)
+            .unwrap_err(),
+        GpuboxError::InvalidBufferSize { got: 3, .. }
+    ));
+}
 
-    // Check sums are not 0
-    assert!(!approx_eq!(f64, sum_bl, 0., F64Margin::default()));
-    assert!(!approx_eq!(f64, sum_freq, 0., F64Margin::default()));
-    assert!(!approx_eq!(f64, sum_freq2, 0., F64Margin::default()));
+#[test]
+fn test_missing_data_policy_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    // Check they all match each other
-    assert!(approx_eq!(f64, sum_bl, sum_freq, F64Margin::default()));
-    assert!(approx_eq!(f64, sum_freq, sum_freq2, F64Margin::default()));
+    let gpuboxfiles = vec![mwax_filename];
+    let mut context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    let hdu_floats = context.num_timestep_coarse_chan_floats;
+    let expected: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
 
-    // Do another test, this time with no data for the timestep/cc combo
-    let result = context.read_by_frequency_into_buffer(10, 10, &mut mwalib_hdu_data_by_freq2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(
-            error,
-            GpuboxError::NoDataForTimeStepCoarseChannel {
-                timestep_index: _,
-                coarse_chan_index: _
-            }
-        ),
-        "Error was {:?}",
-        error
-    );
+    // Timestep 1 has no data, which is an error by default
+    assert!(matches!(
+        context.read_by_baseline(1, 10).unwrap_err(),
+        GpuboxError::NoDataForTimeStepCoarseChannel {
+            timestep_index: 1,
+            coarse_chan_index: 10
+        }
+    ));
 
-    // Do another test, this time with extremely out of bound indices (timestep)
-    let result = context.read_by_frequency_into_buffer(999, 0, &mut mwalib_hdu_data_by_freq2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(error, GpuboxError::InvalidTimeStepIndex(55)),
-        "Error was {:?}",
-        error
-    );
+    context.read_config =
+        CorrelatorReadConfig::new().with_missing_data_policy(MissingDataPolicy::FillZeros);
+    assert!(context
+        .read_by_baseline(1, 10)
+        .expect("Error!")
+        .iter()
+        .all(|&v| v == 0.));
+    assert!(context
+        .read_by_frequency(1, 10)
+        .expect("Error!")
+        .iter()
+        .all(|&v| v == 0.));
+    assert!(context
+        .read_by_baseline_subset(1, 10, &[0, 2], &[1])
+        .expect("Error!")
+        .iter()
+        .all(|&v| v == 0.));
 
-    // Do another test, this time with extremely out of bound indices (cc)
-    let result = context.read_by_frequency_into_buffer(0, 999, &mut mwalib_hdu_data_by_freq2);
-    assert!(result.is_err());
-    let error = result.unwrap_err();
-    assert!(
-        matches!(error, GpuboxError::InvalidCoarseChanIndex(23)),
-        "Error was {:?}",
-        error
+    // Invalid indices are still errors
+    assert!(matches!(
+        context.read_by_baseline(0, 24).unwrap_err(),
+        GpuboxError::InvalidCoarseChanIndex(23)
+    ));
+
+    // As is a buffer of the wrong size, even though there is no data to read into it
+    let mut short_buffer: Vec<f32> = vec![1.; hdu_floats - 1];
+    for result in [
+        context.read_by_baseline_into_buffer(1, 10, &mut short_buffer),
+        context.read_by_frequency_into_buffer(1, 10, &mut short_buffer),
+    ] {
+        assert!(matches!(
+            result.unwrap_err(),
+            GpuboxError::InvalidBufferSize { got, .. } if got == hdu_floats - 1
+        ));
+    }
+    assert!(short_buffer.iter().all(|&v| v == 1.));
+
+    context.read_config =
+        CorrelatorReadConfig::new().with_missing_data_policy(MissingDataPolicy::FillNaN);
+    let mut cube: Vec<f32> = vec![0.; 2 * hdu_floats];
+    let mut missing: Vec<bool> = vec![true, false];
+    context
+        .read_cube_with_flags(&[0, 1], &[10], &mut cube, &mut missing)
+        .expect("Error!");
+    assert_eq!(missing, vec![false, true]);
+    assert_eq!(cube[0..hdu_floats], expected[..]);
+    assert!(cube[hdu_floats..].iter().all(|v| v.is_nan()));
+
+    // The iterators give a full grid, flagging what was missing
+    let items: Vec<_> = context
+        .data_iter(IndexSet::All, IterationOrder::TimeMajor)
+        .collect();
+    assert_eq!(
+        items.len(),
+        context.num_timesteps * context.num_coarse_chans
     );
+    for (timestep_index, coarse_chan_index, result) in items.iter() {
+        let (data, missing) = result.as_ref().expect("Error!");
+        assert_eq!(*missing, (*timestep_index, *coarse_chan_index) != (0, 10));
+        if *missing {
+            assert!(data.iter().all(|v| v.is_nan()));
+        } else {
+            assert_eq!(data, &expected);
+        }
+    }
+
+    let mut buffer: Vec<f32> = vec![0.; hdu_floats];
+    let mut iter = context.data_iter(IndexSet::All, IterationOrder::FrequencyMajor);
+    assert!(iter.next_into_buffer(&mut buffer).unwrap().2.unwrap());
+
+    let items: Vec<(usize, usize, Result<ReadAheadBuffer<f32>, GpuboxError>)> =
+        CorrelatorReadAhead::new(
+            std::sync::Arc::new(context),
+            &[0, 1],
+            &[10],
+            IterationOrder::TimeMajor,
+            1,
+        )
+        .collect();
+    let buffers: Vec<&ReadAheadBuffer<f32>> = items.iter().map(|i| i.2.as_ref().unwrap()).collect();
+    assert!(!buffers[0].is_missing());
+    assert_eq!(buffers[0][..], expected[..]);
+    assert!(buffers[1].is_missing());
+    assert!(buffers[1].iter().all(|v| v.is_nan()));
 }
 
 #[test]
-fn test_get_fits_filename_and_batch_and_hdu() {
-    let mwax_metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
-    let mwax_filename =
-        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+fn test_data_iter_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    //
-    // Read the legacy file by frequency using mwalib
-    //
-    // Open a context and load in a test metafits and gpubox file
     let gpuboxfiles = vec![mwax_filename];
     let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
         .expect("Failed to create CorrelatorContext");
 
-    // Valid
-    let result = context.get_fits_filename_and_batch_and_hdu(0, 0);
-    assert!(result.is_ok());
+    let expected: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+
+    // Only one HDU was provided
+    let items: Vec<_> = context
+        .data_iter(IndexSet::Provided, IterationOrder::TimeMajor)
+        .collect();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].0, 0);
+    assert_eq!(items[0].1, 10);
+    assert_eq!(items[0].2.as_ref().unwrap(), &(expected.clone(), false));
+
+    // Every timestep and coarse channel, most of which have no data
+    let mut iter = context.data_iter(IndexSet::All, IterationOrder::FrequencyMajor);
+    assert_eq!(iter.len(), context.num_timesteps * context.num_coarse_chans);
+
+    let mut buffer: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+    let mut num_ok = 0;
+    while let Some((timestep_index, coarse_chan_index, result)) = iter.next_into_buffer(&mut buffer)
+    {
+        match result {
+            Ok(missing) => {
+                assert!(!missing);
+                assert_eq!((timestep_index, coarse_chan_index), (0, 10));
+                assert_eq!(buffer, expected);
+                num_ok += 1;
+            }
+            Err(GpuboxError::NoDataForTimeStepCoarseChannel {
+                timestep_index: t,
+                coarse_chan_index: c,
+            }) => {
+                assert_eq!((t, c), (timestep_index, coarse_chan_index));
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+        }
+    }
+    assert_eq!(num_ok, 1);
+    assert!(iter.next().is_none());
+}
 
-    // Invalid - out of bounds completely (time)
-    let result = context.get_fits_filename_and_batch_and_hdu(999, 0);
-    let error = result.unwrap_err();
-    assert!(
-        matches!(error, GpuboxError::InvalidTimeStepIndex(55)),
-        "Error was {:?}",
-        error
-    );
+#[test]
+fn test_gpubox_files_kept_open() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    // Invalid - out of bounds completely (channel)
-    let result = context.get_fits_filename_and_batch_and_hdu(0, 999);
-    let error = result.unwrap_err();
-    assert!(
-        matches!(error, GpuboxError::InvalidCoarseChanIndex(23)),
-        "Error was {:?}",
-        error
-    );
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    assert_eq!(context.fits_handle_pool.len(), 0);
 
-    // Invalid - out of bounds no data (time)
-    let result = context.get_fits_filename_and_batch_and_hdu(10, 0);
-    let error = result.unwrap_err();
-    assert!(
-        matches!(
-            error,
-            GpuboxError::NoDataForTimeStepCoarseChannel {
-                timestep_index: _,
-                coarse_chan_index: _
-            }
-        ),
-        "Error was {:?}",
-        error
-    );
+    let first: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    assert_eq!(context.fits_handle_pool.len(), 1);
 
-    // Invalid - out of bounds no data (channel)
-    let result = context.get_fits_filename_and_batch_and_hdu(0, 10);
-    let error = result.unwrap_err();
-    assert!(
-        matches!(
-            error,
-            GpuboxError::NoDataForTimeStepCoarseChannel {
-                timestep_index: _,
-                coarse_chan_index: _
-            }
-        ),
-        "Error was {:?}",
-        error
-    );
+    // Reading again reuses the open file
+    let second: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
+    assert_eq!(context.fits_handle_pool.len(), 1);
+    assert_eq!(first.len(), second.len());
+
+    context.close_gpubox_files();
+    assert_eq!(context.fits_handle_pool.len(), 0);
+
+    // And files are reopened as needed
+    let third: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    assert_eq!(first, third);
 }
 
 #[test]
-fn test_context_legacy_v1_get_fine_chan_feqs_one_coarse_chan() {
-    // Open the test legacy file
-    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
-    let filename = "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+fn test_read_ahead_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
 
-    //
-    // Read the observation using mwalib
-    //
-    // Open a context and load in a test metafits and gpubox file
-    let gpuboxfiles = vec![filename];
-    let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
-        .expect("Failed to create mwalibContext");
+    let gpuboxfiles = vec![mwax_filename];
+    let context = std::sync::Arc::new(
+        CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+            .expect("Failed to create CorrelatorContext"),
+    );
 
-    // Get fine channel freqs
-    let coarse_channels: Vec<usize> = vec![0];
-    let fine_chan_freqs: Vec<f64> = context.get_fine_chan_freqs_hz_array(&coarse_channels);
+    let expected: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
 
-    assert_eq!(fine_chan_freqs.len(), 128);
-    assert!(approx_eq!(
-        f64,
-        fine_chan_freqs[0],
-        138_880_000.0,
-        F64Margin::default()
+    let items: Vec<(usize, usize, Result<ReadAheadBuffer<f32>, GpuboxError>)> =
+        CorrelatorReadAhead::new(context, &[0], &[9, 10], IterationOrder::TimeMajor, 2).collect();
+    assert_eq!(items.len(), 2);
+
+    // No data for coarse chan 9
+    assert_eq!((items[0].0, items[0].1), (0, 9));
+    assert!(matches!(
+        items[0].2,
+        Err(GpuboxError::NoDataForTimeStepCoarseChannel {
+            timestep_index: 0,
+            coarse_chan_index: 9
+        })
     ));
+
+    assert_eq!((items[1].0, items[1].1), (0, 10));
+    assert_eq!(items[1].2.as_ref().unwrap()[..], expected[..]);
+    assert!(!items[1].2.as_ref().unwrap().is_missing());
 }
 
 #[test]
-fn test_context_legacy_v1_get_fine_chan_feqs_some_coarse_chans() {
-    // Open the test legacy file
+fn test_context_is_send_and_sync() {
+    // Needed to share a context between reader threads (e.g. `read_cube`, `CorrelatorReadAhead`)
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<CorrelatorContext>();
+}
+
+#[test]
+fn test_mwax_direct_read() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    let gpuboxfiles = vec![mwax_filename];
+    let mut context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    // Nothing is looked at until the first direct read
+    let key = (mwax_filename.to_string(), 1);
+    assert!(context.gpubox_data_offsets.lock().unwrap().is_empty());
+
+    let cfitsio_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    let cfitsio_by_freq: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
+    context.close_gpubox_files();
+
+    context.read_config = CorrelatorReadConfig::new().with_direct_read(true);
+    let direct_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    let direct_by_freq: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
+
+    assert_eq!(cfitsio_by_bl, direct_by_bl);
+    assert_eq!(cfitsio_by_freq, direct_by_freq);
+
+    // The one data HDU is a plain float image, and its offset is remembered
+    let (offset, num_floats) = context.gpubox_data_offsets.lock().unwrap()[&key].unwrap();
+    assert_eq!(num_floats, context.num_timestep_coarse_chan_floats);
+    assert_eq!(context.direct_read_file_pool.len(), 1);
+
+    // So once the files are closed, cfitsio is not needed
+    context.close_gpubox_files();
+    assert_eq!(context.direct_read_file_pool.len(), 0);
+    let direct_again: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    assert_eq!(cfitsio_by_bl, direct_again);
+    assert_eq!(context.fits_handle_pool.len(), 0);
+    assert_eq!(context.direct_read_file_pool.len(), 1);
+
+    // If the image is not the size of the buffer, cfitsio is used instead
+    context
+        .gpubox_data_offsets
+        .lock()
+        .unwrap()
+        .insert(key, Some((offset, num_floats - 1)));
+    let fallback_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    assert_eq!(cfitsio_by_bl, fallback_by_bl);
+    assert_eq!(context.fits_handle_pool.len(), 1);
+}
+
+#[test]
+fn test_legacy_has_no_direct_read_offsets() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let gpubox_filename =
+        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    let gpuboxfiles = vec![gpubox_filename];
+    let mut context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    // Asking for a direct read falls back to cfitsio
+    let expected: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+    context.read_config = CorrelatorReadConfig::new().with_direct_read(true);
+    let result: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+    assert_eq!(expected, result);
+    assert!(context.gpubox_data_offsets.lock().unwrap().is_empty());
+    assert_eq!(context.direct_read_file_pool.len(), 0);
+}
+
+#[test]
+fn test_validate_first_hdu() {
+    // Open the test mwax file
     let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
     let filename = "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
 
@@ -1156,28 +1637,1465 @@ fn test_context_legacy_v1_get_fine_chan_feqs_some_coarse_chans() {
     // Open a context and load in a test metafits and gpubox file
     let gpuboxfiles = vec![filename];
     let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
-        .expect("Failed to create mwalibContext");
+        .expect("Failed to create CorrelatorContext");
+
+    let coarse_chan = context.coarse_chans[0].gpubox_number;
+    let (batch_index, _) =
+        context.gpubox_time_map[&context.timesteps[0].unix_time_ms][&coarse_chan];
+
+    let mut fptr =
+        fits_open!(&context.gpubox_batches[batch_index].gpubox_files[0].filename).unwrap();
+
+    let result_valid = CorrelatorContext::validate_first_hdu(
+        context.mwa_version,
+        context.metafits_context.num_corr_fine_chans_per_coarse,
+        context.metafits_context.num_baselines,
+        context.metafits_context.num_visibility_pols,
+        &mut fptr,
+    );
+
+    let result_invalid1 = CorrelatorContext::validate_first_hdu(
+        context.mwa_version,
+        context.metafits_context.num_corr_fine_chans_per_coarse + 1,
+        context.metafits_context.num_baselines,
+        context.metafits_context.num_visibility_pols,
+        &mut fptr,
+    );
+
+    let result_invalid2 = CorrelatorContext::validate_first_hdu(
+        context.mwa_version,
+        context.metafits_context.num_corr_fine_chans_per_coarse,
+        context.metafits_context.num_baselines + 1,
+        context.metafits_context.num_visibility_pols,
+        &mut fptr,
+    );
+
+    let result_invalid3 = CorrelatorContext::validate_first_hdu(
+        context.mwa_version,
+        context.metafits_context.num_corr_fine_chans_per_coarse,
+        context.metafits_context.num_baselines,
+        context.metafits_context.num_visibility_pols + 1,
+        &mut fptr,
+    );
+
+    // This is valid
+    assert!(result_valid.is_ok());
+
+    assert!(result_invalid1.is_err());
+
+    assert!(result_invalid2.is_err());
+
+    assert!(result_invalid3.is_err());
+}
+
+#[test]
+fn test_validate_hdu_axes_good() {
+    let metafits_fine_chans_per_coarse = 128;
+    let metafits_baselines = 8256;
+    let visibility_pols = 4;
+    let result_good1 = CorrelatorContext::validate_hdu_axes(
+        MWAVersion::CorrOldLegacy,
+        metafits_fine_chans_per_coarse,
+        metafits_baselines,
+        visibility_pols,
+        8256 * 4 * 2,
+        128,
+    );
+
+    assert!(result_good1.is_ok());
+
+    let result_good2 = CorrelatorContext::validate_hdu_axes(
+        MWAVersion::CorrLegacy,
+        metafits_fine_chans_per_coarse,
+        metafits_baselines,
+        visibility_pols,
+        8256 * 4 * 2,
+        128,
+    );
+
+    assert!(result_good2.is_ok());
+
+    let result_good3 = CorrelatorContext::validate_hdu_axes(
+        MWAVersion::CorrMWAXv2,
+        metafits_fine_chans_per_coarse,
+        metafits_baselines,
+        visibility_pols,
+        128 * 4 * 2,
+        8256,
+    );
+
+    assert!(result_good3.is_ok());
+}
+
+#[test]
+fn test_validate_hdu_axes_naxis_mismatches_oldlegacy() {
+    let metafits_fine_chans_per_coarse = 128;
+    let metafits_baselines = 8256;
+    let visibility_pols = 4;
+    let values = 1;
+
+    // Check for NAXIS1 mismatch
+    let result_bad1 = CorrelatorContext::validate_hdu_axes(
+        MWAVersion::CorrOldLegacy,
+        metafits_fine_chans_per_coarse,
+        metafits_baselines,
+        visibility_pols,
+        metafits_baselines * visibility_pols * values,
+        128,
+    );
+
+    assert!(matches!(
+        result_bad1.unwrap_err(),
+        GpuboxError::LegacyNaxis1Mismatch {
+            metafits_baselines: _,
+            visibility_pols: _,
+            naxis1: _,
+            naxis2: _,
+            calculated_naxis1: _
+        }
+    ));
+
+    // Check for NAXIS2 mismatch
+    let result_bad2 = CorrelatorContext::validate_hdu_axes(
+        MWAVersion::CorrOldLegacy,
+        metafits_fine_chans_per_coarse,
+        metafits_baselines,
+        visibility_pols,
+        8256 * 4 * 2,
+        129,
+    );
+
+    assert!(matches!(
+        result_bad2.unwrap_err(),
+        GpuboxError::LegacyNaxis2Mismatch {
+            metafits_fine_chans_per_coarse: _,
+            naxis2: _,
+            calculated_naxis2: _
+        }
+    ));
+}
+
+#[test]
+fn test_validate_hdu_axes_naxis_mismatches_legacy() {
+    let metafits_fine_chans_per_coarse = 128;
+    let metafits_baselines = 8256;
+    let visibility_pols = 4;
+    let values = 1;
+
+    // Check for NAXIS1 mismatch
+    let result_bad1 = CorrelatorContext::validate_hdu_axes(
+        MWAVersion::CorrLegacy,
+        metafits_fine_chans_per_coarse,
+        metafits_baselines,
+        visibility_pols,
+        metafits_baselines * visibility_pols * values,
+        128,
+    );
+
+    assert!(matches!(
+        result_bad1.unwrap_err(),
+        GpuboxError::LegacyNaxis1Mismatch {
+            metafits_baselines: _,
+            visibility_pols: _,
+            naxis1: _,
+            naxis2: _,
+            calculated_naxis1: _
+        }
+    ));
+
+    // Check for NAXIS2 mismatch
+    let result_bad2 = CorrelatorContext::validate_hdu_axes(
+        MWAVersion::CorrLegacy,
+        metafits_fine_chans_per_coarse,
+        metafits_baselines,
+        visibility_pols,
+        8256 * 4 * 2,
+        129,
+    );
+
+    assert!(matches!(
+        result_bad2.unwrap_err(),
+        GpuboxError::LegacyNaxis2Mismatch {
+            metafits_fine_chans_per_coarse: _,
+            naxis2: _,
+            calculated_naxis2: _
+        }
+    ));
+}
+
+#[test]
+fn test_validate_hdu_axes_naxis_mismatches_v2() {
+    let metafits_fine_chans_per_coarse = 128;
+    let metafits_baselines = 8256;
+    let visibility_pols = 4;
+    let values = 2;
+
+    // Check for NAXIS1 mismatch
+    let result_bad1 = CorrelatorContext::validate_hdu_axes(
+        MWAVersion::CorrMWAXv2,
+        metafits_fine_chans_per_coarse,
+        metafits_baselines,
+        visibility_pols,
+        metafits_fine_chans_per_coarse * visibility_pols,
+        8256,
+    );
+
+    assert!(matches!(
+        result_bad1.unwrap_err(),
+        GpuboxError::MwaxNaxis1Mismatch {
+            metafits_fine_chans_per_coarse: _,
+            visibility_pols: _,
+            naxis1: _,
+            naxis2: _,
+            calculated_naxis1: _
+        }
+    ));
+
+    // Check for NAXIS2 mismatch
+    let result_bad2 = CorrelatorContext::validate_hdu_axes(
+        MWAVersion::CorrMWAXv2,
+        metafits_fine_chans_per_coarse,
+        metafits_baselines,
+        visibility_pols,
+        metafits_fine_chans_per_coarse * visibility_pols * values,
+        8257,
+    );
+
+    assert!(matches!(
+        result_bad2.unwrap_err(),
+        GpuboxError::MwaxNaxis2Mismatch {
+            metafits_baselines: _,
+            naxis2: _,
+            calculated_naxis2: _
+        }
+    ));
+}
+
+#[test]
+fn test_read_by_baseline_into_buffer_mwax() {
+    // Open the test mwa file
+    // a) using mwalib (by baseline) (data will be ordered [baseline][freq][pol][r][i])
+    // b) using mwalib (by frequency) (data will be ordered [freq][baseline][pol][r][i])
+    // Then check b) is the same as a) and
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    //
+    // Read the mwax file by frequency using mwalib
+    //
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    // Read and convert first HDU by baseline
+    let mwalib_hdu_data_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+
+    // Read and convert first HDU by frequency
+    let mwalib_hdu_data_by_freq: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
+
+    // Read into buffer by baseline
+    let mut mwalib_hdu_data_by_bl2: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+
+    let result_read_bl_buffer =
+        context.read_by_baseline_into_buffer(0, 10, &mut mwalib_hdu_data_by_bl2);
+
+    assert!(result_read_bl_buffer.is_ok());
+
+    let sum_freq: f64 = mwalib_hdu_data_by_freq
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    let sum_bl: f64 = mwalib_hdu_data_by_bl
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    let sum_bl2: f64 = mwalib_hdu_data_by_bl2
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    // Check sums are not 0
+    assert!(!approx_eq!(f64, sum_freq, 0., F64Margin::default()));
+    assert!(!approx_eq!(f64, sum_bl, 0., F64Margin::default()));
+    assert!(!approx_eq!(f64, sum_bl2, 0., F64Margin::default()));
+
+    // Check they all match each other
+    assert!(approx_eq!(f64, sum_bl, sum_freq, F64Margin::default()));
+    assert!(approx_eq!(f64, sum_bl, sum_bl2, F64Margin::default()));
+
+    // Do another test, this time with no data for the timestep/cc combo
+    let result = context.read_by_baseline_into_buffer(10, 0, &mut mwalib_hdu_data_by_bl2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            GpuboxError::NoDataForTimeStepCoarseChannel {
+                timestep_index: _,
+                coarse_chan_index: _
+            }
+        ),
+        "Error was {:?}",
+        error
+    );
+
+    // Do another test, this time with extremely out of bound indices (timestep)
+    let result = context.read_by_baseline_into_buffer(999, 10, &mut mwalib_hdu_data_by_bl2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error, GpuboxError::InvalidTimeStepIndex(119)),
+        "Error was {:?}",
+        error
+    );
+
+    // Do another test, this time with extremely out of bound indices (cc)
+    let result = context.read_by_baseline_into_buffer(0, 999, &mut mwalib_hdu_data_by_bl2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error, GpuboxError::InvalidCoarseChanIndex(23)),
+        "Error was {:?}",
+        error
+    );
+}
+
+#[test]
+fn test_read_by_frequency_into_buffer_mwax() {
+    // Open the test mwa file
+    // a) using mwalib (by baseline) (data will be ordered [baseline][freq][pol][r][i])
+    // b) using mwalib (by frequency) (data will be ordered [freq][baseline][pol][r][i])
+    // Then check b) is the same as a) and
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    //
+    // Read the mwax file by frequency using mwalib
+    //
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    // Read and convert first HDU by baseline
+    let mwalib_hdu_data_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+
+    // Read and convert first HDU by frequency
+    let mwalib_hdu_data_by_freq: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
+
+    // Read into buffer by baseline
+    let mut mwalib_hdu_data_by_freq2: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+
+    let result_read_bl_buffer =
+        context.read_by_frequency_into_buffer(0, 10, &mut mwalib_hdu_data_by_freq2);
+
+    assert!(result_read_bl_buffer.is_ok());
+
+    let sum_bl: f64 = mwalib_hdu_data_by_bl
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    let sum_freq: f64 = mwalib_hdu_data_by_freq
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    let sum_freq2: f64 = mwalib_hdu_data_by_freq2
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    // Check sums are not 0
+    assert!(!approx_eq!(f64, sum_bl, 0., F64Margin::default()));
+    assert!(!approx_eq!(f64, sum_freq, 0., F64Margin::default()));
+    assert!(!approx_eq!(f64, sum_freq2, 0., F64Margin::default()));
+
+    // Check they all match each other
+    assert!(approx_eq!(f64, sum_bl, sum_freq, F64Margin::default()));
+    assert!(approx_eq!(f64, sum_freq, sum_freq2, F64Margin::default()));
+
+    // Do another test, this time with no data for the timestep/cc combo
+    let result = context.read_by_frequency_into_buffer(10, 0, &mut mwalib_hdu_data_by_freq2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            GpuboxError::NoDataForTimeStepCoarseChannel {
+                timestep_index: _,
+                coarse_chan_index: _
+            }
+        ),
+        "Error was {:?}",
+        error
+    );
+
+    // Do another test, this time with extremely out of bound indices (timestep)
+    let result = context.read_by_frequency_into_buffer(999, 10, &mut mwalib_hdu_data_by_freq2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error, GpuboxError::InvalidTimeStepIndex(119)),
+        "Error was {:?}",
+        error
+    );
+
+    // Do another test, this time with extremely out of bound indices (cc)
+    let result = context.read_by_frequency_into_buffer(0, 999, &mut mwalib_hdu_data_by_freq2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error, GpuboxError::InvalidCoarseChanIndex(23)),
+        "Error was {:?}",
+        error
+    );
+}
+
+#[test]
+fn test_read_by_baseline_into_buffer_legacy() {
+    // Open the test mwa file
+    // a) using mwalib (by baseline) (data will be ordered [baseline][freq][pol][r][i])
+    // b) using mwalib (by frequency) (data will be ordered [freq][baseline][pol][r][i])
+    // Then check b) is the same as a) and
+    let mwax_metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let mwax_filename =
+        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    //
+    // Read the legacy file by frequency using mwalib
+    //
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    // Read and convert first HDU by baseline
+    let mwalib_hdu_data_by_bl: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+
+    // Read and convert first HDU by frequency
+    let mwalib_hdu_data_by_freq: Vec<f32> = context.read_by_frequency(0, 0).expect("Error!");
+
+    // Read into buffer by baseline
+    let mut mwalib_hdu_data_by_bl2: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+
+    let result_read_bl_buffer =
+        context.read_by_baseline_into_buffer(0, 0, &mut mwalib_hdu_data_by_bl2);
+
+    assert!(result_read_bl_buffer.is_ok());
+
+    let sum_freq: f64 = mwalib_hdu_data_by_freq
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    let sum_bl: f64 = mwalib_hdu_data_by_bl
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    let sum_bl2: f64 = mwalib_hdu_data_by_bl2
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    // Check sums are not 0
+    assert!(!approx_eq!(f64, sum_freq, 0., F64Margin::default()));
+    assert!(!approx_eq!(f64, sum_bl, 0., F64Margin::default()));
+    assert!(!approx_eq!(f64, sum_bl2, 0., F64Margin::default()));
+
+    // Check they all match each other
+    assert!(approx_eq!(f64, sum_bl, sum_freq, F64Margin::default()));
+    assert!(approx_eq!(f64, sum_bl, sum_bl2, F64Margin::default()));
+
+    // Do another test, this time with no data for the timestep/cc combo
+    let result = context.read_by_baseline_into_buffer(10, 10, &mut mwalib_hdu_data_by_bl2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            GpuboxError::NoDataForTimeStepCoarseChannel {
+                timestep_index: _,
+                coarse_chan_index: _
+            }
+        ),
+        "Error was {:?}",
+        error
+    );
+
+    // Do another test, this time with extremely out of bound indices (timestep)
+    let result = context.read_by_baseline_into_buffer(999, 0, &mut mwalib_hdu_data_by_bl2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error, GpuboxError::InvalidTimeStepIndex(55)),
+        "Error was {:?}",
+        error
+    );
+
+    // Do another test, this time with extremely out of bound indices (cc)
+    let result = context.read_by_baseline_into_buffer(0, 999, &mut mwalib_hdu_data_by_bl2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error, GpuboxError::InvalidCoarseChanIndex(23)),
+        "Error was {:?}",
+        error
+    );
+}
+
+#[test]
+fn test_read_by_frequency_into_buffer_legacy() {
+    // Open the test mwa file
+    // a) using mwalib (by baseline) (data will be ordered [baseline][freq][pol][r][i])
+    // b) using mwalib (by frequency) (data will be ordered [freq][baseline][pol][r][i])
+    // Then check b) is the same as a) and
+    let mwax_metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let mwax_filename =
+        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    //
+    // Read the legacy file by frequency using mwalib
+    //
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    // Read and convert first HDU by baseline
+    let mwalib_hdu_data_by_bl: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+
+    // Read and convert first HDU by frequency
+    let mwalib_hdu_data_by_freq: Vec<f32> = context.read_by_frequency(0, 0).expect("Error!");
+
+    // Read into buffer by baseline
+    let mut mwalib_hdu_data_by_freq2: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+
+    let result_read_bl_buffer =
+        context.read_by_frequency_into_buffer(0, 0, &mut mwalib_hdu_data_by_freq2);
+
+    assert!(result_read_bl_buffer.is_ok());
+
+    let sum_bl: f64 = mwalib_hdu_data_by_bl
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    let sum_freq: f64 = mwalib_hdu_data_by_freq
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    let sum_freq2: f64 = mwalib_hdu_data_by_freq2
+        .iter()
+        .fold(0., |sum, x| sum + *x as f64);
+
+    // Check sums are not 0
+    assert!(!approx_eq!(f64, sum_bl, 0., F64Margin::default()));
+    assert!(!approx_eq!(f64, sum_freq, 0., F64Margin::default()));
+    assert!(!approx_eq!(f64, sum_freq2, 0., F64Margin::default()));
+
+    // Check they all match each other
+    assert!(approx_eq!(f64, sum_bl, sum_freq, F64Margin::default()));
+    assert!(approx_eq!(f64, sum_freq, sum_freq2, F64Margin::default()));
+
+    // Do another test, this time with no data for the timestep/cc combo
+    let result = context.read_by_frequency_into_buffer(10, 10, &mut mwalib_hdu_data_by_freq2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            GpuboxError::NoDataForTimeStepCoarseChannel {
+                timestep_index: _,
+                coarse_chan_index: _
+            }
+        ),
+        "Error was {:?}",
+        error
+    );
+
+    // Do another test, this time with extremely out of bound indices (timestep)
+    let result = context.read_by_frequency_into_buffer(999, 0, &mut mwalib_hdu_data_by_freq2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error, GpuboxError::InvalidTimeStepIndex(55)),
+        "Error was {:?}",
+        error
+    );
+
+    // Do another test, this time with extremely out of bound indices (cc)
+    let result = context.read_by_frequency_into_buffer(0, 999, &mut mwalib_hdu_data_by_freq2);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error, GpuboxError::InvalidCoarseChanIndex(23)),
+        "Error was {:?}",
+        error
+    );
+}
+
+#[test]
+fn test_get_fits_filename_and_batch_and_hdu() {
+    let mwax_metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let mwax_filename =
+        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    //
+    // Read the legacy file by frequency using mwalib
+    //
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    // Valid
+    let result = context.get_fits_filename_and_batch_and_hdu(0, 0);
+    assert!(result.is_ok());
+
+    // Invalid - out of bounds completely (time)
+    let result = context.get_fits_filename_and_batch_and_hdu(999, 0);
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error, GpuboxError::InvalidTimeStepIndex(55)),
+        "Error was {:?}",
+        error
+    );
+
+    // Invalid - out of bounds completely (channel)
+    let result = context.get_fits_filename_and_batch_and_hdu(0, 999);
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error, GpuboxError::InvalidCoarseChanIndex(23)),
+        "Error was {:?}",
+        error
+    );
+
+    // Invalid - out of bounds no data (time)
+    let result = context.get_fits_filename_and_batch_and_hdu(10, 0);
+    let error = result.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            GpuboxError::NoDataForTimeStepCoarseChannel {
+                timestep_index: _,
+                coarse_chan_index: _
+            }
+        ),
+        "Error was {:?}",
+        error
+    );
+
+    // Invalid - out of bounds no data (channel)
+    let result = context.get_fits_filename_and_batch_and_hdu(0, 10);
+    let error = result.unwrap_err();
+    assert!(
+        matches!(
+            error,
+            GpuboxError::NoDataForTimeStepCoarseChannel {
+                timestep_index: _,
+                coarse_chan_index: _
+            }
+        ),
+        "Error was {:?}",
+        error
+    );
+}
+
+#[test]
+fn test_context_legacy_v1_get_fine_chan_feqs_one_coarse_chan() {
+    // Open the test legacy file
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let filename = "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    //
+    // Read the observation using mwalib
+    //
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![filename];
+    let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create mwalibContext");
+
+    // Get fine channel freqs
+    let coarse_channels: Vec<usize> = vec![0];
+    let fine_chan_freqs: Vec<f64> = context.get_fine_chan_freqs_hz_array(&coarse_channels);
+
+    assert_eq!(fine_chan_freqs.len(), 128);
+    assert!(approx_eq!(
+        f64,
+        fine_chan_freqs[0],
+        138_880_000.0,
+        F64Margin::default()
+    ));
+}
+
+#[test]
+fn test_context_legacy_v1_get_fine_chan_feqs_some_coarse_chans() {
+    // Open the test legacy file
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let filename = "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    //
+    // Read the observation using mwalib
+    //
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![filename];
+    let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create mwalibContext");
 
     // Get fine channel freqs
     let coarse_channels: Vec<usize> = vec![10, 20];
     let fine_chan_freqs: Vec<f64> = context.get_fine_chan_freqs_hz_array(&coarse_channels);
 
-    assert_eq!(fine_chan_freqs.len(), 256);
-    assert!(approx_eq!(
-        f64,
-        fine_chan_freqs[0],
-        151_680_000.0,
-        F64Margin::default()
+    assert_eq!(fine_chan_freqs.len(), 256);
+    assert!(approx_eq!(
+        f64,
+        fine_chan_freqs[0],
+        151_680_000.0,
+        F64Margin::default()
+    ));
+
+    assert!(
+        approx_eq!(
+            f64,
+            fine_chan_freqs[128],
+            164_480_000.0,
+            F64Margin::default()
+        ),
+        "calc value: {}",
+        fine_chan_freqs[128]
+    );
+}
+
+#[cfg(feature = "ndarray")]
+#[test]
+fn test_mwax_read_array() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    let num_baselines = context.metafits_context.num_baselines;
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    let num_pols = context.metafits_context.num_visibility_pols;
+
+    let data_by_bl: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+    let data_by_freq: Vec<f32> = context.read_by_frequency(0, 10).expect("Error!");
+
+    let array_by_bl = context.read_by_baseline_array(0, 10).expect("Error!");
+    let array_by_freq = context.read_by_frequency_array(0, 10).expect("Error!");
+
+    assert_eq!(
+        array_by_bl.shape(),
+        &[num_baselines, num_fine_chans, num_pols]
+    );
+    assert_eq!(
+        array_by_freq.shape(),
+        &[num_fine_chans, num_baselines, num_pols]
+    );
+
+    // Check every visibility matches the flat buffers
+    for baseline in 0..num_baselines {
+        for fine_chan in 0..num_fine_chans {
+            for pol in 0..num_pols {
+                let bl_index = ((baseline * num_fine_chans + fine_chan) * num_pols + pol) * 2;
+                let freq_index = ((fine_chan * num_baselines + baseline) * num_pols + pol) * 2;
+
+                let vis = array_by_bl[[baseline, fine_chan, pol]];
+                assert_eq!(vis.re, data_by_bl[bl_index]);
+                assert_eq!(vis.im, data_by_bl[bl_index + 1]);
+
+                let vis = array_by_freq[[fine_chan, baseline, pol]];
+                assert_eq!(vis.re, data_by_freq[freq_index]);
+                assert_eq!(vis.im, data_by_freq[freq_index + 1]);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ndarray")]
+#[test]
+fn test_legacy_read_into_array_non_standard_layout() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let gpubox_filename =
+        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![gpubox_filename];
+    let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    let num_baselines = context.metafits_context.num_baselines;
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    let num_pols = context.metafits_context.num_visibility_pols;
+
+    let array_by_bl = context.read_by_baseline_array(0, 0).expect("Error!");
+
+    // A [frequency][baseline][pol] array, viewed as [baseline][frequency][pol], is not in
+    // standard layout
+    let mut array: ndarray::Array3<num_complex::Complex<f32>> =
+        ndarray::Array3::zeros((num_fine_chans, num_baselines, num_pols));
+    let mut view = array.view_mut();
+    view.swap_axes(0, 1);
+    assert!(!view.is_standard_layout());
+
+    context
+        .read_by_baseline_into_array(0, 0, view)
+        .expect("Error!");
+
+    // ... which means the data should now be in [frequency][baseline][pol] order
+    let array_by_freq = context.read_by_frequency_array(0, 0).expect("Error!");
+    assert_eq!(array, array_by_freq);
+    assert_eq!(array.view().permuted_axes([1, 0, 2]), array_by_bl);
+}
+
+#[cfg(feature = "ndarray")]
+#[test]
+fn test_read_into_array_invalid_shape() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let gpubox_filename =
+        "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    // Open a context and load in a test metafits and gpubox file
+    let gpuboxfiles = vec![gpubox_filename];
+    let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    let mut array: ndarray::Array3<num_complex::Complex<f32>> = ndarray::Array3::zeros((1, 2, 3));
+    let result = context.read_by_frequency_into_array(0, 0, array.view_mut());
+
+    assert!(matches!(
+        result.unwrap_err(),
+        GpuboxError::InvalidBufferShape {
+            expected: _,
+            got: _
+        }
     ));
+}
 
-    assert!(
-        approx_eq!(
-            f64,
-            fine_chan_freqs[128],
-            164_480_000.0,
-            F64Margin::default()
+#[test]
+fn test_full_band_legacy_contiguous() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let filename = "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    let gpuboxfiles = vec![filename];
+    let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    let full_band = context.get_full_band();
+
+    // Receiver channels 109..132 are contiguous, even though gpubox numbering reverses above 128
+    assert_eq!(full_band.first_rec_chan_number, 109);
+    assert_eq!(full_band.num_slots(), 24);
+    assert!(full_band
+        .slot_coarse_chan_indices
+        .iter()
+        .all(|c| c.is_some()));
+    assert_eq!(
+        full_band.coarse_chan_indices(),
+        (0..24).collect::<Vec<usize>>()
+    );
+    assert_eq!(
+        full_band.num_fine_chans,
+        24 * full_band.num_fine_chans_per_coarse
+    );
+    assert_eq!(
+        full_band.fine_chan_freqs_hz,
+        context.get_fine_chan_freqs_hz_array(&(0..24).collect::<Vec<usize>>())
+    );
+    assert!(full_band.fine_chan_freqs_hz.windows(2).all(|f| f[0] < f[1]));
+    assert!(!full_band.is_gap(0));
+    assert!(full_band.is_gap(full_band.num_fine_chans));
+}
+
+#[test]
+fn test_full_band_gaps() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let filename = "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    let gpuboxfiles = vec![filename];
+    let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+
+    // Order and duplicates don't matter
+    let full_band = context
+        .get_full_band_for_coarse_chans(&[3, 0, 3])
+        .expect("Error!");
+
+    assert_eq!(
+        full_band.slot_coarse_chan_indices,
+        vec![Some(0), None, None, Some(3)]
+    );
+    assert_eq!(full_band.coarse_chan_indices(), vec![0, 3]);
+    assert!(!full_band.is_gap(num_fine_chans - 1));
+    assert!(full_band.is_gap(num_fine_chans));
+    assert!(!full_band.is_gap(3 * num_fine_chans));
+
+    // Gaps still have a regularly spaced frequency axis
+    let width = context.metafits_context.corr_fine_chan_width_hz as f64;
+    assert!(full_band
+        .fine_chan_freqs_hz
+        .windows(2)
+        .all(|f| (f[1] - f[0] - width).abs() < 1e-6));
+
+    assert!(matches!(
+        context.get_full_band_for_coarse_chans(&[24]).unwrap_err(),
+        GpuboxError::InvalidCoarseChanIndex(23)
+    ));
+    assert_eq!(
+        context
+            .get_full_band_for_coarse_chans(&[])
+            .expect("Error!")
+            .num_fine_chans,
+        0
+    );
+}
+
+#[test]
+fn test_read_full_band_legacy() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let filename = "test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits";
+
+    let gpuboxfiles = vec![filename];
+    let context = CorrelatorContext::new(metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    let num_baselines = context.metafits_context.num_baselines;
+    let hdu_floats = context.num_timestep_coarse_chan_floats;
+
+    // Coarse channel 0 with a gap above it
+    let full_band = context
+        .get_full_band_for_coarse_chans(&[0, 2])
+        .expect("Error!");
+
+    let by_bl = context
+        .read_full_band_by_baseline(0, &full_band)
+        .expect_err("coarse channel 2 has no data");
+    assert!(matches!(
+        by_bl,
+        GpuboxError::NoDataForTimeStepCoarseChannel { .. }
+    ));
+
+    let full_band = context
+        .get_full_band_for_coarse_chans(&[0])
+        .expect("Error!");
+    let expected_by_bl = context.read_by_baseline(0, 0).expect("Error!");
+    let expected_by_freq = context.read_by_frequency(0, 0).expect("Error!");
+
+    assert_eq!(
+        context
+            .read_full_band_by_baseline(0, &full_band)
+            .expect("Error!"),
+        expected_by_bl
+    );
+    assert_eq!(
+        context
+            .read_full_band_by_frequency(0, &full_band)
+            .expect("Error!"),
+        expected_by_freq
+    );
+
+    // Fill the missing coarse channel and the gap
+    let mut context = context;
+    context.read_config =
+        CorrelatorReadConfig::new().with_missing_data_policy(MissingDataPolicy::FillNaN);
+    let full_band = context
+        .get_full_band_for_coarse_chans(&[0, 2])
+        .expect("Error!");
+    assert_eq!(full_band.num_slots(), 3);
+
+    let by_bl = context
+        .read_full_band_by_baseline(0, &full_band)
+        .expect("Error!");
+    let floats_per_bl_slot = num_fine_chans * 8;
+    for (bl, bl_data) in by_bl.chunks_exact(3 * floats_per_bl_slot).enumerate() {
+        assert_eq!(
+            bl_data[0..floats_per_bl_slot],
+            expected_by_bl[bl * floats_per_bl_slot..(bl + 1) * floats_per_bl_slot]
+        );
+        assert!(bl_data[floats_per_bl_slot..].iter().all(|v| v.is_nan()));
+    }
+    assert_eq!(by_bl.len(), num_baselines * 3 * floats_per_bl_slot);
+
+    let by_freq = context
+        .read_full_band_by_frequency(0, &full_band)
+        .expect("Error!");
+    assert_eq!(by_freq[0..hdu_floats], expected_by_freq[..]);
+    assert!(by_freq[hdu_floats..].iter().all(|v| v.is_nan()));
+
+    let mut small_buffer: Vec<f32> = vec![0.; 10];
+    assert!(matches!(
+        context
+            .read_full_band_by_frequency_into_buffer(0, &full_band, &mut small_buffer)
+            .unwrap_err(),
+        GpuboxError::InvalidBufferSize { .. }
+    ));
+
+    // A full band whose fields have been edited so they no longer agree
+    let mut bad_full_band = full_band.clone();
+    bad_full_band.num_fine_chans += num_fine_chans;
+    let mut buffer: Vec<f32> = vec![0.; context.num_full_band_floats(&bad_full_band)];
+    assert!(matches!(
+        context
+            .read_full_band_by_baseline_into_buffer(0, &bad_full_band, &mut buffer)
+            .unwrap_err(),
+        GpuboxError::InvalidFullBand { .. }
+    ));
+
+    let mut bad_full_band = full_band.clone();
+    bad_full_band.fine_chan_freqs_hz.pop();
+    let mut buffer: Vec<f32> = vec![0.; context.num_full_band_floats(&bad_full_band)];
+    assert!(matches!(
+        context
+            .read_full_band_by_frequency_into_buffer(0, &bad_full_band, &mut buffer)
+            .unwrap_err(),
+        GpuboxError::InvalidFullBand { .. }
+    ));
+}
+
+#[test]
+fn test_get_contiguous_bands_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    let bands = context.get_contiguous_bands();
+    assert_eq!(bands.len(), 1);
+    assert_eq!(
+        bands[0].coarse_chan_indices,
+        (0..context.num_coarse_chans).collect::<Vec<usize>>()
+    );
+    assert_eq!(bands[0].first_rec_chan_number, 104);
+    assert_eq!(bands, context.metafits_context.get_contiguous_bands());
+}
+
+#[test]
+fn test_select_chans_by_freq_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    let all_coarse_chans: Vec<usize> = (0..context.num_coarse_chans).collect();
+    let fine_chan_freqs = context.get_fine_chan_freqs_hz_array(&all_coarse_chans);
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+
+    // Coarse channel 10 (receiver channel 114)
+    let centre_hz = context.coarse_chans[10].chan_centre_hz as f64;
+    assert_eq!(
+        context.get_coarse_chan_indices_for_freq_range(centre_hz, centre_hz),
+        vec![10]
+    );
+    assert_eq!(
+        context.get_coarse_chan_indices_for_freq_range(
+            context.coarse_chans[9].chan_centre_hz as f64,
+            centre_hz
         ),
-        "calc value: {}",
-        fine_chan_freqs[128]
+        vec![9, 10]
+    );
+
+    // Every fine channel of coarse channel 10
+    let fine_chans = context.get_fine_chan_indices_for_freq_range(
+        context.coarse_chans[10].chan_start_hz as f64,
+        context.coarse_chans[10].chan_end_hz as f64 - 1.,
+    );
+    assert_eq!(fine_chans.len(), num_fine_chans);
+    for (c, f) in fine_chans {
+        assert_eq!(c, 10);
+        let freq = fine_chan_freqs[c * num_fine_chans + f];
+        assert_eq!(context.get_nearest_fine_chan_index(freq), Some((c, f)));
+    }
+
+    // The metafits context agrees
+    assert_eq!(
+        context
+            .metafits_context
+            .get_coarse_chan_indices_for_freq_range(centre_hz, centre_hz),
+        vec![10]
+    );
+    assert_eq!(
+        context
+            .metafits_context
+            .get_nearest_fine_chan_index(centre_hz),
+        context.get_nearest_fine_chan_index(centre_hz)
+    );
+}
+
+#[test]
+fn test_select_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    let metafits_context = &context.metafits_context;
+
+    // Nothing selected means everything
+    let selection = context.select("").expect("Error!");
+    assert_eq!(selection.timestep_indices.len(), context.num_timesteps);
+    assert_eq!(
+        selection.coarse_chan_indices.len(),
+        context.num_coarse_chans
+    );
+    assert_eq!(
+        selection.fine_chan_indices.len(),
+        metafits_context.num_corr_fine_chans_per_coarse
+    );
+    assert_eq!(selection.antenna_indices.len(), metafits_context.num_ants);
+    assert_eq!(
+        selection.baseline_indices.len(),
+        metafits_context.num_baselines
+    );
+    assert_eq!(selection.pol_indices, vec![0, 1, 2, 3]);
+
+    let tile0 = metafits_context.antennas[0].tile_name.clone();
+    let tile1 = metafits_context.antennas[1].tile_name.clone();
+    let selection = context
+        .select(&format!(
+            "time=0~1; chan=113~114,!113; fine_chan=!0; baseline=!auto; tile={},{}; pol=yy,XX",
+            tile1, tile0
+        ))
+        .expect("Error!");
+    assert_eq!(selection.timestep_indices, vec![0, 1]);
+    assert_eq!(selection.coarse_chan_indices, vec![10]);
+    assert_eq!(
+        selection.fine_chan_indices,
+        (1..metafits_context.num_corr_fine_chans_per_coarse).collect::<Vec<usize>>()
+    );
+    assert_eq!(selection.antenna_indices, vec![0, 1]);
+    // Only the cross correlation between the two tiles
+    assert_eq!(selection.baseline_indices, vec![1]);
+    assert_eq!(selection.pol_indices, vec![0, 3]);
+
+    // The selection can be used to read
+    let data = context
+        .read_by_baseline_subset(
+            selection.timestep_indices[0],
+            selection.coarse_chan_indices[0],
+            &selection.baseline_indices,
+            &selection.fine_chan_indices,
+        )
+        .expect("Error!");
+    assert_eq!(
+        data.len(),
+        selection.fine_chan_indices.len() * metafits_context.num_visibility_pols * 2
+    );
+
+    // Baselines by tile pair, in either order
+    let selection = context
+        .select(&format!("baseline={}-{}", tile1, tile0))
+        .expect("Error!");
+    assert_eq!(selection.baseline_indices, vec![1]);
+
+    // Errors point at the offending token
+    let expression = "time=0; chan=200";
+    assert_eq!(
+        context.select(expression).unwrap_err(),
+        SelectionError::UnknownChannel {
+            token: String::from("200"),
+            position: 13
+        }
+    );
+    let error = context.select("time=0~1000").unwrap_err();
+    assert!(matches!(error, SelectionError::IndexOutOfRange { .. }));
+    assert_eq!(error.position(), 5);
+    assert!(matches!(
+        context.select("tile=NoSuchTile").unwrap_err(),
+        SelectionError::UnknownTile { position: 5, .. }
+    ));
+    assert!(matches!(
+        context.select("baseline=Tile").unwrap_err(),
+        SelectionError::InvalidBaseline { position: 9, .. }
+    ));
+    assert!(matches!(
+        context.select("pol=RR").unwrap_err(),
+        SelectionError::UnknownPol { position: 4, .. }
+    ));
+    assert!(matches!(
+        context.select(" pol=!XX,!XY,!YX,!YY").unwrap_err(),
+        SelectionError::EmptySelection { position: 1, .. }
+    ));
+    assert!(!context
+        .select(&format!("baseline=auto; tile=!{}", tile0))
+        .expect("Error!")
+        .baseline_indices
+        .contains(&0));
+}
+
+#[test]
+fn test_visibility_view_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+
+    let by_bl = context.read_by_baseline(0, 10).expect("Error!");
+    let by_freq = context.read_by_frequency(0, 10).expect("Error!");
+
+    let bl_view = context
+        .visibility_view(&by_bl, VisibilityOrder::ByBaseline)
+        .expect("Error!");
+    let freq_view = context
+        .visibility_view(&by_freq, VisibilityOrder::ByFrequency)
+        .expect("Error!");
+
+    let num_baselines = context.metafits_context.num_baselines;
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    assert_eq!(bl_view.num_baselines(), num_baselines);
+    assert_eq!(bl_view.num_fine_chans(), num_fine_chans);
+    assert_eq!(bl_view.num_pols(), 4);
+
+    // Both orderings give the same visibilities
+    for (b, f, p) in [
+        (0, 0, 0),
+        (1, 2, 3),
+        (num_baselines - 1, num_fine_chans - 1, 1),
+    ] {
+        assert_eq!(bl_view.get(b, f, p), freq_view.get(b, f, p));
+    }
+    assert_eq!(
+        bl_view.get_index(1, 2, 3),
+        ((num_fine_chans + 2) * 4 + 3) * 2
+    );
+    assert_eq!(
+        freq_view.get_index(1, 2, 3),
+        ((2 * num_baselines + 1) * 4 + 3) * 2
+    );
+    assert_eq!(
+        bl_view.get(1, 2, 3),
+        num_complex::Complex::new(
+            by_bl[bl_view.get_index(1, 2, 3)],
+            by_bl[bl_view.get_index(1, 2, 3) + 1]
+        )
+    );
+
+    // Slices are only available when contiguous, but whole baselines and channels are always available
+    assert_eq!(
+        bl_view.get_baseline_slice(1).unwrap().len(),
+        num_fine_chans * 8
+    );
+    assert!(freq_view.get_baseline_slice(1).is_none());
+    assert_eq!(
+        freq_view.get_fine_chan_slice(2).unwrap().len(),
+        num_baselines * 8
+    );
+    assert!(bl_view.get_fine_chan_slice(2).is_none());
+    assert_eq!(bl_view.get_baseline(1), freq_view.get_baseline(1));
+    assert_eq!(bl_view.get_fine_chan(2), freq_view.get_fine_chan(2));
+
+    // Lookups by tile name and VisPol
+    let tile0 = context.metafits_context.antennas[0].tile_name.clone();
+    let tile1 = context.metafits_context.antennas[1].tile_name.clone();
+    assert_eq!(bl_view.get_baseline_index(&tile0, &tile1), Some(1));
+    assert_eq!(bl_view.get_baseline_index(&tile1, &tile0), Some(1));
+    assert_eq!(bl_view.get_baseline_index(&tile0, "NoSuchTile"), None);
+    assert_eq!(
+        bl_view.get_by_vis_pol(1, 2, VisPol::YY),
+        Some(bl_view.get(1, 2, 3))
+    );
+    assert_eq!(
+        bl_view.get_by_tile_names(&tile0, &tile1, 2, VisPol::XY),
+        Some(bl_view.get(1, 2, 1))
+    );
+    assert_eq!(
+        bl_view.get_by_tile_names(&tile1, &tile0, 2, VisPol::XY),
+        Some(bl_view.get(1, 2, 2).conj())
+    );
+
+    // Wrong size buffer
+    assert!(matches!(
+        context
+            .visibility_view(&by_bl[1..], VisibilityOrder::ByBaseline)
+            .unwrap_err(),
+        GpuboxError::InvalidBufferSize { .. }
+    ));
+}
+
+#[test]
+fn test_visibility_view_pseudo_stokes_i() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    let gpuboxfiles = vec![mwax_filename];
+    let mut context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    context.read_config = CorrelatorReadConfig::new().with_pol_products(PolProducts::PseudoStokesI);
+
+    let data = context.read_by_baseline(0, 10).expect("Error!");
+    let view = context
+        .visibility_view(&data, VisibilityOrder::ByBaseline)
+        .expect("Error!");
+
+    assert_eq!(view.num_pols(), 1);
+    assert_eq!(view.get_by_vis_pol(0, 0, VisPol::XX), None);
+    assert_eq!(
+        view.get(0, 1, 0),
+        num_complex::Complex::new(data[2], data[3])
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_visibility_view_out_of_range() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    let gpuboxfiles = vec![mwax_filename];
+    let context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    let data: Vec<f32> = vec![0.; context.num_timestep_coarse_chan_floats];
+    let view = context
+        .visibility_view(&data, VisibilityOrder::ByBaseline)
+        .expect("Error!");
+
+    view.get(0, 0, 4);
+}
+
+/// Reorders a default (MWAX layout) read by baseline into `layout`, the slow way.
+fn relayout_by_baseline(
+    context: &CorrelatorContext,
+    data: &[f32],
+    layout: &VisibilityLayout,
+    by_frequency: bool,
+) -> Vec<f32> {
+    let num_fine_chans = context.metafits_context.num_corr_fine_chans_per_coarse;
+    let output_baselines = layout.get_output_baseline_indices(&context.metafits_context.baselines);
+    let num_output_baselines = output_baselines.len();
+    let sign = match layout.conjugation {
+        ConjugationConvention::Mwax => 1.,
+        ConjugationConvention::Opposite => -1.,
+    };
+    let mut output = vec![0.; num_output_baselines * num_fine_chans * 8];
+
+    for (output_baseline, &baseline) in output_baselines.iter().enumerate() {
+        for fine_chan in 0..num_fine_chans {
+            let vis = if by_frequency {
+                fine_chan * num_output_baselines + output_baseline
+            } else {
+                output_baseline * num_fine_chans + fine_chan
+            };
+
+            for pol in 0..4 {
+                let source = ((baseline * num_fine_chans + fine_chan) * 4 + pol) * 2;
+                let destination = if layout.pol_major {
+                    (pol * num_output_baselines * num_fine_chans + vis) * 2
+                } else {
+                    (vis * 4 + pol) * 2
+                };
+                output[destination] = data[source];
+                output[destination + 1] = sign * data[source + 1];
+            }
+        }
+    }
+
+    output
+}
+
+#[test]
+fn test_read_with_layout_mwax() {
+    let mwax_metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let mwax_filename = "test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits";
+
+    let gpuboxfiles = vec![mwax_filename];
+    let mut context = CorrelatorContext::new(mwax_metafits_filename, &gpuboxfiles)
+        .expect("Failed to create CorrelatorContext");
+    let default_data: Vec<f32> = context.read_by_baseline(0, 10).expect("Error!");
+
+    let layout = VisibilityLayout::new()
+        .with_baseline_order(BaselineOrder::Ant2Major)
+        .with_conjugation(ConjugationConvention::Opposite)
+        .with_exclude_autos(true);
+    context.read_config = CorrelatorReadConfig::new().with_layout(layout);
+
+    assert_eq!(
+        context.num_output_baselines(),
+        context.metafits_context.num_baselines - context.metafits_context.num_ants
+    );
+    assert_eq!(
+        context.num_output_timestep_coarse_chan_floats(),
+        context.num_output_baselines()
+            * context.metafits_context.num_corr_fine_chans_per_coarse
+            * 8
+    );
+    assert_eq!(
+        context.get_output_baseline_indices(),
+        layout.get_output_baseline_indices(&context.metafits_context.baselines)
     );
+
+    for by_frequency in [false, true] {
+        for pol_major in [false, true] {
+            let layout = layout.with_pol_major(pol_major);
+            context.read_config = CorrelatorReadConfig::new().with_layout(layout);
+
+            let data = if by_frequency {
+                context.read_by_frequency(0, 10)
+            } else {
+                context.read_by_baseline(0, 10)
+            }
+            .expect("Error!");
+
+            assert_eq!(
+                data,
+                relayout_by_baseline(&context, &default_data, &layout, by_frequency)
+            );
+        }
+    }
+
+    // Pol-major is only possible for linear pols, and other read functions only support the default layout
+    context.read_config = CorrelatorReadConfig::new()
+        .with_layout(layout.with_pol_major(true))
+        .with_pol_products(PolProducts::Stokes);
+    assert!(matches!(
+        context.read_by_baseline(0, 10).unwrap_err(),
+        GpuboxError::UnsupportedVisibilityLayout(_)
+    ));
+
+    context.read_config = CorrelatorReadConfig::new().with_layout(layout);
+    let data = context.read_by_baseline(0, 10).expect("Error!");
+    assert!(matches!(
+        context
+            .visibility_view(&data, VisibilityOrder::ByBaseline)
+            .unwrap_err(),
+        GpuboxError::UnsupportedVisibilityLayout(_)
+    ));
+    assert!(matches!(
+        context
+            .read_full_band_by_baseline(0, &context.get_full_band())
+            .unwrap_err(),
+        GpuboxError::UnsupportedVisibilityLayout(_)
+    ));
+}
+
+#[test]
+fn test_read_with_layout_legacy() {
+    let metafits = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let gpuboxfiles =
+        vec!["test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits"];
+    let mut context =
+        CorrelatorContext::new(metafits, &gpuboxfiles).expect("Failed to create CorrelatorContext");
+
+    let layout = VisibilityLayout::new()
+        .with_baseline_order(BaselineOrder::Ant2Major)
+        .with_conjugation(ConjugationConvention::Opposite)
+        .with_exclude_autos(true)
+        .with_pol_major(true);
+
+    // With and without the Van Vleck correction, which takes a different path for legacy data
+    for apply_van_vleck_correction in [false, true] {
+        context.read_config =
+            CorrelatorReadConfig::new().with_van_vleck_correction(apply_van_vleck_correction);
+        let default_data: Vec<f32> = context.read_by_baseline(0, 0).expect("Error!");
+
+        context.read_config = context.read_config.with_layout(layout);
+        assert_eq!(
+            context.read_by_frequency(0, 0).expect("Error!"),
+            relayout_by_baseline(&context, &default_data, &layout, true)
+        );
+    }
 }
@@ -0,0 +1,409 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A read-only view over a buffer of visibilities for one timestep and coarse channel, which does the
+//! index arithmetic for either ordering of the read functions.
+
+use num_complex::Complex;
+
+use super::*;
+
+/// The order of the visibilities in a buffer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisibilityOrder {
+    /// [baseline][frequency][pol][r][i], as returned by `read_by_baseline`
+    ByBaseline = 0,
+    /// [frequency][baseline][pol][r][i], as returned by `read_by_frequency`
+    ByFrequency = 1,
+}
+
+/// Implements fmt::Display for VisibilityOrder enum
+///
+/// # Arguments
+///
+/// * `f` - A fmt::Formatter
+///
+///
+/// # Returns
+///
+/// * `fmt::Result` - Result of this method
+///
+///
+impl fmt::Display for VisibilityOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                VisibilityOrder::ByBaseline => "by baseline",
+                VisibilityOrder::ByFrequency => "by frequency",
+            }
+        )
+    }
+}
+
+/// A view over the visibilities of one timestep and coarse channel, as read from a `CorrelatorContext`.
+///
+/// Indexing methods panic if an index is out of range, in the same way as indexing a slice does.
+#[derive(Debug, Clone, Copy)]
+pub struct VisibilityView<'a> {
+    /// The visibilities
+    data: &'a [f32],
+    /// The order of `data`
+    order: VisibilityOrder,
+    /// The metafits context of the observation the data was read from, for tile lookups
+    metafits_context: &'a MetafitsContext,
+    /// The polarisation products of `data`
+    pol_products: PolProducts,
+    /// Number of baselines in `data`
+    num_baselines: usize,
+    /// Number of fine channels in `data`
+    num_fine_chans: usize,
+    /// Number of pols in each visibility of `data`
+    num_pols: usize,
+}
+
+impl<'a> VisibilityView<'a> {
+    /// Returns the order of the underlying buffer.
+    pub fn order(&self) -> VisibilityOrder {
+        self.order
+    }
+
+    /// Returns the polarisation products of the underlying buffer.
+    pub fn pol_products(&self) -> PolProducts {
+        self.pol_products
+    }
+
+    /// Returns the number of baselines in the view.
+    pub fn num_baselines(&self) -> usize {
+        self.num_baselines
+    }
+
+    /// Returns the number of fine channels in the view.
+    pub fn num_fine_chans(&self) -> usize {
+        self.num_fine_chans
+    }
+
+    /// Returns the number of pols in each visibility of the view.
+    pub fn num_pols(&self) -> usize {
+        self.num_pols
+    }
+
+    /// Returns the underlying buffer.
+    pub fn data(&self) -> &'a [f32] {
+        self.data
+    }
+
+    /// Returns the index within the underlying buffer of the real part of a visibility.
+    ///
+    /// # Arguments
+    ///
+    /// * `baseline_index` - index within the metafits baseline array.
+    ///
+    /// * `fine_chan_index` - index of the fine channel within the coarse channel.
+    ///
+    /// * `pol_index` - index of the pol within the visibility.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The index of the real part. The imaginary part immediately follows it.
+    ///
+    pub fn get_index(
+        &self,
+        baseline_index: usize,
+        fine_chan_index: usize,
+        pol_index: usize,
+    ) -> usize {
+        assert!(
+            baseline_index < self.num_baselines
+                && fine_chan_index < self.num_fine_chans
+                && pol_index < self.num_pols,
+            "index (baseline {}, fine chan {}, pol {}) out of range (baselines {}, fine chans {}, pols {})",
+            baseline_index,
+            fine_chan_index,
+            pol_index,
+            self.num_baselines,
+            self.num_fine_chans,
+            self.num_pols
+        );
+
+        let vis_index = match self.order {
+            VisibilityOrder::ByBaseline => baseline_index * self.num_fine_chans + fine_chan_index,
+            VisibilityOrder::ByFrequency => fine_chan_index * self.num_baselines + baseline_index,
+        };
+
+        (vis_index * self.num_pols + pol_index) * 2
+    }
+
+    /// Returns a single visibility.
+    ///
+    /// # Arguments
+    ///
+    /// * `baseline_index` - index within the metafits baseline array.
+    ///
+    /// * `fine_chan_index` - index of the fine channel within the coarse channel.
+    ///
+    /// * `pol_index` - index of the pol within the visibility.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The complex visibility.
+    ///
+    pub fn get(
+        &self,
+        baseline_index: usize,
+        fine_chan_index: usize,
+        pol_index: usize,
+    ) -> Complex<f32> {
+        let index = self.get_index(baseline_index, fine_chan_index, pol_index);
+
+        Complex::new(self.data[index], self.data[index + 1])
+    }
+
+    /// Returns the index of a `VisPol` within each visibility.
+    ///
+    /// # Arguments
+    ///
+    /// * `vis_pol` - the polarisation.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The pol index, or None if the view is not of linear polarisations.
+    ///
+    pub fn get_vis_pol_index(&self, vis_pol: VisPol) -> Option<usize> {
+        match self.pol_products {
+            PolProducts::Linear => Some(vis_pol as usize - 1),
+            _ => None,
+        }
+    }
+
+    /// Returns a single visibility, selecting the pol with a `VisPol`.
+    ///
+    /// # Arguments
+    ///
+    /// * `baseline_index` - index within the metafits baseline array.
+    ///
+    /// * `fine_chan_index` - index of the fine channel within the coarse channel.
+    ///
+    /// * `vis_pol` - the polarisation.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The complex visibility, or None if the view is not of linear polarisations.
+    ///
+    pub fn get_by_vis_pol(
+        &self,
+        baseline_index: usize,
+        fine_chan_index: usize,
+        vis_pol: VisPol,
+    ) -> Option<Complex<f32>> {
+        self.get_vis_pol_index(vis_pol)
+            .map(|pol_index| self.get(baseline_index, fine_chan_index, pol_index))
+    }
+
+    /// Returns the index of the baseline between two tiles.
+    ///
+    /// # Arguments
+    ///
+    /// * `tile1_name` - name of the first tile.
+    ///
+    /// * `tile2_name` - name of the second tile.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The baseline index, or None if either tile is not in the observation. The tiles can be given in either order.
+    ///
+    pub fn get_baseline_index(&self, tile1_name: &str, tile2_name: &str) -> Option<usize> {
+        let (ant1_index, ant2_index) = self.get_antenna_indices(tile1_name, tile2_name)?;
+
+        get_baseline_from_antennas(
+            ant1_index.min(ant2_index),
+            ant1_index.max(ant2_index),
+            self.metafits_context.num_ants,
+        )
+    }
+
+    /// Returns a single visibility between two tiles, selecting the pol with a `VisPol`.
+    ///
+    /// If the tiles are given in the opposite order to the baseline stored in the buffer (i.e. tile1 is after tile2
+    /// in the metafits antennas), the visibility is conjugated and XY and YX are swapped, so that the result is the
+    /// visibility of the baseline in the order the tiles were given.
+    ///
+    /// # Arguments
+    ///
+    /// * `tile1_name` - name of the first tile.
+    ///
+    /// * `tile2_name` - name of the second tile.
+    ///
+    /// * `fine_chan_index` - index of the fine channel within the coarse channel.
+    ///
+    /// * `vis_pol` - the polarisation.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The complex visibility, or None if either tile is not in the observation or the view is not of linear
+    ///   polarisations.
+    ///
+    pub fn get_by_tile_names(
+        &self,
+        tile1_name: &str,
+        tile2_name: &str,
+        fine_chan_index: usize,
+        vis_pol: VisPol,
+    ) -> Option<Complex<f32>> {
+        let (ant1_index, ant2_index) = self.get_antenna_indices(tile1_name, tile2_name)?;
+        let baseline_index = self.get_baseline_index(tile1_name, tile2_name)?;
+
+        if ant1_index <= ant2_index {
+            self.get_by_vis_pol(baseline_index, fine_chan_index, vis_pol)
+        } else {
+            let swapped_vis_pol = match vis_pol {
+                VisPol::XY => VisPol::YX,
+                VisPol::YX => VisPol::XY,
+                p => p,
+            };
+            self.get_by_vis_pol(baseline_index, fine_chan_index, swapped_vis_pol)
+                .map(|v| v.conj())
+        }
+    }
+
+    /// Returns the underlying buffer for one baseline, if it is contiguous (i.e. the order is `ByBaseline`).
+    ///
+    /// # Arguments
+    ///
+    /// * `baseline_index` - index within the metafits baseline array.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The baseline's floats in [frequency][pol][r][i] order, or None if the order is `ByFrequency`.
+    ///
+    pub fn get_baseline_slice(&self, baseline_index: usize) -> Option<&'a [f32]> {
+        match self.order {
+            VisibilityOrder::ByBaseline => {
+                let start = self.get_index(baseline_index, 0, 0);
+                Some(&self.data[start..start + self.num_fine_chans * self.num_pols * 2])
+            }
+            VisibilityOrder::ByFrequency => None,
+        }
+    }
+
+    /// Returns the underlying buffer for one fine channel, if it is contiguous (i.e. the order is `ByFrequency`).
+    ///
+    /// # Arguments
+    ///
+    /// * `fine_chan_index` - index of the fine channel within the coarse channel.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The fine channel's floats in [baseline][pol][r][i] order, or None if the order is `ByBaseline`.
+    ///
+    pub fn get_fine_chan_slice(&self, fine_chan_index: usize) -> Option<&'a [f32]> {
+        match self.order {
+            VisibilityOrder::ByFrequency => {
+                let start = self.get_index(0, fine_chan_index, 0);
+                Some(&self.data[start..start + self.num_baselines * self.num_pols * 2])
+            }
+            VisibilityOrder::ByBaseline => None,
+        }
+    }
+
+    /// Returns all of the visibilities of one baseline, whatever the order.
+    ///
+    /// # Arguments
+    ///
+    /// * `baseline_index` - index within the metafits baseline array.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A vector of complex visibilities in [frequency][pol] order.
+    ///
+    pub fn get_baseline(&self, baseline_index: usize) -> Vec<Complex<f32>> {
+        (0..self.num_fine_chans)
+            .flat_map(|f| (0..self.num_pols).map(move |p| (f, p)))
+            .map(|(f, p)| self.get(baseline_index, f, p))
+            .collect()
+    }
+
+    /// Returns all of the visibilities of one fine channel, whatever the order.
+    ///
+    /// # Arguments
+    ///
+    /// * `fine_chan_index` - index of the fine channel within the coarse channel.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A vector of complex visibilities in [baseline][pol] order.
+    ///
+    pub fn get_fine_chan(&self, fine_chan_index: usize) -> Vec<Complex<f32>> {
+        (0..self.num_baselines)
+            .flat_map(|b| (0..self.num_pols).map(move |p| (b, p)))
+            .map(|(b, p)| self.get(b, fine_chan_index, p))
+            .collect()
+    }
+
+    /// Looks up the antenna indices of two tiles by name.
+    fn get_antenna_indices(&self, tile1_name: &str, tile2_name: &str) -> Option<(usize, usize)> {
+        let find = |name: &str| {
+            self.metafits_context
+                .antennas
+                .iter()
+                .position(|a| a.tile_name == name)
+        };
+
+        Some((find(tile1_name)?, find(tile2_name)?))
+    }
+}
+
+impl CorrelatorContext {
+    /// Create a `VisibilityView` over a buffer read from this context (e.g. by `read_by_baseline` or
+    /// `read_by_frequency`) with the current `read_config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - the visibilities of one timestep and coarse channel.
+    ///
+    /// * `order` - the order of `data`.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the `VisibilityView` if Ok, or a GpuboxError if `data` is not the right length.
+    ///
+    pub fn visibility_view<'a>(
+        &'a self,
+        data: &'a [f32],
+        order: VisibilityOrder,
+    ) -> Result<VisibilityView<'a>, GpuboxError> {
+        self.validate_default_layout()?;
+
+        let expected_len = self.num_output_timestep_coarse_chan_floats();
+        if data.len() != expected_len {
+            return Err(GpuboxError::InvalidBufferSize {
+                expected: expected_len,
+                got: data.len(),
+            });
+        }
+
+        Ok(VisibilityView {
+            data,
+            order,
+            metafits_context: &self.metafits_context,
+            pol_products: self.read_config.pol_products,
+            num_baselines: self.metafits_context.num_baselines,
+            num_fine_chans: self.metafits_context.num_corr_fine_chans_per_coarse,
+            num_pols: self.num_output_pols(),
+        })
+    }
+}
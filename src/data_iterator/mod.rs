@@ -0,0 +1,281 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Iterators over the data of an observation, one timestep and coarse channel at a time.
+
+use crate::correlator_context::CorrelatorContext;
+use crate::gpubox_files::GpuboxError;
+use crate::voltage_context::VoltageContext;
+use crate::voltage_files::error::VoltageFileError;
+
+mod read_ahead;
+pub use read_ahead::{CorrelatorReadAhead, ReadAhead, ReadAheadBuffer, VoltageReadAhead};
+
+#[cfg(test)]
+mod test;
+
+/// The order in which timesteps and coarse channels are visited by a data iterator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IterationOrder {
+    /// Visit every coarse channel of a timestep before moving on to the next timestep.
+    TimeMajor,
+    /// Visit every timestep of a coarse channel before moving on to the next coarse channel.
+    FrequencyMajor,
+}
+
+/// Which timestep and coarse channel indices a data iterator visits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexSet {
+    /// Every timestep and coarse channel in the context.
+    All,
+    /// The `provided_timestep_indices` and `provided_coarse_chan_indices`.
+    Provided,
+    /// The `common_timestep_indices` and `common_coarse_chan_indices`.
+    Common,
+    /// The `common_good_timestep_indices` and `common_good_coarse_chan_indices`.
+    CommonGood,
+}
+
+/// Generates the (timestep index, coarse chan index) pairs to visit, in the requested order.
+///
+/// # Arguments
+///
+/// * `timestep_indices` - the timestep indices to visit.
+///
+/// * `coarse_chan_indices` - the coarse channel indices to visit.
+///
+/// * `order` - the order in which to visit them.
+///
+///
+/// # Returns
+///
+/// * A vector of every combination of timestep and coarse channel index.
+///
+pub(crate) fn get_index_pairs(
+    timestep_indices: &[usize],
+    coarse_chan_indices: &[usize],
+    order: IterationOrder,
+) -> Vec<(usize, usize)> {
+    match order {
+        IterationOrder::TimeMajor => timestep_indices
+            .iter()
+            .flat_map(|&t| coarse_chan_indices.iter().map(move |&c| (t, c)))
+            .collect(),
+        IterationOrder::FrequencyMajor => coarse_chan_indices
+            .iter()
+            .flat_map(|&c| timestep_indices.iter().map(move |&t| (t, c)))
+            .collect(),
+    }
+}
+
+///
+/// Iterator over the visibilities of a `CorrelatorContext`, one HDU at a time.
+///
+/// Each item is (timestep index, coarse chan index, Result containing the data from `read_by_baseline`
+/// and whether it was missing). A timestep and coarse channel with no data yields
+/// `GpuboxError::NoDataForTimeStepCoarseChannel`, so callers can decide whether to skip it or stop,
+/// unless the context's `read_config.missing_data_policy` says to fill it in instead, in which case
+/// the data is the fill value and the missing flag is true.
+///
+/// Iterating with `next()` allocates a new vector for every HDU. To reuse a buffer, call
+/// `next_into_buffer()` instead.
+///
+#[derive(Debug)]
+pub struct CorrelatorDataIterator<'a> {
+    context: &'a CorrelatorContext,
+    index_pairs: Vec<(usize, usize)>,
+    position: usize,
+}
+
+impl<'a> CorrelatorDataIterator<'a> {
+    /// Creates a new `CorrelatorDataIterator` over the given indices.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - the `CorrelatorContext` to read from.
+    ///
+    /// * `corr_timestep_indices` - indices within the CorrelatorContext timestep array to visit.
+    ///
+    /// * `corr_coarse_chan_indices` - indices within the CorrelatorContext coarse_chan array to visit.
+    ///
+    /// * `order` - the order in which to visit them.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A new `CorrelatorDataIterator`
+    ///
+    pub fn new(
+        context: &'a CorrelatorContext,
+        corr_timestep_indices: &[usize],
+        corr_coarse_chan_indices: &[usize],
+        order: IterationOrder,
+    ) -> Self {
+        Self {
+            context,
+            index_pairs: get_index_pairs(corr_timestep_indices, corr_coarse_chan_indices, order),
+            position: 0,
+        }
+    }
+
+    /// Reads the next timestep and coarse channel into a supplied buffer, in [baseline][frequency][pol][r][i] order.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Float buffer as a slice which will be filled with the data. Must be `num_output_timestep_coarse_chan_floats()` long.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * None when the iterator is exhausted, otherwise the timestep index, coarse chan index and a Result
+    ///   containing whether there was no data (so the buffer was filled according to
+    ///   `read_config.missing_data_policy`).
+    ///
+    pub fn next_into_buffer(
+        &mut self,
+        buffer: &mut [f32],
+    ) -> Option<(usize, usize, Result<bool, GpuboxError>)> {
+        let (timestep_index, coarse_chan_index) = *self.index_pairs.get(self.position)?;
+        self.position += 1;
+
+        Some((
+            timestep_index,
+            coarse_chan_index,
+            self.context
+                .read_by_baseline_into_buffer(timestep_index, coarse_chan_index, buffer)
+                .map(|_| !self.context.has_data(timestep_index, coarse_chan_index)),
+        ))
+    }
+}
+
+impl<'a> Iterator for CorrelatorDataIterator<'a> {
+    type Item = (usize, usize, Result<(Vec<f32>, bool), GpuboxError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (timestep_index, coarse_chan_index) = *self.index_pairs.get(self.position)?;
+        self.position += 1;
+
+        Some((
+            timestep_index,
+            coarse_chan_index,
+            self.context
+                .read_by_baseline(timestep_index, coarse_chan_index)
+                .map(|data| {
+                    (
+                        data,
+                        !self.context.has_data(timestep_index, coarse_chan_index),
+                    )
+                }),
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.index_pairs.len() - self.position;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for CorrelatorDataIterator<'a> {}
+
+///
+/// Iterator over the voltage data files of a `VoltageContext`, one file at a time.
+///
+/// Each item is (timestep index, coarse chan index, result of `read_file`). A timestep and
+/// coarse channel with no data yields `VoltageFileError::NoDataForTimeStepCoarseChannel`, so callers
+/// can decide whether to skip it or stop.
+///
+/// Iterating with `next()` allocates a new vector for every file. To reuse a buffer, call
+/// `next_into_buffer()` instead.
+///
+#[derive(Debug)]
+pub struct VoltageDataIterator<'a> {
+    context: &'a VoltageContext,
+    index_pairs: Vec<(usize, usize)>,
+    position: usize,
+}
+
+impl<'a> VoltageDataIterator<'a> {
+    /// Creates a new `VoltageDataIterator` over the given indices.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - the `VoltageContext` to read from.
+    ///
+    /// * `volt_timestep_indices` - indices within the VoltageContext timestep array to visit.
+    ///
+    /// * `volt_coarse_chan_indices` - indices within the VoltageContext coarse_chan array to visit.
+    ///
+    /// * `order` - the order in which to visit them.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A new `VoltageDataIterator`
+    ///
+    pub fn new(
+        context: &'a VoltageContext,
+        volt_timestep_indices: &[usize],
+        volt_coarse_chan_indices: &[usize],
+        order: IterationOrder,
+    ) -> Self {
+        Self {
+            context,
+            index_pairs: get_index_pairs(volt_timestep_indices, volt_coarse_chan_indices, order),
+            position: 0,
+        }
+    }
+
+    /// Reads the next timestep and coarse channel into a supplied buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Byte buffer as a slice which will be filled with the data from one voltage data file.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * None when the iterator is exhausted, otherwise the timestep index, coarse chan index and the result of the read.
+    ///
+    pub fn next_into_buffer(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Option<(usize, usize, Result<(), VoltageFileError>)> {
+        let (timestep_index, coarse_chan_index) = *self.index_pairs.get(self.position)?;
+        self.position += 1;
+
+        Some((
+            timestep_index,
+            coarse_chan_index,
+            self.context
+                .read_file(timestep_index, coarse_chan_index, buffer),
+        ))
+    }
+}
+
+impl<'a> Iterator for VoltageDataIterator<'a> {
+    type Item = (usize, usize, Result<Vec<u8>, VoltageFileError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.index_pairs.len() {
+            return None;
+        }
+
+        let mut buffer: Vec<u8> = vec![
+            0;
+            (self.context.voltage_block_size_bytes * self.context.num_voltage_blocks_per_timestep)
+                as usize
+        ];
+
+        let (timestep_index, coarse_chan_index, result) = self.next_into_buffer(&mut buffer)?;
+
+        Some((timestep_index, coarse_chan_index, result.map(|_| buffer)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.index_pairs.len() - self.position;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for VoltageDataIterator<'a> {}
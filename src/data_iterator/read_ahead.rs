@@ -0,0 +1,273 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Read-ahead of observation data on a background thread, so that I/O can overlap with processing.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::Arc;
+use std::thread;
+
+use super::*;
+
+///
+/// A buffer of data for one timestep and coarse channel, owned by the caller.
+///
+/// When it is dropped the underlying memory is handed back to the `ReadAhead` it came from, to be
+/// reused for a later read. Use `into_vec()` to keep the memory instead.
+///
+#[derive(Debug)]
+pub struct ReadAheadBuffer<T> {
+    data: Vec<T>,
+    missing: bool,
+    recycle: Option<Sender<Vec<T>>>,
+}
+
+impl<T> ReadAheadBuffer<T> {
+    /// Whether there was no data for this timestep and coarse channel, so the buffer was filled
+    /// according to the context's `read_config.missing_data_policy`.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * true if the data is a fill value rather than real data.
+    ///
+    pub fn is_missing(&self) -> bool {
+        self.missing
+    }
+
+    /// Take ownership of the underlying vector. It will not be reused by the `ReadAhead`.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The vector of data.
+    ///
+    pub fn into_vec(mut self) -> Vec<T> {
+        // Don't hand the memory back when we are dropped
+        self.recycle = None;
+        std::mem::take(&mut self.data)
+    }
+}
+
+impl<T> Deref for ReadAheadBuffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.data
+    }
+}
+
+impl<T> DerefMut for ReadAheadBuffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+}
+
+impl<T> Drop for ReadAheadBuffer<T> {
+    fn drop(&mut self) {
+        if let Some(recycle) = self.recycle.take() {
+            // If the reader has gone away there is no one to recycle the buffer to, which is fine
+            let _ = recycle.send(std::mem::take(&mut self.data));
+        }
+    }
+}
+
+///
+/// Reads the data for a sequence of timesteps and coarse channels on a background thread, ahead of
+/// the caller consuming it.
+///
+/// Each item is (timestep index, coarse chan index, Result containing a `ReadAheadBuffer`, which also
+/// says whether the data was missing and filled in, see `ReadAheadBuffer::is_missing`). Buffers
+/// dropped by the caller are reused for later reads, so if each one is dropped before the next item is
+/// taken, only `num_read_ahead + 2` buffers are ever allocated (`num_read_ahead` waiting to be consumed,
+/// one being read and one held by the caller). If the caller holds on to buffers (e.g. with `collect()`),
+/// a new buffer is allocated for each read that has none to reuse.
+///
+/// Use `CorrelatorReadAhead::new` or `VoltageReadAhead::new` to create one.
+///
+#[derive(Debug)]
+pub struct ReadAhead<T, E> {
+    receiver: Receiver<(usize, usize, Result<ReadAheadBuffer<T>, E>)>,
+    remaining: usize,
+}
+
+/// A `ReadAhead` of correlator visibilities, in [baseline][frequency][pol][r][i] order.
+pub type CorrelatorReadAhead = ReadAhead<f32, GpuboxError>;
+
+/// A `ReadAhead` of voltage data files.
+pub type VoltageReadAhead = ReadAhead<u8, VoltageFileError>;
+
+impl<T, E> ReadAhead<T, E>
+where
+    T: Clone + Default + Send + 'static,
+    E: Send + 'static,
+{
+    /// Start a background thread which reads each of `index_pairs` in turn with `read_fn`, which
+    /// returns whether the data was missing (and so filled in).
+    pub(crate) fn start<F>(
+        index_pairs: Vec<(usize, usize)>,
+        buffer_len: usize,
+        num_read_ahead: usize,
+        read_fn: F,
+    ) -> Self
+    where
+        F: Fn(usize, usize, &mut [T]) -> Result<bool, E> + Send + 'static,
+    {
+        let (sender, receiver): (SyncSender<_>, Receiver<_>) = sync_channel(num_read_ahead);
+        let (recycle_sender, recycle_receiver) = channel::<Vec<T>>();
+        let remaining = index_pairs.len();
+
+        thread::spawn(move || {
+            let mut free_buffers: Vec<Vec<T>> = Vec::new();
+
+            for (timestep_index, coarse_chan_index) in index_pairs {
+                // Reuse a buffer if the caller has finished with one. Never wait for one to come back,
+                // as the caller may be keeping every buffer until the iterator is exhausted.
+                let mut buffer = match free_buffers.pop() {
+                    Some(b) => b,
+                    None => match recycle_receiver.try_recv() {
+                        Ok(b) => b,
+                        Err(_) => vec![T::default(); buffer_len],
+                    },
+                };
+
+                let result = match read_fn(timestep_index, coarse_chan_index, &mut buffer) {
+                    Ok(missing) => Ok(ReadAheadBuffer {
+                        data: buffer,
+                        missing,
+                        recycle: Some(recycle_sender.clone()),
+                    }),
+                    Err(e) => {
+                        free_buffers.push(buffer);
+                        Err(e)
+                    }
+                };
+
+                // Stop if the caller has dropped the ReadAhead
+                if sender
+                    .send((timestep_index, coarse_chan_index, result))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            receiver,
+            remaining,
+        }
+    }
+}
+
+impl ReadAhead<f32, GpuboxError> {
+    /// Creates a new `CorrelatorReadAhead`, which immediately starts reading in the background.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - the `CorrelatorContext` to read from.
+    ///
+    /// * `corr_timestep_indices` - indices within the CorrelatorContext timestep array to read.
+    ///
+    /// * `corr_coarse_chan_indices` - indices within the CorrelatorContext coarse_chan array to read.
+    ///
+    /// * `order` - the order in which to read them.
+    ///
+    /// * `num_read_ahead` - how many timesteps/coarse channels may be read before they are consumed.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A new `CorrelatorReadAhead`
+    ///
+    pub fn new(
+        context: Arc<CorrelatorContext>,
+        corr_timestep_indices: &[usize],
+        corr_coarse_chan_indices: &[usize],
+        order: IterationOrder,
+        num_read_ahead: usize,
+    ) -> Self {
+        let buffer_len = context.num_output_timestep_coarse_chan_floats();
+
+        Self::start(
+            get_index_pairs(corr_timestep_indices, corr_coarse_chan_indices, order),
+            buffer_len,
+            num_read_ahead,
+            move |timestep_index, coarse_chan_index, buffer| {
+                context
+                    .read_by_baseline_into_buffer(timestep_index, coarse_chan_index, buffer)
+                    .map(|_| !context.has_data(timestep_index, coarse_chan_index))
+            },
+        )
+    }
+}
+
+impl ReadAhead<u8, VoltageFileError> {
+    /// Creates a new `VoltageReadAhead`, which immediately starts reading in the background.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - the `VoltageContext` to read from.
+    ///
+    /// * `volt_timestep_indices` - indices within the VoltageContext timestep array to read.
+    ///
+    /// * `volt_coarse_chan_indices` - indices within the VoltageContext coarse_chan array to read.
+    ///
+    /// * `order` - the order in which to read them.
+    ///
+    /// * `num_read_ahead` - how many data files may be read before they are consumed.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A new `VoltageReadAhead`
+    ///
+    pub fn new(
+        context: Arc<VoltageContext>,
+        volt_timestep_indices: &[usize],
+        volt_coarse_chan_indices: &[usize],
+        order: IterationOrder,
+        num_read_ahead: usize,
+    ) -> Self {
+        let buffer_len =
+            (context.voltage_block_size_bytes * context.num_voltage_blocks_per_timestep) as usize;
+
+        Self::start(
+            get_index_pairs(volt_timestep_indices, volt_coarse_chan_indices, order),
+            buffer_len,
+            num_read_ahead,
+            move |timestep_index, coarse_chan_index, buffer| {
+                // Voltage reads have no missing data policy; missing files are always an error
+                context
+                    .read_file(timestep_index, coarse_chan_index, buffer)
+                    .map(|_| false)
+            },
+        )
+    }
+}
+
+impl<T, E> Iterator for ReadAhead<T, E> {
+    type Item = (usize, usize, Result<ReadAheadBuffer<T>, E>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // If the background thread has gone (e.g. it panicked), we are done
+        let item = self.receiver.recv().ok()?;
+        self.remaining -= 1;
+
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
@@ -0,0 +1,109 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Unit tests for data iterators
+#[cfg(test)]
+use super::*;
+
+#[test]
+fn test_get_index_pairs() {
+    assert_eq!(
+        get_index_pairs(&[0, 1], &[5, 6, 7], IterationOrder::TimeMajor),
+        vec![(0, 5), (0, 6), (0, 7), (1, 5), (1, 6), (1, 7)]
+    );
+
+    assert_eq!(
+        get_index_pairs(&[0, 1], &[5, 6, 7], IterationOrder::FrequencyMajor),
+        vec![(0, 5), (1, 5), (0, 6), (1, 6), (0, 7), (1, 7)]
+    );
+
+    assert!(get_index_pairs(&[], &[5, 6, 7], IterationOrder::TimeMajor).is_empty());
+    assert!(get_index_pairs(&[0, 1], &[], IterationOrder::FrequencyMajor).is_empty());
+}
+
+#[test]
+fn test_read_ahead() {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    let buffer_addresses: Arc<Mutex<HashSet<usize>>> = Arc::new(Mutex::new(HashSet::new()));
+    let addresses = buffer_addresses.clone();
+    let num_read_ahead = 1;
+
+    let read_ahead: ReadAhead<usize, String> = ReadAhead::start(
+        get_index_pairs(&[0, 1, 2], &[5, 6], IterationOrder::TimeMajor),
+        3,
+        num_read_ahead,
+        move |timestep_index, coarse_chan_index, buffer: &mut [usize]| {
+            addresses.lock().unwrap().insert(buffer.as_ptr() as usize);
+
+            if (timestep_index, coarse_chan_index) == (1, 6) {
+                return Err("no data".to_string());
+            }
+            buffer.fill(timestep_index * 100 + coarse_chan_index);
+            Ok(coarse_chan_index == 6)
+        },
+    );
+    assert_eq!(read_ahead.size_hint(), (6, Some(6)));
+
+    let mut kept: Vec<Vec<usize>> = Vec::new();
+    let mut visited: Vec<(usize, usize)> = Vec::new();
+    for (timestep_index, coarse_chan_index, result) in read_ahead {
+        visited.push((timestep_index, coarse_chan_index));
+
+        if (timestep_index, coarse_chan_index) == (1, 6) {
+            assert_eq!(result.unwrap_err(), "no data");
+            continue;
+        }
+
+        let buffer = result.unwrap();
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.is_missing(), coarse_chan_index == 6);
+        assert!(buffer
+            .iter()
+            .all(|&v| v == timestep_index * 100 + coarse_chan_index));
+
+        // Keep one buffer for ourselves, the rest are recycled when dropped
+        if (timestep_index, coarse_chan_index) == (0, 5) {
+            kept.push(buffer.into_vec());
+        }
+    }
+
+    assert_eq!(
+        visited,
+        vec![(0, 5), (0, 6), (1, 5), (1, 6), (2, 5), (2, 6)]
+    );
+    assert_eq!(kept, vec![vec![5, 5, 5]]);
+
+    // Buffers were reused: never more than the maximum (plus the replacement for the kept buffer)
+    assert!(buffer_addresses.lock().unwrap().len() <= num_read_ahead + 3);
+}
+
+#[test]
+fn test_read_ahead_collect() {
+    let num_read_ahead = 1;
+    let index_pairs = get_index_pairs(&[0, 1, 2, 3], &[5, 6], IterationOrder::TimeMajor);
+    assert!(index_pairs.len() > num_read_ahead + 2);
+
+    // Holding on to every buffer must not stop the background thread from reading the rest
+    let items: Vec<(usize, usize, Result<ReadAheadBuffer<usize>, String>)> = ReadAhead::start(
+        index_pairs.clone(),
+        2,
+        num_read_ahead,
+        |timestep_index, coarse_chan_index, buffer: &mut [usize]| {
+            buffer.fill(timestep_index * 100 + coarse_chan_index);
+            Ok(coarse_chan_index == 6)
+        },
+    )
+    .collect();
+
+    assert_eq!(items.len(), index_pairs.len());
+    for ((timestep_index, coarse_chan_index, result), &expected) in
+        items.iter().zip(index_pairs.iter())
+    {
+        assert_eq!((*timestep_index, *coarse_chan_index), expected);
+        let value = timestep_index * 100 + coarse_chan_index;
+        assert_eq!(**result.as_ref().unwrap(), [value, value]);
+    }
+}
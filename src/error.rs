@@ -32,6 +32,26 @@ pub enum MwalibError {
     #[error("{0}")]
     Voltage(#[from] crate::voltage_files::error::VoltageFileError),
 
+    /// An error derived from `SelectionError`.
+    #[error("{0}")]
+    Selection(#[from] crate::selection::error::SelectionError),
+
+    /// An error derived from `UvfitsError`.
+    #[error("{0}")]
+    Uvfits(#[from] crate::uvfits::error::UvfitsError),
+
+    /// An error derived from `NpyError`.
+    #[error("{0}")]
+    Npy(#[from] crate::npy::error::NpyError),
+
+    /// An error derived from `GpuboxWriteError`.
+    #[error("{0}")]
+    GpuboxWrite(#[from] crate::gpubox_writer::error::GpuboxWriteError),
+
+    /// An error derived from `SubsetError`.
+    #[error("{0}")]
+    Subset(#[from] crate::subset::error::SubsetError),
+
     // An error associated with parsing a string into another type.
     #[error("{source_file}:{source_line}\nCouldn't parse {key} in {fits_filename} HDU {hdu_num}")]
     Parse {
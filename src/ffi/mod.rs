@@ -718,6 +718,62 @@ pub unsafe extern "C" fn mwalib_correlator_context_read_by_frequency(
     }
 }
 
+/// Set the options applied to visibilities by the `mwalib_correlator_context_read_by_*` functions.
+///
+/// # Arguments
+///
+/// * `correlator_context_ptr` - pointer to an already populated `CorrelatorContext` object.
+///
+/// * `read_config` - a populated `CorrelatorReadConfig` struct describing the processing to apply. Its fields are
+///   checked before any are applied.
+///
+/// * `error_message` - pointer to already allocated buffer for any error messages to be returned to the caller.
+///
+/// * `error_message_length` - length of error_message char* buffer.
+///
+///
+/// # Returns
+///
+/// * MWALIB_SUCCESS on success, non-zero on failure (including any field of `read_config` having an invalid value, in
+///   which case the context's read config is left unchanged)
+///
+///
+/// # Safety
+/// * `error_message` *must* point to an already allocated char* buffer for any error messages.
+/// * `correlator_context_ptr` must point to a populated object from the `mwalib_correlator_context_new` function.
+#[no_mangle]
+pub unsafe extern "C" fn mwalib_correlator_context_set_read_config(
+    correlator_context_ptr: *mut CorrelatorContext,
+    read_config: CorrelatorReadConfig,
+    error_message: *const c_char,
+    error_message_length: size_t,
+) -> i32 {
+    let corr_context = if correlator_context_ptr.is_null() {
+        set_c_string(
+            "mwalib_correlator_context_set_read_config() ERROR: null pointer for correlator_context_ptr passed in",
+            error_message as *mut u8,
+            error_message_length,
+        );
+        return MWALIB_FAILURE;
+    } else {
+        &mut *correlator_context_ptr
+    };
+
+    corr_context.read_config = match crate::CorrelatorReadConfig::try_from(read_config) {
+        Ok(read_config) => read_config,
+        Err(e) => {
+            set_c_string(
+                &format!("mwalib_correlator_context_set_read_config() ERROR: {}", e),
+                error_message as *mut u8,
+                error_message_length,
+            );
+            return MWALIB_FAILURE;
+        }
+    };
+
+    MWALIB_SUCCESS
+}
+
 /// For a given slice of correlator coarse channel indices, return a vector of the center
 /// frequencies for all the fine channels in the given coarse channels
 ///
@@ -818,6 +874,104 @@ pub unsafe extern "C" fn mwalib_correlator_context_get_fine_chan_freqs_hz_array(
     MWALIB_SUCCESS
 }
 
+/// Generate flags for many timesteps and coarse channels, in [time][coarse_chan][baseline][frequency] order.
+///
+/// # Arguments
+///
+/// * `correlator_context_ptr` - pointer to an already populated `CorrelatorContext` object.
+///
+/// * `corr_timestep_indices_array_ptr` - a pointer to an array containing correlator timestep indices to generate flags for.
+///
+/// * `corr_timestep_indices_array_len` - length of `corr_timestep_indices_array_ptr`.
+///
+/// * `corr_coarse_chan_indices_array_ptr` - a pointer to an array containing correlator coarse channel indices to generate flags for.
+///
+/// * `corr_coarse_chan_indices_array_len` - length of `corr_coarse_chan_indices_array_ptr`.
+///
+/// * `flag_config` - a populated `CorrelatorFlagConfig` struct describing which flags to generate.
+///
+/// * `out_flags_array_ptr` - pointer to caller-owned and allocated array of bools to write the flags into (true is flagged).
+///
+/// * `out_flags_array_len` - length of `out_flags_array_ptr`. Must be timesteps * coarse chans * baselines * fine chans per coarse.
+///
+/// * `error_message` - pointer to already allocated buffer for any error messages to be returned to the caller.
+///
+/// * `error_message_length` - length of error_message char* buffer.
+///
+///
+/// # Returns
+///
+/// * MWALIB_SUCCESS on success, non-zero on failure
+///
+///
+/// # Safety
+/// * `error_message` *must* point to an already allocated char* buffer for any error messages.
+/// * `correlator_context_ptr` must point to a populated object from the `mwalib_correlator_context_new` function.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn mwalib_correlator_context_get_flags(
+    correlator_context_ptr: *mut CorrelatorContext,
+    corr_timestep_indices_array_ptr: *const size_t,
+    corr_timestep_indices_array_len: size_t,
+    corr_coarse_chan_indices_array_ptr: *const size_t,
+    corr_coarse_chan_indices_array_len: size_t,
+    flag_config: CorrelatorFlagConfig,
+    out_flags_array_ptr: *mut bool,
+    out_flags_array_len: size_t,
+    error_message: *const c_char,
+    error_message_length: size_t,
+) -> i32 {
+    let corr_context = if correlator_context_ptr.is_null() {
+        set_c_string(
+            "mwalib_correlator_context_get_flags() ERROR: null pointer for correlator_context_ptr passed in",
+            error_message as *mut u8,
+            error_message_length,
+        );
+        return MWALIB_FAILURE;
+    } else {
+        &*correlator_context_ptr
+    };
+
+    if corr_timestep_indices_array_ptr.is_null()
+        || corr_coarse_chan_indices_array_ptr.is_null()
+        || out_flags_array_ptr.is_null()
+    {
+        set_c_string(
+            "mwalib_correlator_context_get_flags() ERROR: null pointer for corr_timestep_indices_array_ptr, corr_coarse_chan_indices_array_ptr or out_flags_array_ptr passed in",
+            error_message as *mut u8,
+            error_message_length,
+        );
+        return MWALIB_FAILURE;
+    }
+
+    let timestep_indices = slice::from_raw_parts(
+        corr_timestep_indices_array_ptr,
+        corr_timestep_indices_array_len,
+    );
+    let coarse_chan_indices = slice::from_raw_parts(
+        corr_coarse_chan_indices_array_ptr,
+        corr_coarse_chan_indices_array_len,
+    );
+    let output_slice = slice::from_raw_parts_mut(out_flags_array_ptr, out_flags_array_len);
+
+    match corr_context.get_flags_into_buffer(
+        timestep_indices,
+        coarse_chan_indices,
+        &flag_config,
+        output_slice,
+    ) {
+        Ok(_) => MWALIB_SUCCESS,
+        Err(e) => {
+            set_c_string(
+                &format!("{}", e),
+                error_message as *mut u8,
+                error_message_length,
+            );
+            MWALIB_FAILURE
+        }
+    }
+}
+
 /// For a given slice of voltage coarse channel indices, return a vector of the center
 /// frequencies for all the fine channels in the given coarse channels
 ///
@@ -2124,7 +2278,13 @@ pub unsafe extern "C" fn mwalib_correlator_metadata_get(
             num_gpubox_files,
             gpubox_batches: _, // This is currently not provided to FFI as it is private
             gpubox_time_map: _, // This is currently not provided to FFI
+            gpubox_data_offsets: _, // This is currently not provided to FFI as it is private
+            read_config: _,    // This is set via mwalib_correlator_context_set_read_config
             legacy_conversion_table: _, // This is currently not provided to FFI as it is private
+            output_conversion_table: _, // This is currently not provided to FFI as it is private
+            fits_handle_pool: _, // This is currently not provided to FFI as it is private
+            direct_read_file_pool: _, // This is currently not provided to FFI as it is private
+            thread_pool: _,    // This is currently not provided to FFI as it is private
         } = context;
         CorrelatorMetadata {
             mwa_version: *mwa_version,
@@ -2795,3 +2955,96 @@ pub struct TimeStep {
     pub unix_time_ms: u64,
     pub gps_time_ms: u64,
 }
+
+///
+/// C Representation of a `CorrelatorReadConfig` struct
+///
+/// Fields which are bools or enums in Rust are plain integers here, as a C caller can put any value in them. They are
+/// checked when `mwalib_correlator_context_set_read_config` converts this into a `CorrelatorReadConfig`.
+///
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CorrelatorReadConfig {
+    /// 1 to multiply MWAX visibilities by the metafits `RAWSCALE` value, 0 not to
+    pub apply_raw_scale_factor: u8,
+    /// 1 to apply a Van Vleck correction to the auto and cross-correlations (legacy data only), 0 not to
+    pub apply_van_vleck_correction: u8,
+    /// Number of threads used to read gpubox files in parallel, or 0 to use rayon's global thread pool
+    pub num_threads: usize,
+    /// Maximum number of gpubox files kept open between reads, or 0 for no limit
+    pub max_open_files: usize,
+    /// 1 to read plain MWAX float HDUs directly from the gpubox file, bypassing cfitsio, 0 not to
+    pub direct_read: u8,
+    /// The `PolProducts` to return: 0 = Linear, 1 = Stokes, 2 = PseudoStokesI
+    pub pol_products: u32,
+    /// The `MissingDataPolicy`: 0 = Error, 1 = FillZeros, 2 = FillNaN
+    pub missing_data_policy: u32,
+    /// The `BaselineOrder` of the output layout: 0 = Ant1Major, 1 = Ant2Major
+    pub layout_baseline_order: u32,
+    /// The `ConjugationConvention` of the output layout: 0 = Mwax, 1 = Opposite
+    pub layout_conjugation: u32,
+    /// 1 to leave the autocorrelations out of the output layout, 0 not to
+    pub layout_exclude_autos: u8,
+    /// 1 for pol to be the slowest varying axis of the output layout, 0 for the fastest
+    pub layout_pol_major: u8,
+}
+
+impl TryFrom<CorrelatorReadConfig> for crate::CorrelatorReadConfig {
+    type Error = String;
+
+    fn try_from(read_config: CorrelatorReadConfig) -> Result<Self, Self::Error> {
+        Ok(crate::CorrelatorReadConfig {
+            apply_raw_scale_factor: ffi_bool(
+                "apply_raw_scale_factor",
+                read_config.apply_raw_scale_factor,
+            )?,
+            apply_van_vleck_correction: ffi_bool(
+                "apply_van_vleck_correction",
+                read_config.apply_van_vleck_correction,
+            )?,
+            num_threads: read_config.num_threads,
+            max_open_files: read_config.max_open_files,
+            direct_read: ffi_bool("direct_read", read_config.direct_read)?,
+            pol_products: match read_config.pol_products {
+                0 => PolProducts::Linear,
+                1 => PolProducts::Stokes,
+                2 => PolProducts::PseudoStokesI,
+                v => return Err(ffi_invalid_value("pol_products", v)),
+            },
+            missing_data_policy: match read_config.missing_data_policy {
+                0 => MissingDataPolicy::Error,
+                1 => MissingDataPolicy::FillZeros,
+                2 => MissingDataPolicy::FillNaN,
+                v => return Err(ffi_invalid_value("missing_data_policy", v)),
+            },
+            layout: VisibilityLayout {
+                baseline_order: match read_config.layout_baseline_order {
+                    0 => BaselineOrder::Ant1Major,
+                    1 => BaselineOrder::Ant2Major,
+                    v => return Err(ffi_invalid_value("layout_baseline_order", v)),
+                },
+                conjugation: match read_config.layout_conjugation {
+                    0 => ConjugationConvention::Mwax,
+                    1 => ConjugationConvention::Opposite,
+                    v => return Err(ffi_invalid_value("layout_conjugation", v)),
+                },
+                exclude_autos: ffi_bool("layout_exclude_autos", read_config.layout_exclude_autos)?,
+                pol_major: ffi_bool("layout_pol_major", read_config.layout_pol_major)?,
+            },
+        })
+    }
+}
+
+/// Convert a 0 or 1 passed in from C into a bool, or an error message naming the field if it is anything else.
+fn ffi_bool(field_name: &str, value: u8) -> Result<bool, String> {
+    match value {
+        0 => Ok(false),
+        1 => Ok(true),
+        v => Err(ffi_invalid_value(field_name, v)),
+    }
+}
+
+/// The error message for a field passed in from C with an invalid value.
+fn ffi_invalid_value<T: std::fmt::Display>(field_name: &str, value: T) -> String {
+    format!("invalid value {} for {}", value, field_name)
+}
@@ -668,6 +668,132 @@ fn test_mwalib_correlator_context_legacy_read_by_baseline_null_buffer() {
     }
 }
 
+#[test]
+fn test_mwalib_correlator_context_set_read_config_valid() {
+    let correlator_context_ptr: *mut CorrelatorContext = get_test_ffi_correlator_context();
+
+    let error_message_length: size_t = 128;
+    let error_message = CString::new(" ".repeat(error_message_length)).unwrap();
+    let error_message_ptr = error_message.as_ptr() as *const c_char;
+
+    let read_config = CorrelatorReadConfig {
+        apply_raw_scale_factor: 1,
+        apply_van_vleck_correction: 1,
+        num_threads: 2,
+        max_open_files: 0,
+        direct_read: 0,
+        pol_products: 2,
+        missing_data_policy: 1,
+        layout_baseline_order: 1,
+        layout_conjugation: 0,
+        layout_exclude_autos: 1,
+        layout_pol_major: 0,
+    };
+
+    unsafe {
+        let retval = mwalib_correlator_context_set_read_config(
+            correlator_context_ptr,
+            read_config,
+            error_message_ptr,
+            error_message_length,
+        );
+
+        assert_eq!(retval, 0);
+
+        // Check the context was updated
+        let context = &*correlator_context_ptr;
+        assert_eq!(
+            context.read_config,
+            crate::CorrelatorReadConfig::new()
+                .with_raw_scale_factor(true)
+                .with_van_vleck_correction(true)
+                .with_num_threads(2)
+                .with_pol_products(PolProducts::PseudoStokesI)
+                .with_missing_data_policy(MissingDataPolicy::FillZeros)
+                .with_layout(
+                    VisibilityLayout::new()
+                        .with_baseline_order(BaselineOrder::Ant2Major)
+                        .with_exclude_autos(true)
+                )
+        );
+    }
+}
+
+#[test]
+fn test_mwalib_correlator_context_set_read_config_invalid_values() {
+    let correlator_context_ptr: *mut CorrelatorContext = get_test_ffi_correlator_context();
+
+    let error_message_length: size_t = 128;
+    let error_message = CString::new(" ".repeat(error_message_length)).unwrap();
+    let error_message_ptr = error_message.as_ptr() as *const c_char;
+
+    let bad_read_configs = [
+        CorrelatorReadConfig {
+            apply_raw_scale_factor: 2,
+            ..Default::default()
+        },
+        CorrelatorReadConfig {
+            pol_products: 3,
+            ..Default::default()
+        },
+        CorrelatorReadConfig {
+            missing_data_policy: 3,
+            ..Default::default()
+        },
+        CorrelatorReadConfig {
+            layout_baseline_order: 2,
+            ..Default::default()
+        },
+        CorrelatorReadConfig {
+            layout_conjugation: 2,
+            ..Default::default()
+        },
+        CorrelatorReadConfig {
+            layout_pol_major: 255,
+            ..Default::default()
+        },
+    ];
+
+    for read_config in bad_read_configs {
+        unsafe {
+            let retval = mwalib_correlator_context_set_read_config(
+                correlator_context_ptr,
+                read_config,
+                error_message_ptr,
+                error_message_length,
+            );
+
+            // Should get a non-zero return code, and the read config should be unchanged
+            assert_ne!(retval, 0);
+            assert_eq!(
+                (*correlator_context_ptr).read_config,
+                crate::CorrelatorReadConfig::default()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_mwalib_correlator_context_set_read_config_null_context() {
+    let correlator_context_ptr: *mut CorrelatorContext = std::ptr::null_mut();
+
+    let error_message_length: size_t = 128;
+    let error_message = CString::new(" ".repeat(error_message_length)).unwrap();
+    let error_message_ptr = error_message.as_ptr() as *const c_char;
+
+    unsafe {
+        let retval = mwalib_correlator_context_set_read_config(
+            correlator_context_ptr,
+            CorrelatorReadConfig::default(),
+            error_message_ptr,
+            error_message_length,
+        );
+
+        // Should get a non-zero return code
+        assert_ne!(retval, 0);
+    }
+}
+
 #[test]
 fn test_mwalib_correlator_context_legacy_read_by_frequency_valid() {
     let correlator_context_ptr: *mut CorrelatorContext = get_test_ffi_correlator_context();
@@ -831,6 +957,73 @@ fn test_mwalib_correlator_context_get_fine_chan_freqs_hz_array_valid() {
     }
 }
 
+#[test]
+fn test_mwalib_correlator_context_get_flags_valid() {
+    let correlator_context_ptr: *mut CorrelatorContext = get_test_ffi_correlator_context();
+
+    let error_message_length: size_t = 128;
+    let error_message = CString::new(" ".repeat(error_message_length)).unwrap();
+    let error_message_ptr = error_message.as_ptr() as *const c_char;
+
+    unsafe {
+        let context = &*correlator_context_ptr;
+        let hdu_flags_len = context.metafits_context.num_baselines
+            * context.metafits_context.num_corr_fine_chans_per_coarse;
+
+        // Timestep 1 has no data
+        let timestep_indices: Vec<usize> = vec![0, 1];
+        let coarse_chan_indices: Vec<usize> = vec![0];
+        let mut flags: Vec<bool> = vec![false; 2 * hdu_flags_len];
+
+        let retval = mwalib_correlator_context_get_flags(
+            correlator_context_ptr,
+            timestep_indices.as_ptr(),
+            timestep_indices.len(),
+            coarse_chan_indices.as_ptr(),
+            coarse_chan_indices.len(),
+            CorrelatorFlagConfig::new().with_missing_hdu_flags(true),
+            flags.as_mut_ptr(),
+            flags.len(),
+            error_message_ptr,
+            error_message_length,
+        );
+
+        assert_eq!(retval, 0);
+        assert!(flags[0..hdu_flags_len].iter().all(|&f| !f));
+        assert!(flags[hdu_flags_len..].iter().all(|&f| f));
+    }
+}
+
+#[test]
+fn test_mwalib_correlator_context_get_flags_invalid_buffer_len() {
+    let correlator_context_ptr: *mut CorrelatorContext = get_test_ffi_correlator_context();
+
+    let error_message_length: size_t = 128;
+    let error_message = CString::new(" ".repeat(error_message_length)).unwrap();
+    let error_message_ptr = error_message.as_ptr() as *const c_char;
+
+    unsafe {
+        let timestep_indices: Vec<usize> = vec![0];
+        let coarse_chan_indices: Vec<usize> = vec![0];
+        let mut flags: Vec<bool> = vec![false; 10];
+
+        let retval = mwalib_correlator_context_get_flags(
+            correlator_context_ptr,
+            timestep_indices.as_ptr(),
+            timestep_indices.len(),
+            coarse_chan_indices.as_ptr(),
+            coarse_chan_indices.len(),
+            CorrelatorFlagConfig::new(),
+            flags.as_mut_ptr(),
+            flags.len(),
+            error_message_ptr,
+            error_message_length,
+        );
+
+        assert_ne!(retval, 0);
+    }
+}
+
 #[test]
 fn test_mwalib_correlator_context_get_fine_chan_freqs_hz_array_invalid_buffer_len() {
     let correlator_context_ptr: *mut CorrelatorContext = get_test_ffi_correlator_context();
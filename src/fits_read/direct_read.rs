@@ -0,0 +1,312 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Reading of uncompressed FITS image data directly from a file, bypassing cfitsio.
+//!
+//! FITS stores floats big-endian, so after reading the raw bytes they need byte swapping on
+//! little-endian machines. On unix each file is memory mapped once, when it is first read, and the
+//! floats are byte swapped straight from the mapping into the caller's buffer, so the data is only
+//! copied once (cfitsio copies it from the file into its own buffers, then into the caller's
+//! buffer, then swaps it). Open files are kept in a `DirectReadFilePool`, alongside the cfitsio
+//! handles in the `FitsHandlePool`.
+
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+
+use super::handle_pool::HandlePool;
+
+/// A pool of files open for direct reads.
+pub(crate) type DirectReadFilePool = HandlePool<DirectReadFile>;
+
+impl DirectReadFilePool {
+    /// Get a file open for direct reads, opening it if it is not already in the pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - the file to open.
+    ///
+    /// * `max_open_files` - the maximum number of files to keep open, or 0 for no limit.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the `DirectReadFile`, or an io::Error if the file could not be opened.
+    ///
+    pub(crate) fn get(
+        &self,
+        filename: &str,
+        max_open_files: usize,
+    ) -> io::Result<Arc<DirectReadFile>> {
+        self.get_or_open(filename, max_open_files, DirectReadFile::open)
+    }
+}
+
+/// A file open for direct reads of big-endian floats. On unix the whole file is memory mapped.
+pub(crate) struct DirectReadFile {
+    /// Name of the file, for error messages
+    filename: String,
+    /// Length of the file (bytes) when it was opened
+    len: u64,
+    /// Mapping of the whole file, or None if the file is empty (which can't be mapped)
+    #[cfg(unix)]
+    map: Option<Mmap>,
+    /// The file, which is seeked and read by one reader at a time
+    #[cfg(not(unix))]
+    file: std::sync::Mutex<File>,
+}
+
+impl DirectReadFile {
+    /// Open a file for direct reads.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - the file to open.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the `DirectReadFile`, or an io::Error if the file could not be opened
+    ///   (or mapped).
+    ///
+    pub(crate) fn open(filename: &str) -> io::Result<Self> {
+        let file = File::open(filename)?;
+        let len = file.metadata()?.len();
+
+        Ok(Self {
+            filename: filename.to_string(),
+            len,
+            #[cfg(unix)]
+            map: match len {
+                0 => None,
+                _ => Some(Mmap::new(&file, len)?),
+            },
+            #[cfg(not(unix))]
+            file: std::sync::Mutex::new(file),
+        })
+    }
+
+    /// Read `buffer.len()` big-endian 32 bit floats, starting at byte `offset`.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - byte offset within the file of the first float (e.g. the start of a HDU's data).
+    ///
+    /// * `buffer` - Buffer of floats (as a slice) to fill, in native byte order.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok on success, or an io::Error if the file is too short or could not be read.
+    ///
+    pub(crate) fn read_be_f32_at(&self, offset: u64, buffer: &mut [f32]) -> io::Result<()> {
+        let num_bytes = std::mem::size_of_val(buffer) as u64;
+
+        // Check the data is all there first. When memory mapped, reading past the end of the file
+        // would be a SIGBUS rather than an error.
+        if offset
+            .checked_add(num_bytes)
+            .map_or(true, |end| end > self.len)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "{} is too short to read {} bytes at offset {}",
+                    self.filename, num_bytes, offset
+                ),
+            ));
+        }
+
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.read_be_f32_from_file(offset, buffer)
+    }
+
+    /// Byte swap the bytes to read straight out of the mapping into `buffer`.
+    #[cfg(unix)]
+    fn read_be_f32_from_file(&self, offset: u64, buffer: &mut [f32]) -> io::Result<()> {
+        // The caller checked the bytes are within the file, and so within the mapping, which
+        // exists as the file is not empty
+        let start = offset as usize;
+        let bytes =
+            &self.map.as_ref().unwrap().as_slice()[start..start + std::mem::size_of_val(buffer)];
+        swap_be_bytes_to_f32(bytes, buffer);
+
+        Ok(())
+    }
+
+    /// Seek and read the bytes into a temporary buffer, then byte swap them into `buffer`.
+    #[cfg(not(unix))]
+    fn read_be_f32_from_file(&self, offset: u64, buffer: &mut [f32]) -> io::Result<()> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let mut bytes: Vec<u8> = vec![0; std::mem::size_of_val(buffer)];
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut bytes)?;
+        drop(file);
+        swap_be_bytes_to_f32(&bytes, buffer);
+
+        Ok(())
+    }
+}
+
+/// A read-only memory mapping of a whole file, which is unmapped when dropped.
+#[cfg(unix)]
+struct Mmap {
+    /// Start of the mapping
+    ptr: *mut libc::c_void,
+    /// Length of the mapping
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and private, and is never handed out except as a shared
+// slice, so it can be read from any number of threads and unmapped from any thread.
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+    /// Map the first `len` bytes of `file`. `len` must not be 0.
+    fn new(file: &File, len: u64) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = usize::try_from(len).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "file is too large to memory map",
+            )
+        })?;
+
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { ptr, len })
+    }
+
+    /// The mapped bytes.
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: the mapping is `len` bytes long and lives as long as `self`. Every byte of it is
+        // backed by the file, as long as the file is not truncated while it is mapped (gpubox
+        // files are not written to while they are being read).
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// Convert big-endian bytes into floats in native byte order.
+///
+/// On x86_64 with SSSE3 and on aarch64, 4 floats at a time are byte swapped with a SIMD shuffle.
+/// Elsewhere (and for any remaining floats) this is a loop over `f32::from_be_bytes`.
+///
+/// # Arguments
+///
+/// * `bytes` - the big-endian floats, as bytes. Must be exactly 4 times as long as `buffer`.
+///
+/// * `buffer` - Buffer of floats (as a slice) to fill.
+///
+///
+/// # Returns
+///
+/// * Nothing
+///
+pub(crate) fn swap_be_bytes_to_f32(bytes: &[u8], buffer: &mut [f32]) {
+    assert_eq!(bytes.len(), std::mem::size_of_val(buffer));
+
+    let num_done = swap_be_bytes_to_f32_simd(bytes, buffer);
+
+    for (v, b) in buffer[num_done..]
+        .iter_mut()
+        .zip(bytes[num_done * 4..].chunks_exact(4))
+    {
+        *v = f32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    }
+}
+
+/// Byte swap as many whole groups of 4 floats as possible with SSSE3, returning how many floats
+/// were done.
+#[cfg(target_arch = "x86_64")]
+fn swap_be_bytes_to_f32_simd(bytes: &[u8], buffer: &mut [f32]) -> usize {
+    if is_x86_feature_detected!("ssse3") {
+        // SAFETY: we just checked the CPU supports SSSE3
+        unsafe { swap_be_bytes_to_f32_ssse3(bytes, buffer) }
+    } else {
+        0
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "ssse3")]
+unsafe fn swap_be_bytes_to_f32_ssse3(bytes: &[u8], buffer: &mut [f32]) -> usize {
+    use std::arch::x86_64::*;
+
+    let num_groups = buffer.len() / 4;
+    let shuffle = _mm_setr_epi8(3, 2, 1, 0, 7, 6, 5, 4, 11, 10, 9, 8, 15, 14, 13, 12);
+    let src = bytes.as_ptr() as *const __m128i;
+    let dst = buffer.as_mut_ptr() as *mut __m128i;
+
+    for i in 0..num_groups {
+        let v = _mm_loadu_si128(src.add(i));
+        _mm_storeu_si128(dst.add(i), _mm_shuffle_epi8(v, shuffle));
+    }
+
+    num_groups * 4
+}
+
+/// Byte swap as many whole groups of 4 floats as possible with NEON, returning how many floats
+/// were done.
+#[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+fn swap_be_bytes_to_f32_simd(bytes: &[u8], buffer: &mut [f32]) -> usize {
+    use std::arch::aarch64::*;
+
+    let num_groups = buffer.len() / 4;
+    let src = bytes.as_ptr();
+    let dst = buffer.as_mut_ptr() as *mut u8;
+
+    // SAFETY: NEON is always available on aarch64, and both slices are at least num_groups * 16
+    // bytes long
+    unsafe {
+        for i in 0..num_groups {
+            let v = vld1q_u8(src.add(i * 16));
+            vst1q_u8(dst.add(i * 16), vrev32q_u8(v));
+        }
+    }
+
+    num_groups * 4
+}
+
+/// No SIMD byte swap on this platform: `f32::from_be_bytes` does all of the floats.
+#[cfg(not(any(
+    target_arch = "x86_64",
+    all(target_arch = "aarch64", target_endian = "little")
+)))]
+fn swap_be_bytes_to_f32_simd(_bytes: &[u8], _buffer: &mut [f32]) -> usize {
+    0
+}
@@ -0,0 +1,206 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A pool of open files, so that repeated reads of the same file do not have to reopen it.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use fitsio::FitsFile;
+
+use super::*;
+use crate::fits_open;
+
+/// A `FitsFile` which may be moved between threads.
+///
+/// `FitsFile` is not `Send` because it wraps a raw cfitsio pointer, but a cfitsio file handle is
+/// not tied to the thread which opened it. It is only ever accessed behind the `Mutex` of a
+/// `FitsHandle`, so only one thread uses it at a time (this is the same approach as fitsio's
+/// `ThreadsafeFitsFile`).
+pub(crate) struct PooledFitsFile(FitsFile);
+
+// SAFETY: a cfitsio `fitsfile` has no thread affinity; what cfitsio does not allow is the same
+// handle being used by two threads at once (its buffers and current HDU are per handle). A
+// `PooledFitsFile` is only reachable through the `Mutex` of its `FitsHandle`, and is never handed
+// out except inside the `MutexGuard` from `lock_fits_handle`, so only one thread can use it at a
+// time. `Sync` is not implemented; the `Mutex` provides that for the `FitsHandle`.
+unsafe impl Send for PooledFitsFile {}
+
+impl Deref for PooledFitsFile {
+    type Target = FitsFile;
+
+    fn deref(&self) -> &FitsFile {
+        &self.0
+    }
+}
+
+impl DerefMut for PooledFitsFile {
+    fn deref_mut(&mut self) -> &mut FitsFile {
+        &mut self.0
+    }
+}
+
+/// A shared handle to an open FITS file. Lock it (see `lock_fits_handle`) before use; this
+/// serialises readers of the same file, while different files can be read concurrently.
+pub(crate) type FitsHandle = Arc<Mutex<PooledFitsFile>>;
+
+/// Lock a `FitsHandle`. If another reader panicked while holding the lock, the handle is still
+/// returned; every read opens the HDU it wants first, so the file is still usable.
+///
+/// # Arguments
+///
+/// * `handle` - the `FitsHandle` to lock.
+///
+///
+/// # Returns
+///
+/// * A guard which dereferences to the `FitsFile`.
+///
+pub(crate) fn lock_fits_handle(handle: &FitsHandle) -> MutexGuard<'_, PooledFitsFile> {
+    handle
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+///
+/// A thread-safe pool of open files, keyed by filename. Each file is shared via an `Arc`.
+///
+/// Optionally the number of open files can be bounded, in which case the least recently used
+/// file which is not currently being read is closed to make room for a new one.
+///
+pub(crate) struct HandlePool<T> {
+    /// Open files, least recently used first.
+    handles: Mutex<Vec<(String, Arc<T>)>>,
+}
+
+/// A pool of open FITS files.
+pub(crate) type FitsHandlePool = HandlePool<Mutex<PooledFitsFile>>;
+
+impl<T> Default for HandlePool<T> {
+    fn default() -> Self {
+        Self {
+            handles: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl FitsHandlePool {
+    /// Get a handle to an open FITS file, opening it if it is not already in the pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - the FITS file to open.
+    ///
+    /// * `max_open_files` - the maximum number of files to keep open, or 0 for no limit. If every
+    ///   file is in use, the pool may temporarily exceed this.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the `FitsHandle`, or a `FitsError` if the file could not be opened.
+    ///
+    pub(crate) fn get(
+        &self,
+        filename: &str,
+        max_open_files: usize,
+    ) -> Result<FitsHandle, FitsError> {
+        self.get_or_open(filename, max_open_files, |filename| {
+            Ok(Mutex::new(PooledFitsFile(fits_open!(filename)?)))
+        })
+    }
+}
+
+impl<T> HandlePool<T> {
+    /// Get a shared open file, opening it with `open` if it is not already in the pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - the file to open.
+    ///
+    /// * `max_open_files` - the maximum number of files to keep open, or 0 for no limit. If every
+    ///   file is in use, the pool may temporarily exceed this.
+    ///
+    /// * `open` - opens the file.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the open file, or the error from `open`.
+    ///
+    pub(crate) fn get_or_open<E, F: FnOnce(&str) -> Result<T, E>>(
+        &self,
+        filename: &str,
+        max_open_files: usize,
+        open: F,
+    ) -> Result<Arc<T>, E> {
+        if let Some(handle) = self.get_existing(filename) {
+            return Ok(handle);
+        }
+
+        // Open the file without holding the lock, as this may be slow (e.g. on a network filesystem)
+        let handle: Arc<T> = Arc::new(open(filename)?);
+
+        let mut handles = self.lock_handles();
+
+        // Another thread may have beaten us to it
+        if let Some(position) = handles.iter().position(|(f, _)| f == filename) {
+            let existing = handles.remove(position);
+            let existing_handle = existing.1.clone();
+            handles.push(existing);
+            return Ok(existing_handle);
+        }
+
+        if max_open_files > 0 {
+            // Close the least recently used files which no one else is using
+            while handles.len() >= max_open_files {
+                match handles.iter().position(|(_, h)| Arc::strong_count(h) == 1) {
+                    Some(position) => {
+                        handles.remove(position);
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        handles.push((filename.to_string(), handle.clone()));
+
+        Ok(handle)
+    }
+
+    /// Close all files in the pool. Files currently being read are closed once their readers are done.
+    pub(crate) fn clear(&self) {
+        self.lock_handles().clear();
+    }
+
+    /// Returns the number of files in the pool.
+    pub(crate) fn len(&self) -> usize {
+        self.lock_handles().len()
+    }
+
+    /// Returns the handle for `filename` if it is already open, marking it as the most recently used.
+    fn get_existing(&self, filename: &str) -> Option<Arc<T>> {
+        let mut handles = self.lock_handles();
+        let position = handles.iter().position(|(f, _)| f == filename)?;
+        let existing = handles.remove(position);
+        let handle = existing.1.clone();
+        handles.push(existing);
+
+        Some(handle)
+    }
+
+    fn lock_handles(&self) -> MutexGuard<'_, Vec<(String, Arc<T>)>> {
+        self.handles
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<T> fmt::Debug for HandlePool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HandlePool")
+            .field("num_open_files", &self.len())
+            .finish()
+    }
+}
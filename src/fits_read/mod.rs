@@ -12,6 +12,12 @@ use log::trace;
 use std::ffi::*;
 use std::ptr;
 
+mod direct_read;
+pub(crate) use direct_read::DirectReadFilePool;
+
+mod handle_pool;
+pub(crate) use handle_pool::{lock_fits_handle, FitsHandle, FitsHandlePool};
+
 #[cfg(test)]
 mod test;
 
@@ -268,6 +274,51 @@ macro_rules! get_fits_float_image_into_buffer {
     };
 }
 
+/// Given a FITS file pointer and a HDU, read a rectangular region of the associated float image.
+///
+/// # Arguments
+///
+/// * `fits_fptr` - A reference to the `FITSFile` object.
+///
+/// * `hdu` - A reference to the HDU you want to read.
+///
+/// * `ranges` - A slice of (0-based, exclusive end) ranges, one per image axis, in the same order as returned by `get_hdu_image_size!` (i.e. slowest varying axis first).
+///
+/// * `buffer` - Buffer of floats (as a slice) to fill with data from the region. Must be exactly the size of the region.
+///
+///
+/// # Returns
+///
+/// * A Result of Ok on success, Err on error.
+///
+#[macro_export]
+macro_rules! get_fits_float_image_subset_into_buffer {
+    ($fptr:expr, $hdu:expr, $ranges:expr, $buffer:expr) => {
+        _get_fits_float_img_subset_into_buf($fptr, $hdu, $ranges, $buffer, file!(), line!())
+    };
+}
+
+/// Given a FITS file pointer and a HDU, get the byte offsets of the start and end of the HDU's data
+/// within the file.
+///
+/// # Arguments
+///
+/// * `fits_fptr` - A reference to the `FITSFile` object.
+///
+/// * `hdu` - A reference to the HDU you want the data offsets of.
+///
+///
+/// # Returns
+///
+/// * A Result containing a tuple of (start byte, end byte) of the HDU's data, if Ok.
+///
+#[macro_export]
+macro_rules! get_hdu_data_offsets {
+    ($fptr:expr, $hdu:expr) => {
+        _get_hdu_data_offsets($fptr, $hdu, file!(), line!())
+    };
+}
+
 /// Open a fits file.
 ///
 /// To only be used internally; use the `fits_open!` macro instead.
@@ -656,3 +707,119 @@ pub fn _get_fits_float_img_into_buf(
 
     Ok(())
 }
+
+/// Direct read of a region of a FITS HDU
+#[doc(hidden)]
+pub fn _get_fits_float_img_subset_into_buf(
+    fits_fptr: &mut FitsFile,
+    hdu: &FitsHdu,
+    ranges: &[std::ops::Range<usize>],
+    buffer: &mut [f32],
+    source_file: &'static str,
+    source_line: u32,
+) -> Result<(), FitsError> {
+    // cfitsio will write the whole region into the buffer, so it had better fit
+    assert_eq!(
+        buffer.len(),
+        ranges.iter().map(|r| r.len()).product::<usize>(),
+        "buffer is not the same size as the requested region"
+    );
+
+    // cfitsio wants 1-based, inclusive pixel coordinates with the fastest varying axis first
+    let mut first_pixel: Vec<std::os::raw::c_long> =
+        ranges.iter().rev().map(|r| (r.start + 1) as _).collect();
+    let mut last_pixel: Vec<std::os::raw::c_long> =
+        ranges.iter().rev().map(|r| r.end as _).collect();
+    let mut increment: Vec<std::os::raw::c_long> = vec![1; ranges.len()];
+
+    unsafe {
+        // Call the underlying cfitsio read subset function for floats
+        let mut status = 0;
+        fitsio_sys::ffgsv(
+            fits_fptr.as_raw(),
+            fitsio_sys::TFLOAT as _,
+            first_pixel.as_mut_ptr(),
+            last_pixel.as_mut_ptr(),
+            increment.as_mut_ptr(),
+            ptr::null_mut(),
+            buffer.as_mut_ptr() as *mut _,
+            ptr::null_mut(),
+            &mut status,
+        );
+
+        // Check fits call status
+        match fitsio::errors::check_status(status) {
+            Ok(_) => {}
+            Err(e) => {
+                return Err(FitsError::Fitsio {
+                    fits_error: e,
+                    fits_filename: fits_fptr.filename.clone(),
+                    hdu_num: hdu.number + 1,
+                    source_file,
+                    source_line,
+                });
+            }
+        }
+    }
+
+    trace!(
+        "_get_fits_float_img_subset_into_buf() filename: '{}' hdu: {} ranges: {:?}",
+        fits_fptr.filename.display(),
+        hdu.number,
+        ranges
+    );
+
+    Ok(())
+}
+
+/// Get the byte offsets of the data of a HDU
+#[doc(hidden)]
+pub fn _get_hdu_data_offsets(
+    fits_fptr: &mut FitsFile,
+    hdu: &FitsHdu,
+    source_file: &'static str,
+    source_line: u32,
+) -> Result<(u64, u64), FitsError> {
+    // Make sure the HDU we want is the current HDU
+    let hdu = _open_hdu(fits_fptr, hdu.number, source_file, source_line)?;
+
+    let mut header_start: fitsio_sys::LONGLONG = 0;
+    let mut data_start: fitsio_sys::LONGLONG = 0;
+    let mut data_end: fitsio_sys::LONGLONG = 0;
+
+    unsafe {
+        // Call the underlying cfitsio get HDU address function
+        let mut status = 0;
+        fitsio_sys::ffghadll(
+            fits_fptr.as_raw(),
+            &mut header_start,
+            &mut data_start,
+            &mut data_end,
+            &mut status,
+        );
+
+        // Check fits call status
+        match fitsio::errors::check_status(status) {
+            Ok(_) => {}
+            Err(e) => {
+                return Err(FitsError::Fitsio {
+                    fits_error: e,
+                    fits_filename: fits_fptr.filename.clone(),
+                    hdu_num: hdu.number + 1,
+                    source_file,
+                    source_line,
+                });
+            }
+        }
+    }
+
+    trace!(
+        "_get_hdu_data_offsets() filename: '{}' hdu: {} data start: {} data end: {}",
+        fits_fptr.filename.display(),
+        hdu.number,
+        data_start,
+        data_end
+    );
+
+    Ok((data_start as u64, data_end as u64))
+}
@@ -8,6 +8,7 @@
 use super::*;
 use crate::misc::test::*;
 use crate::*;
+use direct_read::DirectReadFile;
 use fitsio::images::{ImageDescription, ImageType};
 use fitsio::tables::{ColumnDataType, ColumnDescription};
 use fitsio_sys::ffpkls;
@@ -607,3 +608,184 @@ fn test_1244973688_metafits() -> Result<(), FitsError> {
     assert!(doesnt_exist.is_err());
     Ok(())
 }
+
+#[test]
+fn test_fits_handle_pool() {
+    let metafits1 = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let metafits2 = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let pool = FitsHandlePool::default();
+
+    // The same file gives the same handle
+    let handle1 = pool.get(metafits1, 0).unwrap();
+    let handle1_again = pool.get(metafits1, 0).unwrap();
+    assert!(std::sync::Arc::ptr_eq(&handle1, &handle1_again));
+    assert_eq!(pool.len(), 1);
+
+    // Handles are usable
+    let mut fptr = lock_fits_handle(&handle1);
+    let hdu = fits_open_hdu!(&mut fptr, 0).unwrap();
+    let obsid: i32 = get_required_fits_key!(&mut fptr, &hdu, "GPSTIME").unwrap();
+    assert_eq!(obsid, 1101503312);
+    drop(fptr);
+
+    // Unbounded
+    let handle2 = pool.get(metafits2, 0).unwrap();
+    assert_eq!(pool.len(), 2);
+
+    // Bounded, but both files are in use, so nothing can be closed
+    pool.get(metafits1, 1).unwrap();
+    assert_eq!(pool.len(), 2);
+
+    drop(handle1);
+    drop(handle1_again);
+    drop(handle2);
+    pool.clear();
+    assert_eq!(pool.len(), 0);
+
+    // Once no one else is using it, the least recently used file (metafits2) is closed
+    pool.get(metafits2, 1).unwrap();
+    pool.get(metafits1, 1).unwrap();
+    assert_eq!(pool.len(), 1);
+    let handle1 = pool.get(metafits1, 1).unwrap();
+    assert_eq!(std::sync::Arc::strong_count(&handle1), 2);
+
+    // Missing files are an error
+    assert!(pool.get("test_files/does_not_exist.fits", 0).is_err());
+    assert_eq!(pool.len(), 1);
+}
+
+#[test]
+fn test_read_be_f32_at() {
+    let values: Vec<f32> = vec![1.0, -2.5, 0.0, 1.0e-20, f32::MAX];
+
+    // 3 bytes of junk, then the big-endian floats
+    let mut bytes: Vec<u8> = vec![0xAB; 3];
+    for v in values.iter() {
+        bytes.extend_from_slice(&v.to_be_bytes());
+    }
+
+    let tdir = tempdir::TempDir::new("direct-read-").unwrap();
+    let filename = tdir.path().join("test_read_be_f32_at.dat");
+    std::fs::write(&filename, &bytes).unwrap();
+    let file = DirectReadFile::open(filename.to_str().unwrap()).unwrap();
+
+    let mut buffer: Vec<f32> = vec![0.; values.len()];
+    file.read_be_f32_at(3, &mut buffer).unwrap();
+    assert_eq!(buffer, values);
+
+    // Only part of the data
+    let mut buffer: Vec<f32> = vec![0.; 2];
+    file.read_be_f32_at(3 + 4, &mut buffer).unwrap();
+    assert_eq!(buffer, values[1..3]);
+
+    // Past the end of the file
+    let mut buffer: Vec<f32> = vec![0.; values.len()];
+    assert!(file.read_be_f32_at(4, &mut buffer).is_err());
+    assert!(file.read_be_f32_at(u64::MAX, &mut buffer).is_err());
+
+    // Nothing to read
+    file.read_be_f32_at(bytes.len() as u64, &mut []).unwrap();
+
+    // Missing file
+    assert!(DirectReadFile::open("test_files/does_not_exist.dat").is_err());
+
+    // Empty file
+    let empty_filename = tdir.path().join("test_read_be_f32_at_empty.dat");
+    std::fs::write(&empty_filename, []).unwrap();
+    let empty_file = DirectReadFile::open(empty_filename.to_str().unwrap()).unwrap();
+    empty_file.read_be_f32_at(0, &mut []).unwrap();
+    assert!(empty_file.read_be_f32_at(0, &mut buffer).is_err());
+}
+
+#[test]
+fn test_read_be_f32_at_large_offset() {
+    // Data starting part way through a later page of the file, and spanning several pages
+    let offset = 3 * 2880 + 7;
+    let values: Vec<f32> = (0..10_000).map(|i| i as f32 * 0.25 - 1000.0).collect();
+    let mut bytes: Vec<u8> = vec![0xAB; offset];
+    for v in values.iter() {
+        bytes.extend_from_slice(&v.to_be_bytes());
+    }
+
+    let tdir = tempdir::TempDir::new("direct-read-").unwrap();
+    let filename = tdir.path().join("test_read_be_f32_at_large_offset.dat");
+    std::fs::write(&filename, &bytes).unwrap();
+    let file = DirectReadFile::open(filename.to_str().unwrap()).unwrap();
+
+    let mut buffer: Vec<f32> = vec![0.; values.len()];
+    file.read_be_f32_at(offset as u64, &mut buffer).unwrap();
+    assert_eq!(buffer, values);
+}
+
+#[test]
+fn test_direct_read_file_pool() {
+    let tdir = tempdir::TempDir::new("direct-read-").unwrap();
+    let filename1 = tdir.path().join("test_direct_read_file_pool1.dat");
+    let filename2 = tdir.path().join("test_direct_read_file_pool2.dat");
+    std::fs::write(&filename1, 1.5_f32.to_be_bytes()).unwrap();
+    std::fs::write(&filename2, (-3.0_f32).to_be_bytes()).unwrap();
+    let filename1 = filename1.to_str().unwrap();
+    let filename2 = filename2.to_str().unwrap();
+    let pool = DirectReadFilePool::default();
+
+    // The same file is only opened (mapped) once
+    let file1 = pool.get(filename1, 0).unwrap();
+    let file1_again = pool.get(filename1, 0).unwrap();
+    assert!(std::sync::Arc::ptr_eq(&file1, &file1_again));
+    assert_eq!(pool.len(), 1);
+
+    let mut buffer = [0.0_f32];
+    file1.read_be_f32_at(0, &mut buffer).unwrap();
+    assert_eq!(buffer, [1.5]);
+
+    // Bounded, so once it is not in use file1 is closed to make room for file2
+    drop(file1);
+    drop(file1_again);
+    pool.get(filename2, 1)
+        .unwrap()
+        .read_be_f32_at(0, &mut buffer)
+        .unwrap();
+    assert_eq!(buffer, [-3.0]);
+    assert_eq!(pool.len(), 1);
+
+    pool.clear();
+    assert_eq!(pool.len(), 0);
+
+    // Missing files are an error
+    assert!(pool.get("test_files/does_not_exist.dat", 0).is_err());
+    assert_eq!(pool.len(), 0);
+}
+
+#[test]
+fn test_swap_be_bytes_to_f32() {
+    // Lengths which are and are not whole numbers of SIMD groups
+    for len in [0, 1, 3, 4, 5, 8, 17, 64] {
+        let values: Vec<f32> = (0..len).map(|i| i as f32 * -1.5 + 0.1).collect();
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+
+        let mut buffer: Vec<f32> = vec![0.; len];
+        direct_read::swap_be_bytes_to_f32(&bytes, &mut buffer);
+        assert_eq!(buffer, values);
+    }
+}
+
+#[test]
+fn test_get_hdu_data_offsets() {
+    with_new_temp_fits_file("test_get_hdu_data_offsets.fits", |fptr| {
+        let image_description = ImageDescription {
+            data_type: ImageType::Float,
+            dimensions: &[10, 20],
+        };
+        let hdu = fptr
+            .create_image("EXTNAME".to_string(), &image_description)
+            .unwrap();
+        hdu.write_image(fptr, &vec![1.0_f32; 200]).unwrap();
+
+        let (data_start, data_end) = get_hdu_data_offsets!(fptr, &hdu).unwrap();
+
+        // FITS data is always a multiple of 2880 byte blocks, after a header of at least one block
+        assert!(data_start >= 2880 * 2);
+        assert_eq!(data_start % 2880, 0);
+        assert_eq!(data_end - data_start, 2880);
+    });
+}
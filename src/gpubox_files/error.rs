@@ -16,6 +16,12 @@ pub enum GpuboxError {
     #[error("Invalid coarse chan index provided. The coarse chan index must be between 0 and {0}")]
     InvalidCoarseChanIndex(usize),
 
+    #[error("Invalid baseline index provided. The baseline index must be between 0 and {0}")]
+    InvalidBaselineIndex(usize),
+
+    #[error("Invalid fine chan index provided. The fine chan index must be between 0 and {0}")]
+    InvalidFineChanIndex(usize),
+
     #[error("No gpubox / mwax fits files were supplied")]
     NoGpuboxes,
 
@@ -104,6 +110,47 @@ maybe you have a mix of different files?"#)]
         coarse_chan_index: usize,
     },
 
+    #[error(
+        "The Van Vleck correction is only supported for legacy correlator data, not {mwa_version}"
+    )]
+    VanVleckCorrectionNotSupported { mwa_version: MWAVersion },
+
+    #[error("Provided buffer has shape {got:?} but should have shape {expected:?}")]
+    InvalidBufferShape {
+        expected: Vec<usize>,
+        got: Vec<usize>,
+    },
+
+    #[error("Provided buffer has {got} floats but should have {expected}")]
+    InvalidBufferSize { expected: usize, got: usize },
+
+    #[error("Invalid time averaging factor {0}. It must be at least 1")]
+    InvalidTimeAveragingFactor(usize),
+
+    #[error("Invalid frequency averaging factor {factor}. It must be at least 1 and divide the number of fine channels per coarse channel ({num_fine_chans_per_coarse})")]
+    InvalidFreqAveragingFactor {
+        factor: usize,
+        num_fine_chans_per_coarse: usize,
+    },
+
+    #[error("Invalid decorrelation tolerance {0}. It must be greater than 0 and less than 1")]
+    InvalidDecorrelationTolerance(f64),
+
+    #[error("Invalid full band axis: {num_slots} slots of {num_fine_chans_per_coarse} fine chans should have {expected} fine chans, but it has num_fine_chans {num_fine_chans} and {num_fine_chan_freqs} fine chan frequencies")]
+    InvalidFullBand {
+        num_slots: usize,
+        num_fine_chans_per_coarse: usize,
+        expected: usize,
+        num_fine_chans: usize,
+        num_fine_chan_freqs: usize,
+    },
+
+    #[error("Unsupported visibility layout: {0}")]
+    UnsupportedVisibilityLayout(String),
+
+    #[error("Failed to create a thread pool: {0}")]
+    ThreadPoolBuild(#[from] rayon::ThreadPoolBuildError),
+
     /// An error derived from `FitsError`.
     #[error("{0}")]
     Fits(#[from] crate::fits_read::error::FitsError),
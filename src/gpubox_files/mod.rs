@@ -132,6 +132,14 @@ lazy_static::lazy_static! {
 ///                                      Unix          Chan    Batch  Hdu
 pub(crate) type GpuboxTimeMap = BTreeMap<u64, BTreeMap<usize, (usize, usize)>>;
 
+/// A type alias for the byte offset and number of floats of the data of each MWAX HDU, if it
+/// can be read directly, i.e. it is an uncompressed, unscaled float image:
+/// `BTreeMap<(String, usize), Option<(u64, usize)>>`
+///
+/// The keys are gpubox filenames and HDU indices.
+///                                             Filename  Hdu            Offset Floats
+pub(crate) type GpuboxDataOffsetMap = BTreeMap<(String, usize), Option<(u64, usize)>>;
+
 /// A little struct to help us not get confused when dealing with the returned
 /// values from complex functions.
 pub(crate) struct GpuboxInfo {
@@ -628,6 +636,55 @@ fn create_time_map(
     Ok(gpubox_time_map)
 }
 
+/// Determine whether the data of a HDU is a plain, big-endian 32 bit float image, which can be
+/// read directly from the file without cfitsio, and if so where it starts.
+///
+/// Anything unusual (a different BITPIX, scaling via BSCALE/BZERO, a compressed image, or the data
+/// being shorter than the image) means the HDU must be read via cfitsio. This is only worked out
+/// when a HDU is first read directly, so opening a context doesn't have to look at every HDU.
+///
+///
+/// # Arguments
+///
+/// * `gpubox_fptr` - A FitsFile reference to this gpubox file.
+///
+/// * `hdu` - A reference to the HDU to examine.
+///
+///
+/// # Returns
+///
+/// * A Result containing the byte offset of the image data and the number of floats in the image if it
+///   can be read directly, or None if not.
+///
+///
+pub(crate) fn determine_raw_float_image_data_offset(
+    gpubox_fptr: &mut FitsFile,
+    hdu: &FitsHdu,
+) -> Result<Option<(u64, usize)>, FitsError> {
+    // Compressed images are stored as binary tables, so are not images here
+    let num_floats: usize = match get_hdu_image_size!(gpubox_fptr, hdu) {
+        Ok(shape) => shape.iter().product(),
+        Err(FitsError::NotImage { .. }) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let bitpix: i32 = get_required_fits_key!(gpubox_fptr, hdu, "BITPIX")?;
+    let bscale: Option<f64> = get_optional_fits_key!(gpubox_fptr, hdu, "BSCALE")?;
+    let bzero: Option<f64> = get_optional_fits_key!(gpubox_fptr, hdu, "BZERO")?;
+
+    if bitpix != -32 || bscale.unwrap_or(1.0) != 1.0 || bzero.unwrap_or(0.0) != 0.0 {
+        return Ok(None);
+    }
+
+    let (data_start, data_end) = get_hdu_data_offsets!(gpubox_fptr, hdu)?;
+
+    if data_end - data_start < (num_floats * std::mem::size_of::<f32>()) as u64 {
+        return Ok(None);
+    }
+
+    Ok(Some((data_start, num_floats)))
+}
+
 /// Returns a vector of timestep indicies which exist in the GpuBoxTimeMap (i.e. the user has provided at least some data files for these timesteps)
 ///
 /// # Arguments
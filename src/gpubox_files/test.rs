@@ -455,6 +455,52 @@ fn test_map_unix_times_to_hdus_test() {
     });
 }
 
+#[test]
+fn test_determine_raw_float_image_data_offset() {
+    with_new_temp_fits_file("determine_raw_float_image_data_offset.fits", |fptr| {
+        let float_image = ImageDescription {
+            data_type: ImageType::Float,
+            dimensions: &[10, 20],
+        };
+        let int_image = ImageDescription {
+            data_type: ImageType::Long,
+            dimensions: &[10, 20],
+        };
+
+        // A plain float image can be read directly
+        let hdu = fptr
+            .create_image("EXTNAME".to_string(), &float_image)
+            .unwrap();
+        hdu.write_image(fptr, &vec![1.0_f32; 200]).unwrap();
+        let (data_start, _) = get_hdu_data_offsets!(fptr, &hdu).unwrap();
+        assert_eq!(
+            determine_raw_float_image_data_offset(fptr, &hdu).unwrap(),
+            Some((data_start, 200))
+        );
+
+        // A scaled float image can not
+        let hdu = fptr
+            .create_image("EXTNAME".to_string(), &float_image)
+            .unwrap();
+        hdu.write_image(fptr, &vec![1.0_f32; 200]).unwrap();
+        hdu.write_key(fptr, "BSCALE", 2.0).unwrap();
+        assert_eq!(
+            determine_raw_float_image_data_offset(fptr, &hdu).unwrap(),
+            None
+        );
+
+        // Nor can an integer image
+        let hdu = fptr
+            .create_image("EXTNAME".to_string(), &int_image)
+            .unwrap();
+        hdu.write_image(fptr, &vec![1_i32; 200]).unwrap();
+        assert_eq!(
+            determine_raw_float_image_data_offset(fptr, &hdu).unwrap(),
+            None
+        );
+    });
+}
+
 #[test]
 fn test_determine_common_times_test_many_timesteps() {
     // Create two files, with mostly overlapping times, but also a little
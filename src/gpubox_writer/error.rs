@@ -0,0 +1,41 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Errors associated with writing MWAX gpubox files.
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::stokes::PolProducts;
+
+#[derive(Error, Debug)]
+pub enum GpuboxWriteError {
+    #[error(
+        "MWAX gpubox files can only be written from linear pols, but the read config asks for {0}"
+    )]
+    UnsupportedPolProducts(PolProducts),
+
+    #[error("MWAX gpubox files are written with the visibilities as stored, but the read config applies {0}")]
+    UnsupportedProcessing(String),
+
+    #[error("Invalid number of timesteps per file ({0}). It must be at least 1")]
+    InvalidTimestepsPerFile(usize),
+
+    #[error("There is no data to write")]
+    NoData,
+
+    #[error("Couldn't create {dir}: {error}")]
+    CreateDir { dir: PathBuf, error: std::io::Error },
+
+    #[error("{filename}: {fits_error}")]
+    Fitsio {
+        fits_error: fitsio::errors::Error,
+        filename: PathBuf,
+    },
+
+    /// An error derived from `GpuboxError`.
+    #[error("{0}")]
+    Gpubox(#[from] crate::gpubox_files::error::GpuboxError),
+}
@@ -0,0 +1,343 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Writing the visibilities of any correlator observation as MWAX v2 gpubox files, so e.g. a legacy observation
+//! can be converted once and then always read via the MWAX code path.
+//!
+//! There is one file per coarse channel per batch of timesteps, named `obsid_datetime_chNNN_BBB.fits`, where
+//! `datetime` is the UTC start of the batch's first timestep, `NNN` the receiver channel number and `BBB` the batch
+//! number. The primary HDU has `OBSID` and `CORR_VER = 2`, and is followed by a data HDU
+//! ([baseline][fine chan][pol][r,i]) and a weights HDU ([baseline][pol]) for each timestep, both with `TIME` and
+//! `MILLITIM` keys. Visibilities are written as returned by `read_by_baseline`, so e.g. legacy visibilities are
+//! converted to MWAX ordering and conjugation. The context's `read_config` must not scale or correct the visibilities
+//! (or change their pols or layout), as the files are read with the same metafits (e.g. the same `RAWSCALE`). Weights
+//! are copied from MWAX gpubox files; legacy gpubox files have no weights, so they are written as 1.
+//!
+//! Every file of a batch has the same timesteps, so if a coarse channel has no data for a timestep, zeros are
+//! written with weights of zero.
+
+pub mod error;
+#[cfg(test)]
+mod test;
+
+use std::path::{Path, PathBuf};
+
+use fitsio::images::{ImageDescription, ImageType};
+use fitsio::FitsFile;
+
+use crate::stokes::PolProducts;
+use crate::*;
+use error::GpuboxWriteError;
+
+///
+/// Options for `CorrelatorContext::write_mwax_gpubox_files`.
+///
+/// The default is to write all of the timesteps to a single batch.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GpuboxWriteConfig {
+    /// Maximum number of timesteps in each file. None puts every timestep in one batch.
+    pub max_timesteps_per_file: Option<usize>,
+}
+
+impl GpuboxWriteConfig {
+    /// Creates a new `GpuboxWriteConfig` which writes a single batch.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A new `GpuboxWriteConfig`
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum number of timesteps in each file, splitting the observation into batches.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_timesteps_per_file` - maximum number of timesteps in each file. Must be at least 1.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `GpuboxWriteConfig`
+    ///
+    pub fn with_max_timesteps_per_file(mut self, max_timesteps_per_file: usize) -> Self {
+        self.max_timesteps_per_file = Some(max_timesteps_per_file);
+        self
+    }
+}
+
+/// Get the filename of an MWAX gpubox file.
+///
+/// # Arguments
+///
+/// * `obs_id` - observation id.
+///
+/// * `datetime` - UTC start of the first timestep in the file.
+///
+/// * `rec_chan_number` - receiver channel number of the file's coarse channel.
+///
+/// * `batch_number` - batch number of the file.
+///
+///
+/// # Returns
+///
+/// * The filename, e.g. `1244973688_20190619100110_ch114_000.fits`.
+///
+pub(crate) fn get_mwax_gpubox_filename(
+    obs_id: u32,
+    datetime: &chrono::DateTime<chrono::FixedOffset>,
+    rec_chan_number: usize,
+    batch_number: usize,
+) -> String {
+    format!(
+        "{}_{}_ch{:03}_{:03}.fits",
+        obs_id,
+        datetime.format("%Y%m%d%H%M%S"),
+        rec_chan_number,
+        batch_number
+    )
+}
+
+impl CorrelatorContext {
+    /// Write the visibilities of every provided timestep and coarse channel to MWAX v2 gpubox files. See the
+    /// `gpubox_writer` module documentation for the layout of the files. The files can then be opened with the
+    /// same metafits in a new `CorrelatorContext`.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - directory to write the files to. It is created if needed, and existing files are replaced.
+    ///
+    /// * `config` - how to split the observation into batches.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the paths of the files written, or a GpuboxWriteError on failure.
+    ///
+    pub fn write_mwax_gpubox_files<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        config: &GpuboxWriteConfig,
+    ) -> Result<Vec<PathBuf>, GpuboxWriteError> {
+        let antenna_indices: Vec<usize> = (0..self.metafits_context.num_ants).collect();
+
+        self.write_mwax_gpubox_files_subset(
+            output_dir.as_ref(),
+            config,
+            &self.provided_timestep_indices,
+            &self.provided_coarse_chan_indices,
+            &antenna_indices,
+        )
+    }
+
+    /// Write the visibilities of some timesteps, coarse channels and antennas to MWAX v2 gpubox files. Only the
+    /// baselines between the given antennas are written, in their original order, so the files match a metafits
+    /// which only has those antennas.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - directory to write the files to. It is created if needed, and existing files are replaced.
+    ///
+    /// * `config` - how to split the observation into batches.
+    ///
+    /// * `timestep_indices` - ascending indices within the CorrelatorContext timestep array.
+    ///
+    /// * `coarse_chan_indices` - ascending indices within the CorrelatorContext coarse_chan array.
+    ///
+    /// * `antenna_indices` - ascending indices within the metafits antenna array.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the paths of the files written, or a GpuboxWriteError on failure.
+    ///
+    pub(crate) fn write_mwax_gpubox_files_subset(
+        &self,
+        output_dir: &Path,
+        config: &GpuboxWriteConfig,
+        timestep_indices: &[usize],
+        coarse_chan_indices: &[usize],
+        antenna_indices: &[usize],
+    ) -> Result<Vec<PathBuf>, GpuboxWriteError> {
+        let metafits_context = &self.metafits_context;
+
+        if self.read_config.pol_products != PolProducts::Linear {
+            return Err(GpuboxWriteError::UnsupportedPolProducts(
+                self.read_config.pol_products,
+            ));
+        }
+        // RAWSCALE and the Van Vleck correction would be applied again when the files are read
+        if self.read_config.apply_raw_scale_factor {
+            return Err(GpuboxWriteError::UnsupportedProcessing(
+                "RAWSCALE scaling".to_string(),
+            ));
+        }
+        if self.read_config.apply_van_vleck_correction {
+            return Err(GpuboxWriteError::UnsupportedProcessing(
+                "the Van Vleck correction".to_string(),
+            ));
+        }
+        self.validate_default_layout()?;
+        if timestep_indices.is_empty()
+            || coarse_chan_indices.is_empty()
+            || antenna_indices.is_empty()
+        {
+            return Err(GpuboxWriteError::NoData);
+        }
+        let timesteps_per_file = match config.max_timesteps_per_file {
+            Some(0) => return Err(GpuboxWriteError::InvalidTimestepsPerFile(0)),
+            Some(n) => n,
+            None => timestep_indices.len(),
+        };
+
+        // The baselines between the selected antennas, in their original order
+        let mut antenna_selected = vec![false; metafits_context.num_ants];
+        for &a in antenna_indices {
+            antenna_selected[a] = true;
+        }
+        let baseline_indices: Vec<usize> = metafits_context
+            .baselines
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| antenna_selected[b.ant1_index] && antenna_selected[b.ant2_index])
+            .map(|(i, _)| i)
+            .collect();
+        let all_baselines = baseline_indices.len() == metafits_context.num_baselines;
+
+        std::fs::create_dir_all(output_dir).map_err(|error| GpuboxWriteError::CreateDir {
+            dir: output_dir.to_path_buf(),
+            error,
+        })?;
+
+        let num_pols = metafits_context.num_visibility_pols;
+        let num_baselines = baseline_indices.len();
+        let num_fine_chans = metafits_context.num_corr_fine_chans_per_coarse;
+        let fine_chan_indices: Vec<usize> = (0..num_fine_chans).collect();
+        let data_dims = [num_baselines, num_fine_chans * num_pols * 2];
+        let weights_dims = [num_baselines, num_pols];
+        let zeros: Vec<f32> = vec![0.; data_dims.iter().product()];
+
+        let mut filenames: Vec<PathBuf> = Vec::new();
+
+        for (batch_number, batch_timestep_indices) in
+            timestep_indices.chunks(timesteps_per_file).enumerate()
+        {
+            let first_timestep = &self.timesteps[batch_timestep_indices[0]];
+            let batch_start_utc = metafits_context.sched_start_utc
+                + chrono::Duration::milliseconds(
+                    first_timestep.unix_time_ms as i64
+                        - metafits_context.sched_start_unix_time_ms as i64,
+                );
+
+            for &coarse_chan_index in coarse_chan_indices {
+                let coarse_chan = &self.coarse_chans[coarse_chan_index];
+                let filename = output_dir.join(get_mwax_gpubox_filename(
+                    metafits_context.obs_id,
+                    &batch_start_utc,
+                    coarse_chan.rec_chan_number,
+                    batch_number,
+                ));
+                let fits_error = |fits_error| GpuboxWriteError::Fitsio {
+                    fits_error,
+                    filename: filename.clone(),
+                };
+
+                let mut fptr = FitsFile::create(&filename)
+                    .overwrite()
+                    .open()
+                    .map_err(fits_error)?;
+                let primary_hdu = fptr.primary_hdu().map_err(fits_error)?;
+                for (key, value) in [
+                    ("OBSID", metafits_context.obs_id as i64),
+                    ("CORR_VER", 2),
+                    ("TIME", (first_timestep.unix_time_ms / 1000) as i64),
+                    ("MILLITIM", (first_timestep.unix_time_ms % 1000) as i64),
+                    ("NINPUTS", (antenna_indices.len() * 2) as i64),
+                    ("NFINECHS", num_fine_chans as i64),
+                    ("CHANNEL", coarse_chan.rec_chan_number as i64),
+                ] {
+                    primary_hdu
+                        .write_key(&mut fptr, key, value)
+                        .map_err(fits_error)?;
+                }
+                primary_hdu
+                    .write_key(
+                        &mut fptr,
+                        "INTTIME",
+                        metafits_context.corr_int_time_ms as f64 / 1000.,
+                    )
+                    .map_err(fits_error)?;
+                primary_hdu
+                    .write_key(
+                        &mut fptr,
+                        "FINECHAN",
+                        metafits_context.corr_fine_chan_width_hz as f64 / 1000.,
+                    )
+                    .map_err(fits_error)?;
+                primary_hdu
+                    .write_key(&mut fptr, "PROJID", metafits_context.project_id.as_str())
+                    .map_err(fits_error)?;
+
+                for &timestep_index in batch_timestep_indices {
+                    let unix_time_ms = self.timesteps[timestep_index].unix_time_ms;
+                    let has_data = self.has_data(timestep_index, coarse_chan_index);
+                    let data: Vec<f32> = match (has_data, all_baselines) {
+                        (true, true) => self.read_by_baseline(timestep_index, coarse_chan_index)?,
+                        (true, false) => self.read_by_baseline_subset(
+                            timestep_index,
+                            coarse_chan_index,
+                            &baseline_indices,
+                            &fine_chan_indices,
+                        )?,
+                        (false, _) => vec![],
+                    };
+                    let weights: Vec<f32> = if has_data {
+                        let all_weights =
+                            self.read_weights_by_baseline(timestep_index, coarse_chan_index)?;
+                        baseline_indices
+                            .iter()
+                            .flat_map(|&b| &all_weights[b * num_pols..(b + 1) * num_pols])
+                            .copied()
+                            .collect()
+                    } else {
+                        vec![0.; weights_dims.iter().product()]
+                    };
+
+                    for (extname, dims, image) in [
+                        ("DATA", &data_dims, if has_data { &data } else { &zeros }),
+                        ("WEIGHTS", &weights_dims, &weights),
+                    ] {
+                        let hdu = fptr
+                            .create_image(
+                                extname.to_string(),
+                                &ImageDescription {
+                                    data_type: ImageType::Float,
+                                    dimensions: dims,
+                                },
+                            )
+                            .map_err(fits_error)?;
+                        hdu.write_key(&mut fptr, "TIME", (unix_time_ms / 1000) as i64)
+                            .map_err(fits_error)?;
+                        hdu.write_key(&mut fptr, "MILLITIM", (unix_time_ms % 1000) as i64)
+                            .map_err(fits_error)?;
+                        hdu.write_key(&mut fptr, "MARKER", timestep_index as i64)
+                            .map_err(fits_error)?;
+                        hdu.write_image(&mut fptr, image).map_err(fits_error)?;
+                    }
+                }
+
+                filenames.push(filename);
+            }
+        }
+
+        Ok(filenames)
+    }
+}
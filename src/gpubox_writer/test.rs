@@ -0,0 +1,145 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Unit tests for writing MWAX gpubox files
+
+use super::*;
+
+/// Write `context` as MWAX gpubox files, open them with the same metafits and check the visibilities of each of
+/// the provided timesteps and coarse channels read back the same.
+fn check_round_trip(
+    context: &CorrelatorContext,
+    metafits_filename: &str,
+    config: &GpuboxWriteConfig,
+) {
+    let temp_dir = tempdir::TempDir::new("gpubox_writer_test").unwrap();
+    let filenames = context
+        .write_mwax_gpubox_files(temp_dir.path(), config)
+        .unwrap();
+
+    let mwax_context = CorrelatorContext::new(metafits_filename, &filenames).unwrap();
+    assert_eq!(mwax_context.mwa_version, MWAVersion::CorrMWAXv2);
+
+    for &t in context.provided_timestep_indices.iter() {
+        for &c in context.provided_coarse_chan_indices.iter() {
+            if !context.has_data(t, c) {
+                continue;
+            }
+            let unix_time_ms = context.timesteps[t].unix_time_ms;
+            let rec_chan_number = context.coarse_chans[c].rec_chan_number;
+            let mwax_t = mwax_context
+                .timesteps
+                .iter()
+                .position(|ts| ts.unix_time_ms == unix_time_ms)
+                .unwrap();
+            let mwax_c = mwax_context
+                .coarse_chans
+                .iter()
+                .position(|cc| cc.rec_chan_number == rec_chan_number)
+                .unwrap();
+
+            assert_eq!(
+                mwax_context.read_by_baseline(mwax_t, mwax_c).unwrap(),
+                context.read_by_baseline(t, c).unwrap()
+            );
+            assert_eq!(
+                mwax_context
+                    .read_weights_by_baseline(mwax_t, mwax_c)
+                    .unwrap(),
+                context.read_weights_by_baseline(t, c).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_get_mwax_gpubox_filename() {
+    let datetime = chrono::DateTime::parse_from_rfc3339("2019-06-19T10:01:10+00:00").unwrap();
+    assert_eq!(
+        get_mwax_gpubox_filename(1244973688, &datetime, 114, 0),
+        "1244973688_20190619100110_ch114_000.fits"
+    );
+    assert_eq!(
+        get_mwax_gpubox_filename(1244973688, &datetime, 62, 12),
+        "1244973688_20190619100110_ch062_012.fits"
+    );
+}
+
+#[test]
+fn test_gpubox_write_config() {
+    assert_eq!(GpuboxWriteConfig::new().max_timesteps_per_file, None);
+    assert_eq!(
+        GpuboxWriteConfig::new()
+            .with_max_timesteps_per_file(4)
+            .max_timesteps_per_file,
+        Some(4)
+    );
+}
+
+#[test]
+fn test_write_mwax_gpubox_files_mwax() {
+    let metafits_filename = "test_files/1244973688_1_timestep/1244973688.metafits";
+    let context = CorrelatorContext::new(
+        metafits_filename,
+        &["test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits"],
+    )
+    .unwrap();
+
+    check_round_trip(&context, metafits_filename, &GpuboxWriteConfig::new());
+}
+
+#[test]
+fn test_write_mwax_gpubox_files_legacy() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let context = CorrelatorContext::new(
+        metafits_filename,
+        &["test_files/1101503312_1_timestep/1101503312_20141201210818_gpubox01_00.fits"],
+    )
+    .unwrap();
+
+    check_round_trip(
+        &context,
+        metafits_filename,
+        &GpuboxWriteConfig::new().with_max_timesteps_per_file(1),
+    );
+}
+
+#[test]
+fn test_write_mwax_gpubox_files_invalid() {
+    let mut context = CorrelatorContext::new(
+        "test_files/1244973688_1_timestep/1244973688.metafits",
+        &["test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits"],
+    )
+    .unwrap();
+    let temp_dir = tempdir::TempDir::new("gpubox_writer_test").unwrap();
+
+    assert!(matches!(
+        context.write_mwax_gpubox_files(
+            temp_dir.path(),
+            &GpuboxWriteConfig::new().with_max_timesteps_per_file(0)
+        ),
+        Err(GpuboxWriteError::InvalidTimestepsPerFile(0))
+    ));
+
+    context.read_config = context.read_config.with_pol_products(PolProducts::Stokes);
+    assert!(matches!(
+        context.write_mwax_gpubox_files(temp_dir.path(), &GpuboxWriteConfig::new()),
+        Err(GpuboxWriteError::UnsupportedPolProducts(
+            PolProducts::Stokes
+        ))
+    ));
+
+    // Scaling or correcting the visibilities would be done again when they are read back
+    context.read_config = CorrelatorReadConfig::new().with_raw_scale_factor(true);
+    assert!(matches!(
+        context.write_mwax_gpubox_files(temp_dir.path(), &GpuboxWriteConfig::new()),
+        Err(GpuboxWriteError::UnsupportedProcessing(_))
+    ));
+
+    context.read_config = CorrelatorReadConfig::new().with_van_vleck_correction(true);
+    assert!(matches!(
+        context.write_mwax_gpubox_files(temp_dir.path(), &GpuboxWriteConfig::new()),
+        Err(GpuboxWriteError::UnsupportedProcessing(_))
+    ));
+}
@@ -11,14 +11,22 @@ mod baseline;
 mod coarse_channel;
 mod convert;
 mod correlator_context;
+mod data_iterator;
 mod error;
 mod ffi;
 mod fits_read;
 mod gpubox_files;
+mod gpubox_writer;
 mod metafits_context;
 mod misc;
+mod npy;
 mod rfinput;
+mod selection;
+mod stokes;
+mod subset;
 mod timestep;
+mod uvfits;
+mod van_vleck;
 mod voltage_context;
 mod voltage_files;
 
@@ -55,21 +63,45 @@ pub mod built_info {
 pub use antenna::Antenna;
 pub use baseline::Baseline;
 pub use coarse_channel::error::CoarseChannelError;
-pub use coarse_channel::CoarseChannel;
-pub use correlator_context::CorrelatorContext;
+pub use coarse_channel::{CoarseChannel, CoarseChannelBand};
+pub use convert::{
+    convert_mwax_to_legacy_hdu, BaselineOrder, ConjugationConvention, VisibilityLayout,
+};
+pub use correlator_context::{
+    AveragedVisibilities, BaselineAveragedVisibilities, CorrelatorContext, CorrelatorFlagConfig,
+    CorrelatorReadConfig, FullBand, MissingDataPolicy, VisibilityOrder, VisibilityView,
+};
+pub use data_iterator::{
+    CorrelatorDataIterator, CorrelatorReadAhead, IndexSet, IterationOrder, ReadAhead,
+    ReadAheadBuffer, VoltageDataIterator, VoltageReadAhead,
+};
 pub use error::MwalibError;
 pub use fits_read::*;
 pub use gpubox_files::GpuboxError;
+pub use gpubox_writer::{error::GpuboxWriteError, GpuboxWriteConfig};
 pub use metafits_context::error::MetafitsError;
 pub use metafits_context::{
     CableDelaysApplied, GeometricDelaysApplied, MWAMode, MWAVersion, MetafitsContext, VisPol,
 };
 pub use misc::*;
+pub use npy::error::NpyError;
 pub use rfinput::{error::RfinputError, Pol, Rfinput};
+pub use selection::{error::SelectionError, Selection};
+pub use stokes::{
+    convert_linear_to_pol_products, convert_linear_to_pseudo_stokes_i, convert_linear_to_stokes,
+    PolProducts, StokesPol,
+};
+pub use subset::{error::SubsetError, SubsetConfig, SubsetFiles};
 pub use timestep::TimeStep;
+pub use uvfits::{error::UvfitsError, UvfitsWriteConfig};
+pub use van_vleck::{
+    van_vleck_correlation, van_vleck_cross_gain, van_vleck_quantised_covariance, van_vleck_sighat,
+    van_vleck_sigma, MWA_LEGACY_VAN_VLECK_MAX_LEVEL,
+};
 pub use voltage_context::VoltageContext;
 pub use voltage_files::error::VoltageFileError;
 
 // So that callers don't use a different version of fitsio, export them here.
 pub use fitsio;
 pub use fitsio_sys;
+pub use num_complex;
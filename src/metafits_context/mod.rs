@@ -991,6 +991,101 @@ impl MetafitsContext {
         Ok(())
     }
 
+    /// Group the metafits coarse channels into bands of contiguous receiver channels
+    /// (e.g. for picket fence observations).
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * a vector of `CoarseChannelBand`s in ascending sky frequency order. Coarse channel indices in each band are
+    ///   indices into `metafits_coarse_chans`.
+    ///
+    pub fn get_contiguous_bands(&self) -> Vec<CoarseChannelBand> {
+        CoarseChannel::get_contiguous_bands(&self.metafits_coarse_chans)
+    }
+
+    /// Return the indices of the metafits coarse channels which overlap a range of sky frequencies.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_hz` - start of the frequency range in Hz (inclusive).
+    ///
+    /// * `end_hz` - end of the frequency range in Hz (inclusive).
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * a vector of indices into `metafits_coarse_chans` of every coarse channel whose [`chan_start_hz`, `chan_end_hz`) range
+    ///   intersects [`start_hz`, `end_hz`].
+    ///
+    pub fn get_coarse_chan_indices_for_freq_range(&self, start_hz: f64, end_hz: f64) -> Vec<usize> {
+        CoarseChannel::get_coarse_chan_indices_in_freq_range(
+            &self.metafits_coarse_chans,
+            start_hz,
+            end_hz,
+        )
+    }
+
+    /// Return the fine channels whose centre frequencies are within a range of sky frequencies.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_hz` - start of the frequency range in Hz (inclusive).
+    ///
+    /// * `end_hz` - end of the frequency range in Hz (inclusive).
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * a vector of (index into `metafits_coarse_chans`, fine channel index within the coarse channel) pairs in ascending
+    ///   frequency order, consistent with `metafits_fine_chan_freqs_hz`.
+    ///
+    pub fn get_fine_chan_indices_for_freq_range(
+        &self,
+        start_hz: f64,
+        end_hz: f64,
+    ) -> Vec<(usize, usize)> {
+        CoarseChannel::get_fine_chan_indices_in_freq_range(
+            &self.metafits_fine_chan_freqs_hz,
+            self.num_metafits_fine_chans_per_coarse(),
+            start_hz,
+            end_hz,
+        )
+    }
+
+    /// Return the fine channel whose centre frequency is nearest to a sky frequency.
+    ///
+    /// # Arguments
+    ///
+    /// * `freq_hz` - the sky frequency in Hz.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * the (index into `metafits_coarse_chans`, fine channel index within the coarse channel) of the nearest fine channel,
+    ///   consistent with `metafits_fine_chan_freqs_hz`, or None if there are no fine channels.
+    ///
+    pub fn get_nearest_fine_chan_index(&self, freq_hz: f64) -> Option<(usize, usize)> {
+        CoarseChannel::get_nearest_fine_chan_index(
+            &self.metafits_fine_chan_freqs_hz,
+            self.num_metafits_fine_chans_per_coarse(),
+            freq_hz,
+        )
+    }
+
+    /// Returns the number of fine channels per coarse channel in `metafits_fine_chan_freqs_hz`, which depends on
+    /// whether this is a correlator or voltage observation.
+    fn num_metafits_fine_chans_per_coarse(&self) -> usize {
+        match self.num_metafits_coarse_chans {
+            0 => 0,
+            n => self.num_metafits_fine_chan_freqs / n,
+        }
+    }
+
     /// Return an expected voltage filenames for the input timestep and coarse channel indices.
     ///
     /// # Arguments    
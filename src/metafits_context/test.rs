@@ -661,3 +661,27 @@ fn test_mode_enum() {
     assert!(MWAMode::from_str("MWAX_BUFFER").is_ok());
     assert!(MWAMode::from_str("something invalid").is_err());
 }
+
+#[test]
+fn test_metafits_context_get_contiguous_bands() {
+    let metafits_filename = "test_files/1101503312_1_timestep/1101503312.metafits";
+    let context = MetafitsContext::new(metafits_filename, Some(MWAVersion::CorrLegacy))
+        .expect("Failed to create MetafitsContext");
+
+    // Receiver channels 109..132 are one contiguous band
+    let bands = context.get_contiguous_bands();
+    assert_eq!(bands.len(), 1);
+    assert_eq!(bands[0].num_coarse_chans, 24);
+    assert_eq!(bands[0].first_rec_chan_number, 109);
+    assert_eq!(bands[0].last_rec_chan_number, 132);
+    assert_eq!(
+        bands[0].start_hz,
+        context.metafits_coarse_chans[0].chan_start_hz
+    );
+    assert_eq!(
+        bands[0].end_hz,
+        context.metafits_coarse_chans[23].chan_end_hz
+    );
+    assert_eq!(bands[0].bandwidth_hz, context.obs_bandwidth_hz);
+    assert_eq!(bands[0].centre_hz, 154_240_000);
+}
@@ -0,0 +1,28 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Errors associated with writing NumPy files.
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NpyError {
+    #[error("The selection has no {0}")]
+    EmptySelection(String),
+
+    #[error("Array {name} is {size} bytes, which is too large for an .npz file (the limit is 4 GiB per array)")]
+    ArrayTooLarge { name: String, size: usize },
+
+    #[error("Couldn't write {filename}: {error}")]
+    Io {
+        filename: PathBuf,
+        error: std::io::Error,
+    },
+
+    /// An error derived from `GpuboxError`.
+    #[error("{0}")]
+    Gpubox(#[from] crate::gpubox_files::error::GpuboxError),
+}
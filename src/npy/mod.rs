@@ -0,0 +1,596 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Exporting a selection of a correlator observation to NumPy `.npy` and `.npz` files, so it can be loaded with
+//! nothing more than `numpy.load`.
+//!
+//! The visibilities are a complex64 array with shape [timestep][frequency][baseline][pol], where the frequency axis
+//! is the selected fine channels of each selected coarse channel, in order. Timesteps and coarse channels with no
+//! data are zeros. The `.npz` also contains:
+//!
+//! | Array            | Type      | Shape                           | Contents                                           |
+//! |------------------|-----------|---------------------------------|----------------------------------------------------|
+//! | `visibilities`   | complex64 | [timestep][freq][baseline][pol] | the visibilities                                   |
+//! | `data_present`   | bool      | [timestep][coarse chan]         | false where there was no data to read              |
+//! | `frequencies_hz` | float64   | [freq]                          | centre frequency of each fine channel              |
+//! | `unix_times_ms`  | uint64    | [timestep]                      | UNIX start time of each timestep                   |
+//! | `gps_times_ms`   | uint64    | [timestep]                      | GPS start time of each timestep                    |
+//! | `baselines`      | uint64    | [baseline][2]                   | indices into `tile_names` of each baseline's tiles |
+//! | `tile_names`     | str       | [antenna]                       | name of every tile in the metafits                 |
+//! | `pols`           | str       | [pol]                           | name of each pol                                   |
+//!
+//! Files are written in the version 1.0 `.npy` format, and `.npz` files are uncompressed zip archives (just like
+//! `numpy.savez`), so no extra dependencies are needed.
+
+pub mod error;
+#[cfg(test)]
+mod test;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use num_complex::Complex;
+
+use crate::selection::{Selection, POL_NAMES};
+use crate::stokes::PolProducts;
+use crate::*;
+use error::NpyError;
+
+/// The magic string at the start of every `.npy` file, followed by the format version (1.0).
+const NPY_MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+
+/// The CRC-32 (IEEE) lookup table, needed for zip archives.
+const CRC32_TABLE: [u32; 256] = make_crc32_table();
+
+const fn make_crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Calculate the CRC-32 (IEEE) checksum of some bytes.
+///
+/// # Arguments
+///
+/// * `bytes` - the bytes to checksum.
+///
+///
+/// # Returns
+///
+/// * The checksum.
+///
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// A type which can be an element of a NumPy array.
+pub(crate) trait NpyElement {
+    /// The NumPy dtype description, e.g. `<f4`.
+    const DESCR: &'static str;
+
+    /// Append the little endian bytes of the element.
+    fn extend_le_bytes(&self, bytes: &mut Vec<u8>);
+}
+
+impl NpyElement for f32 {
+    const DESCR: &'static str = "<f4";
+
+    fn extend_le_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl NpyElement for f64 {
+    const DESCR: &'static str = "<f8";
+
+    fn extend_le_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl NpyElement for u64 {
+    const DESCR: &'static str = "<u8";
+
+    fn extend_le_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl NpyElement for bool {
+    const DESCR: &'static str = "|b1";
+
+    fn extend_le_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.push(*self as u8);
+    }
+}
+
+impl NpyElement for Complex<f32> {
+    const DESCR: &'static str = "<c8";
+
+    fn extend_le_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.re.to_le_bytes());
+        bytes.extend_from_slice(&self.im.to_le_bytes());
+    }
+}
+
+/// A C-ordered NumPy array, ready to be written.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct NpyArray {
+    /// The NumPy dtype description.
+    pub descr: String,
+    /// The size of each dimension.
+    pub shape: Vec<usize>,
+    /// The elements, as little endian bytes.
+    pub data: Vec<u8>,
+}
+
+impl NpyArray {
+    /// Create an array of numbers.
+    ///
+    /// # Arguments
+    ///
+    /// * `shape` - the size of each dimension.
+    ///
+    /// * `values` - the elements, in C (row major) order. Must be the product of `shape` long.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The array.
+    ///
+    pub fn new<T: NpyElement>(shape: &[usize], values: &[T]) -> Self {
+        assert_eq!(
+            shape.iter().product::<usize>(),
+            values.len(),
+            "NpyArray::new: shape doesn't match the number of values"
+        );
+
+        let mut data: Vec<u8> = Vec::with_capacity(std::mem::size_of_val(values));
+        for v in values {
+            v.extend_le_bytes(&mut data);
+        }
+
+        Self {
+            descr: T::DESCR.to_string(),
+            shape: shape.to_vec(),
+            data,
+        }
+    }
+
+    /// Create a 1D array of unicode strings, which is as wide as the longest string.
+    ///
+    /// # Arguments
+    ///
+    /// * `strings` - the elements.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The array.
+    ///
+    pub fn from_strings<S: AsRef<str>>(strings: &[S]) -> Self {
+        let width = strings
+            .iter()
+            .map(|s| s.as_ref().chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        let mut data: Vec<u8> = Vec::with_capacity(strings.len() * width * 4);
+        for s in strings {
+            let mut num_chars = 0;
+            for c in s.as_ref().chars() {
+                data.extend_from_slice(&(c as u32).to_le_bytes());
+                num_chars += 1;
+            }
+            data.resize(data.len() + (width - num_chars) * 4, 0);
+        }
+
+        Self {
+            descr: format!("<U{}", width),
+            shape: vec![strings.len()],
+            data,
+        }
+    }
+
+    /// Get the `.npy` file header for this array: the magic string, version and a Python dict literal describing
+    /// the array, padded so the data starts on a 64 byte boundary.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The header bytes.
+    ///
+    pub fn header(&self) -> Vec<u8> {
+        let shape = match self.shape.len() {
+            1 => format!("({},)", self.shape[0]),
+            _ => format!(
+                "({})",
+                self.shape
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        };
+        let mut dict = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+            self.descr, shape
+        );
+
+        // The magic string and version, the header length, the dict and a newline
+        let unpadded_len = NPY_MAGIC.len() + 2 + dict.len() + 1;
+        let padded_len = (unpadded_len + 63) / 64 * 64;
+        dict.push_str(&" ".repeat(padded_len - unpadded_len));
+        dict.push('\n');
+
+        let mut header: Vec<u8> = Vec::with_capacity(padded_len);
+        header.extend_from_slice(NPY_MAGIC);
+        header.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+        header.extend_from_slice(dict.as_bytes());
+        header
+    }
+
+    /// Get the whole `.npy` file for this array.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The `.npy` file bytes.
+    ///
+    pub fn to_npy_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header();
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+/// An `.npz` (uncompressed zip of `.npy` files) being written.
+pub(crate) struct NpzWriter {
+    writer: BufWriter<File>,
+    filename: PathBuf,
+    /// Bytes written so far.
+    offset: usize,
+    /// The name, CRC-32, size and offset of each file in the archive so far.
+    entries: Vec<(String, u32, usize, usize)>,
+}
+
+impl NpzWriter {
+    /// Create a new, empty archive, replacing any existing file.
+    pub fn create(filename: &Path) -> Result<Self, NpyError> {
+        let file = File::create(filename).map_err(|error| NpyError::Io {
+            filename: filename.to_path_buf(),
+            error,
+        })?;
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+            filename: filename.to_path_buf(),
+            offset: 0,
+            entries: vec![],
+        })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), NpyError> {
+        self.writer.write_all(bytes).map_err(|error| NpyError::Io {
+            filename: self.filename.clone(),
+            error,
+        })?;
+        self.offset += bytes.len();
+        Ok(())
+    }
+
+    /// Add an array to the archive as `<name>.npy`.
+    pub fn add(&mut self, name: &str, array: &NpyArray) -> Result<(), NpyError> {
+        let npy_name = format!("{}.npy", name);
+        let bytes = array.to_npy_bytes();
+        // Without zip64 extensions, sizes and offsets are 32 bit
+        if bytes.len() + self.offset > u32::MAX as usize {
+            return Err(NpyError::ArrayTooLarge {
+                name: name.to_string(),
+                size: bytes.len(),
+            });
+        }
+        let crc = crc32(&bytes);
+
+        let mut local_header: Vec<u8> = Vec::with_capacity(30 + npy_name.len());
+        local_header.extend_from_slice(&0x0403_4b50_u32.to_le_bytes());
+        write_zip_file_info(&mut local_header, crc, bytes.len(), &npy_name);
+        local_header.extend_from_slice(npy_name.as_bytes());
+
+        self.entries.push((npy_name, crc, bytes.len(), self.offset));
+        self.write(&local_header)?;
+        self.write(&bytes)
+    }
+
+    /// Write the zip central directory and close the archive.
+    pub fn finish(mut self) -> Result<(), NpyError> {
+        let central_directory_offset = self.offset;
+        let entries = std::mem::take(&mut self.entries);
+
+        for (name, crc, size, offset) in entries.iter() {
+            let mut header: Vec<u8> = Vec::with_capacity(46 + name.len());
+            header.extend_from_slice(&0x0201_4b50_u32.to_le_bytes());
+            // Version made by
+            header.extend_from_slice(&20u16.to_le_bytes());
+            write_zip_file_info(&mut header, *crc, *size, name);
+            // File comment length, disk number, internal and external attributes
+            header.extend_from_slice(&[0; 10]);
+            header.extend_from_slice(&(*offset as u32).to_le_bytes());
+            header.extend_from_slice(name.as_bytes());
+            self.write(&header)?;
+        }
+
+        let central_directory_size = self.offset - central_directory_offset;
+        let mut end: Vec<u8> = Vec::with_capacity(22);
+        end.extend_from_slice(&0x0605_4b50_u32.to_le_bytes());
+        // Disk numbers
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        end.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        end.extend_from_slice(&(central_directory_size as u32).to_le_bytes());
+        end.extend_from_slice(&(central_directory_offset as u32).to_le_bytes());
+        // Comment length
+        end.extend_from_slice(&[0; 2]);
+        self.write(&end)?;
+
+        self.writer.flush().map_err(|error| NpyError::Io {
+            filename: self.filename.clone(),
+            error,
+        })
+    }
+}
+
+/// Append the part of a zip file header shared by the local and central directory headers, for an uncompressed
+/// file: version needed, flags, method, time, date, CRC-32, sizes and name and extra field lengths.
+fn write_zip_file_info(header: &mut Vec<u8>, crc: u32, size: usize, name: &str) {
+    header.extend_from_slice(&20u16.to_le_bytes());
+    // Flags and compression method (stored)
+    header.extend_from_slice(&[0; 4]);
+    // Modification time and date (1980-01-01 00:00)
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&0x21u16.to_le_bytes());
+    header.extend_from_slice(&crc.to_le_bytes());
+    header.extend_from_slice(&(size as u32).to_le_bytes());
+    header.extend_from_slice(&(size as u32).to_le_bytes());
+    header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+}
+
+impl CorrelatorContext {
+    /// Get the names of the pols of a selection, and their indices within each visibility returned by the read
+    /// functions. For linear pols these are the selected pols, otherwise all of the output pols.
+    fn get_npy_pols(&self, selection: &Selection) -> (Vec<usize>, Vec<String>) {
+        match self.read_config.pol_products {
+            PolProducts::Linear => (
+                selection.pol_indices.clone(),
+                selection
+                    .pol_indices
+                    .iter()
+                    .map(|&p| POL_NAMES[p].to_string())
+                    .collect(),
+            ),
+            PolProducts::Stokes => (
+                vec![0, 1, 2, 3],
+                [StokesPol::I, StokesPol::Q, StokesPol::U, StokesPol::V]
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect(),
+            ),
+            PolProducts::PseudoStokesI => (vec![0], vec![StokesPol::I.to_string()]),
+        }
+    }
+
+    /// Read the selected visibilities into a [timestep][frequency][baseline][pol] array.
+    fn read_npy_visibilities(
+        &self,
+        selection: &Selection,
+    ) -> Result<(NpyArray, NpyArray), NpyError> {
+        for (name, indices) in [
+            ("timesteps", &selection.timestep_indices),
+            ("coarse channels", &selection.coarse_chan_indices),
+            ("fine channels", &selection.fine_chan_indices),
+            ("baselines", &selection.baseline_indices),
+            ("pols", &selection.pol_indices),
+        ] {
+            if indices.is_empty() {
+                return Err(NpyError::EmptySelection(name.to_string()));
+            }
+        }
+
+        let (pols, _) = self.get_npy_pols(selection);
+        let num_output_pols = self.num_output_pols();
+        let num_fine_chans = selection.fine_chan_indices.len();
+        let num_freqs = selection.coarse_chan_indices.len() * num_fine_chans;
+        let num_baselines = selection.baseline_indices.len();
+        let shape = [
+            selection.timestep_indices.len(),
+            num_freqs,
+            num_baselines,
+            pols.len(),
+        ];
+
+        let mut visibilities: Vec<Complex<f32>> =
+            vec![Complex::new(0., 0.); shape.iter().product()];
+        let mut data_present: Vec<bool> =
+            vec![false; selection.timestep_indices.len() * selection.coarse_chan_indices.len()];
+
+        for (t, &timestep_index) in selection.timestep_indices.iter().enumerate() {
+            for (c, &coarse_chan_index) in selection.coarse_chan_indices.iter().enumerate() {
+                // Leave the zeros, whatever `read_config.missing_data_policy` says
+                if !self.has_data(timestep_index, coarse_chan_index) {
+                    continue;
+                }
+                let data = self.read_by_baseline_subset(
+                    timestep_index,
+                    coarse_chan_index,
+                    &selection.baseline_indices,
+                    &selection.fine_chan_indices,
+                )?;
+                data_present[t * selection.coarse_chan_indices.len() + c] = true;
+
+                for b in 0..num_baselines {
+                    for f in 0..num_fine_chans {
+                        let input = (b * num_fine_chans + f) * num_output_pols * 2;
+                        let output = ((t * num_freqs + c * num_fine_chans + f) * num_baselines + b)
+                            * pols.len();
+
+                        for (p, &pol) in pols.iter().enumerate() {
+                            visibilities[output + p] =
+                                Complex::new(data[input + pol * 2], data[input + pol * 2 + 1]);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok((
+            NpyArray::new(&shape, &visibilities),
+            NpyArray::new(
+                &[
+                    selection.timestep_indices.len(),
+                    selection.coarse_chan_indices.len(),
+                ],
+                &data_present,
+            ),
+        ))
+    }
+
+    /// Write the selected visibilities to a NumPy `.npy` file, as a complex64 array with shape
+    /// [timestep][frequency][baseline][pol]. See the `npy` module documentation for details.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - path of the `.npy` file to write. Any existing file is replaced.
+    ///
+    /// * `selection` - the timesteps, coarse channels, fine channels, baselines and pols to write (see `select`).
+    ///   If `read_config` is for Stokes or pseudo-Stokes I, all of those pols are written.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or an NpyError on failure.
+    ///
+    pub fn write_npy<P: AsRef<Path>>(
+        &self,
+        filename: P,
+        selection: &Selection,
+    ) -> Result<(), NpyError> {
+        let (visibilities, _) = self.read_npy_visibilities(selection)?;
+
+        std::fs::write(filename.as_ref(), visibilities.to_npy_bytes()).map_err(|error| {
+            NpyError::Io {
+                filename: filename.as_ref().to_path_buf(),
+                error,
+            }
+        })
+    }
+
+    /// Write the selected visibilities, along with their frequencies, times, baselines, tile names and pols to a
+    /// NumPy `.npz` file. See the `npy` module documentation for the arrays in the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - path of the `.npz` file to write. Any existing file is replaced.
+    ///
+    /// * `selection` - the timesteps, coarse channels, fine channels, baselines and pols to write (see `select`).
+    ///   If `read_config` is for Stokes or pseudo-Stokes I, all of those pols are written.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or an NpyError on failure.
+    ///
+    pub fn write_npz<P: AsRef<Path>>(
+        &self,
+        filename: P,
+        selection: &Selection,
+    ) -> Result<(), NpyError> {
+        let metafits_context = &self.metafits_context;
+        let (visibilities, data_present) = self.read_npy_visibilities(selection)?;
+        let (_, pol_names) = self.get_npy_pols(selection);
+
+        let num_fine_chans_per_coarse = metafits_context.num_corr_fine_chans_per_coarse;
+        let all_freqs_hz = self.get_fine_chan_freqs_hz_array(&selection.coarse_chan_indices);
+        let frequencies_hz: Vec<f64> = (0..selection.coarse_chan_indices.len())
+            .flat_map(|c| {
+                selection
+                    .fine_chan_indices
+                    .iter()
+                    .map(move |&f| c * num_fine_chans_per_coarse + f)
+            })
+            .map(|i| all_freqs_hz[i])
+            .collect();
+
+        let timesteps: Vec<&TimeStep> = selection
+            .timestep_indices
+            .iter()
+            .map(|&t| &self.timesteps[t])
+            .collect();
+        let unix_times_ms: Vec<u64> = timesteps.iter().map(|t| t.unix_time_ms).collect();
+        let gps_times_ms: Vec<u64> = timesteps.iter().map(|t| t.gps_time_ms).collect();
+
+        let baselines: Vec<u64> = selection
+            .baseline_indices
+            .iter()
+            .flat_map(|&b| {
+                let baseline = &metafits_context.baselines[b];
+                [baseline.ant1_index as u64, baseline.ant2_index as u64]
+            })
+            .collect();
+        let tile_names: Vec<&str> = metafits_context
+            .antennas
+            .iter()
+            .map(|a| a.tile_name.as_str())
+            .collect();
+
+        let mut npz = NpzWriter::create(filename.as_ref())?;
+        npz.add("visibilities", &visibilities)?;
+        npz.add("data_present", &data_present)?;
+        npz.add(
+            "frequencies_hz",
+            &NpyArray::new(&[frequencies_hz.len()], &frequencies_hz),
+        )?;
+        npz.add(
+            "unix_times_ms",
+            &NpyArray::new(&[unix_times_ms.len()], &unix_times_ms),
+        )?;
+        npz.add(
+            "gps_times_ms",
+            &NpyArray::new(&[gps_times_ms.len()], &gps_times_ms),
+        )?;
+        npz.add(
+            "baselines",
+            &NpyArray::new(&[selection.baseline_indices.len(), 2], &baselines),
+        )?;
+        npz.add("tile_names", &NpyArray::from_strings(&tile_names))?;
+        npz.add("pols", &NpyArray::from_strings(&pol_names))?;
+        npz.finish()
+    }
+}
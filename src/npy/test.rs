@@ -0,0 +1,203 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Unit tests for writing NumPy files
+
+use super::*;
+
+fn get_mwax_context() -> CorrelatorContext {
+    CorrelatorContext::new(
+        "test_files/1244973688_1_timestep/1244973688.metafits",
+        &["test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits"],
+    )
+    .unwrap()
+}
+
+fn get_mwax_selection(context: &CorrelatorContext) -> Selection {
+    Selection {
+        timestep_indices: vec![0, 1],
+        coarse_chan_indices: vec![10],
+        fine_chan_indices: vec![2, 3, 4],
+        antenna_indices: (0..context.metafits_context.num_ants).collect(),
+        baseline_indices: vec![0, 1, 5],
+        pol_indices: vec![0, 3],
+    }
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_npy_header() {
+    let array = NpyArray::new(&[3], &[1.0_f64, 2.0, 3.0]);
+    let header = array.header();
+    assert_eq!(header.len(), 128);
+    assert_eq!(&header[0..8], NPY_MAGIC);
+    assert_eq!(
+        u16::from_le_bytes([header[8], header[9]]) as usize,
+        128 - 10
+    );
+    let dict = std::str::from_utf8(&header[10..]).unwrap();
+    assert!(dict.starts_with("{'descr': '<f8', 'fortran_order': False, 'shape': (3,), }"));
+    assert!(dict.ends_with(" \n"));
+
+    let array = NpyArray::new(&[2, 2], &[Complex::new(1.0_f32, -1.0); 4]);
+    let header = std::str::from_utf8(&array.header()[10..])
+        .unwrap()
+        .to_string();
+    assert!(header.starts_with("{'descr': '<c8', 'fortran_order': False, 'shape': (2, 2), }"));
+    assert_eq!(array.data.len(), 32);
+    assert_eq!(
+        array.data[0..8],
+        [1.0_f32.to_le_bytes(), (-1.0_f32).to_le_bytes()].concat()
+    );
+}
+
+#[test]
+fn test_npy_from_strings() {
+    let array = NpyArray::from_strings(&["Tile011", "Tile1"]);
+    assert_eq!(array.descr, "<U7");
+    assert_eq!(array.shape, vec![2]);
+    assert_eq!(array.data.len(), 2 * 7 * 4);
+    assert_eq!(array.data[0..4], [b'T', 0, 0, 0]);
+    // Padded with nuls
+    assert_eq!(array.data[7 * 4 + 5 * 4..], [0; 8]);
+}
+
+#[test]
+#[should_panic]
+fn test_npy_array_wrong_shape() {
+    NpyArray::new(&[2, 2], &[1.0_f32; 3]);
+}
+
+#[test]
+fn test_npz_writer() {
+    let temp_dir = tempdir::TempDir::new("npy_test").unwrap();
+    let filename = temp_dir.path().join("test.npz");
+    let a = NpyArray::new(&[2], &[1_u64, 2]);
+    let b = NpyArray::new(&[1], &[true]);
+
+    let mut npz = NpzWriter::create(&filename).unwrap();
+    npz.add("a", &a).unwrap();
+    npz.add("b", &b).unwrap();
+    npz.finish().unwrap();
+
+    let bytes = std::fs::read(&filename).unwrap();
+    let a_bytes = a.to_npy_bytes();
+    let b_bytes = b.to_npy_bytes();
+    let b_offset = 30 + 5 + a_bytes.len();
+    let central_directory_offset = b_offset + 30 + 5 + b_bytes.len();
+    assert_eq!(bytes.len(), central_directory_offset + 2 * (46 + 5) + 22);
+
+    // Local headers and data
+    assert_eq!(bytes[0..4], [b'P', b'K', 3, 4]);
+    assert_eq!(bytes[14..18], crc32(&a_bytes).to_le_bytes());
+    assert_eq!(&bytes[30..35], b"a.npy");
+    assert_eq!(bytes[35..b_offset], a_bytes[..]);
+    assert_eq!(bytes[b_offset..b_offset + 4], [b'P', b'K', 3, 4]);
+
+    // Central directory
+    assert_eq!(
+        bytes[central_directory_offset..central_directory_offset + 4],
+        [b'P', b'K', 1, 2]
+    );
+    let second_entry = central_directory_offset + 46 + 5;
+    assert_eq!(
+        bytes[second_entry + 42..second_entry + 46],
+        (b_offset as u32).to_le_bytes()
+    );
+
+    // End of central directory
+    let end = &bytes[bytes.len() - 22..];
+    assert_eq!(end[0..4], [b'P', b'K', 5, 6]);
+    assert_eq!(end[10..12], 2u16.to_le_bytes());
+    assert_eq!(end[16..20], (central_directory_offset as u32).to_le_bytes());
+}
+
+#[test]
+fn test_write_npy_mwax() {
+    let context = get_mwax_context();
+    let selection = get_mwax_selection(&context);
+    let temp_dir = tempdir::TempDir::new("npy_test").unwrap();
+    let filename = temp_dir.path().join("test.npy");
+
+    context.write_npy(&filename, &selection).unwrap();
+
+    let bytes = std::fs::read(&filename).unwrap();
+    let header = std::str::from_utf8(&bytes[10..128]).unwrap();
+    assert!(header.contains("'shape': (2, 3, 3, 2)"), "{}", header);
+    let values: Vec<f32> = bytes[128..]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    assert_eq!(values.len(), 2 * 3 * 3 * 2 * 2);
+
+    // Timestep 0 is [freq][baseline][pol]
+    let data = context
+        .read_by_baseline_subset(
+            0,
+            10,
+            &selection.baseline_indices,
+            &selection.fine_chan_indices,
+        )
+        .unwrap();
+    for f in 0..3 {
+        for b in 0..3 {
+            let input = (b * 3 + f) * 8;
+            let output = (f * 3 + b) * 4;
+            // XX
+            assert_eq!(values[output..output + 2], data[input..input + 2]);
+            // YY
+            assert_eq!(values[output + 2..output + 4], data[input + 6..input + 8]);
+        }
+    }
+
+    // Timestep 1 has no data, so is zeros
+    assert!(values[36..].iter().all(|&v| v == 0.));
+}
+
+#[test]
+fn test_write_npz_mwax() {
+    let context = get_mwax_context();
+    let selection = get_mwax_selection(&context);
+    let temp_dir = tempdir::TempDir::new("npy_test").unwrap();
+    let filename = temp_dir.path().join("test.npz");
+
+    context.write_npz(&filename, &selection).unwrap();
+
+    let bytes = std::fs::read(&filename).unwrap();
+    let end = &bytes[bytes.len() - 22..];
+    assert_eq!(end[0..4], [b'P', b'K', 5, 6]);
+    assert_eq!(end[10..12], 8u16.to_le_bytes());
+
+    let contents = String::from_utf8_lossy(&bytes);
+    for name in [
+        "visibilities.npy",
+        "data_present.npy",
+        "frequencies_hz.npy",
+        "unix_times_ms.npy",
+        "gps_times_ms.npy",
+        "baselines.npy",
+        "tile_names.npy",
+        "pols.npy",
+    ] {
+        assert!(contents.contains(name), "{} is missing", name);
+    }
+}
+
+#[test]
+fn test_write_npy_empty_selection() {
+    let context = get_mwax_context();
+    let mut selection = get_mwax_selection(&context);
+    selection.fine_chan_indices = vec![];
+    let temp_dir = tempdir::TempDir::new("npy_test").unwrap();
+
+    assert!(matches!(
+        context.write_npy(temp_dir.path().join("test.npy"), &selection),
+        Err(NpyError::EmptySelection(_))
+    ));
+}
@@ -0,0 +1,82 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Errors associated with parsing and resolving data selection expressions.
+
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SelectionError {
+    #[error("Expected key=value but found '{clause}' at position {position}")]
+    MissingEquals { clause: String, position: usize },
+
+    #[error("Unknown selection key '{key}' at position {position}. Valid keys are time, chan, fine_chan, tile, baseline and pol")]
+    UnknownKey { key: String, position: usize },
+
+    #[error("Selection key '{key}' at position {position} was already given")]
+    DuplicateKey { key: String, position: usize },
+
+    #[error("Empty value at position {position}")]
+    EmptyValue { position: usize },
+
+    #[error("Invalid number '{token}' at position {position}")]
+    InvalidNumber { token: String, position: usize },
+
+    #[error("Invalid range '{token}' at position {position}. The start must not be after the end")]
+    InvalidRange { token: String, position: usize },
+
+    #[error("'{token}' at position {position} is out of range. It must be between 0 and {max}")]
+    IndexOutOfRange {
+        token: String,
+        position: usize,
+        max: usize,
+    },
+
+    #[error("No coarse channel with receiver channel number '{token}' (position {position}) in this observation")]
+    UnknownChannel { token: String, position: usize },
+
+    #[error("No tile named or with tile id '{token}' (position {position}) in this observation")]
+    UnknownTile { token: String, position: usize },
+
+    #[error(
+        "Invalid baseline '{token}' at position {position}. Expected auto, cross or tile1-tile2"
+    )]
+    InvalidBaseline { token: String, position: usize },
+
+    #[error("Unknown polarisation '{token}' at position {position}. Expected XX, XY, YX or YY")]
+    UnknownPol { token: String, position: usize },
+
+    #[error("Selection '{key}' at position {position} selects nothing")]
+    EmptySelection { key: String, position: usize },
+}
+
+impl SelectionError {
+    /// Returns the position (byte offset within the selection expression) of the token which caused this error.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The byte offset of the offending token.
+    ///
+    pub fn position(&self) -> usize {
+        match self {
+            SelectionError::MissingEquals { position, .. }
+            | SelectionError::UnknownKey { position, .. }
+            | SelectionError::DuplicateKey { position, .. }
+            | SelectionError::EmptyValue { position }
+            | SelectionError::InvalidNumber { position, .. }
+            | SelectionError::InvalidRange { position, .. }
+            | SelectionError::IndexOutOfRange { position, .. }
+            | SelectionError::UnknownChannel { position, .. }
+            | SelectionError::UnknownTile { position, .. }
+            | SelectionError::InvalidBaseline { position, .. }
+            | SelectionError::UnknownPol { position, .. }
+            | SelectionError::EmptySelection { position, .. } => *position,
+        }
+    }
+}
@@ -0,0 +1,490 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Parsing of data selection expressions (similar to CASA selection strings) and resolving them against a
+//! `CorrelatorContext` into index sets the read functions accept.
+//!
+//! An expression is a `;` separated list of `key=value` clauses, e.g.
+//!
+//! ```text
+//! time=10~20; chan=131~140; baseline=!auto; tile=Tile011,Tile012; pol=XX,YY
+//! ```
+//!
+//! Each value is a `,` separated list of items. An item prefixed with `!` is excluded. If a clause only has
+//! excluded items, they are excluded from everything. Keys which are not given select everything.
+//!
+//! | Key                     | Items                                                                          |
+//! |-------------------------|--------------------------------------------------------------------------------|
+//! | `time` / `timestep`     | timestep indices or inclusive ranges of them, e.g. `3` or `10~20`             |
+//! | `chan` / `coarse_chan`  | receiver (sky) channel numbers or inclusive ranges of them, e.g. `131~140`     |
+//! | `fine_chan`             | fine channel indices (within each coarse channel) or inclusive ranges of them |
+//! | `tile` / `antenna`      | tile names or tile ids. Only baselines with both tiles selected are kept       |
+//! | `baseline`              | `auto`, `cross` or a pair of tile names or ids, e.g. `Tile011-Tile012`         |
+//! | `pol`                   | `XX`, `XY`, `YX` or `YY`                                                       |
+//!
+//! Keys and polarisations are case insensitive. All resulting index sets are in ascending order.
+
+use std::collections::BTreeSet;
+
+use crate::correlator_context::CorrelatorContext;
+
+pub mod error;
+use error::SelectionError;
+
+#[cfg(test)]
+mod test;
+
+/// Names of the polarisations, in the order they are in the visibilities.
+pub(crate) const POL_NAMES: [&str; 4] = ["XX", "XY", "YX", "YY"];
+
+/// The things a clause of a selection expression can select.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SelectionKey {
+    Time,
+    Chan,
+    FineChan,
+    Tile,
+    Baseline,
+    Pol,
+}
+
+impl SelectionKey {
+    /// Matches a key (case insensitively) to a `SelectionKey`, or None if it is not a valid key.
+    fn from_key(key: &str) -> Option<Self> {
+        match key.to_ascii_lowercase().as_str() {
+            "time" | "timestep" => Some(SelectionKey::Time),
+            "chan" | "coarse_chan" => Some(SelectionKey::Chan),
+            "fine_chan" => Some(SelectionKey::FineChan),
+            "tile" | "antenna" => Some(SelectionKey::Tile),
+            "baseline" => Some(SelectionKey::Baseline),
+            "pol" => Some(SelectionKey::Pol),
+            _ => None,
+        }
+    }
+}
+
+/// A piece of a selection expression and where it starts within the expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SelectionToken<'a> {
+    pub text: &'a str,
+    pub position: usize,
+}
+
+impl<'a> SelectionToken<'a> {
+    /// Returns the token with leading and trailing whitespace removed.
+    fn trim(self) -> Self {
+        let trimmed_start = self.text.trim_start();
+        Self {
+            text: trimmed_start.trim_end(),
+            position: self.position + (self.text.len() - trimmed_start.len()),
+        }
+    }
+
+    /// Splits the token on `separator`, trimming each piece.
+    fn split(self, separator: char) -> Vec<Self> {
+        let mut position = self.position;
+
+        self.text
+            .split(separator)
+            .map(|text| {
+                let token = Self { text, position }.trim();
+                position += text.len() + separator.len_utf8();
+                token
+            })
+            .collect()
+    }
+
+    /// Splits the token into two trimmed pieces at the first `separator`, if there is one.
+    fn split_once(self, separator: char) -> Option<(Self, Self)> {
+        self.text.find(separator).map(|index| {
+            (
+                Self {
+                    text: &self.text[..index],
+                    position: self.position,
+                }
+                .trim(),
+                Self {
+                    text: &self.text[index + separator.len_utf8()..],
+                    position: self.position + index + separator.len_utf8(),
+                }
+                .trim(),
+            )
+        })
+    }
+}
+
+/// An item of a clause's value, which may be negated (excluded).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SelectionItem<'a> {
+    pub negated: bool,
+    pub token: SelectionToken<'a>,
+}
+
+/// A single `key=value` clause of a selection expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SelectionClause<'a> {
+    pub key: SelectionKey,
+    pub key_token: SelectionToken<'a>,
+    pub items: Vec<SelectionItem<'a>>,
+}
+
+/// Splits a selection expression into clauses, without resolving them against an observation.
+///
+/// # Arguments
+///
+/// * `expression` - the selection expression.
+///
+///
+/// # Returns
+///
+/// * A Result containing the clauses of the expression, in the order given, if Ok.
+///
+pub(crate) fn parse_selection(
+    expression: &str,
+) -> Result<Vec<SelectionClause<'_>>, SelectionError> {
+    let mut clauses: Vec<SelectionClause> = Vec::new();
+
+    let expression_token = SelectionToken {
+        text: expression,
+        position: 0,
+    };
+
+    for clause_token in expression_token.split(';') {
+        if clause_token.text.is_empty() {
+            continue;
+        }
+
+        let (key_token, value_token) =
+            clause_token
+                .split_once('=')
+                .ok_or_else(|| SelectionError::MissingEquals {
+                    clause: clause_token.text.to_string(),
+                    position: clause_token.position,
+                })?;
+
+        let key =
+            SelectionKey::from_key(key_token.text).ok_or_else(|| SelectionError::UnknownKey {
+                key: key_token.text.to_string(),
+                position: key_token.position,
+            })?;
+
+        if clauses.iter().any(|c| c.key == key) {
+            return Err(SelectionError::DuplicateKey {
+                key: key_token.text.to_string(),
+                position: key_token.position,
+            });
+        }
+
+        let items = value_token
+            .split(',')
+            .into_iter()
+            .map(|item_token| {
+                let item = match item_token.text.strip_prefix('!') {
+                    Some(text) => SelectionItem {
+                        negated: true,
+                        token: SelectionToken {
+                            text,
+                            position: item_token.position + 1,
+                        }
+                        .trim(),
+                    },
+                    None => SelectionItem {
+                        negated: false,
+                        token: item_token,
+                    },
+                };
+
+                match item.token.text.is_empty() {
+                    true => Err(SelectionError::EmptyValue {
+                        position: item.token.position,
+                    }),
+                    false => Ok(item),
+                }
+            })
+            .collect::<Result<Vec<SelectionItem>, SelectionError>>()?;
+
+        clauses.push(SelectionClause {
+            key,
+            key_token,
+            items,
+        });
+    }
+
+    Ok(clauses)
+}
+
+/// Parses a number or an inclusive `start~end` range of numbers.
+///
+/// # Arguments
+///
+/// * `token` - the token to parse.
+///
+///
+/// # Returns
+///
+/// * A Result containing the (start, end) of the range, if Ok. A single number is returned as (n, n).
+///
+pub(crate) fn parse_range(token: &SelectionToken) -> Result<(usize, usize), SelectionError> {
+    let parse = |t: &SelectionToken| -> Result<usize, SelectionError> {
+        t.text
+            .parse::<usize>()
+            .map_err(|_| SelectionError::InvalidNumber {
+                token: t.text.to_string(),
+                position: t.position,
+            })
+    };
+
+    let (start, end) = match token.split_once('~') {
+        Some((start_token, end_token)) => (parse(&start_token)?, parse(&end_token)?),
+        None => {
+            let n = parse(token)?;
+            (n, n)
+        }
+    };
+
+    if start > end {
+        return Err(SelectionError::InvalidRange {
+            token: token.text.to_string(),
+            position: token.position,
+        });
+    }
+
+    Ok((start, end))
+}
+
+/// Parses an index or range of indices, checking they are less than `count`.
+fn resolve_index_range(token: &SelectionToken, count: usize) -> Result<Vec<usize>, SelectionError> {
+    let (start, end) = parse_range(token)?;
+
+    if end >= count {
+        return Err(SelectionError::IndexOutOfRange {
+            token: token.text.to_string(),
+            position: token.position,
+            max: count.saturating_sub(1),
+        });
+    }
+
+    Ok((start..=end).collect())
+}
+
+/// Resolves a clause against every possible index, including all of the non-negated items (or everything if there
+/// are none) then removing all of the negated items.
+fn resolve_clause<F>(
+    clause: Option<&SelectionClause>,
+    count: usize,
+    mut resolve_item: F,
+) -> Result<Vec<usize>, SelectionError>
+where
+    F: FnMut(&SelectionToken) -> Result<Vec<usize>, SelectionError>,
+{
+    let clause = match clause {
+        Some(c) => c,
+        None => return Ok((0..count).collect()),
+    };
+
+    let mut included: BTreeSet<usize> = BTreeSet::new();
+    let mut excluded: BTreeSet<usize> = BTreeSet::new();
+    let mut any_included = false;
+
+    for item in &clause.items {
+        let indices = resolve_item(&item.token)?;
+        if item.negated {
+            excluded.extend(indices);
+        } else {
+            any_included = true;
+            included.extend(indices);
+        }
+    }
+
+    if !any_included {
+        included.extend(0..count);
+    }
+
+    let indices: Vec<usize> = included.difference(&excluded).copied().collect();
+
+    match indices.is_empty() {
+        true => Err(SelectionError::EmptySelection {
+            key: clause.key_token.text.to_string(),
+            position: clause.key_token.position,
+        }),
+        false => Ok(indices),
+    }
+}
+
+/// Index sets resolved from a selection expression, which can be passed to the read functions of the
+/// `CorrelatorContext` the selection was made from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    /// Indices within the CorrelatorContext timestep array.
+    pub timestep_indices: Vec<usize>,
+    /// Indices within the CorrelatorContext coarse_chan array.
+    pub coarse_chan_indices: Vec<usize>,
+    /// Indices of fine channels within each coarse channel.
+    pub fine_chan_indices: Vec<usize>,
+    /// Indices within the metafits antenna array.
+    pub antenna_indices: Vec<usize>,
+    /// Indices within the metafits baseline array.
+    pub baseline_indices: Vec<usize>,
+    /// Indices of the polarisations within each visibility (0 = XX, 1 = XY, 2 = YX, 3 = YY).
+    pub pol_indices: Vec<usize>,
+}
+
+impl Selection {
+    /// Parses a selection expression and resolves it against a `CorrelatorContext`.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - the `CorrelatorContext` to resolve the selection against.
+    ///
+    /// * `expression` - the selection expression (see the module documentation for the syntax).
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the resolved `Selection` if Ok, or a `SelectionError` pointing at the offending token.
+    ///
+    pub(crate) fn new(
+        context: &CorrelatorContext,
+        expression: &str,
+    ) -> Result<Self, SelectionError> {
+        let clauses = parse_selection(expression)?;
+        let get_clause = |key: SelectionKey| clauses.iter().find(|c| c.key == key);
+
+        let metafits_context = &context.metafits_context;
+
+        let timestep_indices = resolve_clause(
+            get_clause(SelectionKey::Time),
+            context.num_timesteps,
+            |token| resolve_index_range(token, context.num_timesteps),
+        )?;
+
+        let coarse_chan_indices = resolve_clause(
+            get_clause(SelectionKey::Chan),
+            context.num_coarse_chans,
+            |token| {
+                let (start, end) = parse_range(token)?;
+                let indices: Vec<usize> = context
+                    .coarse_chans
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| c.rec_chan_number >= start && c.rec_chan_number <= end)
+                    .map(|(i, _)| i)
+                    .collect();
+
+                match indices.is_empty() {
+                    true => Err(SelectionError::UnknownChannel {
+                        token: token.text.to_string(),
+                        position: token.position,
+                    }),
+                    false => Ok(indices),
+                }
+            },
+        )?;
+
+        let fine_chan_indices = resolve_clause(
+            get_clause(SelectionKey::FineChan),
+            metafits_context.num_corr_fine_chans_per_coarse,
+            |token| resolve_index_range(token, metafits_context.num_corr_fine_chans_per_coarse),
+        )?;
+
+        let find_antenna = |token: &SelectionToken| -> Result<usize, SelectionError> {
+            metafits_context
+                .antennas
+                .iter()
+                .position(|a| {
+                    a.tile_name.eq_ignore_ascii_case(token.text)
+                        || token.text.parse::<u32>() == Ok(a.tile_id)
+                })
+                .ok_or_else(|| SelectionError::UnknownTile {
+                    token: token.text.to_string(),
+                    position: token.position,
+                })
+        };
+
+        let tile_clause = get_clause(SelectionKey::Tile);
+        let antenna_indices = resolve_clause(tile_clause, metafits_context.num_ants, |token| {
+            Ok(vec![find_antenna(token)?])
+        })?;
+
+        let baseline_clause = get_clause(SelectionKey::Baseline);
+        let baseline_indices = resolve_clause(
+            baseline_clause,
+            metafits_context.num_baselines,
+            |token| match token.text.to_ascii_lowercase().as_str() {
+                "auto" | "cross" => {
+                    let auto = token.text.eq_ignore_ascii_case("auto");
+                    Ok(metafits_context
+                        .baselines
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, b)| (b.ant1_index == b.ant2_index) == auto)
+                        .map(|(i, _)| i)
+                        .collect())
+                }
+                _ => {
+                    let invalid_baseline = || SelectionError::InvalidBaseline {
+                        token: token.text.to_string(),
+                        position: token.position,
+                    };
+
+                    let (ant1_token, ant2_token) =
+                        token.split_once('-').ok_or_else(invalid_baseline)?;
+                    let ant1_index = find_antenna(&ant1_token)?;
+                    let ant2_index = find_antenna(&ant2_token)?;
+
+                    metafits_context
+                        .baselines
+                        .iter()
+                        .position(|b| {
+                            b.ant1_index == ant1_index.min(ant2_index)
+                                && b.ant2_index == ant1_index.max(ant2_index)
+                        })
+                        .map(|i| vec![i])
+                        .ok_or_else(invalid_baseline)
+                }
+            },
+        )?
+        .into_iter()
+        .filter(|&i| {
+            let baseline = &metafits_context.baselines[i];
+            antenna_indices.contains(&baseline.ant1_index)
+                && antenna_indices.contains(&baseline.ant2_index)
+        })
+        .collect::<Vec<usize>>();
+
+        // The tile selection can be incompatible with the baseline selection
+        if let (true, Some(clause)) = (baseline_indices.is_empty(), baseline_clause.or(tile_clause))
+        {
+            return Err(SelectionError::EmptySelection {
+                key: clause.key_token.text.to_string(),
+                position: clause.key_token.position,
+            });
+        }
+
+        let pol_indices = resolve_clause(
+            get_clause(SelectionKey::Pol),
+            metafits_context.num_visibility_pols,
+            |token| {
+                POL_NAMES
+                    .iter()
+                    .take(metafits_context.num_visibility_pols)
+                    .position(|p| p.eq_ignore_ascii_case(token.text))
+                    .map(|i| vec![i])
+                    .ok_or_else(|| SelectionError::UnknownPol {
+                        token: token.text.to_string(),
+                        position: token.position,
+                    })
+            },
+        )?;
+
+        Ok(Self {
+            timestep_indices,
+            coarse_chan_indices,
+            fine_chan_indices,
+            antenna_indices,
+            baseline_indices,
+            pol_indices,
+        })
+    }
+}
@@ -0,0 +1,118 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Unit tests for data selection expressions
+#[cfg(test)]
+use super::*;
+
+#[test]
+fn test_parse_selection() {
+    let expression = "time=10~20; chan=131~140; baseline=!auto; tile=Tile011, Tile012; pol=XX,YY";
+    let clauses = parse_selection(expression).expect("Error!");
+
+    assert_eq!(clauses.len(), 5);
+    assert_eq!(clauses[0].key, SelectionKey::Time);
+    assert_eq!(clauses[0].key_token.position, 0);
+    assert_eq!(clauses[0].items.len(), 1);
+    assert_eq!(clauses[0].items[0].token.text, "10~20");
+    assert_eq!(clauses[0].items[0].token.position, 5);
+
+    assert_eq!(clauses[2].key, SelectionKey::Baseline);
+    assert!(clauses[2].items[0].negated);
+    assert_eq!(clauses[2].items[0].token.text, "auto");
+    assert_eq!(
+        &expression[clauses[2].items[0].token.position..][..4],
+        "auto"
+    );
+
+    // Whitespace around items is ignored, but positions still point at the item
+    assert_eq!(clauses[3].items[1].token.text, "Tile012");
+    assert_eq!(
+        &expression[clauses[3].items[1].token.position..][..7],
+        "Tile012"
+    );
+    assert_eq!(clauses[4].key, SelectionKey::Pol);
+}
+
+#[test]
+fn test_parse_selection_keys() {
+    assert!(parse_selection("").expect("Error!").is_empty());
+    assert!(parse_selection(" ; ;").expect("Error!").is_empty());
+
+    let clauses =
+        parse_selection("TIMESTEP=1;coarse_chan=100;fine_chan=0~3;antenna=1").expect("Error!");
+    assert_eq!(
+        clauses.iter().map(|c| c.key).collect::<Vec<SelectionKey>>(),
+        vec![
+            SelectionKey::Time,
+            SelectionKey::Chan,
+            SelectionKey::FineChan,
+            SelectionKey::Tile
+        ]
+    );
+}
+
+#[test]
+fn test_parse_selection_errors() {
+    assert_eq!(
+        parse_selection("time=1; chan").unwrap_err(),
+        SelectionError::MissingEquals {
+            clause: String::from("chan"),
+            position: 8
+        }
+    );
+    assert_eq!(
+        parse_selection("time=1;  freq=2").unwrap_err(),
+        SelectionError::UnknownKey {
+            key: String::from("freq"),
+            position: 9
+        }
+    );
+    assert_eq!(
+        parse_selection("time=1;time=2").unwrap_err(),
+        SelectionError::DuplicateKey {
+            key: String::from("time"),
+            position: 7
+        }
+    );
+    assert_eq!(
+        parse_selection("pol=XX,,YY").unwrap_err(),
+        SelectionError::EmptyValue { position: 7 }
+    );
+    assert_eq!(
+        parse_selection("baseline=auto, !").unwrap_err(),
+        SelectionError::EmptyValue { position: 16 }
+    );
+    assert_eq!(parse_selection("time=").unwrap_err().position(), 5);
+}
+
+#[test]
+fn test_parse_range() {
+    let token = |text| SelectionToken { text, position: 4 };
+
+    assert_eq!(parse_range(&token("7")), Ok((7, 7)));
+    assert_eq!(parse_range(&token("10~20")), Ok((10, 20)));
+    assert_eq!(parse_range(&token("10 ~ 10")), Ok((10, 10)));
+    assert_eq!(
+        parse_range(&token("20~10")),
+        Err(SelectionError::InvalidRange {
+            token: String::from("20~10"),
+            position: 4
+        })
+    );
+    assert_eq!(
+        parse_range(&token("10~x")),
+        Err(SelectionError::InvalidNumber {
+            token: String::from("x"),
+            position: 7
+        })
+    );
+    assert_eq!(
+        parse_range(&token("-1")),
+        Err(SelectionError::InvalidNumber {
+            token: String::from("-1"),
+            position: 4
+        })
+    );
+}
@@ -0,0 +1,204 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Conversion of linear polarisation visibilities (XX, XY, YX, YY) to Stokes parameters.
+//!
+//! The MWA has linearly polarised feeds, and no correction is made for the parallactic angle or the
+//! primary beam, so these are "instrumental" Stokes parameters:
+//!
+//! ```text
+//! I = (XX + YY) / 2
+//! Q = (XX - YY) / 2
+//! U = (XY + YX) / 2
+//! V = -i (XY - YX) / 2
+//! ```
+//!
+//! Pseudo-Stokes I is just the first of these.
+
+use std::fmt;
+
+#[cfg(test)]
+mod test;
+
+/// Stokes polarisation.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StokesPol {
+    I = 1,
+    Q = 2,
+    U = 3,
+    V = 4,
+}
+
+/// Implements fmt::Display for StokesPol enum
+///
+/// # Arguments
+///
+/// * `f` - A fmt::Formatter
+///
+///
+/// # Returns
+///
+/// * `fmt::Result` - Result of this method
+///
+///
+impl fmt::Display for StokesPol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                StokesPol::I => "I",
+                StokesPol::Q => "Q",
+                StokesPol::U => "U",
+                StokesPol::V => "V",
+            }
+        )
+    }
+}
+
+/// The polarisation products visibilities are returned as.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolProducts {
+    /// XX, XY, YX, YY, exactly as produced by the correlator (see `VisPol`)
+    Linear = 0,
+    /// Stokes I, Q, U, V (see `StokesPol`)
+    Stokes = 1,
+    /// Pseudo-Stokes I, (XX + YY) / 2, only
+    PseudoStokesI = 2,
+}
+
+impl PolProducts {
+    /// Returns the number of pols in each visibility for these polarisation products.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The number of pols: 4 for `Linear` and `Stokes`, 1 for `PseudoStokesI`.
+    ///
+    pub fn num_pols(&self) -> usize {
+        match self {
+            PolProducts::Linear | PolProducts::Stokes => 4,
+            PolProducts::PseudoStokesI => 1,
+        }
+    }
+}
+
+impl Default for PolProducts {
+    fn default() -> Self {
+        PolProducts::Linear
+    }
+}
+
+/// Implements fmt::Display for PolProducts enum
+///
+/// # Arguments
+///
+/// * `f` - A fmt::Formatter
+///
+///
+/// # Returns
+///
+/// * `fmt::Result` - Result of this method
+///
+///
+impl fmt::Display for PolProducts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PolProducts::Linear => "Linear",
+                PolProducts::Stokes => "Stokes",
+                PolProducts::PseudoStokesI => "Pseudo-Stokes I",
+            }
+        )
+    }
+}
+
+/// Convert a buffer of visibilities from linear pols to Stokes I, Q, U and V.
+///
+/// # Arguments
+///
+/// * `input` - visibilities in [...][XX,XY,YX,YY][r][i] order (any ordering of baselines and frequencies).
+///
+/// * `output` - buffer to fill with visibilities in [...][I,Q,U,V][r][i] order. Must be the same length as `input`.
+///
+///
+/// # Returns
+///
+/// * Nothing
+///
+pub fn convert_linear_to_stokes(input: &[f32], output: &mut [f32]) {
+    assert_eq!(input.len(), output.len());
+
+    for (vis, stokes) in input.chunks_exact(8).zip(output.chunks_exact_mut(8)) {
+        let (xx_r, xx_i, xy_r, xy_i, yx_r, yx_i, yy_r, yy_i) = (
+            vis[0], vis[1], vis[2], vis[3], vis[4], vis[5], vis[6], vis[7],
+        );
+
+        stokes[0] = (xx_r + yy_r) / 2.;
+        stokes[1] = (xx_i + yy_i) / 2.;
+        stokes[2] = (xx_r - yy_r) / 2.;
+        stokes[3] = (xx_i - yy_i) / 2.;
+        stokes[4] = (xy_r + yx_r) / 2.;
+        stokes[5] = (xy_i + yx_i) / 2.;
+        stokes[6] = (xy_i - yx_i) / 2.;
+        stokes[7] = (yx_r - xy_r) / 2.;
+    }
+}
+
+/// Convert a buffer of visibilities from linear pols to pseudo-Stokes I, (XX + YY) / 2.
+///
+/// # Arguments
+///
+/// * `input` - visibilities in [...][XX,XY,YX,YY][r][i] order (any ordering of baselines and frequencies).
+///
+/// * `output` - buffer to fill with visibilities in [...][r][i] order. Must be a quarter of the length of `input`.
+///
+///
+/// # Returns
+///
+/// * Nothing
+///
+pub fn convert_linear_to_pseudo_stokes_i(input: &[f32], output: &mut [f32]) {
+    assert_eq!(input.len(), output.len() * 4);
+
+    for (vis, stokes_i) in input.chunks_exact(8).zip(output.chunks_exact_mut(2)) {
+        stokes_i[0] = (vis[0] + vis[6]) / 2.;
+        stokes_i[1] = (vis[1] + vis[7]) / 2.;
+    }
+}
+
+/// Convert a buffer of visibilities from linear pols to any `PolProducts`.
+///
+/// # Arguments
+///
+/// * `input` - visibilities in [...][XX,XY,YX,YY][r][i] order (any ordering of baselines and frequencies).
+///
+/// * `output` - buffer to fill with visibilities in [...][pol][r][i] order. Must be `input.len() / 4 * pol_products.num_pols()` long.
+///
+/// * `pol_products` - the polarisation products to convert to.
+///
+///
+/// # Returns
+///
+/// * Nothing
+///
+pub fn convert_linear_to_pol_products(
+    input: &[f32],
+    output: &mut [f32],
+    pol_products: PolProducts,
+) {
+    match pol_products {
+        PolProducts::Linear => output.copy_from_slice(input),
+        PolProducts::Stokes => convert_linear_to_stokes(input, output),
+        PolProducts::PseudoStokesI => convert_linear_to_pseudo_stokes_i(input, output),
+    }
+}
@@ -0,0 +1,77 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Unit tests for Stokes conversion
+#[cfg(test)]
+use super::*;
+
+/// Two visibilities of XX, XY, YX, YY
+fn get_linear_vis() -> Vec<f32> {
+    vec![
+        4., 2., 1., -1., 3., 5., 2., 6., // vis 0
+        1., 0., 0., 0., 0., 0., -1., 0., // vis 1
+    ]
+}
+
+#[test]
+fn test_convert_linear_to_stokes() {
+    let mut output: Vec<f32> = vec![0.; 16];
+    convert_linear_to_stokes(&get_linear_vis(), &mut output);
+
+    assert_eq!(
+        output,
+        vec![
+            3., 4., 1., -2., 2., 2., -3., 1., // vis 0
+            0., 0., 1., 0., 0., 0., 0., 0., // vis 1
+        ]
+    );
+}
+
+#[test]
+fn test_convert_linear_to_stokes_v_is_circular() {
+    // XY = i, YX = -i is a purely circularly polarised signal
+    let mut output: Vec<f32> = vec![0.; 8];
+    convert_linear_to_stokes(&[1., 0., 0., 1., 0., -1., 1., 0.], &mut output);
+
+    assert_eq!(output, vec![1., 0., 0., 0., 0., 0., 1., 0.]);
+}
+
+#[test]
+fn test_convert_linear_to_pseudo_stokes_i() {
+    let mut output: Vec<f32> = vec![0.; 4];
+    convert_linear_to_pseudo_stokes_i(&get_linear_vis(), &mut output);
+
+    assert_eq!(output, vec![3., 4., 0., 0.]);
+}
+
+#[test]
+#[should_panic]
+fn test_convert_linear_to_pseudo_stokes_i_wrong_size() {
+    let mut output: Vec<f32> = vec![0.; 16];
+    convert_linear_to_pseudo_stokes_i(&get_linear_vis(), &mut output);
+}
+
+#[test]
+fn test_convert_linear_to_pol_products() {
+    for pol_products in [
+        PolProducts::Linear,
+        PolProducts::Stokes,
+        PolProducts::PseudoStokesI,
+    ] {
+        let mut output: Vec<f32> = vec![0.; 2 * 2 * pol_products.num_pols()];
+        convert_linear_to_pol_products(&get_linear_vis(), &mut output, pol_products);
+
+        match pol_products {
+            PolProducts::Linear => assert_eq!(output, get_linear_vis()),
+            _ => assert_eq!(output[0..2], [3., 4.]),
+        }
+    }
+}
+
+#[test]
+fn test_pol_products_display() {
+    assert_eq!(format!("{}", PolProducts::PseudoStokesI), "Pseudo-Stokes I");
+    assert_eq!(format!("{}", StokesPol::V), "V");
+    assert_eq!(PolProducts::default(), PolProducts::Linear);
+}
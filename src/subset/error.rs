@@ -0,0 +1,56 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Errors associated with writing a subset of an observation.
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SubsetError {
+    #[error("Invalid timestep index {index}. It must be less than {num_timesteps}")]
+    InvalidTimestepIndex { index: usize, num_timesteps: usize },
+
+    #[error("Invalid coarse channel index {index}. It must be less than {num_coarse_chans}")]
+    InvalidCoarseChanIndex {
+        index: usize,
+        num_coarse_chans: usize,
+    },
+
+    #[error("Invalid antenna index {index}. It must be less than {num_ants}")]
+    InvalidAntennaIndex { index: usize, num_ants: usize },
+
+    #[error("The subset selects {0}")]
+    EmptySubset(&'static str),
+
+    #[error("None of the selected timesteps and coarse channels have any data")]
+    NoData,
+
+    #[error("{filename} is {actual} bytes, but {expected} bytes were expected")]
+    UnexpectedFileSize {
+        filename: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("Couldn't create {dir}: {error}")]
+    CreateDir { dir: PathBuf, error: std::io::Error },
+
+    #[error("{filename}: {error}")]
+    Io {
+        filename: PathBuf,
+        error: std::io::Error,
+    },
+
+    #[error("{filename}: {fits_error}")]
+    Fitsio {
+        fits_error: fitsio::errors::Error,
+        filename: PathBuf,
+    },
+
+    /// An error derived from `GpuboxWriteError`.
+    #[error("{0}")]
+    GpuboxWrite(#[from] crate::gpubox_writer::error::GpuboxWriteError),
+}
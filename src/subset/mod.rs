@@ -0,0 +1,891 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Writing a subset of an observation (some of its timesteps, coarse channels and tiles) to disk as a smaller
+//! observation, which can be opened like any other, e.g. for test fixtures or bug reports.
+//!
+//! The subset has a copy of the metafits (named `obsid.metafits`) with only the selected tiles in the TILEDATA
+//! table, and `NINPUTS`, `CHANNELS`, `BANDWDTH`, `FREQCENT` and `EXPOSURE` updated to match. The digital gains of
+//! each tile are reordered so the gains of the selected coarse channels come first, which is where they are read
+//! from. `GPSTIME` (the obsid) is not changed, so `EXPOSURE` covers everything from the start of the observation
+//! to the end of the last selected timestep. The `Input` and `Antenna` numbers of the tiles are not changed either,
+//! so the tiles keep their relative order.
+//!
+//! * Correlator observations are written as MWAX v2 gpubox files (see the `gpubox_writer` module) with only the
+//!   baselines between the selected tiles. Legacy observations are converted to MWAX ordering in the process. The
+//!   context's `read_config` must not scale or correct the visibilities, as the subset has the same `RAWSCALE`.
+//! * Voltage observations keep the format and filenames of the original files, with only the samples of the
+//!   selected tiles' rf inputs. MWAX v2 headers are copied with `NINPUTS`, `NINPUTS_XGPU` and `TRANSFER_SIZE`
+//!   updated to match.
+//!
+//! Selected timesteps and coarse channels which have no data at all are left out of the subset.
+
+pub mod error;
+#[cfg(test)]
+mod test;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use fitsio::FitsFile;
+use std::os::raw::{c_int, c_long, c_void};
+
+use crate::gpubox_writer::GpuboxWriteConfig;
+use crate::uvfits::c_string;
+use crate::*;
+use error::SubsetError;
+
+/// cfitsio datatype code for a C int.
+const TINT: c_int = 31;
+
+/// Maximum number of bytes to read from a voltage file at once.
+const VOLTAGE_CHUNK_SIZE_BYTES: usize = 8 * 1024 * 1024;
+
+///
+/// The timesteps, coarse channels and antennas (tiles) to keep in a subset of an observation.
+///
+/// Anything not set is taken from the context: all of the provided timesteps, all of the provided coarse channels
+/// and all of the antennas.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SubsetConfig {
+    /// Indices within the context timestep array.
+    pub timestep_indices: Option<Vec<usize>>,
+    /// Indices within the context coarse_chan array.
+    pub coarse_chan_indices: Option<Vec<usize>>,
+    /// Indices within the metafits antenna array.
+    pub antenna_indices: Option<Vec<usize>>,
+}
+
+impl SubsetConfig {
+    /// Creates a new `SubsetConfig` which keeps everything.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A new `SubsetConfig`
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the timesteps to keep.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestep_indices` - indices within the context timestep array, in any order.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `SubsetConfig`
+    ///
+    pub fn with_timestep_indices(mut self, timestep_indices: &[usize]) -> Self {
+        self.timestep_indices = Some(timestep_indices.to_vec());
+        self
+    }
+
+    /// Sets the coarse channels to keep.
+    ///
+    /// # Arguments
+    ///
+    /// * `coarse_chan_indices` - indices within the context coarse_chan array, in any order.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `SubsetConfig`
+    ///
+    pub fn with_coarse_chan_indices(mut self, coarse_chan_indices: &[usize]) -> Self {
+        self.coarse_chan_indices = Some(coarse_chan_indices.to_vec());
+        self
+    }
+
+    /// Sets the antennas (tiles) to keep.
+    ///
+    /// # Arguments
+    ///
+    /// * `antenna_indices` - indices within the metafits antenna array, in any order.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `SubsetConfig`
+    ///
+    pub fn with_antenna_indices(mut self, antenna_indices: &[usize]) -> Self {
+        self.antenna_indices = Some(antenna_indices.to_vec());
+        self
+    }
+}
+
+///
+/// The files of a subset written by `write_subset`, ready to be passed to `CorrelatorContext::new` or
+/// `VoltageContext::new`.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubsetFiles {
+    /// Path of the new metafits file.
+    pub metafits_filename: PathBuf,
+    /// Paths of the new gpubox or voltage files.
+    pub data_filenames: Vec<PathBuf>,
+}
+
+/// Sort, deduplicate and check some indices, or use the defaults if none were given.
+///
+/// # Arguments
+///
+/// * `indices` - an Option containing the indices from the `SubsetConfig`.
+///
+/// * `default_indices` - indices to use if `indices` is None.
+///
+/// * `count` - the number of items the indices index into.
+///
+/// * `invalid_index` - makes the error for an index which is not less than `count`.
+///
+///
+/// # Returns
+///
+/// * A Result containing the sorted indices, or a SubsetError if any are out of range.
+///
+fn resolve_indices<F: Fn(usize) -> SubsetError>(
+    indices: Option<&[usize]>,
+    default_indices: &[usize],
+    count: usize,
+    invalid_index: F,
+) -> Result<Vec<usize>, SubsetError> {
+    let mut resolved: Vec<usize> = indices.unwrap_or(default_indices).to_vec();
+    resolved.sort_unstable();
+    resolved.dedup();
+
+    match resolved.iter().find(|&&i| i >= count) {
+        Some(&index) => Err(invalid_index(index)),
+        None => Ok(resolved),
+    }
+}
+
+/// Resolve the antenna indices of a `SubsetConfig`, defaulting to all antennas.
+fn resolve_antenna_indices(
+    metafits_context: &MetafitsContext,
+    config: &SubsetConfig,
+) -> Result<Vec<usize>, SubsetError> {
+    let num_ants = metafits_context.num_ants;
+    let antenna_indices = resolve_indices(
+        config.antenna_indices.as_deref(),
+        &(0..num_ants).collect::<Vec<usize>>(),
+        num_ants,
+        |index| SubsetError::InvalidAntennaIndex { index, num_ants },
+    )?;
+
+    match antenna_indices.is_empty() {
+        true => Err(SubsetError::EmptySubset("no antennas")),
+        false => Ok(antenna_indices),
+    }
+}
+
+/// Create the output directory of a subset.
+fn create_output_dir(output_dir: &Path) -> Result<(), SubsetError> {
+    std::fs::create_dir_all(output_dir).map_err(|error| SubsetError::CreateDir {
+        dir: output_dir.to_path_buf(),
+        error,
+    })
+}
+
+/// Copy every `num_elements` x `element_size` byte group of the input, keeping only some of the elements of each
+/// group. This is how the rf inputs of a voltage file are subset, as they are interleaved at a fixed stride.
+///
+/// # Arguments
+///
+/// * `reader` - where to read the groups from.
+///
+/// * `writer` - where to write the subset groups to.
+///
+/// * `num_bytes` - number of bytes to read. Must be a multiple of the group size.
+///
+/// * `element_size` - number of bytes in each element.
+///
+/// * `num_elements` - number of elements in each group.
+///
+/// * `element_indices` - ascending indices of the elements to keep within each group.
+///
+///
+/// # Returns
+///
+/// * A Result of Ok on success, or an io error on failure.
+///
+pub(crate) fn subset_interleaved<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    num_bytes: usize,
+    element_size: usize,
+    num_elements: usize,
+    element_indices: &[usize],
+) -> std::io::Result<()> {
+    let group_size = element_size * num_elements;
+    if group_size == 0 || num_bytes % group_size != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{} bytes is not a multiple of the {} byte group size",
+                num_bytes, group_size
+            ),
+        ));
+    }
+
+    let groups_per_chunk = std::cmp::max(1, VOLTAGE_CHUNK_SIZE_BYTES / group_size);
+    let mut input: Vec<u8> = vec![0; groups_per_chunk * group_size];
+    let mut output: Vec<u8> =
+        Vec::with_capacity(groups_per_chunk * element_indices.len() * element_size);
+    let mut num_groups_left = num_bytes / group_size;
+
+    while num_groups_left > 0 {
+        let num_groups = std::cmp::min(groups_per_chunk, num_groups_left);
+        let chunk = &mut input[..num_groups * group_size];
+        reader.read_exact(chunk)?;
+
+        output.clear();
+        for group in chunk.chunks_exact(group_size) {
+            for &e in element_indices {
+                output.extend_from_slice(&group[e * element_size..(e + 1) * element_size]);
+            }
+        }
+        writer.write_all(&output)?;
+
+        num_groups_left -= num_groups;
+    }
+
+    Ok(())
+}
+
+/// A copy of a metafits file being edited via cfitsio, which is closed when dropped.
+struct MetafitsEditor {
+    fptr: FitsFile,
+    filename: PathBuf,
+}
+
+impl MetafitsEditor {
+    /// Copy a metafits file and open the copy for editing, with the primary HDU current.
+    fn copy(metafits_filename: &str, filename: &Path) -> Result<Self, SubsetError> {
+        std::fs::copy(metafits_filename, filename).map_err(|error| SubsetError::Io {
+            filename: filename.to_path_buf(),
+            error,
+        })?;
+        let fptr = FitsFile::edit(filename).map_err(|fits_error| SubsetError::Fitsio {
+            fits_error,
+            filename: filename.to_path_buf(),
+        })?;
+
+        Ok(MetafitsEditor {
+            fptr,
+            filename: filename.to_path_buf(),
+        })
+    }
+
+    /// Turn a cfitsio status into a Result.
+    fn check(&self, status: c_int) -> Result<(), SubsetError> {
+        fitsio::errors::check_status(status).map_err(|fits_error| SubsetError::Fitsio {
+            fits_error,
+            filename: self.filename.clone(),
+        })
+    }
+
+    /// Make an HDU (0 is the primary HDU) current.
+    fn move_to_hdu(&mut self, hdu_index: usize) -> Result<(), SubsetError> {
+        self.fptr
+            .hdu(hdu_index)
+            .map_err(|fits_error| SubsetError::Fitsio {
+                fits_error,
+                filename: self.filename.clone(),
+            })?;
+        Ok(())
+    }
+
+    fn update_key_i64(&mut self, key: &str, value: i64) -> Result<(), SubsetError> {
+        let key = c_string(key);
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffukyj(
+                self.fptr.as_raw(),
+                key.as_ptr(),
+                value,
+                std::ptr::null(),
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+
+    fn update_key_f64(&mut self, key: &str, value: f64) -> Result<(), SubsetError> {
+        let key = c_string(key);
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffukyd(
+                self.fptr.as_raw(),
+                key.as_ptr(),
+                value,
+                -15,
+                std::ptr::null(),
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+
+    /// Update a string key, using CONTINUE keywords if it is too long for one card (like `CHANNELS`).
+    fn update_key_long_string(&mut self, key: &str, value: &str) -> Result<(), SubsetError> {
+        let (key, value) = (c_string(key), c_string(value));
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffukls(
+                self.fptr.as_raw(),
+                key.as_ptr(),
+                value.as_ptr(),
+                std::ptr::null(),
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+
+    /// Get the (1-based) number of a column of the current table HDU.
+    fn get_column_number(&mut self, col_name: &str) -> Result<c_int, SubsetError> {
+        let col_name = c_string(col_name);
+        let mut col_num = 0;
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffgcno(
+                self.fptr.as_raw(),
+                0,
+                col_name.as_ptr() as *mut _,
+                &mut col_num,
+                &mut status,
+            );
+        }
+        self.check(status)?;
+        Ok(col_num)
+    }
+
+    /// Read the first elements of an integer array cell of the current table HDU.
+    fn read_cell_i32s(
+        &mut self,
+        col_num: c_int,
+        row: usize,
+        num_elements: usize,
+    ) -> Result<Vec<i32>, SubsetError> {
+        let mut values: Vec<i32> = vec![0; num_elements];
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffgcv(
+                self.fptr.as_raw(),
+                TINT,
+                col_num,
+                row as i64 + 1,
+                1,
+                num_elements as i64,
+                std::ptr::null_mut(),
+                values.as_mut_ptr() as *mut c_void,
+                &mut 0,
+                &mut status,
+            );
+        }
+        self.check(status)?;
+        Ok(values)
+    }
+
+    /// Write the first elements of an integer array cell of the current table HDU.
+    fn write_cell_i32s(
+        &mut self,
+        col_num: c_int,
+        row: usize,
+        values: &mut [i32],
+    ) -> Result<(), SubsetError> {
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffpcl(
+                self.fptr.as_raw(),
+                TINT,
+                col_num,
+                row as i64 + 1,
+                1,
+                values.len() as i64,
+                values.as_mut_ptr() as *mut c_void,
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+
+    /// Delete rows of the current table HDU.
+    ///
+    /// # Arguments
+    ///
+    /// * `rows` - ascending (0-based) indices of the rows to delete.
+    ///
+    fn delete_rows(&mut self, rows: &[usize]) -> Result<(), SubsetError> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut row_nums: Vec<c_long> = rows.iter().map(|&r| r as c_long + 1).collect();
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffdrws(
+                self.fptr.as_raw(),
+                row_nums.as_mut_ptr(),
+                row_nums.len() as c_long,
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+}
+
+/// Write the metafits of a subset to `output_dir`. See the `subset` module documentation for what is changed.
+///
+/// # Arguments
+///
+/// * `metafits_context` - the metafits of the observation being subset.
+///
+/// * `output_dir` - directory to write the metafits file to.
+///
+/// * `rec_chan_numbers` - ascending receiver channel numbers of the coarse channels in the subset.
+///
+/// * `antenna_indices` - ascending indices within the metafits antenna array of the antennas in the subset.
+///
+/// * `exposure_s` - the new `EXPOSURE`, in seconds.
+///
+///
+/// # Returns
+///
+/// * A Result containing the path of the new metafits file, or a SubsetError on failure.
+///
+pub(crate) fn write_subset_metafits(
+    metafits_context: &MetafitsContext,
+    output_dir: &Path,
+    rec_chan_numbers: &[usize],
+    antenna_indices: &[usize],
+    exposure_s: u64,
+) -> Result<PathBuf, SubsetError> {
+    let filename = output_dir.join(format!("{}.metafits", metafits_context.obs_id));
+    let mut metafits = MetafitsEditor::copy(&metafits_context.metafits_filename, &filename)?;
+
+    // Primary HDU
+    let coarse_chan_width_mhz = metafits_context.coarse_chan_width_hz as f64 / 1e6;
+    let channels: Vec<String> = rec_chan_numbers.iter().map(|c| c.to_string()).collect();
+    let centre_chan_number =
+        (rec_chan_numbers[0] + rec_chan_numbers[rec_chan_numbers.len() - 1]) as f64 / 2.;
+    metafits.update_key_i64("NINPUTS", (antenna_indices.len() * 2) as i64)?;
+    metafits.update_key_long_string("CHANNELS", &channels.join(","))?;
+    metafits.update_key_f64(
+        "BANDWDTH",
+        rec_chan_numbers.len() as f64 * coarse_chan_width_mhz,
+    )?;
+    metafits.update_key_f64("FREQCENT", centre_chan_number * coarse_chan_width_mhz)?;
+    metafits.update_key_i64("EXPOSURE", exposure_s as i64)?;
+
+    // TILEDATA. The gains are in ascending receiver channel order, for every channel of the original observation
+    metafits.move_to_hdu(1)?;
+    let mut all_rec_chan_numbers: Vec<usize> = metafits_context
+        .metafits_coarse_chans
+        .iter()
+        .map(|c| c.rec_chan_number)
+        .collect();
+    all_rec_chan_numbers.sort_unstable();
+    let gains_indices: Vec<usize> = rec_chan_numbers
+        .iter()
+        .filter_map(|r| all_rec_chan_numbers.iter().position(|a| a == r))
+        .collect();
+
+    let mut inputs: Vec<u32> = Vec::with_capacity(antenna_indices.len() * 2);
+    for &a in antenna_indices {
+        let antenna = &metafits_context.antennas[a];
+        inputs.push(antenna.rfinput_x.input);
+        inputs.push(antenna.rfinput_y.input);
+    }
+
+    let input_col_num = metafits.get_column_number("Input")?;
+    let gains_col_num = metafits.get_column_number("Gains")?;
+    let mut rows_to_delete: Vec<usize> = Vec::new();
+    for row in 0..metafits_context.num_rf_inputs {
+        let input = metafits.read_cell_i32s(input_col_num, row, 1)?[0] as u32;
+        if !inputs.contains(&input) {
+            rows_to_delete.push(row);
+            continue;
+        }
+
+        let gains = metafits.read_cell_i32s(gains_col_num, row, all_rec_chan_numbers.len())?;
+        let mut subset_gains: Vec<i32> = gains_indices.iter().map(|&g| gains[g]).collect();
+        metafits.write_cell_i32s(gains_col_num, row, &mut subset_gains)?;
+    }
+    metafits.delete_rows(&rows_to_delete)?;
+
+    Ok(filename)
+}
+
+/// Get the `EXPOSURE` of a subset, which runs from the scheduled start of the observation to the end of its last
+/// timestep, rounded up to whole seconds.
+fn get_subset_exposure_s(
+    metafits_context: &MetafitsContext,
+    last_timestep_gps_time_ms: u64,
+    timestep_duration_ms: u64,
+) -> u64 {
+    let end_gps_time_ms = last_timestep_gps_time_ms + timestep_duration_ms;
+    (end_gps_time_ms.saturating_sub(metafits_context.sched_start_gps_time_ms) + 999) / 1000
+}
+
+/// Get a copy of an MWAX v2 voltage file header (a PSRDADA header of `KEY value` lines, padded with NULs) for a subset
+/// of its rf inputs. `NINPUTS`, `NINPUTS_XGPU` and `TRANSFER_SIZE` (the number of bytes after the header) are
+/// replaced, and everything else is copied unchanged.
+///
+/// # Arguments
+///
+/// * `header` - the header of the original file.
+///
+/// * `num_rf_inputs` - number of rf inputs in the subset.
+///
+/// * `transfer_size` - number of bytes after the header in the subset file.
+///
+///
+/// # Returns
+///
+/// * The subset header, the same length as `header`.
+///
+fn subset_mwax_voltage_header(header: &[u8], num_rf_inputs: usize, transfer_size: u64) -> Vec<u8> {
+    let mut subset_header: Vec<u8> = Vec::with_capacity(header.len());
+
+    for line in header.split_inclusive(|&b| b == b'\n') {
+        let key_len = line
+            .iter()
+            .position(|b| b.is_ascii_whitespace())
+            .unwrap_or(line.len());
+        let value = match &line[..key_len] {
+            b"NINPUTS" => num_rf_inputs as u64,
+            // xGPU needs the number of inputs padded to a multiple of 16
+            b"NINPUTS_XGPU" => ((num_rf_inputs + 15) / 16 * 16) as u64,
+            b"TRANSFER_SIZE" => transfer_size,
+            _ => {
+                subset_header.extend_from_slice(line);
+                continue;
+            }
+        };
+
+        // Keep the key and the spacing after it, and replace the value
+        let value_start = key_len
+            + line[key_len..]
+                .iter()
+                .take_while(|&&b| b == b' ' || b == b'\t')
+                .count();
+        let value_end = value_start
+            + line[value_start..]
+                .iter()
+                .take_while(|b| !b.is_ascii_whitespace())
+                .count();
+        subset_header.extend_from_slice(&line[..value_start]);
+        subset_header.extend_from_slice(value.to_string().as_bytes());
+        subset_header.extend_from_slice(&line[value_end..]);
+    }
+
+    // The values only get smaller, so this just pads the header back out with NULs
+    subset_header.resize(header.len(), 0);
+    subset_header
+}
+
+impl CorrelatorContext {
+    /// Write a subset of the observation to `output_dir` as a new metafits and MWAX v2 gpubox files. See the
+    /// `subset` module documentation for details.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - directory to write the files to. It is created if needed, and existing files are replaced.
+    ///
+    /// * `config` - the timesteps, coarse channels and antennas to keep.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the paths of the files written, or a SubsetError on failure.
+    ///
+    pub fn write_subset<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        config: &SubsetConfig,
+    ) -> Result<SubsetFiles, SubsetError> {
+        let output_dir = output_dir.as_ref();
+        let metafits_context = &self.metafits_context;
+
+        let timestep_indices = resolve_indices(
+            config.timestep_indices.as_deref(),
+            &self.provided_timestep_indices,
+            self.num_timesteps,
+            |index| SubsetError::InvalidTimestepIndex {
+                index,
+                num_timesteps: self.num_timesteps,
+            },
+        )?;
+        let coarse_chan_indices = resolve_indices(
+            config.coarse_chan_indices.as_deref(),
+            &self.provided_coarse_chan_indices,
+            self.num_coarse_chans,
+            |index| SubsetError::InvalidCoarseChanIndex {
+                index,
+                num_coarse_chans: self.num_coarse_chans,
+            },
+        )?;
+        let antenna_indices = resolve_antenna_indices(metafits_context, config)?;
+
+        // Leave out the timesteps and coarse channels with no data
+        let timestep_indices: Vec<usize> = timestep_indices
+            .into_iter()
+            .filter(|&t| coarse_chan_indices.iter().any(|&c| self.has_data(t, c)))
+            .collect();
+        let coarse_chan_indices: Vec<usize> = coarse_chan_indices
+            .into_iter()
+            .filter(|&c| timestep_indices.iter().any(|&t| self.has_data(t, c)))
+            .collect();
+        if timestep_indices.is_empty() || coarse_chan_indices.is_empty() {
+            return Err(SubsetError::NoData);
+        }
+
+        create_output_dir(output_dir)?;
+        let data_filenames = self.write_mwax_gpubox_files_subset(
+            output_dir,
+            &GpuboxWriteConfig::new(),
+            &timestep_indices,
+            &coarse_chan_indices,
+            &antenna_indices,
+        )?;
+
+        let rec_chan_numbers: Vec<usize> = coarse_chan_indices
+            .iter()
+            .map(|&c| self.coarse_chans[c].rec_chan_number)
+            .collect();
+        let exposure_s = get_subset_exposure_s(
+            metafits_context,
+            self.timesteps[timestep_indices[timestep_indices.len() - 1]].gps_time_ms,
+            metafits_context.corr_int_time_ms,
+        );
+        let metafits_filename = write_subset_metafits(
+            metafits_context,
+            output_dir,
+            &rec_chan_numbers,
+            &antenna_indices,
+            exposure_s,
+        )?;
+
+        Ok(SubsetFiles {
+            metafits_filename,
+            data_filenames,
+        })
+    }
+}
+
+impl VoltageContext {
+    /// Write a subset of the observation to `output_dir` as a new metafits and voltage files. See the `subset`
+    /// module documentation for details.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - directory to write the files to. It is created if needed, and existing files are replaced.
+    ///
+    /// * `config` - the timesteps, coarse channels and antennas to keep.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result containing the paths of the files written, or a SubsetError on failure.
+    ///
+    pub fn write_subset<P: AsRef<Path>>(
+        &self,
+        output_dir: P,
+        config: &SubsetConfig,
+    ) -> Result<SubsetFiles, SubsetError> {
+        let output_dir = output_dir.as_ref();
+        let metafits_context = &self.metafits_context;
+
+        let timestep_indices = resolve_indices(
+            config.timestep_indices.as_deref(),
+            &self.provided_timestep_indices,
+            self.num_timesteps,
+            |index| SubsetError::InvalidTimestepIndex {
+                index,
+                num_timesteps: self.num_timesteps,
+            },
+        )?;
+        let coarse_chan_indices = resolve_indices(
+            config.coarse_chan_indices.as_deref(),
+            &self.provided_coarse_chan_indices,
+            self.num_coarse_chans,
+            |index| SubsetError::InvalidCoarseChanIndex {
+                index,
+                num_coarse_chans: self.num_coarse_chans,
+            },
+        )?;
+        let antenna_indices = resolve_antenna_indices(metafits_context, config)?;
+
+        // Find the file of each timestep and coarse channel, if there is one
+        let mut files: Vec<(usize, usize, &str)> = Vec::new();
+        for &t in timestep_indices.iter() {
+            let batch = self
+                .voltage_batches
+                .iter()
+                .find(|b| b.gps_time_seconds * 1000 == self.timesteps[t].gps_time_ms);
+            for &c in coarse_chan_indices.iter() {
+                let channel_identifier = self.coarse_chans[c].gpubox_number;
+                if let Some(f) = batch.and_then(|b| {
+                    b.voltage_files
+                        .iter()
+                        .find(|f| f.channel_identifier == channel_identifier)
+                }) {
+                    files.push((t, c, &f.filename));
+                }
+            }
+        }
+        if files.is_empty() {
+            return Err(SubsetError::NoData);
+        }
+
+        // The rf inputs of the selected antennas, by their position in the files
+        let ants: Vec<u32> = antenna_indices
+            .iter()
+            .map(|&a| metafits_context.antennas[a].ant)
+            .collect();
+        let rf_input_positions: Vec<usize> = metafits_context
+            .rf_inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| ants.contains(&r.ant))
+            .map(|(i, _)| i)
+            .collect();
+
+        create_output_dir(output_dir)?;
+        let mut data_filenames: Vec<PathBuf> = Vec::with_capacity(files.len());
+        for &(_, _, filename) in files.iter() {
+            let output_filename = output_dir.join(
+                Path::new(filename)
+                    .file_name()
+                    .expect("voltage filenames have already been validated"),
+            );
+            self.write_voltage_file_subset(filename, &output_filename, &rf_input_positions)?;
+            data_filenames.push(output_filename);
+        }
+
+        let mut rec_chan_numbers: Vec<usize> = files
+            .iter()
+            .map(|&(_, c, _)| self.coarse_chans[c].rec_chan_number)
+            .collect();
+        rec_chan_numbers.sort_unstable();
+        rec_chan_numbers.dedup();
+        let last_timestep_index = files.iter().map(|&(t, _, _)| t).max().unwrap_or(0);
+        let exposure_s = get_subset_exposure_s(
+            metafits_context,
+            self.timesteps[last_timestep_index].gps_time_ms,
+            self.timestep_duration_ms,
+        );
+        let metafits_filename = write_subset_metafits(
+            metafits_context,
+            output_dir,
+            &rec_chan_numbers,
+            &antenna_indices,
+            exposure_s,
+        )?;
+
+        Ok(SubsetFiles {
+            metafits_filename,
+            data_filenames,
+        })
+    }
+
+    /// Copy a voltage file, keeping only some of its rf inputs. The header (if any) is copied with its number of
+    /// inputs and sizes updated (see `subset_mwax_voltage_header`); the delay block (if any) and the voltage blocks
+    /// are subset.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - the voltage file to copy.
+    ///
+    /// * `output_filename` - the file to write.
+    ///
+    /// * `rf_input_positions` - ascending positions of the rf inputs to keep within each block, i.e. indices
+    ///   within the metafits rf_inputs array.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok on success, or a SubsetError on failure.
+    ///
+    fn write_voltage_file_subset(
+        &self,
+        filename: &str,
+        output_filename: &Path,
+        rf_input_positions: &[usize],
+    ) -> Result<(), SubsetError> {
+        let num_rf_inputs = self.metafits_context.num_rf_inputs;
+        let voltage_bytes = self.voltage_block_size_bytes * self.num_voltage_blocks_per_timestep;
+        let expected_size =
+            self.data_file_header_size_bytes + self.delay_block_size_bytes + voltage_bytes;
+
+        let read_error = |error| SubsetError::Io {
+            filename: PathBuf::from(filename),
+            error,
+        };
+        let write_error = |error| SubsetError::Io {
+            filename: output_filename.to_path_buf(),
+            error,
+        };
+
+        let actual_size = std::fs::metadata(filename).map_err(read_error)?.len();
+        if actual_size != expected_size {
+            return Err(SubsetError::UnexpectedFileSize {
+                filename: filename.to_string(),
+                expected: expected_size,
+                actual: actual_size,
+            });
+        }
+
+        let mut reader = BufReader::new(File::open(filename).map_err(read_error)?);
+        let mut writer = BufWriter::new(File::create(output_filename).map_err(write_error)?);
+        let mut header: Vec<u8> = vec![0; self.data_file_header_size_bytes as usize];
+        reader.read_exact(&mut header).map_err(read_error)?;
+        if self.mwa_version == MWAVersion::VCSMWAXv2 {
+            let transfer_size = (self.delay_block_size_bytes + voltage_bytes)
+                / num_rf_inputs as u64
+                * rf_input_positions.len() as u64;
+            header = subset_mwax_voltage_header(&header, rf_input_positions.len(), transfer_size);
+        }
+        writer.write_all(&header).map_err(write_error)?;
+
+        // MWAX delay blocks have the same layout as the voltage blocks: [rf_input][...]
+        if self.delay_block_size_bytes > 0 {
+            subset_interleaved(
+                &mut reader,
+                &mut writer,
+                self.delay_block_size_bytes as usize,
+                self.delay_block_size_bytes as usize / num_rf_inputs,
+                num_rf_inputs,
+                rf_input_positions,
+            )
+            .map_err(write_error)?;
+        }
+
+        // Legacy: [sample][fine_chan][rf_input][sample_size]. MWAX: [block][rf_input][samples][sample_size]
+        let element_size = match self.mwa_version {
+            MWAVersion::VCSLegacyRecombined => self.sample_size_bytes,
+            _ => self.voltage_block_size_bytes / num_rf_inputs as u64,
+        };
+        subset_interleaved(
+            &mut reader,
+            &mut writer,
+            voltage_bytes as usize,
+            element_size as usize,
+            num_rf_inputs,
+            rf_input_positions,
+        )
+        .map_err(write_error)?;
+
+        writer.flush().map_err(write_error)
+    }
+}
@@ -0,0 +1,346 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Unit tests for writing a subset of an observation
+
+use super::*;
+use crate::voltage_context::test::get_test_voltage_context;
+
+fn get_mwax_context() -> CorrelatorContext {
+    CorrelatorContext::new(
+        "test_files/1244973688_1_timestep/1244973688.metafits",
+        &["test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits"],
+    )
+    .unwrap()
+}
+
+/// Subset the test voltage observation, open the subset and check each of its files has the updated header and the
+/// selected rf inputs of the original file.
+fn check_voltage_subset(mwa_version: MWAVersion, expected_exposure_ms: u64) {
+    let mut context = get_test_voltage_context(mwa_version);
+    // Our test files only have 2 rf inputs' worth of voltage block, not 256
+    context.voltage_block_size_bytes /= 128;
+    let antenna_indices = [5, 0];
+    let temp_dir = tempdir::TempDir::new("subset_test").unwrap();
+    let header_size = context.data_file_header_size_bytes as usize;
+
+    // Give the MWAX files a header with the keys which need updating
+    let header_text = |num_rf_inputs: usize, num_xgpu_inputs: usize, transfer_size: usize| {
+        let mut header = format!(
+            "HDR_SIZE {}\nNINPUTS    {}\nNINPUTS_XGPU {}\nTRANSFER_SIZE {}\nOBS_ID 1101503312\n",
+            header_size, num_rf_inputs, num_xgpu_inputs, transfer_size
+        )
+        .into_bytes();
+        header.resize(header_size, 0);
+        header
+    };
+    for batch in context.voltage_batches.iter() {
+        for file in batch.voltage_files.iter() {
+            if header_size > 0 {
+                let file_size = std::fs::metadata(&file.filename).unwrap().len() as usize;
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&file.filename)
+                    .unwrap()
+                    .write_all(&header_text(256, 256, file_size - header_size))
+                    .unwrap();
+            }
+        }
+    }
+
+    let files = context
+        .write_subset(
+            temp_dir.path(),
+            &SubsetConfig::new().with_antenna_indices(&antenna_indices),
+        )
+        .unwrap();
+    assert_eq!(files.data_filenames.len(), 4);
+
+    let subset_context =
+        VoltageContext::new(&files.metafits_filename, &files.data_filenames).unwrap();
+    assert_eq!(subset_context.mwa_version, mwa_version);
+    assert_eq!(subset_context.metafits_context.num_ants, 2);
+    assert_eq!(subset_context.metafits_context.num_rf_inputs, 4);
+    assert_eq!(subset_context.num_provided_timesteps, 2);
+    assert_eq!(subset_context.num_provided_coarse_chans, 2);
+    assert_eq!(
+        subset_context.metafits_context.sched_duration_ms,
+        expected_exposure_ms
+    );
+    for (subset_antenna, &a) in subset_context
+        .metafits_context
+        .antennas
+        .iter()
+        .zip([0, 5].iter())
+    {
+        assert_eq!(
+            subset_antenna.tile_name,
+            context.metafits_context.antennas[a].tile_name
+        );
+    }
+
+    let positions: Vec<usize> = context
+        .metafits_context
+        .rf_inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| {
+            antenna_indices
+                .iter()
+                .any(|&a| context.metafits_context.antennas[a].ant == r.ant)
+        })
+        .map(|(i, _)| i)
+        .collect();
+    let header_size = context.data_file_header_size_bytes as usize;
+    let (delay_element_size, element_size) = match mwa_version {
+        MWAVersion::VCSMWAXv2 => (
+            context.delay_block_size_bytes as usize / 256,
+            context.voltage_block_size_bytes as usize / 256,
+        ),
+        _ => (0, 1),
+    };
+
+    for batch in context.voltage_batches.iter() {
+        for file in batch.voltage_files.iter() {
+            let input = std::fs::read(&file.filename).unwrap();
+            let output = std::fs::read(
+                temp_dir
+                    .path()
+                    .join(Path::new(&file.filename).file_name().unwrap()),
+            )
+            .unwrap();
+            let voltage_bytes = input.len() - header_size - delay_element_size * 256;
+            assert_eq!(
+                output.len(),
+                header_size + (delay_element_size * 4) + voltage_bytes / 64
+            );
+            // The header has the new number of inputs and size
+            if header_size > 0 {
+                assert_eq!(
+                    output[..header_size],
+                    header_text(4, 16, output.len() - header_size)[..]
+                );
+            }
+
+            let input_voltages = &input[header_size + delay_element_size * 256..];
+            let output_voltages = &output[header_size + delay_element_size * 4..];
+            for group in [0, 1, voltage_bytes / (element_size * 256) - 1] {
+                for (k, &p) in positions.iter().enumerate() {
+                    let o = (group * 4 + k) * element_size;
+                    let i = (group * 256 + p) * element_size;
+                    assert_eq!(
+                        output_voltages[o..o + element_size],
+                        input_voltages[i..i + element_size]
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_subset_config() {
+    assert_eq!(
+        SubsetConfig::new(),
+        SubsetConfig {
+            timestep_indices: None,
+            coarse_chan_indices: None,
+            antenna_indices: None
+        }
+    );
+
+    let config = SubsetConfig::new()
+        .with_timestep_indices(&[1, 0])
+        .with_coarse_chan_indices(&[3])
+        .with_antenna_indices(&[7, 2]);
+    assert_eq!(config.timestep_indices, Some(vec![1, 0]));
+    assert_eq!(config.coarse_chan_indices, Some(vec![3]));
+    assert_eq!(config.antenna_indices, Some(vec![7, 2]));
+}
+
+#[test]
+fn test_resolve_indices() {
+    let invalid = |index| SubsetError::InvalidTimestepIndex {
+        index,
+        num_timesteps: 4,
+    };
+
+    assert_eq!(
+        resolve_indices(None, &[0, 2], 4, invalid).unwrap(),
+        vec![0, 2]
+    );
+    assert_eq!(
+        resolve_indices(Some(&[3, 1, 3]), &[0, 2], 4, invalid).unwrap(),
+        vec![1, 3]
+    );
+    assert!(matches!(
+        resolve_indices(Some(&[1, 4]), &[0, 2], 4, invalid),
+        Err(SubsetError::InvalidTimestepIndex {
+            index: 4,
+            num_timesteps: 4
+        })
+    ));
+}
+
+#[test]
+fn test_subset_interleaved() {
+    // 3 groups of 4 elements of 2 bytes
+    let input: Vec<u8> = (0..24).collect();
+    let mut output: Vec<u8> = Vec::new();
+    subset_interleaved(&mut input.as_slice(), &mut output, 24, 2, 4, &[1, 3]).unwrap();
+    assert_eq!(output, vec![2, 3, 6, 7, 10, 11, 14, 15, 18, 19, 22, 23]);
+
+    // Only some of the input is subset
+    let mut reader = input.as_slice();
+    let mut output: Vec<u8> = Vec::new();
+    subset_interleaved(&mut reader, &mut output, 8, 1, 8, &[0]).unwrap();
+    assert_eq!(output, vec![0]);
+    assert_eq!(reader.len(), 16);
+
+    // Not a whole number of groups
+    let mut output: Vec<u8> = Vec::new();
+    assert!(subset_interleaved(&mut input.as_slice(), &mut output, 20, 2, 4, &[0]).is_err());
+}
+
+#[test]
+fn test_subset_mwax_voltage_header() {
+    let mut header =
+        b"HDR_SIZE 4096\nNINPUTS  256\nNINPUTS_XGPU\t256\nNINPUTS_OTHER 256\nTRANSFER_SIZE 5275648000\n".to_vec();
+    header.resize(128, 0);
+
+    let mut expected =
+        b"HDR_SIZE 4096\nNINPUTS  34\nNINPUTS_XGPU\t48\nNINPUTS_OTHER 256\nTRANSFER_SIZE 700780000\n".to_vec();
+    expected.resize(128, 0);
+
+    assert_eq!(subset_mwax_voltage_header(&header, 34, 700780000), expected);
+
+    // No header keys at all
+    let header: Vec<u8> = vec![1; 16];
+    assert_eq!(subset_mwax_voltage_header(&header, 34, 700780000), header);
+}
+
+#[test]
+fn test_write_subset_correlator() {
+    let context = get_mwax_context();
+    let antenna_indices = [0, 3, 7];
+    let temp_dir = tempdir::TempDir::new("subset_test").unwrap();
+
+    let files = context
+        .write_subset(
+            temp_dir.path(),
+            &SubsetConfig::new().with_antenna_indices(&antenna_indices),
+        )
+        .unwrap();
+    assert_eq!(
+        files.metafits_filename,
+        temp_dir.path().join("1244973688.metafits")
+    );
+    assert_eq!(files.data_filenames.len(), 1);
+
+    let subset_context =
+        CorrelatorContext::new(&files.metafits_filename, &files.data_filenames).unwrap();
+    let subset_metafits_context = &subset_context.metafits_context;
+    assert_eq!(subset_metafits_context.obs_id, 1244973688);
+    assert_eq!(subset_metafits_context.num_ants, 3);
+    assert_eq!(subset_metafits_context.num_rf_inputs, 6);
+    assert_eq!(subset_metafits_context.num_baselines, 6);
+    assert_eq!(subset_context.num_coarse_chans, 1);
+    assert_eq!(subset_context.coarse_chans[0].rec_chan_number, 114);
+    assert_eq!(subset_context.num_provided_timesteps, 1);
+
+    let baseline_indices: Vec<usize> = context
+        .metafits_context
+        .baselines
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| {
+            antenna_indices.contains(&b.ant1_index) && antenna_indices.contains(&b.ant2_index)
+        })
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(baseline_indices.len(), 6);
+
+    for (subset_antenna, &a) in subset_metafits_context
+        .antennas
+        .iter()
+        .zip(antenna_indices.iter())
+    {
+        let antenna = &context.metafits_context.antennas[a];
+        assert_eq!(subset_antenna.tile_id, antenna.tile_id);
+        assert_eq!(subset_antenna.tile_name, antenna.tile_name);
+        // Ch114 is the 11th coarse channel, so its gain is now the first
+        assert_eq!(
+            subset_antenna.rfinput_x.digital_gains,
+            vec![antenna.rfinput_x.digital_gains[10]]
+        );
+    }
+
+    let fine_chan_indices: Vec<usize> =
+        (0..context.metafits_context.num_corr_fine_chans_per_coarse).collect();
+    assert_eq!(
+        subset_context.read_by_baseline(0, 0).unwrap(),
+        context
+            .read_by_baseline_subset(0, 10, &baseline_indices, &fine_chan_indices)
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_write_subset_correlator_invalid() {
+    let context = get_mwax_context();
+    let num_ants = context.metafits_context.num_ants;
+    let temp_dir = tempdir::TempDir::new("subset_test").unwrap();
+
+    assert!(matches!(
+        context.write_subset(
+            temp_dir.path(),
+            &SubsetConfig::new().with_antenna_indices(&[0, num_ants])
+        ),
+        Err(SubsetError::InvalidAntennaIndex { index, .. }) if index == num_ants
+    ));
+    assert!(matches!(
+        context.write_subset(
+            temp_dir.path(),
+            &SubsetConfig::new().with_antenna_indices(&[])
+        ),
+        Err(SubsetError::EmptySubset(_))
+    ));
+    assert!(matches!(
+        context.write_subset(
+            temp_dir.path(),
+            &SubsetConfig::new().with_coarse_chan_indices(&[context.num_coarse_chans])
+        ),
+        Err(SubsetError::InvalidCoarseChanIndex { index, .. }) if index == context.num_coarse_chans
+    ));
+    // Timestep 1 has no data
+    assert!(matches!(
+        context.write_subset(
+            temp_dir.path(),
+            &SubsetConfig::new().with_timestep_indices(&[1])
+        ),
+        Err(SubsetError::NoData)
+    ));
+
+    // The subset has the same RAWSCALE, so the visibilities can't be written scaled
+    let mut context = context;
+    context.read_config = CorrelatorReadConfig::new().with_raw_scale_factor(true);
+    assert!(matches!(
+        context.write_subset(temp_dir.path(), &SubsetConfig::new()),
+        Err(SubsetError::GpuboxWrite(
+            GpuboxWriteError::UnsupportedProcessing(_)
+        ))
+    ));
+}
+
+#[test]
+fn test_write_subset_voltage_legacy() {
+    // Two 1 second timesteps
+    check_voltage_subset(MWAVersion::VCSLegacyRecombined, 2000);
+}
+
+#[test]
+fn test_write_subset_voltage_mwax() {
+    // Two 8 second timesteps
+    check_voltage_subset(MWAVersion::VCSMWAXv2, 16000);
+}
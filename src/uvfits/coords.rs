@@ -0,0 +1,235 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Time and coordinate helpers needed to write UVFITS files: Julian dates, sidereal time, precession and
+//! antenna/baseline coordinates.
+//!
+//! Only mean sidereal time and IAU 1976 precession are modelled (no nutation or aberration), which is accurate
+//! to a few arcseconds; plenty for the UVWs of an interferometer the size of the MWA.
+
+use std::f64::consts::PI;
+
+/// Julian date of the UNIX epoch
+const UNIX_EPOCH_JD: f64 = 2_440_587.5;
+/// Julian date of J2000.0
+const J2000_JD: f64 = 2_451_545.0;
+/// Arcseconds to radians
+const ARCSEC_TO_RAD: f64 = PI / (180.0 * 3600.0);
+
+/// WGS84 semi-major axis (metres)
+const WGS84_A: f64 = 6_378_137.0;
+/// WGS84 flattening
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+/// Convert a UNIX time to a Julian date.
+///
+/// # Arguments
+///
+/// * `unix_time_ms` - UNIX time in milliseconds.
+///
+///
+/// # Returns
+///
+/// * The Julian date.
+///
+pub(crate) fn unix_time_ms_to_jd(unix_time_ms: f64) -> f64 {
+    unix_time_ms / 86_400_000.0 + UNIX_EPOCH_JD
+}
+
+/// Calculate Greenwich Mean Sidereal Time using the IAU 1982 expression.
+///
+/// # Arguments
+///
+/// * `jd_ut1` - Julian date (UT1).
+///
+///
+/// # Returns
+///
+/// * GMST in radians, between 0 and 2pi.
+///
+pub(crate) fn get_gmst_rad(jd_ut1: f64) -> f64 {
+    let t = (jd_ut1 - J2000_JD) / 36525.0;
+    let gmst_s = 67_310.548_41 + (876_600.0 * 3600.0 + 8_640_184.812_866) * t + 0.093_104 * t * t
+        - 6.2e-6 * t * t * t;
+
+    (gmst_s.rem_euclid(86_400.0) / 86_400.0) * 2.0 * PI
+}
+
+/// Multiply a 3x3 matrix by a vector.
+fn mat_mul_vec(m: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+/// Multiply two 3x3 matrices.
+fn mat_mul(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+/// Rotation matrix about the z axis.
+fn rot_z(angle: f64) -> [[f64; 3]; 3] {
+    let (s, c) = angle.sin_cos();
+    [[c, s, 0.0], [-s, c, 0.0], [0.0, 0.0, 1.0]]
+}
+
+/// Rotation matrix about the y axis.
+fn rot_y(angle: f64) -> [[f64; 3]; 3] {
+    let (s, c) = angle.sin_cos();
+    [[c, 0.0, -s], [0.0, 1.0, 0.0], [s, 0.0, c]]
+}
+
+/// Calculate the IAU 1976 precession matrix, which takes a vector in the J2000 mean equatorial frame into the mean
+/// equatorial frame of date.
+///
+/// # Arguments
+///
+/// * `jd` - Julian date of the frame of date.
+///
+///
+/// # Returns
+///
+/// * The precession matrix.
+///
+pub(crate) fn get_precession_matrix(jd: f64) -> [[f64; 3]; 3] {
+    let t = (jd - J2000_JD) / 36525.0;
+    let zeta = (2306.2181 * t + 0.30188 * t * t + 0.017998 * t * t * t) * ARCSEC_TO_RAD;
+    let z = (2306.2181 * t + 1.09468 * t * t + 0.018203 * t * t * t) * ARCSEC_TO_RAD;
+    let theta = (2004.3109 * t - 0.42665 * t * t - 0.041833 * t * t * t) * ARCSEC_TO_RAD;
+
+    mat_mul(&rot_z(-z), &mat_mul(&rot_y(theta), &rot_z(-zeta)))
+}
+
+/// Precess the local sidereal time and latitude of the array (i.e. the direction of its zenith) from the mean
+/// equator of date to J2000, so that UVWs can be calculated directly in the J2000 frame.
+///
+/// # Arguments
+///
+/// * `lmst_rad` - local mean sidereal time (radians) of date.
+///
+/// * `latitude_rad` - latitude of the array (radians).
+///
+/// * `jd` - Julian date.
+///
+///
+/// # Returns
+///
+/// * The (local sidereal time, latitude) in radians in the J2000 frame.
+///
+pub(crate) fn precess_zenith_to_j2000(lmst_rad: f64, latitude_rad: f64, jd: f64) -> (f64, f64) {
+    let precession = get_precession_matrix(jd);
+    let zenith_of_date = [
+        latitude_rad.cos() * lmst_rad.cos(),
+        latitude_rad.cos() * lmst_rad.sin(),
+        latitude_rad.sin(),
+    ];
+
+    // The inverse of a rotation is its transpose
+    let mut transpose = [[0.0; 3]; 3];
+    for (i, row) in precession.iter().enumerate() {
+        for (j, cell) in row.iter().enumerate() {
+            transpose[j][i] = *cell;
+        }
+    }
+    let zenith_j2000 = mat_mul_vec(&transpose, &zenith_of_date);
+
+    (
+        zenith_j2000[1].atan2(zenith_j2000[0]).rem_euclid(2.0 * PI),
+        zenith_j2000[2].clamp(-1.0, 1.0).asin(),
+    )
+}
+
+/// Convert a position east, north and up from the array centre into the local equatorial XYZ frame used by
+/// UVFITS (X towards hour angle 0 on the equator, Y towards hour angle -6h, Z towards the pole).
+///
+/// # Arguments
+///
+/// * `east_m` - metres east of the array centre.
+///
+/// * `north_m` - metres north of the array centre.
+///
+/// * `height_m` - metres above the array centre.
+///
+/// * `latitude_rad` - latitude of the array (radians).
+///
+///
+/// # Returns
+///
+/// * The [X, Y, Z] position in metres.
+///
+pub(crate) fn enh_to_xyz(east_m: f64, north_m: f64, height_m: f64, latitude_rad: f64) -> [f64; 3] {
+    let (sin_lat, cos_lat) = latitude_rad.sin_cos();
+
+    [
+        -north_m * sin_lat + height_m * cos_lat,
+        east_m,
+        north_m * cos_lat + height_m * sin_lat,
+    ]
+}
+
+/// Calculate the UVW of a baseline for a phase centre.
+///
+/// # Arguments
+///
+/// * `xyz` - the baseline in the local equatorial XYZ frame (metres), see `enh_to_xyz`.
+///
+/// * `hour_angle_rad` - hour angle of the phase centre (radians).
+///
+/// * `dec_rad` - declination of the phase centre (radians).
+///
+///
+/// # Returns
+///
+/// * The [U, V, W] in metres.
+///
+pub(crate) fn xyz_to_uvw(xyz: &[f64; 3], hour_angle_rad: f64, dec_rad: f64) -> [f64; 3] {
+    let (sin_ha, cos_ha) = hour_angle_rad.sin_cos();
+    let (sin_dec, cos_dec) = dec_rad.sin_cos();
+
+    [
+        sin_ha * xyz[0] + cos_ha * xyz[1],
+        -sin_dec * cos_ha * xyz[0] + sin_dec * sin_ha * xyz[1] + cos_dec * xyz[2],
+        cos_dec * cos_ha * xyz[0] - cos_dec * sin_ha * xyz[1] + sin_dec * xyz[2],
+    ]
+}
+
+/// Convert a WGS84 geodetic position into geocentric (ITRF) XYZ.
+///
+/// # Arguments
+///
+/// * `latitude_rad` - geodetic latitude (radians).
+///
+/// * `longitude_rad` - longitude (radians).
+///
+/// * `altitude_m` - height above the ellipsoid (metres).
+///
+///
+/// # Returns
+///
+/// * The [X, Y, Z] position in metres.
+///
+pub(crate) fn geodetic_to_geocentric_xyz(
+    latitude_rad: f64,
+    longitude_rad: f64,
+    altitude_m: f64,
+) -> [f64; 3] {
+    let e2 = WGS84_F * (2.0 - WGS84_F);
+    let (sin_lat, cos_lat) = latitude_rad.sin_cos();
+    let (sin_lon, cos_lon) = longitude_rad.sin_cos();
+    let n = WGS84_A / (1.0 - e2 * sin_lat * sin_lat).sqrt();
+
+    [
+        (n + altitude_m) * cos_lat * cos_lon,
+        (n + altitude_m) * cos_lat * sin_lon,
+        (n * (1.0 - e2) + altitude_m) * sin_lat,
+    ]
+}
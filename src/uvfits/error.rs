@@ -0,0 +1,42 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Errors associated with writing UVFITS files.
+
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::stokes::PolProducts;
+
+#[derive(Error, Debug)]
+pub enum UvfitsError {
+    #[error("UVFITS files can only be written from linear pols, but the read config asks for {0}")]
+    UnsupportedPolProducts(PolProducts),
+
+    #[error("The selected pols ({0}) can't be written to a UVFITS STOKES axis. It must be regularly spaced in the order XX, YY, XY, YX")]
+    IrregularPols(String),
+
+    #[error("The selection has no {0}")]
+    EmptySelection(String),
+
+    #[error("Passband has {got} gains but there are {expected} fine channels per coarse channel")]
+    InvalidPassbandLength { expected: usize, got: usize },
+
+    #[error("Couldn't remove existing file {filename}: {error}")]
+    RemoveFile {
+        filename: PathBuf,
+        error: std::io::Error,
+    },
+
+    #[error("{filename}: {fits_error}")]
+    Fitsio {
+        fits_error: fitsio::errors::Error,
+        filename: PathBuf,
+    },
+
+    /// An error derived from `GpuboxError`.
+    #[error("{0}")]
+    Gpubox(#[from] crate::gpubox_files::error::GpuboxError),
+}
@@ -0,0 +1,907 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Writing a selection of a correlator observation to a random groups UVFITS file, which most imagers can read.
+//!
+//! The file has one group per timestep and baseline, with the UVW (in seconds), baseline and date as group
+//! parameters, and a [frequency][pol][real, imag, weight] data array. The frequency axis runs from the first to
+//! the last selected fine channel across the selected coarse channels; anything in between which was not selected
+//! (other fine channels, or coarse channels missing from a picket fence observation) has a weight of zero.
+//! Flagged visibilities have a negative weight. The antenna positions are written to an `AIPS AN` table.
+//!
+//! The visibilities are phased to the phase centre (or the tile pointing centre if the metafits has no phase centre),
+//! and the UVWs and the `OBSRA`/`OBSDEC` and RA/DEC axes are for that centre, in the J2000 frame. Visibilities the
+//! correlator left phased to the zenith (i.e. the metafits says it applied no geometric delays, or zenith ones) are
+//! rotated to the phase centre; any others are taken to be phased to it already.
+
+pub(crate) mod coords;
+pub mod error;
+#[cfg(test)]
+mod test;
+
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use log::warn;
+use num_complex::Complex;
+use std::os::raw::{c_char, c_int, c_long, c_void};
+
+use crate::correlator_context::CorrelatorFlagConfig;
+use crate::metafits_context::{CableDelaysApplied, GeometricDelaysApplied};
+use crate::selection::{Selection, POL_NAMES};
+use crate::stokes::PolProducts;
+use crate::*;
+use coords::*;
+use error::UvfitsError;
+
+/// UVFITS STOKES axis codes for XX, XY, YX and YY, in the order of the pols in a visibility.
+const UVFITS_POL_CODES: [i32; 4] = [-5, -7, -8, -6];
+
+/// Number of random group parameters: UU, VV, WW, BASELINE and DATE.
+const NUM_GROUP_PARAMS: usize = 5;
+
+///
+/// Options which control what `CorrelatorContext::write_uvfits` does to the visibilities before they are written.
+/// Anything set in the context's `read_config` (e.g. the Van Vleck correction) is applied first.
+///
+/// The default is to write the visibilities as they are, with no flags.
+///
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UvfitsWriteConfig {
+    /// If true, correct for the electrical length of each rf input's cable. Skipped if the metafits says cable
+    /// delays were already applied by the correlator.
+    pub apply_cable_delays: bool,
+    /// Gains of each fine channel of a coarse channel, which visibilities are divided by to correct for the
+    /// shape of the coarse channel passband. mwalib doesn't model the passband itself, so it must be supplied.
+    pub passband_gains: Option<Vec<f64>>,
+    /// Which flags to generate (see `CorrelatorContext::get_flags`). Timesteps and coarse channels with no data are
+    /// always flagged.
+    pub flag_config: CorrelatorFlagConfig,
+}
+
+impl UvfitsWriteConfig {
+    /// Creates a new `UvfitsWriteConfig` with no corrections or flags.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A new `UvfitsWriteConfig`
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enables or disables the cable length correction.
+    ///
+    /// # Arguments
+    ///
+    /// * `apply_cable_delays` - true to correct for the electrical length of each rf input.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `UvfitsWriteConfig`
+    ///
+    pub fn with_cable_delays(mut self, apply_cable_delays: bool) -> Self {
+        self.apply_cable_delays = apply_cable_delays;
+        self
+    }
+
+    /// Sets the passband gains to divide the visibilities by.
+    ///
+    /// # Arguments
+    ///
+    /// * `passband_gains` - one gain per fine channel of a coarse channel.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `UvfitsWriteConfig`
+    ///
+    pub fn with_passband_gains(mut self, passband_gains: Vec<f64>) -> Self {
+        self.passband_gains = Some(passband_gains);
+        self
+    }
+
+    /// Sets which flags to generate.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag_config` - which flags to generate.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * The modified `UvfitsWriteConfig`
+    ///
+    pub fn with_flag_config(mut self, flag_config: CorrelatorFlagConfig) -> Self {
+        self.flag_config = flag_config;
+        self
+    }
+}
+
+/// Encode a pair of (1-based) antenna numbers as a UVFITS BASELINE group parameter.
+///
+/// # Arguments
+///
+/// * `ant1` - 1-based number of the first antenna.
+///
+/// * `ant2` - 1-based number of the second antenna.
+///
+///
+/// # Returns
+///
+/// * The BASELINE value, using the 2048 based encoding if either antenna number is over 255.
+///
+pub(crate) fn encode_uvfits_baseline(ant1: usize, ant2: usize) -> f32 {
+    if ant1 > 255 || ant2 > 255 {
+        (ant1 * 2048 + ant2 + 65_536) as f32
+    } else {
+        (ant1 * 256 + ant2) as f32
+    }
+}
+
+/// Get the W which the visibilities of a baseline have to be rotated by to phase them to the phase centre.
+///
+/// # Arguments
+///
+/// * `baseline_xyz` - the baseline in the local equatorial XYZ frame (metres), see `enh_to_xyz`.
+///
+/// * `uvw` - the UVW (metres) of the baseline for the phase centre.
+///
+/// * `latitude_j2000_rad` - latitude of the array (radians) precessed to J2000, see `precess_zenith_to_j2000`.
+///
+/// * `geometric_delays_applied` - the geometric delays the correlator applied, from the metafits.
+///
+///
+/// # Returns
+///
+/// * The W (metres) to rotate by: the W of the phase centre less the W of the zenith if the correlator left the
+///   visibilities phased to the zenith, otherwise 0.
+///
+pub(crate) fn get_phasing_w_m(
+    baseline_xyz: &[f64; 3],
+    uvw: &[f64; 3],
+    latitude_j2000_rad: f64,
+    geometric_delays_applied: GeometricDelaysApplied,
+) -> f64 {
+    match geometric_delays_applied {
+        GeometricDelaysApplied::No | GeometricDelaysApplied::Zenith => {
+            // The zenith has an hour angle of 0 and a declination of the latitude
+            uvw[2] - xyz_to_uvw(baseline_xyz, 0.0, latitude_j2000_rad)[2]
+        }
+        GeometricDelaysApplied::TilePointing | GeometricDelaysApplied::AzElTracking => 0.0,
+    }
+}
+
+/// Work out the UVFITS STOKES axis for a selection of pols.
+///
+/// # Arguments
+///
+/// * `pol_indices` - indices of the pols within each visibility (0 = XX, 1 = XY, 2 = YX, 3 = YY).
+///
+///
+/// # Returns
+///
+/// * A Result containing the index of each pol within a visibility, in the order of the STOKES axis, and the
+///   (first code, code step) of the axis, or an error if the codes are not regularly spaced.
+///
+pub(crate) fn get_uvfits_pols(
+    pol_indices: &[usize],
+) -> Result<(Vec<usize>, i32, i32), UvfitsError> {
+    let mut pols: Vec<usize> = pol_indices.to_vec();
+    pols.sort_unstable_by_key(|&p| -UVFITS_POL_CODES[p]);
+    pols.dedup();
+
+    let codes: Vec<i32> = pols.iter().map(|&p| UVFITS_POL_CODES[p]).collect();
+    let step = match codes.len() {
+        0 => return Err(UvfitsError::EmptySelection("pols".to_string())),
+        1 => -1,
+        _ => codes[1] - codes[0],
+    };
+
+    if codes.windows(2).any(|w| w[1] - w[0] != step) {
+        let names: Vec<&str> = pols.iter().map(|&p| POL_NAMES[p]).collect();
+        return Err(UvfitsError::IrregularPols(names.join(",")));
+    }
+
+    Ok((pols, codes[0], step))
+}
+
+/// A UVFITS file being written via cfitsio, which is closed when dropped.
+struct UvfitsFile {
+    fptr: *mut fitsio_sys::fitsfile,
+    filename: PathBuf,
+}
+
+impl UvfitsFile {
+    /// Create a new, empty file, replacing any existing file.
+    fn create(filename: &Path) -> Result<Self, UvfitsError> {
+        match std::fs::remove_file(filename) {
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(error) => {
+                return Err(UvfitsError::RemoveFile {
+                    filename: filename.to_path_buf(),
+                    error,
+                })
+            }
+        }
+
+        let mut file = UvfitsFile {
+            fptr: ptr::null_mut(),
+            filename: filename.to_path_buf(),
+        };
+        let c_filename = CString::new(filename.to_string_lossy().as_bytes())
+            .expect("UvfitsFile::create: CString::new() failed for filename");
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffinit(&mut file.fptr, c_filename.as_ptr(), &mut status);
+        }
+        file.check(status)?;
+
+        Ok(file)
+    }
+
+    /// Turn a cfitsio status into a Result.
+    fn check(&self, status: c_int) -> Result<(), UvfitsError> {
+        fitsio::errors::check_status(status).map_err(|fits_error| UvfitsError::Fitsio {
+            fits_error,
+            filename: self.filename.clone(),
+        })
+    }
+
+    fn write_key_f64(&mut self, key: &str, value: f64, comment: &str) -> Result<(), UvfitsError> {
+        let (key, comment) = (c_string(key), c_string(comment));
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffpkyd(
+                self.fptr,
+                key.as_ptr(),
+                value,
+                -15,
+                comment.as_ptr(),
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+
+    fn write_key_i64(&mut self, key: &str, value: i64, comment: &str) -> Result<(), UvfitsError> {
+        let (key, comment) = (c_string(key), c_string(comment));
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffpkyj(
+                self.fptr,
+                key.as_ptr(),
+                value,
+                comment.as_ptr(),
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+
+    fn write_key_str(&mut self, key: &str, value: &str, comment: &str) -> Result<(), UvfitsError> {
+        let (key, value, comment) = (c_string(key), c_string(value), c_string(comment));
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffpkys(
+                self.fptr,
+                key.as_ptr(),
+                value.as_ptr(),
+                comment.as_ptr(),
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+
+    /// Write one axis of the random groups array.
+    fn write_axis(
+        &mut self,
+        axis: usize,
+        ctype: &str,
+        crval: f64,
+        cdelt: f64,
+    ) -> Result<(), UvfitsError> {
+        self.write_key_str(&format!("CTYPE{}", axis), ctype, "")?;
+        self.write_key_f64(&format!("CRVAL{}", axis), crval, "")?;
+        self.write_key_f64(&format!("CDELT{}", axis), cdelt, "")?;
+        self.write_key_f64(&format!("CRPIX{}", axis), 1.0, "")
+    }
+
+    /// Write the description of one random group parameter.
+    fn write_group_param(
+        &mut self,
+        param: usize,
+        ptype: &str,
+        pzero: f64,
+    ) -> Result<(), UvfitsError> {
+        self.write_key_str(&format!("PTYPE{}", param), ptype, "")?;
+        self.write_key_f64(&format!("PSCAL{}", param), 1.0, "")?;
+        self.write_key_f64(&format!("PZERO{}", param), pzero, "")
+    }
+
+    /// Write the parameters of a (1-based) group.
+    fn write_group_params(&mut self, group: usize, params: &mut [f32]) -> Result<(), UvfitsError> {
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffpgpe(
+                self.fptr,
+                group as c_long,
+                1,
+                params.len() as c_long,
+                params.as_mut_ptr(),
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+
+    /// Write part of the data of a (1-based) group, starting at a (1-based) element.
+    fn write_group_data(
+        &mut self,
+        group: usize,
+        first_element: usize,
+        data: &mut [f32],
+    ) -> Result<(), UvfitsError> {
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffppre(
+                self.fptr,
+                group as c_long,
+                first_element as i64,
+                data.len() as i64,
+                data.as_mut_ptr(),
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+
+    /// Write a column of a table, one element per row (or `data.len() / rows` elements for vector columns).
+    fn write_col<T>(
+        &mut self,
+        col: usize,
+        datatype: u32,
+        data: &mut [T],
+    ) -> Result<(), UvfitsError> {
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffpcl(
+                self.fptr,
+                datatype as c_int,
+                col as c_int,
+                1,
+                1,
+                data.len() as i64,
+                data.as_mut_ptr() as *mut c_void,
+                &mut status,
+            );
+        }
+        self.check(status)
+    }
+}
+
+impl Drop for UvfitsFile {
+    fn drop(&mut self) {
+        if !self.fptr.is_null() {
+            let mut status = 0;
+            unsafe {
+                fitsio_sys::ffclos(self.fptr, &mut status);
+            }
+            if status != 0 {
+                warn!(
+                    "Failed to close {} (cfitsio status {})",
+                    self.filename.display(),
+                    status
+                );
+            }
+        }
+    }
+}
+
+/// Make a CString for cfitsio from a key, value or comment we control.
+pub(crate) fn c_string(s: &str) -> CString {
+    CString::new(s).expect("c_string: string contains a nul byte")
+}
+
+impl CorrelatorContext {
+    /// Write a selection of the observation to a random groups UVFITS file. See the `uvfits` module documentation
+    /// for the layout of the file. The visibilities are read with `read_config` (which must be for linear pols),
+    /// and then corrected and flagged as set out in `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - path of the UVFITS file to write. Any existing file is replaced.
+    ///
+    /// * `selection` - the timesteps, coarse channels, fine channels, baselines and pols to write (see `select`).
+    ///   The selected pols must form a regular UVFITS STOKES axis, e.g. XX,YY or all four.
+    ///
+    /// * `config` - the corrections and flags to apply.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A Result of Ok if success or a UvfitsError on failure.
+    ///
+    pub fn write_uvfits<P: AsRef<Path>>(
+        &self,
+        filename: P,
+        selection: &Selection,
+        config: &UvfitsWriteConfig,
+    ) -> Result<(), UvfitsError> {
+        let metafits_context = &self.metafits_context;
+        let num_fine_chans_per_coarse = metafits_context.num_corr_fine_chans_per_coarse;
+
+        if self.read_config.pol_products != PolProducts::Linear {
+            return Err(UvfitsError::UnsupportedPolProducts(
+                self.read_config.pol_products,
+            ));
+        }
+        if let Some(gains) = &config.passband_gains {
+            if gains.len() != num_fine_chans_per_coarse {
+                return Err(UvfitsError::InvalidPassbandLength {
+                    expected: num_fine_chans_per_coarse,
+                    got: gains.len(),
+                });
+            }
+        }
+        for (name, indices) in [
+            ("timesteps", &selection.timestep_indices),
+            ("coarse channels", &selection.coarse_chan_indices),
+            ("fine channels", &selection.fine_chan_indices),
+            ("baselines", &selection.baseline_indices),
+        ] {
+            if indices.is_empty() {
+                return Err(UvfitsError::EmptySelection(name.to_string()));
+            }
+        }
+        let (pols, first_pol_code, pol_code_step) = get_uvfits_pols(&selection.pol_indices)?;
+
+        // The frequency axis runs from the first selected fine channel of the first coarse channel to the last
+        // selected fine channel of the last coarse channel
+        let full_band = self.get_full_band_for_coarse_chans(&selection.coarse_chan_indices)?;
+        let mut fine_chan_indices: Vec<usize> = selection.fine_chan_indices.clone();
+        fine_chan_indices.sort_unstable();
+        fine_chan_indices.dedup();
+        if fine_chan_indices[fine_chan_indices.len() - 1] >= num_fine_chans_per_coarse {
+            return Err(GpuboxError::InvalidFineChanIndex(num_fine_chans_per_coarse - 1).into());
+        }
+        let first_fine_chan = fine_chan_indices[0];
+        let last_fine_chan = fine_chan_indices[fine_chan_indices.len() - 1];
+        let num_chans = full_band.num_fine_chans
+            - first_fine_chan
+            - (num_fine_chans_per_coarse - 1 - last_fine_chan);
+
+        let baselines: Vec<&Baseline> = selection
+            .baseline_indices
+            .iter()
+            .map(|&b| &metafits_context.baselines[b])
+            .collect();
+        let num_pols = pols.len();
+        let floats_per_chan = num_pols * 3;
+
+        // Times are the centroids of each integration
+        let int_time_ms = metafits_context.corr_int_time_ms as f64;
+        let dut1 = metafits_context.dut1.unwrap_or(0.0);
+        let timestep_jds: Vec<f64> = selection
+            .timestep_indices
+            .iter()
+            .map(|&t| unix_time_ms_to_jd(self.timesteps[t].unix_time_ms as f64 + int_time_ms / 2.0))
+            .collect();
+        let jd_zero = (timestep_jds[0] - 0.5).floor() + 0.5;
+        let first_timestep = &self.timesteps[selection.timestep_indices[0]];
+        let first_utc = metafits_context.sched_start_utc
+            + chrono::Duration::milliseconds(
+                first_timestep.unix_time_ms as i64
+                    - metafits_context.sched_start_unix_time_ms as i64,
+            );
+
+        let ra_rad = metafits_context
+            .ra_phase_center_degrees
+            .unwrap_or(metafits_context.ra_tile_pointing_degrees)
+            .to_radians();
+        let dec_rad = metafits_context
+            .dec_phase_center_degrees
+            .unwrap_or(metafits_context.dec_tile_pointing_degrees)
+            .to_radians();
+
+        let apply_cable_delays = config.apply_cable_delays
+            && match metafits_context.cable_delays_applied {
+                CableDelaysApplied::NoCableDelaysApplied => true,
+                applied => {
+                    warn!(
+                        "Not applying cable delays, as the correlator applied them ({})",
+                        applied
+                    );
+                    false
+                }
+            };
+
+        // Difference in electrical length (metres) of the rf inputs of each pol of each baseline
+        let cable_length_diffs: Vec<[f64; 4]> = baselines
+            .iter()
+            .map(|b| {
+                let ant1 = &metafits_context.antennas[b.ant1_index];
+                let ant2 = &metafits_context.antennas[b.ant2_index];
+                let (x1, y1) = (
+                    ant1.rfinput_x.electrical_length_m,
+                    ant1.rfinput_y.electrical_length_m,
+                );
+                let (x2, y2) = (
+                    ant2.rfinput_x.electrical_length_m,
+                    ant2.rfinput_y.electrical_length_m,
+                );
+
+                match apply_cable_delays {
+                    true => [x2 - x1, y2 - x1, x2 - y1, y2 - y1],
+                    false => [0.0; 4],
+                }
+            })
+            .collect();
+
+        let mut file = UvfitsFile::create(filename.as_ref())?;
+
+        // Primary header
+        let mut naxes: [c_long; 6] = [0, 3, num_pols as c_long, num_chans as c_long, 1, 1];
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffphpr(
+                file.fptr,
+                1,
+                -32,
+                naxes.len() as c_int,
+                naxes.as_mut_ptr(),
+                NUM_GROUP_PARAMS as i64,
+                (timestep_jds.len() * baselines.len()) as i64,
+                1,
+                &mut status,
+            );
+        }
+        file.check(status)?;
+
+        file.write_key_f64("BSCALE", 1.0, "")?;
+        file.write_key_f64("BZERO", 0.0, "")?;
+        file.write_key_str("OBJECT", &metafits_context.obs_name, "")?;
+        file.write_key_str("TELESCOP", "MWA", "")?;
+        file.write_key_str("INSTRUME", "MWA", "")?;
+        file.write_key_i64("OBSID", metafits_context.obs_id as i64, "Observation id")?;
+        file.write_key_str(
+            "DATE-OBS",
+            &first_utc.format("%Y-%m-%dT%H:%M:%S%.3f").to_string(),
+            "Start of the first timestep (UTC)",
+        )?;
+        file.write_key_f64("EPOCH", 2000.0, "")?;
+        file.write_key_f64(
+            "OBSRA",
+            ra_rad.to_degrees(),
+            "RA of the phase centre (J2000)",
+        )?;
+        file.write_key_f64(
+            "OBSDEC",
+            dec_rad.to_degrees(),
+            "Dec of the phase centre (J2000)",
+        )?;
+        file.write_key_str("SPECSYS", "TOPOCENT", "")?;
+        file.write_key_str(
+            "SOFTWARE",
+            &format!("mwalib {}", env!("CARGO_PKG_VERSION")),
+            "",
+        )?;
+
+        file.write_axis(2, "COMPLEX", 1.0, 1.0)?;
+        file.write_axis(3, "STOKES", first_pol_code as f64, pol_code_step as f64)?;
+        file.write_axis(
+            4,
+            "FREQ",
+            full_band.fine_chan_freqs_hz[first_fine_chan],
+            metafits_context.corr_fine_chan_width_hz as f64,
+        )?;
+        file.write_axis(5, "RA", ra_rad.to_degrees(), 1.0)?;
+        file.write_axis(6, "DEC", dec_rad.to_degrees(), 1.0)?;
+
+        file.write_group_param(1, "UU", 0.0)?;
+        file.write_group_param(2, "VV", 0.0)?;
+        file.write_group_param(3, "WW", 0.0)?;
+        file.write_group_param(4, "BASELINE", 0.0)?;
+        file.write_group_param(5, "DATE", jd_zero)?;
+
+        // Visibilities, one timestep and coarse channel at a time
+        for (timestep_position, (&timestep_index, &jd)) in selection
+            .timestep_indices
+            .iter()
+            .zip(timestep_jds.iter())
+            .enumerate()
+        {
+            let first_group = timestep_position * baselines.len() + 1;
+
+            let lmst = get_gmst_rad(jd + dut1 / 86_400.0) + MWALIB_MWA_LONGITUDE_RADIANS;
+            let (lmst_j2000, latitude_j2000) =
+                precess_zenith_to_j2000(lmst, MWALIB_MWA_LATITUDE_RADIANS, jd);
+            let tile_xyzs: Vec<[f64; 3]> = metafits_context
+                .antennas
+                .iter()
+                .map(|a| enh_to_xyz(a.east_m, a.north_m, a.height_m, latitude_j2000))
+                .collect();
+
+            let mut ws: Vec<f64> = Vec::with_capacity(baselines.len());
+            for (baseline_position, baseline) in baselines.iter().enumerate() {
+                let xyz1 = tile_xyzs[baseline.ant1_index];
+                let xyz2 = tile_xyzs[baseline.ant2_index];
+                let baseline_xyz = [xyz1[0] - xyz2[0], xyz1[1] - xyz2[1], xyz1[2] - xyz2[2]];
+                let uvw = xyz_to_uvw(&baseline_xyz, lmst_j2000 - ra_rad, dec_rad);
+                ws.push(get_phasing_w_m(
+                    &baseline_xyz,
+                    &uvw,
+                    latitude_j2000,
+                    metafits_context.geometric_delays_applied,
+                ));
+
+                let mut params = [
+                    (uvw[0] / MWALIB_SPEED_OF_LIGHT_IN_VACUUM_M_PER_S) as f32,
+                    (uvw[1] / MWALIB_SPEED_OF_LIGHT_IN_VACUUM_M_PER_S) as f32,
+                    (uvw[2] / MWALIB_SPEED_OF_LIGHT_IN_VACUUM_M_PER_S) as f32,
+                    encode_uvfits_baseline(baseline.ant1_index + 1, baseline.ant2_index + 1),
+                    (jd - jd_zero) as f32,
+                ];
+                file.write_group_params(first_group + baseline_position, &mut params)?;
+            }
+
+            for (slot, coarse_chan_index) in full_band.slot_coarse_chan_indices.iter().enumerate() {
+                // The fine channels of this slot which are on the frequency axis
+                let slot_first_fine_chan = if slot == 0 { first_fine_chan } else { 0 };
+                let slot_last_fine_chan = if slot == full_band.num_slots() - 1 {
+                    last_fine_chan
+                } else {
+                    num_fine_chans_per_coarse - 1
+                };
+                let num_slot_chans = slot_last_fine_chan - slot_first_fine_chan + 1;
+                let first_element = (slot * num_fine_chans_per_coarse + slot_first_fine_chan
+                    - first_fine_chan)
+                    * floats_per_chan
+                    + 1;
+
+                let mut chunk: Vec<f32> = vec![0.; num_slot_chans * floats_per_chan];
+
+                let coarse_chan_index = match coarse_chan_index {
+                    Some(c) => *c,
+                    None => {
+                        // Gaps have no data and a weight of 0
+                        for baseline_position in 0..baselines.len() {
+                            file.write_group_data(
+                                first_group + baseline_position,
+                                first_element,
+                                &mut chunk,
+                            )?;
+                        }
+                        continue;
+                    }
+                };
+
+                let has_data = self.has_data(timestep_index, coarse_chan_index);
+                let data: Vec<f32> = match has_data {
+                    true => self.read_by_baseline_subset(
+                        timestep_index,
+                        coarse_chan_index,
+                        &selection.baseline_indices,
+                        &fine_chan_indices,
+                    )?,
+                    false => vec![],
+                };
+                let flags: Vec<bool> =
+                    match has_data && config.flag_config != CorrelatorFlagConfig::default() {
+                        true => self.get_flags(
+                            &[timestep_index],
+                            &[coarse_chan_index],
+                            &config.flag_config,
+                        )?,
+                        false => vec![],
+                    };
+                let freqs_hz = &full_band.fine_chan_freqs_hz
+                    [slot * num_fine_chans_per_coarse..(slot + 1) * num_fine_chans_per_coarse];
+
+                for (baseline_position, &baseline_index) in
+                    selection.baseline_indices.iter().enumerate()
+                {
+                    chunk.fill(0.);
+
+                    for (position, &fine_chan) in fine_chan_indices.iter().enumerate() {
+                        if fine_chan < slot_first_fine_chan || fine_chan > slot_last_fine_chan {
+                            continue;
+                        }
+                        let chan_start = (fine_chan - slot_first_fine_chan) * floats_per_chan;
+
+                        if !has_data {
+                            for pol_position in 0..num_pols {
+                                chunk[chan_start + pol_position * 3 + 2] = -1.;
+                            }
+                            continue;
+                        }
+
+                        let flagged = !flags.is_empty()
+                            && flags[baseline_index * num_fine_chans_per_coarse + fine_chan];
+                        let gain = config.passband_gains.as_ref().map_or(1.0, |g| g[fine_chan]);
+                        let vis_start =
+                            (baseline_position * fine_chan_indices.len() + position) * 8;
+
+                        for (pol_position, &pol) in pols.iter().enumerate() {
+                            let angle = -2.0
+                                * std::f64::consts::PI
+                                * freqs_hz[fine_chan]
+                                * (cable_length_diffs[baseline_position][pol]
+                                    + ws[baseline_position])
+                                / MWALIB_SPEED_OF_LIGHT_IN_VACUUM_M_PER_S;
+                            let (sin, cos) = angle.sin_cos();
+                            let vis = Complex::new(
+                                data[vis_start + pol * 2] as f64,
+                                data[vis_start + pol * 2 + 1] as f64,
+                            ) * Complex::new(cos, sin)
+                                / gain;
+
+                            let out = chan_start + pol_position * 3;
+                            chunk[out] = vis.re as f32;
+                            chunk[out + 1] = vis.im as f32;
+                            chunk[out + 2] = if flagged { -1. } else { 1. };
+                        }
+                    }
+
+                    file.write_group_data(
+                        first_group + baseline_position,
+                        first_element,
+                        &mut chunk,
+                    )?;
+                }
+            }
+        }
+
+        self.write_uvfits_antenna_table(
+            &mut file,
+            jd_zero,
+            &first_utc,
+            dut1,
+            &full_band,
+            first_fine_chan,
+        )?;
+
+        Ok(())
+    }
+
+    /// Write the `AIPS AN` (antenna) table of a UVFITS file.
+    fn write_uvfits_antenna_table(
+        &self,
+        file: &mut UvfitsFile,
+        jd_zero: f64,
+        first_utc: &chrono::DateTime<chrono::FixedOffset>,
+        dut1: f64,
+        full_band: &FullBand,
+        first_fine_chan: usize,
+    ) -> Result<(), UvfitsError> {
+        let metafits_context = &self.metafits_context;
+        let num_ants = metafits_context.num_ants;
+
+        let columns: [(&str, &str, &str); 9] = [
+            ("ANNAME", "8A", ""),
+            ("STABXYZ", "3D", "METERS"),
+            ("NOSTA", "1J", ""),
+            ("MNTSTA", "1J", ""),
+            ("STAXOF", "1E", "METERS"),
+            ("POLTYA", "1A", ""),
+            ("POLAA", "1E", "DEGREES"),
+            ("POLTYB", "1A", ""),
+            ("POLAB", "1E", "DEGREES"),
+        ];
+        let ttypes: Vec<CString> = columns.iter().map(|c| c_string(c.0)).collect();
+        let tforms: Vec<CString> = columns.iter().map(|c| c_string(c.1)).collect();
+        let tunits: Vec<CString> = columns.iter().map(|c| c_string(c.2)).collect();
+        let mut ttype_ptrs: Vec<*mut c_char> =
+            ttypes.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+        let mut tform_ptrs: Vec<*mut c_char> =
+            tforms.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+        let mut tunit_ptrs: Vec<*mut c_char> =
+            tunits.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+        let extname = c_string("AIPS AN");
+
+        let mut status = 0;
+        unsafe {
+            fitsio_sys::ffcrtb(
+                file.fptr,
+                fitsio_sys::BINARY_TBL as c_int,
+                num_ants as i64,
+                columns.len() as c_int,
+                ttype_ptrs.as_mut_ptr(),
+                tform_ptrs.as_mut_ptr(),
+                tunit_ptrs.as_mut_ptr(),
+                extname.as_ptr(),
+                &mut status,
+            );
+        }
+        file.check(status)?;
+
+        let array_xyz = geodetic_to_geocentric_xyz(
+            MWALIB_MWA_LATITUDE_RADIANS,
+            MWALIB_MWA_LONGITUDE_RADIANS,
+            MWALIB_MWA_ALTITUDE_METRES,
+        );
+        // GPS - UTC is the number of leap seconds since 1980, and TAI is 19 seconds ahead of GPS
+        let first_timestep = &self.timesteps[0];
+        let gps_minus_utc_ms = first_timestep.gps_time_ms as i64 + 315_964_800_000
+            - first_timestep.unix_time_ms as i64;
+
+        file.write_key_f64("ARRAYX", array_xyz[0], "Array centre (ITRF)")?;
+        file.write_key_f64("ARRAYY", array_xyz[1], "")?;
+        file.write_key_f64("ARRAYZ", array_xyz[2], "")?;
+        file.write_key_f64(
+            "GSTIA0",
+            get_gmst_rad(jd_zero).to_degrees(),
+            "GMST at 0h on RDATE",
+        )?;
+        file.write_key_f64("DEGPDY", 360.985_647_4, "Earth rotation rate (degrees/day)")?;
+        file.write_key_f64(
+            "FREQ",
+            full_band.fine_chan_freqs_hz[first_fine_chan],
+            "Reference frequency",
+        )?;
+        file.write_key_str(
+            "RDATE",
+            &first_utc.format("%Y-%m-%d").to_string(),
+            "Reference date",
+        )?;
+        file.write_key_f64("POLARX", 0.0, "")?;
+        file.write_key_f64("POLARY", 0.0, "")?;
+        file.write_key_f64("UT1UTC", dut1, "")?;
+        file.write_key_f64("DATUTC", 0.0, "")?;
+        file.write_key_str("TIMSYS", "UTC", "")?;
+        file.write_key_str("ARRNAM", "MWA", "")?;
+        file.write_key_str("XYZHAND", "RIGHT", "")?;
+        file.write_key_str("FRAME", "ITRF", "")?;
+        file.write_key_i64("NUMORB", 0, "")?;
+        file.write_key_i64("NOPCAL", 0, "")?;
+        file.write_key_str("POLTYPE", "X-Y LIN", "")?;
+        file.write_key_f64("IATUTC", (gps_minus_utc_ms / 1000 + 19) as f64, "")?;
+
+        let names: Vec<CString> = metafits_context
+            .antennas
+            .iter()
+            .map(|a| c_string(&a.tile_name))
+            .collect();
+        let mut name_ptrs: Vec<*mut c_char> =
+            names.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+        file.write_col(1, fitsio_sys::TSTRING, &mut name_ptrs)?;
+
+        let mut xyzs: Vec<f64> = metafits_context
+            .antennas
+            .iter()
+            .flat_map(|a| enh_to_xyz(a.east_m, a.north_m, a.height_m, MWALIB_MWA_LATITUDE_RADIANS))
+            .collect();
+        file.write_col(2, fitsio_sys::TDOUBLE, &mut xyzs)?;
+
+        let mut numbers: Vec<c_int> = (1..=num_ants as c_int).collect();
+        file.write_col(3, fitsio_sys::TINT, &mut numbers)?;
+        file.write_col(4, fitsio_sys::TINT, &mut vec![0 as c_int; num_ants])?;
+        file.write_col(5, fitsio_sys::TFLOAT, &mut vec![0f32; num_ants])?;
+
+        let (x, y) = (c_string("X"), c_string("Y"));
+        file.write_col(
+            6,
+            fitsio_sys::TSTRING,
+            &mut vec![x.as_ptr() as *mut c_char; num_ants],
+        )?;
+        file.write_col(7, fitsio_sys::TFLOAT, &mut vec![0f32; num_ants])?;
+        file.write_col(
+            8,
+            fitsio_sys::TSTRING,
+            &mut vec![y.as_ptr() as *mut c_char; num_ants],
+        )?;
+        file.write_col(9, fitsio_sys::TFLOAT, &mut vec![90f32; num_ants])?;
+
+        Ok(())
+    }
+}
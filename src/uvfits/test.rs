@@ -0,0 +1,268 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Unit tests for writing UVFITS files
+
+use super::*;
+use fitsio::FitsFile;
+use float_cmp::*;
+
+fn get_mwax_context() -> CorrelatorContext {
+    CorrelatorContext::new(
+        "test_files/1244973688_1_timestep/1244973688.metafits",
+        &["test_files/1244973688_1_timestep/1244973688_20190619100110_ch114_000.fits"],
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_get_gmst_rad_at_j2000() {
+    // GMST at 2000-01-01 12:00 UT1 is 18h41m50.54841s
+    assert!(approx_eq!(
+        f64,
+        get_gmst_rad(2_451_545.0).to_degrees(),
+        280.460_618_375,
+        epsilon = 1e-6
+    ));
+}
+
+#[test]
+fn test_precession_is_identity_at_j2000() {
+    let (lmst, lat) = precess_zenith_to_j2000(1.0, MWALIB_MWA_LATITUDE_RADIANS, 2_451_545.0);
+    assert!(approx_eq!(f64, lmst, 1.0, epsilon = 1e-12));
+    assert!(approx_eq!(
+        f64,
+        lat,
+        MWALIB_MWA_LATITUDE_RADIANS,
+        epsilon = 1e-12
+    ));
+
+    // ~20 years of precession moves the pole by ~0.1 degrees
+    let (_, lat) = precess_zenith_to_j2000(1.0, MWALIB_MWA_LATITUDE_RADIANS, 2_458_850.0);
+    let moved = (lat - MWALIB_MWA_LATITUDE_RADIANS).abs().to_degrees();
+    assert!(moved > 0.0 && moved < 0.12, "moved {} degrees", moved);
+}
+
+#[test]
+fn test_uvw_at_zenith() {
+    let lat = MWALIB_MWA_LATITUDE_RADIANS;
+
+    // Phased to zenith, an east baseline is all U and an up baseline is all W
+    let uvw = xyz_to_uvw(&enh_to_xyz(100.0, 0.0, 0.0, lat), 0.0, lat);
+    assert!(approx_eq!(f64, uvw[0], 100.0, epsilon = 1e-9));
+    assert!(approx_eq!(f64, uvw[1], 0.0, epsilon = 1e-9));
+    assert!(approx_eq!(f64, uvw[2], 0.0, epsilon = 1e-9));
+
+    let uvw = xyz_to_uvw(&enh_to_xyz(0.0, 0.0, 10.0, lat), 0.0, lat);
+    assert!(approx_eq!(f64, uvw[0], 0.0, epsilon = 1e-9));
+    assert!(approx_eq!(f64, uvw[1], 0.0, epsilon = 1e-9));
+    assert!(approx_eq!(f64, uvw[2], 10.0, epsilon = 1e-9));
+
+    // ... and a north baseline is all V
+    let uvw = xyz_to_uvw(&enh_to_xyz(0.0, 50.0, 0.0, lat), 0.0, lat);
+    assert!(approx_eq!(f64, uvw[1], 50.0, epsilon = 1e-9));
+}
+
+#[test]
+fn test_geodetic_to_geocentric_xyz() {
+    let xyz = geodetic_to_geocentric_xyz(
+        MWALIB_MWA_LATITUDE_RADIANS,
+        MWALIB_MWA_LONGITUDE_RADIANS,
+        MWALIB_MWA_ALTITUDE_METRES,
+    );
+    // The MWA's ITRF position
+    assert!(approx_eq!(f64, xyz[0], -2_559_454.08, epsilon = 1.0));
+    assert!(approx_eq!(f64, xyz[1], 5_095_372.14, epsilon = 1.0));
+    assert!(approx_eq!(f64, xyz[2], -2_849_057.18, epsilon = 1.0));
+}
+
+#[test]
+fn test_encode_uvfits_baseline() {
+    assert_eq!(encode_uvfits_baseline(1, 1), 257.0);
+    assert_eq!(encode_uvfits_baseline(1, 128), 384.0);
+    assert_eq!(encode_uvfits_baseline(1, 256), 67_840.0);
+}
+
+#[test]
+fn test_get_uvfits_pols() {
+    assert_eq!(
+        get_uvfits_pols(&[0, 1, 2, 3]).unwrap(),
+        (vec![0, 3, 1, 2], -5, -1)
+    );
+    assert_eq!(get_uvfits_pols(&[3, 0]).unwrap(), (vec![0, 3], -5, -1));
+    assert_eq!(get_uvfits_pols(&[1]).unwrap(), (vec![1], -7, -1));
+    assert_eq!(get_uvfits_pols(&[0, 1]).unwrap(), (vec![0, 1], -5, -2));
+
+    assert!(matches!(
+        get_uvfits_pols(&[0, 1, 2]),
+        Err(UvfitsError::IrregularPols(_))
+    ));
+    assert!(matches!(
+        get_uvfits_pols(&[]),
+        Err(UvfitsError::EmptySelection(_))
+    ));
+}
+
+#[test]
+fn test_uvfits_write_config() {
+    let config = UvfitsWriteConfig::new();
+    assert_eq!(config, UvfitsWriteConfig::default());
+    assert!(!config.apply_cable_delays);
+    assert!(config.passband_gains.is_none());
+
+    let config = UvfitsWriteConfig::new()
+        .with_cable_delays(true)
+        .with_passband_gains(vec![1.0; 32]);
+    assert!(config.apply_cable_delays);
+    assert_eq!(config.passband_gains.unwrap().len(), 32);
+}
+
+#[test]
+fn test_get_phasing_w_m() {
+    let lat = MWALIB_MWA_LATITUDE_RADIANS;
+    let baseline_xyz = enh_to_xyz(30.0, 40.0, 5.0, lat);
+
+    // Visibilities phased to the zenith need no rotation to phase them to the zenith, whatever the height difference
+    let zenith_uvw = xyz_to_uvw(&baseline_xyz, 0.0, lat);
+    for applied in [GeometricDelaysApplied::No, GeometricDelaysApplied::Zenith] {
+        assert!(approx_eq!(
+            f64,
+            get_phasing_w_m(&baseline_xyz, &zenith_uvw, lat, applied),
+            0.0,
+            epsilon = 1e-9
+        ));
+    }
+
+    // Anywhere else, they are rotated by the W of the phase centre less the height difference
+    let uvw = xyz_to_uvw(&baseline_xyz, 0.3, lat - 0.2);
+    assert!(approx_eq!(
+        f64,
+        get_phasing_w_m(&baseline_xyz, &uvw, lat, GeometricDelaysApplied::No),
+        uvw[2] - 5.0,
+        epsilon = 1e-9
+    ));
+
+    // The correlator already phased them
+    for applied in [
+        GeometricDelaysApplied::TilePointing,
+        GeometricDelaysApplied::AzElTracking,
+    ] {
+        assert_eq!(get_phasing_w_m(&baseline_xyz, &uvw, lat, applied), 0.0);
+    }
+}
+
+#[test]
+fn test_lst_matches_metafits() {
+    // The metafits LST is for the scheduled start
+    for metafits_filename in [
+        "test_files/1244973688_1_timestep/1244973688.metafits",
+        "test_files/1101503312_1_timestep/1101503312.metafits",
+    ] {
+        let context = MetafitsContext::new(metafits_filename, None).unwrap();
+        let jd = unix_time_ms_to_jd(context.sched_start_unix_time_ms as f64);
+        let lst = (get_gmst_rad(jd + context.dut1.unwrap_or(0.0) / 86_400.0)
+            + MWALIB_MWA_LONGITUDE_RADIANS)
+            .to_degrees()
+            .rem_euclid(360.0);
+
+        assert!(
+            approx_eq!(f64, lst, context.lst_deg, epsilon = 0.01),
+            "{}: {} vs {}",
+            metafits_filename,
+            lst,
+            context.lst_deg
+        );
+    }
+}
+
+#[test]
+fn test_write_uvfits_mwax() {
+    let context = get_mwax_context();
+    let selection = Selection {
+        timestep_indices: vec![0, 1],
+        coarse_chan_indices: vec![10],
+        fine_chan_indices: vec![0, 1, 2, 3],
+        antenna_indices: (0..context.metafits_context.num_ants).collect(),
+        baseline_indices: vec![0, 1, 2],
+        pol_indices: vec![0, 3],
+    };
+
+    let temp_dir = tempdir::TempDir::new("uvfits_test").unwrap();
+    let filename = temp_dir.path().join("test.uvfits");
+    context
+        .write_uvfits(
+            &filename,
+            &selection,
+            &UvfitsWriteConfig::new().with_cable_delays(true),
+        )
+        .unwrap();
+
+    let mut fptr = FitsFile::open(&filename).unwrap();
+    let hdu = fptr.primary_hdu().unwrap();
+    let gcount: i64 = hdu.read_key(&mut fptr, "GCOUNT").unwrap();
+    let naxis3: i64 = hdu.read_key(&mut fptr, "NAXIS3").unwrap();
+    let naxis4: i64 = hdu.read_key(&mut fptr, "NAXIS4").unwrap();
+    let crval3: f64 = hdu.read_key(&mut fptr, "CRVAL3").unwrap();
+    let cdelt3: f64 = hdu.read_key(&mut fptr, "CDELT3").unwrap();
+    let crval4: f64 = hdu.read_key(&mut fptr, "CRVAL4").unwrap();
+    let ptype4: String = hdu.read_key(&mut fptr, "PTYPE4").unwrap();
+    assert_eq!(gcount, 6);
+    assert_eq!(naxis3, 2);
+    assert_eq!(naxis4, 4);
+    assert_eq!(crval3, -5.0);
+    assert_eq!(cdelt3, -1.0);
+    assert_eq!(ptype4, "BASELINE");
+    assert!(approx_eq!(
+        f64,
+        crval4,
+        context.get_fine_chan_freqs_hz_array(&[10])[0],
+        epsilon = 1e-3
+    ));
+
+    let hdu = fptr.hdu("AIPS AN").unwrap();
+    let names: Vec<String> = hdu.read_col(&mut fptr, "ANNAME").unwrap();
+    let numbers: Vec<i32> = hdu.read_col(&mut fptr, "NOSTA").unwrap();
+    assert_eq!(names.len(), context.metafits_context.num_ants);
+    assert_eq!(names[0], context.metafits_context.antennas[0].tile_name);
+    assert_eq!(numbers[0], 1);
+    let poltype: String = hdu.read_key(&mut fptr, "POLTYPE").unwrap();
+    assert_eq!(poltype, "X-Y LIN");
+}
+
+#[test]
+fn test_write_uvfits_invalid() {
+    let context = get_mwax_context();
+    let mut selection = Selection {
+        timestep_indices: vec![0],
+        coarse_chan_indices: vec![10],
+        fine_chan_indices: vec![0],
+        antenna_indices: vec![0],
+        baseline_indices: vec![0],
+        pol_indices: vec![0, 1, 2],
+    };
+    let temp_dir = tempdir::TempDir::new("uvfits_test").unwrap();
+    let filename = temp_dir.path().join("test.uvfits");
+    let config = UvfitsWriteConfig::new();
+
+    assert!(matches!(
+        context.write_uvfits(&filename, &selection, &config),
+        Err(UvfitsError::IrregularPols(_))
+    ));
+
+    selection.pol_indices = vec![0];
+    assert!(matches!(
+        context.write_uvfits(
+            &filename,
+            &selection,
+            &UvfitsWriteConfig::new().with_passband_gains(vec![1.0; 3])
+        ),
+        Err(UvfitsError::InvalidPassbandLength { .. })
+    ));
+
+    selection.baseline_indices = vec![];
+    assert!(matches!(
+        context.write_uvfits(&filename, &selection, &config),
+        Err(UvfitsError::EmptySelection(_))
+    ));
+}
@@ -0,0 +1,466 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Van Vleck correction of correlator visibilities.
+//!
+//! The correlator multiplies (and sums) samples which have been quantised to a small number of
+//! integer levels. Quantisation biases the resulting visibilities: the autocorrelations are
+//! over-estimated at low power and clipped at high power, and the cross-correlations are
+//! scaled by a power-dependent gain. This module models the quantiser as rounding to the nearest
+//! integer, clipped to `±max_level`, applied to each of the real and imaginary parts of a complex,
+//! zero mean Gaussian signal.
+//!
+//! The legacy correlator's input is 4 bit (real) + 4 bit (imaginary) samples from the PFB (Ord et
+//! al. 2015, PASA 32, e006), i.e. `max_level` = 7. This is the same model as the Van Vleck
+//! correction in pyuvdata's MWA correlator FITS reader (`mwa_corr_fits.py`). The MWAX correlator
+//! input is not modelled, so only legacy data can be corrected.
+//!
+//! For an input with (true) standard deviation `σ` (in units of the quantiser step) the
+//! expected variance of the quantised output is:
+//!
+//! ```text
+//! σ̂² = Σ_{j=0}^{max_level-1} (2j + 1) erfc((j + ½) / (σ√2))
+//! ```
+//!
+//! Autocorrelations are corrected by measuring `σ̂` and inverting the above for `σ`.
+//!
+//! For two inputs with (true) covariance `C`, Price's theorem gives the derivative of the
+//! quantised covariance `κ̂` with respect to `C` as the sum of the bivariate normal density
+//! evaluated at every pair of quantiser thresholds `(±(j + ½), ±(k + ½))`. `κ̂` is found by
+//! integrating this from `C = 0` (see `van_vleck_quantised_covariance`) and can be inverted
+//! numerically (see `van_vleck_correlation`). To first order in the correlation coefficient
+//! (which, for MWA cross-correlations, is always tiny) this reduces to `κ̂ = C g(σ_x) g(σ_y)`,
+//! where `g` is given by `van_vleck_cross_gain`. This first order form is what is applied to
+//! the cross-correlations when reading data.
+//!
+//! The visibilities in a correlator HDU are sums over `N = corr_int_time * corr_fine_chan_width`
+//! complex samples, so each of the real/imaginary products contributes `2N` samples.
+
+use crate::baseline::Baseline;
+use std::f64::consts::PI;
+
+#[cfg(test)]
+mod test;
+
+/// The maximum quantiser level of the legacy correlator input (4 bit samples).
+pub const MWA_LEGACY_VAN_VLECK_MAX_LEVEL: u32 = 7;
+
+/// Maximum iterations allowed when numerically inverting any of the functions in this module.
+const MAX_ITERATIONS: usize = 100;
+/// Relative tolerance used to decide when numerical inversion has converged.
+const TOLERANCE: f64 = 1e-12;
+/// Number of (Simpson's rule) intervals used to integrate the quantised covariance.
+const NUM_INTEGRATION_INTERVALS: usize = 256;
+/// Largest correlation coefficient considered (the integrand is undefined at exactly 1).
+const MAX_RHO: f64 = 1.0 - 1e-12;
+
+/// Complementary error function.
+///
+/// Rust's standard library does not provide this, so we use the Taylor series of `erf` for small
+/// arguments and a continued fraction for large arguments. Both are accurate to ~1e-15.
+///
+/// # Arguments
+///
+/// * `x` - value to evaluate erfc at.
+///
+///
+/// # Returns
+///
+/// * erfc(x)
+///
+pub(crate) fn erfc(x: f64) -> f64 {
+    if x < 0.0 {
+        return 2.0 - erfc(-x);
+    }
+
+    if x < 1.0 {
+        // erf(x) = 2/sqrt(pi) * sum_n (-1)^n x^(2n+1) / (n! (2n+1))
+        let x2 = x * x;
+        let mut term = x;
+        let mut sum = x;
+        let mut n = 0.0;
+        while term.abs() > 1e-17 * sum.abs() {
+            n += 1.0;
+            term *= -x2 / n;
+            sum += term / (2.0 * n + 1.0);
+        }
+        1.0 - (2.0 / PI.sqrt()) * sum
+    } else {
+        // erfc(x) = exp(-x^2)/sqrt(pi) * 1/(x + (1/2)/(x + 1/(x + (3/2)/(x + 2/(x + ...)))))
+        // evaluated from the tail backwards
+        let mut fraction = x;
+        for k in (1..=200).rev() {
+            fraction = x + (k as f64 / 2.0) / fraction;
+        }
+        (-x * x).exp() / (PI.sqrt() * fraction)
+    }
+}
+
+/// Given the true standard deviation of a Gaussian input, return the expected standard
+/// deviation of the quantised output.
+///
+/// # Arguments
+///
+/// * `sigma` - standard deviation of the unquantised input, in units of the quantiser step.
+///
+/// * `max_level` - the largest (absolute) level the quantiser can output.
+///
+///
+/// # Returns
+///
+/// * The expected standard deviation of the quantised output (σ̂).
+///
+pub fn van_vleck_sighat(sigma: f64, max_level: u32) -> f64 {
+    if sigma <= 0.0 {
+        return 0.0;
+    }
+
+    (0..max_level)
+        .map(|j| {
+            let threshold = j as f64 + 0.5;
+            (2.0 * j as f64 + 1.0) * erfc(threshold / (sigma * 2f64.sqrt()))
+        })
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Derivative with respect to `sigma` of `van_vleck_sighat(sigma)` squared.
+fn van_vleck_sighat_squared_derivative(sigma: f64, max_level: u32) -> f64 {
+    (0..max_level)
+        .map(|j| {
+            let threshold = j as f64 + 0.5;
+            (2.0 * j as f64 + 1.0)
+                * (2.0 / PI).sqrt()
+                * (threshold / (sigma * sigma))
+                * (-(threshold * threshold) / (2.0 * sigma * sigma)).exp()
+        })
+        .sum()
+}
+
+/// Given the measured standard deviation of the quantised output, return the standard
+/// deviation of the (unquantised) input. This is the inverse of `van_vleck_sighat`.
+///
+/// # Arguments
+///
+/// * `sighat` - the measured standard deviation of the quantised output, in units of the quantiser step.
+///
+/// * `max_level` - the largest (absolute) level the quantiser can output.
+///
+///
+/// # Returns
+///
+/// * `Some(sigma)`, or `None` if `sighat` is not positive or the quantiser is saturated
+///   (`sighat` >= `max_level`), in which case it cannot be inverted.
+///
+pub fn van_vleck_sigma(sighat: f64, max_level: u32) -> Option<f64> {
+    if !(sighat > 0.0 && sighat < max_level as f64) {
+        return None;
+    }
+
+    // sighat(sigma) is monotonic, so bracket the solution and use a Newton iteration which falls
+    // back to bisection whenever the Newton step would leave the bracket.
+    let target = sighat * sighat;
+    let mut low = 0.0;
+    let mut high = sighat.max(1.0);
+    while van_vleck_sighat(high, max_level) < sighat {
+        low = high;
+        high *= 2.0;
+        if high > 1e6 {
+            return None;
+        }
+    }
+
+    let mut sigma = sighat.clamp(low, high);
+    for _ in 0..MAX_ITERATIONS {
+        let value = van_vleck_sighat(sigma, max_level).powi(2) - target;
+        if value > 0.0 {
+            high = sigma;
+        } else {
+            low = sigma;
+        }
+
+        let derivative = van_vleck_sighat_squared_derivative(sigma, max_level);
+        let mut next = sigma - value / derivative;
+        if !(next > low && next < high) {
+            next = (low + high) / 2.0;
+        }
+
+        if (next - sigma).abs() <= TOLERANCE * sigma {
+            return Some(next);
+        }
+        sigma = next;
+    }
+
+    Some(sigma)
+}
+
+/// The first order gain applied to cross-correlations by the quantiser, i.e. the expectation
+/// of the derivative of the quantiser transfer function for a Gaussian input.
+///
+/// # Arguments
+///
+/// * `sigma` - standard deviation of the unquantised input, in units of the quantiser step.
+///
+/// * `max_level` - the largest (absolute) level the quantiser can output.
+///
+///
+/// # Returns
+///
+/// * `g(sigma)`, such that for small correlations `κ̂ = C g(σ_x) g(σ_y)`.
+///
+pub fn van_vleck_cross_gain(sigma: f64, max_level: u32) -> f64 {
+    if sigma <= 0.0 {
+        return 0.0;
+    }
+
+    (0..max_level)
+        .map(|j| {
+            let threshold = j as f64 + 0.5;
+            2.0 * (-(threshold * threshold) / (2.0 * sigma * sigma)).exp()
+                / (sigma * (2.0 * PI).sqrt())
+        })
+        .sum()
+}
+
+/// Sum of the bivariate normal density (with standard deviations `sigma_x`, `sigma_y` and
+/// correlation coefficient `rho`) over every pair of quantiser thresholds, multiplied by
+/// `sqrt(1 - rho^2)` to remove the singularity as `rho` approaches 1.
+fn scaled_threshold_density_sum(rho: f64, sigma_x: f64, sigma_y: f64, max_level: u32) -> f64 {
+    let one_minus_rho2 = 1.0 - rho * rho;
+    let norm = 1.0 / (2.0 * PI * sigma_x * sigma_y);
+    let mut sum = 0.0;
+
+    for j in 0..max_level {
+        let tx = (j as f64 + 0.5) / sigma_x;
+        for k in 0..max_level {
+            let ty = (k as f64 + 0.5) / sigma_y;
+            // Thresholds with the same sign (++ and --) and opposite sign (+- and -+)
+            let same = (-(tx * tx - 2.0 * rho * tx * ty + ty * ty) / (2.0 * one_minus_rho2)).exp();
+            let opposite =
+                (-(tx * tx + 2.0 * rho * tx * ty + ty * ty) / (2.0 * one_minus_rho2)).exp();
+            sum += 2.0 * (same + opposite);
+        }
+    }
+
+    norm * sum
+}
+
+/// Given the true correlation coefficient of two Gaussian inputs, return the expected covariance
+/// of the quantised outputs. This is the full (non-linear) form of the cross-correlation bias.
+///
+/// # Arguments
+///
+/// * `rho` - correlation coefficient of the unquantised inputs (-1 < rho < 1).
+///
+/// * `sigma_x` - standard deviation of the first unquantised input, in units of the quantiser step.
+///
+/// * `sigma_y` - standard deviation of the second unquantised input, in units of the quantiser step.
+///
+/// * `max_level` - the largest (absolute) level the quantiser can output.
+///
+///
+/// # Returns
+///
+/// * The expected covariance of the quantised outputs (κ̂).
+///
+pub fn van_vleck_quantised_covariance(rho: f64, sigma_x: f64, sigma_y: f64, max_level: u32) -> f64 {
+    if rho == 0.0 || sigma_x <= 0.0 || sigma_y <= 0.0 {
+        return 0.0;
+    }
+
+    // Price's theorem: d(κ̂)/d(C) = Σ φ(t_x, t_y; C). With C = sin(θ) σx σy, integrate over θ
+    // using Simpson's rule. The substitution cancels the 1/sqrt(1 - rho^2) in the density, which
+    // keeps the integrand smooth all the way to |rho| = 1.
+    let rho = rho.clamp(-MAX_RHO, MAX_RHO);
+    let theta_end = rho.asin();
+    let h = theta_end / NUM_INTEGRATION_INTERVALS as f64;
+    let integrand =
+        |theta: f64| scaled_threshold_density_sum(theta.sin(), sigma_x, sigma_y, max_level);
+
+    let mut sum = integrand(0.0) + integrand(theta_end);
+    for i in 1..NUM_INTEGRATION_INTERVALS {
+        let weight = if i % 2 == 1 { 4.0 } else { 2.0 };
+        sum += weight * integrand(i as f64 * h);
+    }
+
+    sigma_x * sigma_y * sum * h / 3.0
+}
+
+/// Given the measured covariance of two quantised outputs, return the correlation coefficient
+/// of the (unquantised) inputs. This is the inverse of `van_vleck_quantised_covariance`.
+///
+/// # Arguments
+///
+/// * `kappa_hat` - measured covariance of the quantised outputs.
+///
+/// * `sigma_x` - standard deviation of the first unquantised input, in units of the quantiser step.
+///
+/// * `sigma_y` - standard deviation of the second unquantised input, in units of the quantiser step.
+///
+/// * `max_level` - the largest (absolute) level the quantiser can output.
+///
+///
+/// # Returns
+///
+/// * `Some(rho)`, or `None` if the inputs are invalid or `kappa_hat` is larger than any
+///   correlation could produce.
+///
+pub fn van_vleck_correlation(
+    kappa_hat: f64,
+    sigma_x: f64,
+    sigma_y: f64,
+    max_level: u32,
+) -> Option<f64> {
+    if sigma_x <= 0.0 || sigma_y <= 0.0 {
+        return None;
+    }
+    if kappa_hat == 0.0 {
+        return Some(0.0);
+    }
+
+    // Solve for |rho| and restore the sign at the end, as κ̂(rho) is odd in rho
+    let target = kappa_hat.abs();
+    if van_vleck_quantised_covariance(MAX_RHO, sigma_x, sigma_y, max_level) < target {
+        return None;
+    }
+
+    // Start from the first order solution
+    let gain = van_vleck_cross_gain(sigma_x, max_level) * van_vleck_cross_gain(sigma_y, max_level);
+    let mut low = 0.0;
+    let mut high = MAX_RHO;
+    let mut rho = (target / (gain * sigma_x * sigma_y)).clamp(low, high);
+
+    for _ in 0..MAX_ITERATIONS {
+        let value = van_vleck_quantised_covariance(rho, sigma_x, sigma_y, max_level) - target;
+        if value > 0.0 {
+            high = rho;
+        } else {
+            low = rho;
+        }
+
+        let derivative =
+            sigma_x * sigma_y * scaled_threshold_density_sum(rho, sigma_x, sigma_y, max_level)
+                / (1.0 - rho * rho).sqrt();
+        let mut next = rho - value / derivative;
+        if !(next > low && next < high) {
+            next = (low + high) / 2.0;
+        }
+
+        if (next - rho).abs() <= TOLERANCE * rho {
+            rho = next;
+            break;
+        }
+        rho = next;
+    }
+
+    Some(rho.copysign(kappa_hat))
+}
+
+/// Apply the Van Vleck correction, in place, to one timestep and coarse channel of visibilities
+/// in MWAX order (i.e. after any legacy conversion).
+///
+/// Autocorrelations (the real parts of XX and YY for baselines where ant1 == ant2) are corrected
+/// with the inverse of `van_vleck_sighat`. All other products are scaled by the inverse of the
+/// first order gain, `van_vleck_cross_gain`, of each of the two inputs. Any visibility involving an
+/// input whose autocorrelation is zero, negative or saturated is left as is.
+///
+/// # Arguments
+///
+/// * `buffer` - Float buffer as a slice containing one timestep and coarse channel of visibilities.
+///
+/// * `baselines` - the baselines of the observation, in the same order as the buffer.
+///
+/// * `num_ants` - the number of antennas in the observation.
+///
+/// * `num_fine_chans` - the number of fine channels per coarse channel.
+///
+/// * `by_frequency` - true if the buffer is in [frequency][baseline][pol][r][i] order, false if
+///   it is in [baseline][frequency][pol][r][i] order.
+///
+/// * `num_samples` - the number of complex samples summed into each visibility (integration time * fine channel width).
+///
+/// * `max_level` - the largest (absolute) level the correlator input quantiser can output.
+///
+///
+/// # Returns
+///
+/// * Nothing
+///
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn apply_van_vleck_correction(
+    buffer: &mut [f32],
+    baselines: &[Baseline],
+    num_ants: usize,
+    num_fine_chans: usize,
+    by_frequency: bool,
+    num_samples: f64,
+    max_level: u32,
+) {
+    // xx_r,xx_i,xy_r,xy_i,yx_r,yx_i,yy_r,yy_i
+    let floats_per_baseline_fine_chan = 8;
+    let (baseline_stride, fine_chan_stride) = if by_frequency {
+        (
+            floats_per_baseline_fine_chan,
+            floats_per_baseline_fine_chan * baselines.len(),
+        )
+    } else {
+        (
+            floats_per_baseline_fine_chan * num_fine_chans,
+            floats_per_baseline_fine_chan,
+        )
+    };
+
+    assert!(buffer.len() >= baselines.len() * num_fine_chans * floats_per_baseline_fine_chan);
+
+    // Each real or imaginary product is summed 2N times
+    let num_products = 2.0 * num_samples;
+
+    // First work out the true sigma of each input for each fine channel, from the (uncorrected)
+    // autocorrelations. Stored [ant][pol (x=0, y=1)][fine_chan].
+    let mut sigmas: Vec<Option<f64>> = vec![None; num_ants * 2 * num_fine_chans];
+    for (baseline_index, baseline) in baselines.iter().enumerate() {
+        if baseline.ant1_index != baseline.ant2_index {
+            continue;
+        }
+
+        for fine_chan_index in 0..num_fine_chans {
+            let index = baseline_index * baseline_stride + fine_chan_index * fine_chan_stride;
+            // xx_r and yy_r
+            for (pol, offset) in [(0, 0), (1, 6)] {
+                let sighat = (buffer[index + offset] as f64 / num_products).sqrt();
+                sigmas[(baseline.ant1_index * 2 + pol) * num_fine_chans + fine_chan_index] =
+                    van_vleck_sigma(sighat, max_level);
+            }
+        }
+    }
+
+    for (baseline_index, baseline) in baselines.iter().enumerate() {
+        for fine_chan_index in 0..num_fine_chans {
+            let index = baseline_index * baseline_stride + fine_chan_index * fine_chan_stride;
+            let sigma =
+                |ant: usize, pol: usize| sigmas[(ant * 2 + pol) * num_fine_chans + fine_chan_index];
+
+            // (offset into the 8 floats, pol of ant1, pol of ant2) for xx, xy, yx, yy
+            for (offset, pol1, pol2) in [(0, 0, 0), (2, 0, 1), (4, 1, 0), (6, 1, 1)] {
+                let (sigma1, sigma2) = match (
+                    sigma(baseline.ant1_index, pol1),
+                    sigma(baseline.ant2_index, pol2),
+                ) {
+                    (Some(s1), Some(s2)) => (s1, s2),
+                    _ => continue,
+                };
+
+                if baseline.ant1_index == baseline.ant2_index && pol1 == pol2 {
+                    // Autocorrelation: the real part is the power; leave the imaginary part alone
+                    buffer[index + offset] = (num_products * sigma1 * sigma1) as f32;
+                } else {
+                    let gain = van_vleck_cross_gain(sigma1, max_level)
+                        * van_vleck_cross_gain(sigma2, max_level);
+                    buffer[index + offset] = (buffer[index + offset] as f64 / gain) as f32;
+                    buffer[index + offset + 1] = (buffer[index + offset + 1] as f64 / gain) as f32;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,459 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Unit tests for van vleck correction
+//!
+//! The quantiser model is checked against published results (Sheppard's correction, and the
+//! optimum 3 level quantiser in Thompson, Moran & Swenson, "Interferometry and Synthesis in Radio
+//! Astronomy", 3rd ed., Table 8.1) and against a simulation of the quantiser itself.
+#[cfg(test)]
+use super::*;
+use crate::misc::get_baseline_count;
+use float_cmp::*;
+
+#[test]
+fn test_erfc() {
+    let expected = [
+        (0.1, 0.8875370839817152),
+        (0.5, 0.4795001221869535),
+        (1.0, 0.15729920705028513),
+        (1.99, 0.004888586800383003),
+        (2.0, 0.004677734981047265),
+        (2.5, 0.0004069520174449589),
+        (4.0, 1.541725790028002e-08),
+        (-1.0, 1.842700792949715),
+    ];
+
+    for (x, value) in expected {
+        assert!(
+            approx_eq!(
+                f64,
+                erfc(x),
+                value,
+                F64Margin::default().epsilon(1e-13 * value)
+            ),
+            "erfc({}) was {}, expected {}",
+            x,
+            erfc(x),
+            value
+        );
+    }
+
+    assert_eq!(erfc(0.0), 1.0);
+}
+
+#[test]
+fn test_van_vleck_sighat_sheppard() {
+    // Well away from saturation, rounding adds (step size)^2 / 12 to the variance (Sheppard's
+    // correction), up to terms of order exp(-2 pi^2 sigma^2)
+    for sigma in [1.0, 1.2, 1.4] {
+        let sighat = van_vleck_sighat(sigma, MWA_LEGACY_VAN_VLECK_MAX_LEVEL);
+        let expected = sigma * sigma + 1.0 / 12.0;
+        assert!(
+            approx_eq!(
+                f64,
+                sighat * sighat,
+                expected,
+                F64Margin::default().epsilon(1e-6 * expected)
+            ),
+            "sigma {}: sighat^2 was {}, expected {}",
+            sigma,
+            sighat * sighat,
+            expected
+        );
+    }
+
+    // Saturated, the output is almost always ±max_level
+    assert!(approx_eq!(
+        f64,
+        van_vleck_sighat(1e5, MWA_LEGACY_VAN_VLECK_MAX_LEVEL),
+        MWA_LEGACY_VAN_VLECK_MAX_LEVEL as f64,
+        F64Margin::default().epsilon(1e-3)
+    ));
+
+    assert_eq!(van_vleck_sighat(0.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL), 0.0);
+}
+
+#[test]
+fn test_van_vleck_sigma_inverts_sighat() {
+    for sigma in [0.2, 0.5, 1.0, 2.0, 3.0, 5.0] {
+        let sighat = van_vleck_sighat(sigma, MWA_LEGACY_VAN_VLECK_MAX_LEVEL);
+        let result = van_vleck_sigma(sighat, MWA_LEGACY_VAN_VLECK_MAX_LEVEL);
+        assert!(result.is_some());
+        assert!(
+            approx_eq!(
+                f64,
+                result.unwrap(),
+                sigma,
+                F64Margin::default().epsilon(1e-9)
+            ),
+            "sigma {} was inverted to {}",
+            sigma,
+            result.unwrap()
+        );
+    }
+
+    // Sheppard's correction, inverted
+    let result = van_vleck_sigma(
+        (1.0_f64 + 1.0 / 12.0).sqrt(),
+        MWA_LEGACY_VAN_VLECK_MAX_LEVEL,
+    );
+    assert!(approx_eq!(
+        f64,
+        result.unwrap(),
+        1.0,
+        F64Margin::default().epsilon(1e-7)
+    ));
+}
+
+#[test]
+fn test_van_vleck_sigma_invalid() {
+    assert!(van_vleck_sigma(0.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL).is_none());
+    assert!(van_vleck_sigma(-1.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL).is_none());
+    assert!(van_vleck_sigma(f64::NAN, MWA_LEGACY_VAN_VLECK_MAX_LEVEL).is_none());
+    // Saturated
+    assert!(van_vleck_sigma(7.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL).is_none());
+}
+
+#[test]
+fn test_van_vleck_cross_gain_sheppard() {
+    // Well away from saturation, rounding does not change the covariance of two inputs
+    for sigma in [1.0, 1.2, 1.4] {
+        assert!(approx_eq!(
+            f64,
+            van_vleck_cross_gain(sigma, MWA_LEGACY_VAN_VLECK_MAX_LEVEL),
+            1.0,
+            F64Margin::default().epsilon(1e-6)
+        ));
+    }
+
+    // Saturated, every input looks the same so nothing is correlated
+    assert!(van_vleck_cross_gain(1e4, MWA_LEGACY_VAN_VLECK_MAX_LEVEL) < 1e-3);
+}
+
+#[test]
+fn test_van_vleck_three_level_efficiency() {
+    // With max_level = 1 this is the 3 level quantiser, with thresholds at ±0.5 (= v0 sigma). For
+    // small correlations, its efficiency (the quantised correlation coefficient over the true
+    // one) is best at v0 = 0.612, where it is 0.810 (TMS Table 8.1)
+    let efficiency = |v0: f64| {
+        let sigma = 0.5 / v0;
+        let gain = van_vleck_cross_gain(sigma, 1);
+        let sighat = van_vleck_sighat(sigma, 1);
+        (sigma * gain / sighat).powi(2)
+    };
+
+    assert!(approx_eq!(
+        f64,
+        efficiency(0.612),
+        0.810,
+        F64Margin::default().epsilon(5e-4)
+    ));
+    assert!(efficiency(0.612) > efficiency(0.6));
+    assert!(efficiency(0.612) > efficiency(0.625));
+}
+
+/// Samples of a pair of zero mean Gaussian inputs with the given standard deviations and
+/// correlation coefficient, from a fixed seed.
+fn correlated_gaussian_samples(
+    num_samples: usize,
+    sigma_x: f64,
+    sigma_y: f64,
+    rho: f64,
+) -> Vec<(f64, f64)> {
+    // splitmix64, so the test does not need a random number crate
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut uniform = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // (0, 1]
+        ((z >> 11) as f64 + 1.0) / (1u64 << 53) as f64
+    };
+
+    (0..num_samples)
+        .map(|_| {
+            // Box-Muller
+            let r = (-2.0 * uniform().ln()).sqrt();
+            let theta = 2.0 * PI * uniform();
+            let (z1, z2) = (r * theta.cos(), r * theta.sin());
+            (
+                sigma_x * z1,
+                sigma_y * (rho * z1 + (1.0 - rho * rho).sqrt() * z2),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_van_vleck_against_simulated_quantiser() {
+    let max_level = MWA_LEGACY_VAN_VLECK_MAX_LEVEL as f64;
+    let quantise = |v: f64| v.round().clamp(-max_level, max_level);
+    let num_samples = 400_000;
+
+    // Low power (where rounding matters most) and high power (where clipping matters most)
+    for (sigma_x, sigma_y, rho) in [(0.4, 0.6, 0.7), (3.0, 4.0, -0.5)] {
+        let samples = correlated_gaussian_samples(num_samples, sigma_x, sigma_y, rho);
+        let (mut xx, mut yy, mut xy) = (0.0, 0.0, 0.0);
+        for (x, y) in samples {
+            let (qx, qy) = (quantise(x), quantise(y));
+            xx += qx * qx;
+            yy += qy * qy;
+            xy += qx * qy;
+        }
+        let n = num_samples as f64;
+        let (sighat_x, sighat_y, kappa_hat) = ((xx / n).sqrt(), (yy / n).sqrt(), xy / n);
+
+        // Within a few times the statistical error of each estimate
+        let check = |got: f64, expected: f64, what: &str| {
+            assert!(
+                approx_eq!(
+                    f64,
+                    got,
+                    expected,
+                    F64Margin::default().epsilon(1e-2 * expected.abs())
+                ),
+                "{} was {}, expected {}",
+                what,
+                got,
+                expected
+            );
+        };
+        check(
+            sighat_x,
+            van_vleck_sighat(sigma_x, MWA_LEGACY_VAN_VLECK_MAX_LEVEL),
+            "sighat_x",
+        );
+        check(
+            sighat_y,
+            van_vleck_sighat(sigma_y, MWA_LEGACY_VAN_VLECK_MAX_LEVEL),
+            "sighat_y",
+        );
+        check(
+            kappa_hat,
+            van_vleck_quantised_covariance(rho, sigma_x, sigma_y, MWA_LEGACY_VAN_VLECK_MAX_LEVEL),
+            "kappa_hat",
+        );
+
+        // And correcting the measurements recovers the inputs
+        let corrected_sigma_x = van_vleck_sigma(sighat_x, MWA_LEGACY_VAN_VLECK_MAX_LEVEL).unwrap();
+        let corrected_sigma_y = van_vleck_sigma(sighat_y, MWA_LEGACY_VAN_VLECK_MAX_LEVEL).unwrap();
+        let corrected_rho = van_vleck_correlation(
+            kappa_hat,
+            corrected_sigma_x,
+            corrected_sigma_y,
+            MWA_LEGACY_VAN_VLECK_MAX_LEVEL,
+        )
+        .unwrap();
+        check(corrected_sigma_x, sigma_x, "sigma_x");
+        check(corrected_sigma_y, sigma_y, "sigma_y");
+        check(corrected_rho, rho, "rho");
+    }
+}
+
+#[test]
+fn test_van_vleck_quantised_covariance() {
+    // For small correlations, this should match the first order approximation
+    let rho = 1e-4;
+    for (sigma_x, sigma_y) in [(0.5, 1.0), (1.0, 2.0), (3.0, 3.0)] {
+        let gain = van_vleck_cross_gain(sigma_x, MWA_LEGACY_VAN_VLECK_MAX_LEVEL)
+            * van_vleck_cross_gain(sigma_y, MWA_LEGACY_VAN_VLECK_MAX_LEVEL);
+        let kappa_hat =
+            van_vleck_quantised_covariance(rho, sigma_x, sigma_y, MWA_LEGACY_VAN_VLECK_MAX_LEVEL);
+        assert!(approx_eq!(
+            f64,
+            kappa_hat,
+            rho * sigma_x * sigma_y * gain,
+            F64Margin::default().epsilon(1e-8 * kappa_hat)
+        ));
+    }
+
+    // A perfectly correlated input is the autocorrelation
+    for sigma in [0.3, 1.0, 2.0] {
+        let kappa_hat =
+            van_vleck_quantised_covariance(1.0, sigma, sigma, MWA_LEGACY_VAN_VLECK_MAX_LEVEL);
+        let sighat = van_vleck_sighat(sigma, MWA_LEGACY_VAN_VLECK_MAX_LEVEL);
+        assert!(approx_eq!(
+            f64,
+            kappa_hat,
+            sighat * sighat,
+            F64Margin::default().epsilon(1e-4)
+        ));
+    }
+
+    // Odd in rho
+    assert!(approx_eq!(
+        f64,
+        van_vleck_quantised_covariance(-0.5, 1.0, 2.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL),
+        -van_vleck_quantised_covariance(0.5, 1.0, 2.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL),
+        F64Margin::default()
+    ));
+}
+
+#[test]
+fn test_van_vleck_correlation_inverts_quantised_covariance() {
+    for rho in [-0.9, -0.3, 0.001, 0.5, 0.95] {
+        let kappa_hat =
+            van_vleck_quantised_covariance(rho, 1.0, 2.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL);
+        let result = van_vleck_correlation(kappa_hat, 1.0, 2.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL);
+        assert!(
+            approx_eq!(
+                f64,
+                result.unwrap(),
+                rho,
+                F64Margin::default().epsilon(1e-9)
+            ),
+            "rho {} was inverted to {:?}",
+            rho,
+            result
+        );
+    }
+
+    assert_eq!(
+        van_vleck_correlation(0.0, 1.0, 2.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL),
+        Some(0.0)
+    );
+    assert!(van_vleck_correlation(1.0, 0.0, 2.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL).is_none());
+    // Larger than any correlation could produce
+    assert!(van_vleck_correlation(100.0, 1.0, 2.0, MWA_LEGACY_VAN_VLECK_MAX_LEVEL).is_none());
+}
+
+/// Build a buffer of quantised visibilities for 2 antennas with the given true sigmas and
+/// cross-correlation (as the fraction of the product of the sigmas)
+fn build_van_vleck_test_buffer(
+    sigmas: &[(f64, f64)],
+    rho: f64,
+    num_fine_chans: usize,
+    num_samples: f64,
+    by_frequency: bool,
+) -> (Vec<f32>, Vec<Baseline>) {
+    let num_ants = sigmas.len();
+    let num_baselines = get_baseline_count(num_ants);
+    let max_level = MWA_LEGACY_VAN_VLECK_MAX_LEVEL;
+    let mut baselines = Vec::new();
+    for ant1_index in 0..num_ants {
+        for ant2_index in ant1_index..num_ants {
+            baselines.push(Baseline {
+                ant1_index,
+                ant2_index,
+            });
+        }
+    }
+
+    let mut buffer = vec![0.0; num_baselines * num_fine_chans * 8];
+    for (baseline_index, baseline) in baselines.iter().enumerate() {
+        for fine_chan_index in 0..num_fine_chans {
+            let index = if by_frequency {
+                (fine_chan_index * num_baselines + baseline_index) * 8
+            } else {
+                (baseline_index * num_fine_chans + fine_chan_index) * 8
+            };
+            let s1 = [sigmas[baseline.ant1_index].0, sigmas[baseline.ant1_index].1];
+            let s2 = [sigmas[baseline.ant2_index].0, sigmas[baseline.ant2_index].1];
+            for (offset, p1, p2) in [(0, 0, 0), (2, 0, 1), (4, 1, 0), (6, 1, 1)] {
+                if baseline.ant1_index == baseline.ant2_index && p1 == p2 {
+                    buffer[index + offset] =
+                        (2.0 * num_samples * van_vleck_sighat(s1[p1], max_level).powi(2)) as f32;
+                } else {
+                    let value = 2.0
+                        * num_samples
+                        * rho
+                        * s1[p1]
+                        * s2[p2]
+                        * van_vleck_cross_gain(s1[p1], max_level)
+                        * van_vleck_cross_gain(s2[p2], max_level);
+                    buffer[index + offset] = value as f32;
+                    buffer[index + offset + 1] = -value as f32;
+                }
+            }
+        }
+    }
+
+    (buffer, baselines)
+}
+
+#[test]
+fn test_apply_van_vleck_correction() {
+    let sigmas = [(0.5, 0.8), (2.0, 3.0)];
+    let rho = 0.01;
+    let num_fine_chans = 3;
+    let num_samples = 10000.0;
+
+    for by_frequency in [false, true] {
+        let (mut buffer, baselines) =
+            build_van_vleck_test_buffer(&sigmas, rho, num_fine_chans, num_samples, by_frequency);
+
+        apply_van_vleck_correction(
+            &mut buffer,
+            &baselines,
+            sigmas.len(),
+            num_fine_chans,
+            by_frequency,
+            num_samples,
+            MWA_LEGACY_VAN_VLECK_MAX_LEVEL,
+        );
+
+        for (baseline_index, baseline) in baselines.iter().enumerate() {
+            for fine_chan_index in 0..num_fine_chans {
+                let index = if by_frequency {
+                    (fine_chan_index * baselines.len() + baseline_index) * 8
+                } else {
+                    (baseline_index * num_fine_chans + fine_chan_index) * 8
+                };
+                let s1 = [sigmas[baseline.ant1_index].0, sigmas[baseline.ant1_index].1];
+                let s2 = [sigmas[baseline.ant2_index].0, sigmas[baseline.ant2_index].1];
+                for (offset, p1, p2) in [(0, 0, 0), (2, 0, 1), (4, 1, 0), (6, 1, 1)] {
+                    let expected = if baseline.ant1_index == baseline.ant2_index && p1 == p2 {
+                        2.0 * num_samples * s1[p1] * s1[p1]
+                    } else {
+                        2.0 * num_samples * rho * s1[p1] * s2[p2]
+                    };
+                    assert!(
+                        approx_eq!(
+                            f32,
+                            buffer[index + offset],
+                            expected as f32,
+                            F32Margin::default().epsilon(1e-5 * expected as f32)
+                        ),
+                        "baseline {} chan {} offset {}: {} != {}",
+                        baseline_index,
+                        fine_chan_index,
+                        offset,
+                        buffer[index + offset],
+                        expected
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_apply_van_vleck_correction_no_power() {
+    // An antenna with no power must be left untouched
+    let sigmas = [(1.0, 1.0), (2.0, 2.0)];
+    let (mut buffer, baselines) = build_van_vleck_test_buffer(&sigmas, 0.01, 1, 100.0, false);
+
+    // Zero the autos of antenna 0
+    buffer[0] = 0.0;
+    buffer[6] = 0.0;
+    let original = buffer.clone();
+
+    apply_van_vleck_correction(
+        &mut buffer,
+        &baselines,
+        sigmas.len(),
+        1,
+        false,
+        100.0,
+        MWA_LEGACY_VAN_VLECK_MAX_LEVEL,
+    );
+
+    // baseline 0 (0-0) and baseline 1 (0-1) are unchanged
+    assert_eq!(buffer[0..16], original[0..16]);
+    // baseline 2 (1-1) is corrected
+    assert_ne!(buffer[16..24], original[16..24]);
+}
@@ -5,6 +5,7 @@
 //! The main interface to MWA voltage data.
 
 use crate::coarse_channel::*;
+use crate::data_iterator::*;
 use crate::error::*;
 use crate::metafits_context::*;
 use crate::timestep::*;
@@ -470,6 +471,59 @@ impl VoltageContext {
         })
     }
 
+    /// Returns an iterator over the data of this observation, one timestep and coarse channel at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `index_set` - which timestep and coarse channel indices to visit.
+    ///
+    /// * `order` - whether to visit all coarse channels of a timestep first (time-major) or all timesteps of a coarse channel first (frequency-major).
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * A `VoltageDataIterator` yielding (timestep index, coarse chan index, Result containing the data).
+    ///
+    pub fn data_iter(&self, index_set: IndexSet, order: IterationOrder) -> VoltageDataIterator<'_> {
+        let (timestep_indices, coarse_chan_indices): (Vec<usize>, Vec<usize>) = match index_set {
+            IndexSet::All => (
+                (0..self.num_timesteps).collect(),
+                (0..self.num_coarse_chans).collect(),
+            ),
+            IndexSet::Provided => (
+                self.provided_timestep_indices.clone(),
+                self.provided_coarse_chan_indices.clone(),
+            ),
+            IndexSet::Common => (
+                self.common_timestep_indices.clone(),
+                self.common_coarse_chan_indices.clone(),
+            ),
+            IndexSet::CommonGood => (
+                self.common_good_timestep_indices.clone(),
+                self.common_good_coarse_chan_indices.clone(),
+            ),
+        };
+
+        VoltageDataIterator::new(self, &timestep_indices, &coarse_chan_indices, order)
+    }
+
+    /// Group the voltage coarse channels into bands of contiguous receiver channels
+    /// (e.g. for picket fence observations).
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * a vector of `CoarseChannelBand`s in ascending sky frequency order. Coarse channel indices in each band are
+    ///   indices into `coarse_chans`.
+    ///
+    pub fn get_contiguous_bands(&self) -> Vec<CoarseChannelBand> {
+        CoarseChannel::get_contiguous_bands(&self.coarse_chans)
+    }
+
     /// For a given slice of voltage coarse channel indices, return a vector of the center
     /// frequencies for all the fine channels in the given coarse channels
     ///
@@ -496,6 +550,71 @@ impl VoltageContext {
         )
     }
 
+    /// Return the indices of the voltage coarse channels which overlap a range of sky frequencies.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_hz` - start of the frequency range in Hz (inclusive).
+    ///
+    /// * `end_hz` - end of the frequency range in Hz (inclusive).
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * a vector of indices into `coarse_chans` of every coarse channel whose [`chan_start_hz`, `chan_end_hz`) range
+    ///   intersects [`start_hz`, `end_hz`].
+    ///
+    pub fn get_coarse_chan_indices_for_freq_range(&self, start_hz: f64, end_hz: f64) -> Vec<usize> {
+        CoarseChannel::get_coarse_chan_indices_in_freq_range(&self.coarse_chans, start_hz, end_hz)
+    }
+
+    /// Return the fine channels whose centre frequencies are within a range of sky frequencies.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_hz` - start of the frequency range in Hz (inclusive).
+    ///
+    /// * `end_hz` - end of the frequency range in Hz (inclusive).
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * a vector of (index into `coarse_chans`, fine channel index within the coarse channel) pairs in ascending
+    ///   frequency order, consistent with `get_fine_chan_freqs_hz_array`.
+    ///
+    pub fn get_fine_chan_indices_for_freq_range(
+        &self,
+        start_hz: f64,
+        end_hz: f64,
+    ) -> Vec<(usize, usize)> {
+        CoarseChannel::get_fine_chan_indices_in_freq_range(
+            &self.get_fine_chan_freqs_hz_array(&(0..self.num_coarse_chans).collect::<Vec<usize>>()),
+            self.metafits_context.num_volt_fine_chans_per_coarse,
+            start_hz,
+            end_hz,
+        )
+    }
+
+    /// Return the fine channel whose centre frequency is nearest to a sky frequency.
+    ///
+    /// # Arguments
+    ///
+    /// * `freq_hz` - the sky frequency in Hz.
+    ///
+    ///
+    /// # Returns
+    ///
+    /// * the (index into `coarse_chans`, fine channel index within the coarse channel) of the nearest fine channel,
+    ///   consistent with `get_fine_chan_freqs_hz_array`, or None if there are no fine channels.
+    ///
+    pub fn get_nearest_fine_chan_index(&self, freq_hz: f64) -> Option<(usize, usize)> {
+        CoarseChannel::get_nearest_fine_chan_index(
+            &self.get_fine_chan_freqs_hz_array(&(0..self.num_coarse_chans).collect::<Vec<usize>>()),
+            self.metafits_context.num_volt_fine_chans_per_coarse,
+            freq_hz,
+        )
+    }
+
     /// Validates gps time start and gps seconds count, and returns the end gps time or an Error.
     /// The gps end second is the START time of the end second, not the END of the second.
     /// e.g gpstart = 100, count = 1, therefore gpsend = 100.
@@ -1595,3 +1595,39 @@ fn test_context_legacy_v1_get_fine_chan_feqs_some_coarse_chans() {
         F64Margin::default()
     ));
 }
+
+#[test]
+fn test_context_legacy_v1_data_iter() {
+    // Open a context and load in a test metafits and gpubox file
+    let mut context = get_test_voltage_context(MWAVersion::VCSLegacyRecombined);
+
+    //
+    // In order for our smaller voltage files to work with this test we need to reset the voltage_block_size_bytes
+    //
+    context.voltage_block_size_bytes /= 128;
+
+    let mut expected: Vec<u8> = vec![
+        0;
+        (context.voltage_block_size_bytes * context.num_voltage_blocks_per_timestep)
+            as usize
+    ];
+
+    let items: Vec<(usize, usize, Result<Vec<u8>, VoltageFileError>)> = context
+        .data_iter(IndexSet::Provided, IterationOrder::FrequencyMajor)
+        .collect();
+    assert_eq!(
+        items.len(),
+        context.provided_timestep_indices.len() * context.provided_coarse_chan_indices.len()
+    );
+
+    // Coarse channel changes slowest
+    assert_eq!(items[0].1, items[1].1);
+    assert_ne!(items[0].0, items[1].0);
+
+    for (timestep_index, coarse_chan_index, result) in items {
+        context
+            .read_file(timestep_index, coarse_chan_index, &mut expected)
+            .expect("Error!");
+        assert_eq!(result.expect("Error!"), expected);
+    }
+}